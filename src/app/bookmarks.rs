@@ -0,0 +1,139 @@
+use std::path::PathBuf;
+
+use cgmath::Point3;
+
+/// Camera viewpoint, oriented by the yaw and pitch of the camera controller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewpoint {
+    pub eye: Point3<f32>,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl Viewpoint {
+    fn parse(numbers: &[&str]) -> Option<Self> {
+        let numbers: Vec<f32> = numbers.iter().map(|n| n.parse().ok()).collect::<Option<_>>()?;
+        match numbers[..] {
+            [x, y, z, yaw, pitch] => Some(Self {
+                eye: Point3::new(x, y, z),
+                yaw,
+                pitch,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Viewpoints numbered from 1, written to a text file on every change so
+/// they survive restarts. Each line holds the number, the eye, the yaw and
+/// the pitch.
+pub struct Bookmarks {
+    path: PathBuf,
+    slots: [Option<Viewpoint>; Self::SLOTS],
+}
+
+impl Bookmarks {
+    pub const SLOTS: usize = 9;
+    const FILE_NAME: &'static str = "bookmarks.txt";
+
+    /// `wgpu-instancing/bookmarks.txt` in the per-user config directory, so
+    /// the bookmarks don't depend on where the program is started from.
+    /// That's `$XDG_CONFIG_HOME` or `~/.config`, `%APPDATA%` on Windows,
+    /// falling back to the working directory when none is set.
+    pub fn default_path() -> PathBuf {
+        let config_dir = if cfg!(windows) {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        };
+        match config_dir {
+            Some(dir) => dir.join("wgpu-instancing").join(Self::FILE_NAME),
+            None => PathBuf::from(Self::FILE_NAME),
+        }
+    }
+
+    /// Starts out empty when the file doesn't exist yet.
+    pub fn load(path: PathBuf) -> Self {
+        let mut bookmarks = Self {
+            path,
+            slots: [None; Self::SLOTS],
+        };
+        let Ok(text) = std::fs::read_to_string(&bookmarks.path) else {
+            return bookmarks;
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let Some((slot, numbers)) = words.split_first() else {
+                continue;
+            };
+            match (slot.parse::<usize>(), Viewpoint::parse(numbers)) {
+                (Ok(slot @ 1..=Self::SLOTS), Some(viewpoint)) => bookmarks.slots[slot - 1] = Some(viewpoint),
+                _ => log::warn!("Ignoring bookmark on line {} of {}.", number + 1, bookmarks.path.display()),
+            }
+        }
+        log::debug!("Loaded {} bookmarks.", bookmarks.slots.iter().flatten().count());
+        bookmarks
+    }
+
+    pub fn get(&self, slot: usize) -> Option<Viewpoint> {
+        self.slots.get(slot.checked_sub(1)?).copied().flatten()
+    }
+
+    /// Stores the viewpoint in a slot from 1 to [`Self::SLOTS`] and saves
+    /// every bookmark.
+    pub fn set(&mut self, slot: usize, viewpoint: Viewpoint) -> std::io::Result<()> {
+        self.slots[slot - 1] = Some(viewpoint);
+
+        let mut text = String::new();
+        for (index, viewpoint) in self.slots.iter().enumerate() {
+            if let Some(Viewpoint { eye, yaw, pitch }) = viewpoint {
+                text += &format!("{} {} {} {} {yaw} {pitch}\n", index + 1, eye.x, eye.y, eye.z);
+            }
+        }
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, text)
+    }
+}
+
+/// Eased move of the camera between two viewpoints.
+pub struct CameraTransition {
+    from: Viewpoint,
+    to: Viewpoint,
+    elapsed: f32,
+}
+
+impl CameraTransition {
+    const DURATION: f32 = 0.6;
+
+    pub fn new(from: Viewpoint, mut to: Viewpoint) -> Self {
+        use std::f32::consts::{PI, TAU};
+
+        // Turn the short way around
+        to.yaw = from.yaw + (to.yaw - from.yaw + PI).rem_euclid(TAU) - PI;
+        Self { from, to, elapsed: 0.0 }
+    }
+
+    /// Advances the transition, returning where the camera is now.
+    pub fn advance(&mut self, delta: f32) -> Viewpoint {
+        self.elapsed = (self.elapsed + delta).min(Self::DURATION);
+        let t = self.elapsed / Self::DURATION;
+        let t = t * t * (3.0 - 2.0 * t);
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+
+        Viewpoint {
+            eye: self.from.eye + (self.to.eye - self.from.eye) * t,
+            yaw: lerp(self.from.yaw, self.to.yaw),
+            pitch: lerp(self.from.pitch, self.to.pitch),
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.elapsed >= Self::DURATION
+    }
+}
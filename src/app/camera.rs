@@ -1,23 +1,65 @@
-use std::f32::consts::FRAC_PI_2;
+use std::{collections::HashMap, f32::consts::FRAC_PI_2};
 
 use bytemuck::{Pod, Zeroable};
-use cgmath::{InnerSpace, Matrix3, Matrix4, Point3, SquareMatrix, Vector3};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix3, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
 use winit::{
     event::{DeviceEvent, KeyEvent, WindowEvent},
     keyboard::KeyCode,
 };
 
+use super::shader;
+
+/// Which way depth runs in the scene, see `--reversed-z`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthOrder {
+    /// Near plane at 0 and far plane at 1.
+    #[default]
+    Standard,
+    /// Near plane at 1 and no far plane, depth reaching 0 infinitely far
+    /// away. Floats are densest near 0, which evens out the precision over
+    /// distance.
+    Reversed,
+}
+
+impl DepthOrder {
+    /// Depth test passing the nearer fragment.
+    pub fn compare(self) -> wgpu::CompareFunction {
+        match self {
+            Self::Standard => wgpu::CompareFunction::Less,
+            Self::Reversed => wgpu::CompareFunction::Greater,
+        }
+    }
+
+    /// Depth of the far end, which depth buffers are cleared to.
+    pub fn far(self) -> f32 {
+        match self {
+            Self::Standard => 1.0,
+            Self::Reversed => 0.0,
+        }
+    }
+
+    /// `REVERSED_Z` for the shaders reading depth.
+    pub fn override_constants(self) -> HashMap<String, f64> {
+        shader::override_constants([("REVERSED_Z", f64::from(u8::from(self == Self::Reversed)))])
+    }
+}
+
 pub struct Camera {
     pub eye: Point3<f32>,
     pub direction: Vector3<f32>,
     pub up: Vector3<f32>,
     pub near: f32,
+    /// Ignored with reversed depth, which has no far plane.
     pub far: f32,
     pub aspect: f32,
+    pub depth_order: DepthOrder,
 }
 
 #[allow(dead_code)]
 impl Camera {
+    /// Vertical field of view in radians.
+    pub const FOVY: f32 = FRAC_PI_2;
+
     pub fn new(aspect: f32) -> Self {
         Self {
             eye: Point3::new(0.0, 0.0, 1.0),
@@ -26,6 +68,7 @@ impl Camera {
             near: 0.1,
             far: 40000.0,
             aspect,
+            depth_order: DepthOrder::Standard,
         }
     }
 
@@ -46,12 +89,46 @@ impl Camera {
     }
 
     pub fn projection(&self, aspect: f32) -> Matrix4<f32> {
-        cgmath::perspective(
-            cgmath::Rad(std::f32::consts::FRAC_PI_2),
-            aspect,
-            self.near,
-            self.far,
-        )
+        match self.depth_order {
+            DepthOrder::Standard => cgmath::perspective(
+                cgmath::Rad(Self::FOVY),
+                aspect,
+                self.near,
+                self.far,
+            ),
+            // Depth is near / distance
+            DepthOrder::Reversed => {
+                let focal = 1.0 / (Self::FOVY / 2.0).tan();
+                #[rustfmt::skip]
+                let projection = Matrix4::new(
+                    focal / aspect, 0.0, 0.0, 0.0,
+                    0.0, focal, 0.0, 0.0,
+                    0.0, 0.0, 0.0, -1.0,
+                    0.0, 0.0, self.near, 0.0,
+                );
+                projection
+            }
+        }
+    }
+
+    /// Corners of the part of the view between the distances `near` and
+    /// `far` along it, the near ones first.
+    pub fn slice_corners(&self, near: f32, far: f32) -> [Point3<f32>; 8] {
+        let projection = cgmath::perspective(cgmath::Rad(Self::FOVY), self.aspect, near, far);
+        let inverse = (projection * self.view()).invert().unwrap();
+        let mut corners = [Point3::origin(); 8];
+        for (index, corner) in corners.iter_mut().enumerate() {
+            let x = if index & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if index & 2 == 0 { -1.0 } else { 1.0 };
+            let z = if index & 4 == 0 { -1.0 } else { 1.0 };
+            let point = inverse * Vector4::new(x, y, z, 1.0);
+            *corner = Point3::from_homogeneous(point);
+        }
+        corners
+    }
+
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(self.projection(self.aspect) * self.view())
     }
 
     pub fn uniform(&self) -> CameraUniform {
@@ -65,6 +142,80 @@ impl Camera {
     }
 }
 
+/// Point ahead of the camera the simulated instances are pulled towards
+/// while it is on, so moving the camera stirs them.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraAttractor {
+    pub enabled: bool,
+    /// Distance of the point ahead of the eye.
+    pub distance: f32,
+    /// Acceleration towards the point, the same from any distance.
+    pub strength: f32,
+}
+
+impl Default for CameraAttractor {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            distance: 500.0,
+            strength: 400.0,
+        }
+    }
+}
+
+impl CameraAttractor {
+    /// The point in `xyz` and the strength in `w`, 0 while off.
+    pub fn uniform(&self, camera: &Camera) -> [f32; 4] {
+        let point = camera.eye + camera.direction.normalize() * self.distance;
+        let strength = if self.enabled { self.strength } else { 0.0 };
+        [point.x, point.y, point.z, strength]
+    }
+}
+
+/// View frustum as six inward-facing planes `(normal, distance)`.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    pub fn from_matrix(view_projection: Matrix4<f32>) -> Self {
+        let r0 = view_projection.row(0);
+        let r1 = view_projection.row(1);
+        let r2 = view_projection.row(2);
+        let r3 = view_projection.row(3);
+
+        // NOTE: Near plane uses the -w <= z convention of cgmath::perspective,
+        // which is looser than wgpu's 0 <= z and therefore conservative. With
+        // reversed depth the last two planes are the near plane and one
+        // behind the camera, leaving the frustum without a far end.
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 + r2, r3 - r2].map(|plane| {
+            plane / plane.truncate().magnitude()
+        });
+
+        Self { planes }
+    }
+
+    pub fn intersects_aabb(&self, min: Point3<f32>, max: Point3<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            // Test the box corner furthest along the plane normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+            );
+
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+
+    pub fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center.to_vec()) + plane.w >= -radius)
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Zeroable, Pod)]
 pub struct CameraUniform {
@@ -99,8 +250,8 @@ impl Axis {
     }
 
     pub fn get(&self) -> f32 {
-        return if self.negative_pressed { -1.0 } else { 0.0 }
-            + if self.positive_pressed { 1.0 } else { 0.0 };
+        (if self.negative_pressed { -1.0 } else { 0.0 })
+            + if self.positive_pressed { 1.0 } else { 0.0 }
     }
 }
 
@@ -140,27 +291,47 @@ impl CameraController {
 
                 self.speed *= 1.0 + self.arrowkey_axis.get() * 0.2;
             }
-            WindowEvent::MouseWheel { delta, .. } => match delta {
-                winit::event::MouseScrollDelta::LineDelta(_, y) => {
-                    self.speed *= 1.0 + y * 0.1;
-                }
-                _ => {}
-            },
+            WindowEvent::MouseWheel {
+                delta: winit::event::MouseScrollDelta::LineDelta(_, y),
+                ..
+            } => {
+                self.speed *= 1.0 + y * 0.1;
+            }
             _ => {}
         }
     }
 
     pub fn process_device_events(&mut self, device_event: &DeviceEvent) {
-        match device_event {
-            DeviceEvent::MouseMotion { delta } => {
-                self.camera_motion.0 -= delta.0 as f32 * self.sensitivity;
-                self.camera_motion.1 -= delta.1 as f32 * self.sensitivity;
-                self.camera_motion.1 = self
-                    .camera_motion
-                    .1
-                    .clamp(-FRAC_PI_2 + 0.001, FRAC_PI_2 - 0.001);
-            }
-            _ => {}
+        if let DeviceEvent::MouseMotion { delta } = device_event {
+            self.camera_motion.0 -= delta.0 as f32 * self.sensitivity;
+            self.camera_motion.1 -= delta.1 as f32 * self.sensitivity;
+            self.camera_motion.1 = self
+                .camera_motion
+                .1
+                .clamp(-FRAC_PI_2 + 0.001, FRAC_PI_2 - 0.001);
+        }
+    }
+
+    /// Yaw and pitch of the camera, in radians.
+    pub fn orientation(&self) -> (f32, f32) {
+        self.camera_motion
+    }
+
+    pub fn set_orientation(&mut self, yaw: f32, pitch: f32) {
+        self.camera_motion = (yaw, pitch.clamp(-FRAC_PI_2 + 0.001, FRAC_PI_2 - 0.001));
+    }
+
+    /// Forgets held keys, for when their releases can't be seen.
+    pub fn release_keys(&mut self) {
+        for axis in [
+            &mut self.horizontal,
+            &mut self.vertical,
+            &mut self.qe_axis,
+            &mut self.updown_axis,
+            &mut self.arrowkey_axis,
+        ] {
+            axis.negative_pressed = false;
+            axis.positive_pressed = false;
         }
     }
 
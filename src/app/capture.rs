@@ -0,0 +1,256 @@
+use std::{
+    fmt::Display,
+    path::PathBuf,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::{camera::Camera, culling::Aabb};
+
+#[derive(Debug, Clone)]
+pub struct CaptureError {
+    pub message: String,
+}
+
+impl CaptureError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CaptureError {}
+pub type CaptureResult<T> = Result<T, CaptureError>;
+
+/// Turntable capture options, capturing is disabled while `frames` is 0.
+#[derive(Debug, Clone)]
+pub struct CaptureSettings {
+    pub frames: u32,
+    pub directory: PathBuf,
+    /// Simulated frame rate, sets the time step between captured frames.
+    pub fps: u32,
+    /// Orbit center, the center of the instances when `None`.
+    pub focus: Option<Point3<f32>>,
+    /// Orbit radius, fitted to the instances when `None`.
+    pub radius: Option<f32>,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            frames: 0,
+            directory: PathBuf::from("capture"),
+            fps: 30,
+            focus: None,
+            radius: None,
+        }
+    }
+}
+
+struct CapturedFrame {
+    index: u32,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+struct Readback {
+    buffer: wgpu::Buffer,
+    size: wgpu::Extent3d,
+    padded_bytes_per_row: u32,
+}
+
+/// Orbits the camera once around a focus point over a fixed number of
+/// frames and saves every frame as a numbered PNG. Time advances by a fixed
+/// step per captured frame instead of following the wall clock, so the
+/// same settings always produce the same sequence.
+pub struct TurntableCapture {
+    frames: u32,
+    fps: u32,
+    focus: Point3<f32>,
+    radius: f32,
+    /// Frames captured so far.
+    frame: u32,
+    /// Red and blue are swapped when saving.
+    bgra: bool,
+    readback: Option<Readback>,
+    sender: Option<mpsc::Sender<CapturedFrame>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl TurntableCapture {
+    /// Camera height above the focus, relative to the radius.
+    const ELEVATION: f32 = 0.35;
+    const MIN_RADIUS: f32 = 10.0;
+    const BYTES_PER_PIXEL: u32 = 4;
+
+    /// The surface has to be 8-bit RGBA or BGRA and support `COPY_SRC`.
+    /// Unset focus and radius are fitted to the bounds of `points`.
+    pub fn new(
+        settings: &CaptureSettings,
+        surface_format: wgpu::TextureFormat,
+        points: &[[f32; 4]],
+    ) -> CaptureResult<Self> {
+        let bgra = match surface_format.remove_srgb_suffix() {
+            wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8Unorm => true,
+            format => {
+                return Err(CaptureError::new(format!("Can't capture surface format {format:?}")));
+            }
+        };
+
+        let mut bounds = Aabb::empty();
+        points.iter().for_each(|p| bounds.extend(*p));
+        let (focus, radius) = match points {
+            [] => (Point3::new(0.0, 0.0, 0.0), Self::MIN_RADIUS),
+            _ => (bounds.center(), (bounds.max - bounds.min).magnitude().max(Self::MIN_RADIUS)),
+        };
+        let focus = settings.focus.unwrap_or(focus);
+        let radius = settings.radius.unwrap_or(radius);
+
+        let directory = settings.directory.clone();
+        std::fs::create_dir_all(&directory)
+            .map_err(|e| CaptureError::new(format!("Failed to create {}: {e}", directory.display())))?;
+
+        // PNG encoding takes longer than a frame, so it happens off the render thread
+        let (sender, receiver) = mpsc::channel::<CapturedFrame>();
+        let writer = thread::spawn(move || {
+            for frame in receiver {
+                let path = directory.join(format!("frame_{:05}.png", frame.index));
+                if let Err(error) =
+                    image::save_buffer(&path, &frame.rgba, frame.width, frame.height, image::ColorType::Rgba8)
+                {
+                    log::error!("Failed to save {}: {error}", path.display());
+                }
+            }
+        });
+
+        log::info!(
+            "Capturing {} frames around {focus:?} at radius {radius:.1} into {}.",
+            settings.frames,
+            settings.directory.display()
+        );
+
+        Ok(Self {
+            frames: settings.frames,
+            fps: settings.fps,
+            focus,
+            radius,
+            frame: 0,
+            bgra,
+            readback: None,
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    /// Simulation time of the next captured frame.
+    pub fn time(&self) -> f64 {
+        self.frame as f64 / self.fps as f64
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.frames
+    }
+
+    /// Moves the camera to the orbit position of the next captured frame.
+    pub fn place_camera(&self, camera: &mut Camera) {
+        let angle = std::f32::consts::TAU * self.frame as f32 / self.frames as f32;
+        let offset = Vector3::new(angle.cos(), Self::ELEVATION, angle.sin()) * self.radius;
+
+        camera.eye = self.focus + offset;
+        camera.direction = -offset.normalize();
+    }
+
+    /// Records a copy of the presented texture, read back by [`Self::save_frame`]
+    /// once the encoder has been submitted.
+    pub fn copy_frame(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        let size = texture.size();
+        if self.readback.as_ref().is_none_or(|readback| readback.size != size) {
+            let padded_bytes_per_row = (size.width * Self::BYTES_PER_PIXEL)
+                .next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+            self.readback = Some(Readback {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("capture_readback"),
+                    size: padded_bytes_per_row as u64 * size.height as u64,
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                }),
+                size,
+                padded_bytes_per_row,
+            });
+        }
+        let readback = self.readback.as_ref().unwrap();
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback.buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(readback.padded_bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+    }
+
+    /// Waits for the copy recorded by [`Self::copy_frame`] and queues the
+    /// frame for saving. Stalls the GPU pipeline, which is fine offline.
+    pub fn save_frame(&mut self, device: &wgpu::Device) -> CaptureResult<()> {
+        let Some(readback) = &self.readback else {
+            return Ok(());
+        };
+
+        let slice = readback.buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| _ = sender.send(result));
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .map_err(|e| CaptureError::new(e.to_string()))?
+            .map_err(|e| CaptureError::new(format!("Failed to map the capture buffer: {e}")))?;
+
+        let row_len = (readback.size.width * Self::BYTES_PER_PIXEL) as usize;
+        let mut rgba = Vec::with_capacity(row_len * readback.size.height as usize);
+        for row in slice.get_mapped_range().chunks_exact(readback.padded_bytes_per_row as usize) {
+            rgba.extend_from_slice(&row[..row_len]);
+        }
+        readback.buffer.unmap();
+
+        if self.bgra {
+            rgba.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        }
+        // The surface is presented as is, so the alpha channel is meaningless
+        rgba.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+
+        if let Some(sender) = &self.sender {
+            _ = sender.send(CapturedFrame {
+                index: self.frame,
+                width: readback.size.width,
+                height: readback.size.height,
+                rgba,
+            });
+        }
+        self.frame += 1;
+
+        Ok(())
+    }
+
+    /// Waits for queued frames to be written.
+    pub fn finish(&mut self) {
+        drop(self.sender.take());
+        if let Some(writer) = self.writer.take() {
+            _ = writer.join();
+            log::info!("Captured {} frames.", self.frame);
+        }
+    }
+}
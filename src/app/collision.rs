@@ -0,0 +1,464 @@
+use std::{fmt::Display, path::{Path, PathBuf}};
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+#[derive(Debug, Clone)]
+pub struct CollisionError {
+    pub message: String,
+}
+
+impl CollisionError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Display for CollisionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CollisionError {}
+pub type CollisionResult<T> = Result<T, CollisionError>;
+
+/// Static obstacle with an analytic distance function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collider {
+    Sphere { center: Point3<f32>, radius: f32 },
+    Box { center: Point3<f32>, half_extent: Vector3<f32> },
+}
+
+impl Collider {
+    /// Parses `sphere:x,y,z,radius` or `box:x,y,z,half_x,half_y,half_z`.
+    pub fn parse(value: &str) -> CollisionResult<Self> {
+        let invalid = || CollisionError::new(format!("Invalid collider: {value}, try sphere:x,y,z,r or box:x,y,z,hx,hy,hz"));
+
+        let (shape, numbers) = value.split_once(':').ok_or_else(invalid)?;
+        let numbers: Vec<f32> = numbers
+            .split(',')
+            .map(|n| n.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+
+        match (shape, &numbers[..]) {
+            ("sphere", &[x, y, z, radius]) if radius > 0.0 => Ok(Self::Sphere {
+                center: Point3::new(x, y, z),
+                radius,
+            }),
+            ("box", &[x, y, z, hx, hy, hz]) if hx > 0.0 && hy > 0.0 && hz > 0.0 => Ok(Self::Box {
+                center: Point3::new(x, y, z),
+                half_extent: Vector3::new(hx, hy, hz),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Mirrors `collider_distance` in `compute.wgsl`.
+    pub fn distance(&self, p: Point3<f32>) -> f32 {
+        match *self {
+            Self::Sphere { center, radius } => (p - center).magnitude() - radius,
+            Self::Box { center, half_extent } => {
+                let q = p - center;
+                let d = Vector3::new(q.x.abs(), q.y.abs(), q.z.abs()) - half_extent;
+                let outside = Vector3::new(d.x.max(0.0), d.y.max(0.0), d.z.max(0.0)).magnitude();
+                outside + d.x.max(d.y.max(d.z)).min(0.0)
+            }
+        }
+    }
+
+    fn uniform(&self) -> ColliderUniform {
+        match *self {
+            Self::Sphere { center, radius } => ColliderUniform {
+                center: [center.x, center.y, center.z, 0.0],
+                extent: [radius, radius, radius, 0.0],
+            },
+            Self::Box { center, half_extent } => ColliderUniform {
+                center: [center.x, center.y, center.z, 1.0],
+                extent: [half_extent.x, half_extent.y, half_extent.z, 0.0],
+            },
+        }
+    }
+}
+
+/// Signed distances sampled at the corners of a regular grid, baked from a
+/// mesh by SDFGen. Distances and the grid are in world units.
+pub struct SdfVolume {
+    pub size: [u32; 3],
+    pub origin: Point3<f32>,
+    pub cell_size: f32,
+    /// Samples with x varying fastest, then y.
+    pub distances: Vec<f32>,
+}
+
+impl SdfVolume {
+    /// Reads the text format of SDFGen: the grid size, the origin and the
+    /// cell size on the first three lines, followed by every sample.
+    pub fn open(path: &Path) -> CollisionResult<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| CollisionError::new(format!("Failed to read {}: {e}", path.display())))?;
+        Self::parse(&text).map_err(|e| CollisionError::new(format!("{}: {e}", path.display())))
+    }
+
+    pub fn parse(text: &str) -> CollisionResult<Self> {
+        let mut words = text.split_whitespace();
+        let mut number = |what: &str| {
+            words
+                .next()
+                .ok_or_else(|| CollisionError::new(format!("Missing {what}")))?
+                .parse::<f32>()
+                .map_err(|_| CollisionError::new(format!("Invalid {what}")))
+        };
+
+        let size = [number("grid size")?, number("grid size")?, number("grid size")?].map(|n| n as u32);
+        if size.iter().any(|&n| n < 2) {
+            return Err(CollisionError::new(format!("Grid of {size:?} samples is too small")));
+        }
+        let origin = Point3::new(number("origin")?, number("origin")?, number("origin")?);
+        let cell_size = number("cell size")?;
+        let count = size.iter().product::<u32>() as usize;
+        let distances = (0..count).map(|_| number("distance")).collect::<CollisionResult<_>>()?;
+
+        Ok(Self {
+            size,
+            origin,
+            cell_size,
+            distances,
+        })
+    }
+
+    /// Mirrors `volume_distance` in `compute.wgsl`, trilinear between the
+    /// samples and unbounded outside the grid.
+    pub fn distance(&self, p: Point3<f32>) -> f32 {
+        let [nx, ny, nz] = self.size.map(|n| n as usize);
+        let g = (p - self.origin) / self.cell_size;
+        let inside = |c: f32, n: usize| (0.0..=(n - 1) as f32).contains(&c);
+        if !(inside(g.x, nx) && inside(g.y, ny) && inside(g.z, nz)) {
+            return f32::MAX;
+        }
+
+        // The last cell along each axis includes its far corner
+        let base = [(g.x as usize).min(nx - 2), (g.y as usize).min(ny - 2), (g.z as usize).min(nz - 2)];
+        let f = [g.x - base[0] as f32, g.y - base[1] as f32, g.z - base[2] as f32];
+        let sample = |x: usize, y: usize, z: usize| {
+            self.distances[base[0] + x + (base[1] + y) * nx + (base[2] + z) * nx * ny]
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+        let near = lerp(
+            lerp(sample(0, 0, 0), sample(1, 0, 0), f[0]),
+            lerp(sample(0, 1, 0), sample(1, 1, 0), f[0]),
+            f[1],
+        );
+        let far = lerp(
+            lerp(sample(0, 0, 1), sample(1, 0, 1), f[0]),
+            lerp(sample(0, 1, 1), sample(1, 1, 1), f[0]),
+            f[1],
+        );
+        lerp(near, far, f[2])
+    }
+}
+
+/// Static geometry instances collide with, from the command line.
+#[derive(Debug, Clone)]
+pub struct CollisionSettings {
+    pub colliders: Vec<Collider>,
+    /// SDFGen volume baked from a mesh.
+    pub volume: Option<PathBuf>,
+    /// Fraction of the speed towards an obstacle kept when bouncing off it.
+    pub restitution: f32,
+    /// Fraction of the speed along an obstacle lost on every contact.
+    pub friction: f32,
+}
+
+impl Default for CollisionSettings {
+    fn default() -> Self {
+        Self {
+            colliders: Vec::new(),
+            volume: None,
+            restitution: 0.5,
+            friction: 0.1,
+        }
+    }
+}
+
+/// The obstacles and how instances bounce off them.
+pub struct Obstacles {
+    pub colliders: Vec<Collider>,
+    pub volume: Option<SdfVolume>,
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+impl Obstacles {
+    pub fn load(settings: &CollisionSettings) -> CollisionResult<Self> {
+        if settings.colliders.len() > Collision::MAX_COLLIDERS {
+            return Err(CollisionError::new(format!(
+                "At most {} colliders are supported",
+                Collision::MAX_COLLIDERS
+            )));
+        }
+
+        Ok(Self {
+            colliders: settings.colliders.clone(),
+            volume: settings.volume.as_deref().map(SdfVolume::open).transpose()?,
+            restitution: settings.restitution,
+            friction: settings.friction,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colliders.is_empty() && self.volume.is_none()
+    }
+
+    /// Mirrors `scene_distance` in `compute.wgsl`.
+    pub fn distance(&self, p: Point3<f32>) -> f32 {
+        let volume = self.volume.as_ref().map_or(f32::MAX, |volume| volume.distance(p));
+        self.colliders.iter().map(|collider| collider.distance(p)).fold(volume, f32::min)
+    }
+}
+
+/// What happens to instances leaving the world bounds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BoundsBehavior {
+    /// Back inside, keeping the restitution of the obstacles of the speed
+    /// they left with.
+    #[default]
+    Bounce,
+    /// Over to the opposite side, keeping their velocity.
+    Wrap,
+}
+
+impl BoundsBehavior {
+    pub const ALL: [Self; 2] = [Self::Bounce, Self::Wrap];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bounce => "bounce",
+            Self::Wrap => "wrap",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|behavior| behavior.name() == name)
+    }
+}
+
+/// Region instances are kept within, a sphere or box like the colliders
+/// but with instances inside of it. Pushed with every simulation step, so it
+/// can change without rebuilding the pipelines.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WorldBounds {
+    /// Unbounded when `None`.
+    pub shape: Option<Collider>,
+    pub behavior: BoundsBehavior,
+}
+
+impl WorldBounds {
+    pub fn uniform(&self) -> BoundsUniform {
+        let Some(shape) = self.shape else {
+            return BoundsUniform::default();
+        };
+        let ColliderUniform { center, extent } = shape.uniform();
+        BoundsUniform {
+            center,
+            extent: [extent[0], extent[1], extent[2]],
+            behavior: self.behavior as u32 + 1,
+        }
+    }
+}
+
+impl Display for WorldBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.shape {
+            None => write!(f, "off"),
+            Some(Collider::Sphere { center, radius }) => write!(
+                f,
+                "{} sphere:{},{},{},{radius}",
+                self.behavior.name(),
+                center.x,
+                center.y,
+                center.z
+            ),
+            Some(Collider::Box { center, half_extent }) => write!(
+                f,
+                "{} box:{},{},{},{},{},{}",
+                self.behavior.name(),
+                center.x,
+                center.y,
+                center.z,
+                half_extent.x,
+                half_extent.y,
+                half_extent.z
+            ),
+        }
+    }
+}
+
+/// Mirrors `Collider` in `compute.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+struct ColliderUniform {
+    /// w is 0 for spheres and 1 for boxes.
+    center: [f32; 4],
+    /// Radius or half extent.
+    extent: [f32; 4],
+}
+
+/// Mirrors `Collision` in `compute.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct CollisionUniform {
+    colliders: [ColliderUniform; Collision::MAX_COLLIDERS],
+    count: u32,
+    restitution: f32,
+    friction: f32,
+    has_volume: u32,
+    /// w is the cell size.
+    volume_origin: [f32; 4],
+    volume_size: [u32; 4],
+}
+
+/// Mirrors `WorldBounds` in `compute.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+pub struct BoundsUniform {
+    /// w is 0 for spheres and 1 for boxes.
+    center: [f32; 4],
+    /// Radius or half extent.
+    extent: [f32; 3],
+    /// 0 when unbounded, otherwise one more than the `BoundsBehavior`.
+    behavior: u32,
+}
+
+/// Obstacles on the GPU, bound at group 2 of the simulation kernels. The
+/// volume is an `R32Float` 3D texture, a single texel when there is none.
+pub struct Collision {
+    pub obstacles: Obstacles,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl Collision {
+    pub const MAX_COLLIDERS: usize = 8;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, obstacles: Obstacles) -> Self {
+        let (size, distances) = match &obstacles.volume {
+            Some(volume) => (volume.size, volume.distances.as_slice()),
+            None => ([1; 3], [f32::MAX].as_slice()),
+        };
+        let volume_texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("collision_volume"),
+                size: wgpu::Extent3d {
+                    width: size[0],
+                    height: size[1],
+                    depth_or_array_layers: size[2],
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::R32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(distances),
+        );
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("collision_uniform"),
+            contents: bytemuck::bytes_of(&Self::uniform(&obstacles)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("collision"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("collision"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &volume_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+            ],
+        });
+
+        Self {
+            obstacles,
+            bind_group_layout,
+            bind_group,
+            uniform_buffer,
+        }
+    }
+
+    fn uniform(obstacles: &Obstacles) -> CollisionUniform {
+        let mut colliders = [ColliderUniform::default(); Self::MAX_COLLIDERS];
+        for (uniform, collider) in colliders.iter_mut().zip(&obstacles.colliders) {
+            *uniform = collider.uniform();
+        }
+        let (volume_origin, volume_size) = obstacles.volume.as_ref().map_or(([0.0; 4], [1; 4]), |volume| {
+            let [x, y, z] = volume.size;
+            (
+                [volume.origin.x, volume.origin.y, volume.origin.z, volume.cell_size],
+                [x, y, z, 0],
+            )
+        });
+
+        CollisionUniform {
+            colliders,
+            count: obstacles.colliders.len() as u32,
+            restitution: obstacles.restitution,
+            friction: obstacles.friction,
+            has_volume: obstacles.volume.is_some() as u32,
+            volume_origin,
+            volume_size,
+        }
+    }
+
+    /// Uploads the obstacles after their parameters changed.
+    pub fn write(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&Self::uniform(&self.obstacles)));
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
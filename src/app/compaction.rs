@@ -0,0 +1,226 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::{debug_labels, instances::InstanceBuffers, mesh::Mesh, shader::ShaderLoader};
+
+/// Mirrors `Params` in `compact.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct CompactParams {
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// Surviving instances of one chunk.
+struct CompactChunk {
+    params_buffer: wgpu::Buffer,
+    draw_buffer: wgpu::Buffer,
+    /// For either side of the simulation state.
+    bind_groups: [wgpu::BindGroup; 2],
+    positions: wgpu::Buffer,
+    transforms: wgpu::Buffer,
+    colors: wgpu::Buffer,
+    count: u32,
+}
+
+/// Leaves expired instances out of the draw. Every frame a compute pass
+/// copies the instances with lifetime left, the w of their velocity, into a
+/// list per chunk, counting them into the arguments of an indirect draw.
+///
+/// The simulation keeps stepping expired instances, they are only skipped
+/// when drawing.
+pub struct Compaction {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    chunks: Vec<CompactChunk>,
+}
+
+impl Compaction {
+    const WORKGROUP_SIZE: u32 = 256;
+    const DRAW_SIZE: u64 = std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64;
+
+    pub fn new(device: &wgpu::Device, shaders: &ShaderLoader, instances: &InstanceBuffers) -> Self {
+        let module = shaders.module(device, "compact.wgsl", include_str!("../shaders/compact.wgsl"));
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("compact"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Uniform),
+                entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(2, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(3, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(4, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(5, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(6, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(7, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(8, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("compact_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("compact_pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: Some("compact"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let mut compaction = Self {
+            layout,
+            pipeline,
+            chunks: Vec::new(),
+        };
+        compaction.resize(device, instances);
+        compaction
+    }
+
+    /// Recreates the lists after the instance buffers changed.
+    pub fn resize(&mut self, device: &wgpu::Device, instances: &InstanceBuffers) {
+        self.chunks = instances
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let buffer = |label, size: u64, usage| {
+                    device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some(label),
+                        size,
+                        usage: wgpu::BufferUsages::STORAGE | usage,
+                        mapped_at_creation: false,
+                    })
+                };
+                let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("compact_params"),
+                    size: std::mem::size_of::<CompactParams>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let draw_buffer = buffer(
+                    "compact_draw",
+                    Self::DRAW_SIZE,
+                    wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                );
+                let positions = buffer("live_positions", chunk.positions[0].size(), wgpu::BufferUsages::VERTEX);
+                let transforms = buffer("live_transforms", chunk.transforms.size(), wgpu::BufferUsages::VERTEX);
+                let colors = buffer("live_colors", chunk.colors.size(), wgpu::BufferUsages::VERTEX);
+
+                let bind_groups = [0, 1].map(|side| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("compact"),
+                        layout: &self.layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: params_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: chunk.positions[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: chunk.velocities[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: chunk.transforms.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 4,
+                                resource: chunk.colors.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 5,
+                                resource: positions.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 6,
+                                resource: transforms.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 7,
+                                resource: colors.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 8,
+                                resource: draw_buffer.as_entire_binding(),
+                            },
+                        ],
+                    })
+                });
+
+                CompactChunk {
+                    params_buffer,
+                    draw_buffer,
+                    bind_groups,
+                    positions,
+                    transforms,
+                    colors,
+                    count: chunk.range.len() as u32,
+                }
+            })
+            .collect();
+    }
+
+    /// Records the compaction of every chunk drawn with `mesh`, reading the
+    /// `front` side of the simulation state.
+    pub fn record(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        front: usize,
+        mesh: &Mesh,
+    ) {
+        let max_groups = device.limits().max_compute_workgroups_per_dimension;
+        let draw = wgpu::util::DrawIndexedIndirectArgs {
+            index_count: mesh.element_count(),
+            instance_count: 0,
+            first_index: 0,
+            base_vertex: 0,
+            first_instance: 0,
+        };
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("compact_pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let params = CompactParams {
+                count: chunk.count,
+                _padding: [0; 3],
+            };
+            queue.write_buffer(&chunk.params_buffer, 0, bytemuck::bytes_of(&params));
+            queue.write_buffer(&chunk.draw_buffer, 0, draw.as_bytes());
+
+            debug_labels::marker(&mut compute_pass, || format!("chunk {index}: {} instances", chunk.count));
+            compute_pass.set_bind_group(0, &chunk.bind_groups[front], &[]);
+            let groups = chunk.count.div_ceil(Self::WORKGROUP_SIZE);
+            compute_pass.dispatch_workgroups(groups.clamp(1, max_groups), groups.div_ceil(max_groups).max(1), 1);
+        }
+    }
+
+    /// Draws the surviving instances of every chunk, with a pipeline using
+    /// the default vertex streams.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, mesh: &Mesh) {
+        for chunk in &self.chunks {
+            render_pass.set_vertex_buffer(2, chunk.transforms.slice(..));
+            render_pass.set_vertex_buffer(3, chunk.colors.slice(..));
+            mesh.draw_indexed_indirect(render_pass, chunk.positions.slice(..), &chunk.draw_buffer, 0);
+        }
+    }
+}
@@ -0,0 +1,1129 @@
+use std::{fmt::Display, path::PathBuf};
+
+use cgmath::{Point3, Vector3};
+
+use super::{
+    Integrator, KERNELS, bookmarks::Bookmarks, camera::{CameraAttractor, DepthOrder}, capture::CaptureSettings, collision::{BoundsBehavior, Collider, CollisionSettings, WorldBounds}, culling::CullingMode, deferred::DeferredSettings, demo, emitter::EmitterSettings, frames::FrameRing,
+    group::{GroupSettings, GroupShape},
+    hierarchy::HierarchySettings, history::HistorySettings, instances::{InstanceColoring, TransformSettings}, lod::LodSettings, material::Material, post::BloomSettings, scene::{DirectionalLight, Fog, SRGB_GAMMA}, shader::ShaderFeatures, shadow::{ShadowMaps, ShadowSettings}, timing::FixedTimestep, tonemap::ToneMapper, upscale::{UpscaleSettings, Upscaler},
+};
+
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+type ConfigResult<T> = Result<T, ConfigError>;
+
+/// Named benchmark scene. Presets fully specify the scene so numbers are
+/// comparable between machines.
+pub struct ScenePreset {
+    pub name: &'static str,
+    pub dimensions: (u32, u32, u32),
+    pub simulate: bool,
+    pub culling_mode: CullingMode,
+    pub impostors: bool,
+    pub max_draw_distance: f32,
+    /// Opacity of the instances, blended back to front below 1.
+    pub instance_alpha: f32,
+}
+
+pub const PRESETS: &[ScenePreset] = &[
+    ScenePreset {
+        name: "1m-static",
+        dimensions: (1024, 1024, 1),
+        simulate: false,
+        culling_mode: CullingMode::Disabled,
+        impostors: true,
+        max_draw_distance: 40000.0,
+        instance_alpha: 1.0,
+    },
+    ScenePreset {
+        name: "4m-simulated",
+        dimensions: (1024, 1024, 4),
+        simulate: true,
+        culling_mode: CullingMode::Disabled,
+        impostors: true,
+        max_draw_distance: 40000.0,
+        instance_alpha: 1.0,
+    },
+    ScenePreset {
+        name: "8m-culled",
+        dimensions: (1024, 1024, 8),
+        simulate: true,
+        culling_mode: CullingMode::CpuChunks,
+        impostors: true,
+        max_draw_distance: 20000.0,
+        instance_alpha: 1.0,
+    },
+    ScenePreset {
+        name: "transparent",
+        dimensions: (256, 256, 4),
+        simulate: true,
+        culling_mode: CullingMode::Disabled,
+        impostors: false,
+        max_draw_distance: 20000.0,
+        instance_alpha: 0.35,
+    },
+];
+
+/// What the app does while its window is unfocused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackgroundMode {
+    /// Keep rendering as if focused.
+    Full,
+    /// Render at most this many frames per second.
+    Throttle(u32),
+    /// Stop rendering and simulating until focused again.
+    Pause,
+}
+
+impl Default for BackgroundMode {
+    fn default() -> Self {
+        Self::Throttle(5)
+    }
+}
+
+pub struct AppConfig {
+    pub preset: Option<&'static str>,
+    /// Demo generating and simulating the instances, from `demo::DEMOS`.
+    pub demo: &'static str,
+    /// Simulation kernel in place of the demo's, from `KERNELS`.
+    pub kernel: Option<&'static str>,
+    pub dimensions: (u32, u32, u32),
+    pub simulate: bool,
+    pub culling_mode: CullingMode,
+    pub impostors: bool,
+    pub max_draw_distance: f32,
+    /// Directory to record a wgpu API trace into.
+    pub trace_dir: Option<PathBuf>,
+    pub backends: wgpu::Backends,
+    /// Run without push constants or compute shaders, with a small CPU-simulated scene.
+    pub compat: bool,
+    /// Leave push constants unused even where the adapter offers them, as
+    /// on WebGPU.
+    pub push_constants: bool,
+    pub power_preference: wgpu::PowerPreference,
+    /// Frame rate cap, uncapped when `None`.
+    pub max_fps: Option<u32>,
+    pub background: BackgroundMode,
+    /// Surface composite alpha mode, the surface default when `None`.
+    pub alpha_mode: Option<wgpu::CompositeAlphaMode>,
+    /// Samples per pixel of the scene, lowered to the most the adapter
+    /// supports.
+    pub msaa: u32,
+    /// Whether depth runs from 1 at the near plane to 0 infinitely far away.
+    pub depth_order: DepthOrder,
+    pub upscale: UpscaleSettings,
+    /// Render the scene in HDR and map it to the surface with this curve,
+    /// straight to the surface when `None`.
+    pub tone_mapping: Option<ToneMapper>,
+    /// Makes the bright parts of the HDR scene glow, off when `None`.
+    pub bloom: Option<BloomSettings>,
+    pub material: Material,
+    /// Lights the instances with the `lit` shader feature.
+    pub light: DirectionalLight,
+    /// Fogs distant instances with the `fogged` shader feature.
+    pub fog: Fog,
+    /// Scales the shaded colors, before tone mapping when that's on.
+    pub exposure: f32,
+    /// Gamma of the output, whatever the surface format.
+    pub gamma: f32,
+    /// Color the scene is drawn over while the fog is off.
+    pub background_color: [f32; 3],
+    /// Shadows of the light with the `shadowed` shader feature.
+    pub shadows: ShadowSettings,
+    /// Shade the opaque instances from a G-buffer, forward when `None`.
+    pub deferred: Option<DeferredSettings>,
+    /// Directory searched for shaders before the embedded copies.
+    pub shader_dir: Option<PathBuf>,
+    /// Rebuild pipelines when their shaders change in the shader directory.
+    pub hot_reload: bool,
+    /// Pass SPIR-V from the shader directory to the driver without translation.
+    pub spirv_passthrough: bool,
+    /// Frames the CPU may prepare before the GPU finishes them.
+    pub frames_in_flight: u32,
+    /// Wait for the previous frame before sampling input, and prefer mailbox presentation.
+    pub low_latency: bool,
+    /// Point dataset streamed from disk in place of the generated instances.
+    pub dataset: Option<PathBuf>,
+    /// Merge runs of voxels in `.vox` datasets into single instances.
+    pub merge_voxels: bool,
+    /// Extract and draw an isosurface of a noise field on the GPU.
+    pub isosurface: bool,
+    /// Raymarch a signed distance field scene into the instanced one.
+    pub sdf: bool,
+    /// Draw the instances with `multi_draw_indexed_indirect` when supported.
+    pub multi_draw: bool,
+    /// Pick a mesh per instance by distance on the GPU.
+    pub lod: Option<LodSettings>,
+    /// Static instances drawn with other meshes next to the simulated ones.
+    pub groups: Vec<GroupSettings>,
+    /// Split the simulated instances into clusters orbiting the origin.
+    pub hierarchy: Option<HierarchySettings>,
+    pub capture: CaptureSettings,
+    /// Console commands run once at startup.
+    pub script: Option<PathBuf>,
+    pub history: HistorySettings,
+    pub integrator: Integrator,
+    /// Seconds simulated by every step.
+    pub timestep: f64,
+    /// Most steps run in one frame.
+    pub max_substeps: u32,
+    /// Seed of the generated scenes, random every run without one.
+    pub seed: Option<u64>,
+    /// Steps simulated one per frame before logging a digest of the
+    /// positions and exiting.
+    pub replay: Option<u32>,
+    pub transforms: TransformSettings,
+    /// Colors drawn with the `color-attribute` shader feature.
+    pub coloring: InstanceColoring,
+    /// Opacity of the instances. Below 1 they are sorted and blended.
+    pub instance_alpha: f32,
+    /// Longest lifetime in seconds, instances expire at random up to it.
+    pub lifetime: Option<f32>,
+    /// Respawns expired instances, needs a lifetime.
+    pub emitter: EmitterSettings,
+    pub collision: CollisionSettings,
+    /// Region the simulated instances are kept within.
+    pub bounds: WorldBounds,
+    /// Pulls the instances towards a point ahead of the camera.
+    pub follow: CameraAttractor,
+    /// File the camera bookmarks are kept in.
+    pub bookmarks: PathBuf,
+    /// Record debug groups and markers for graphics debuggers.
+    pub debug_labels: bool,
+    /// Time the simulation and render passes with timestamp queries.
+    pub gpu_profile: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        let mut config = Self {
+            preset: None,
+            demo: demo::DEMOS[0].name,
+            kernel: None,
+            dimensions: (0, 0, 0),
+            simulate: true,
+            culling_mode: CullingMode::Disabled,
+            impostors: true,
+            max_draw_distance: 40000.0,
+            trace_dir: None,
+            backends: wgpu::Backends::PRIMARY.with_env(),
+            compat: false,
+            push_constants: true,
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            max_fps: None,
+            background: BackgroundMode::default(),
+            alpha_mode: None,
+            msaa: 8,
+            depth_order: DepthOrder::Standard,
+            upscale: UpscaleSettings::default(),
+            tone_mapping: Some(ToneMapper::default()),
+            bloom: None,
+            material: Material::default(),
+            light: DirectionalLight::default(),
+            fog: Fog::default(),
+            exposure: 1.0,
+            gamma: SRGB_GAMMA,
+            background_color: [0.0; 3],
+            shadows: ShadowSettings::default(),
+            deferred: None,
+            shader_dir: None,
+            hot_reload: false,
+            spirv_passthrough: false,
+            frames_in_flight: FrameRing::DEFAULT_FRAMES_IN_FLIGHT,
+            low_latency: false,
+            dataset: None,
+            merge_voxels: false,
+            isosurface: false,
+            sdf: false,
+            multi_draw: false,
+            lod: None,
+            groups: Vec::new(),
+            hierarchy: None,
+            capture: CaptureSettings::default(),
+            script: None,
+            history: HistorySettings::default(),
+            integrator: Integrator::default(),
+            timestep: FixedTimestep::DEFAULT_STEP,
+            max_substeps: FixedTimestep::DEFAULT_MAX_STEPS,
+            seed: None,
+            replay: None,
+            transforms: TransformSettings::default(),
+            coloring: InstanceColoring::default(),
+            instance_alpha: 1.0,
+            lifetime: None,
+            emitter: EmitterSettings::default(),
+            collision: CollisionSettings::default(),
+            bounds: WorldBounds::default(),
+            follow: CameraAttractor::default(),
+            bookmarks: Bookmarks::default_path(),
+            debug_labels: cfg!(debug_assertions),
+            gpu_profile: false,
+        };
+        config.apply_preset(&PRESETS[1]);
+        config.preset = None;
+
+        config
+    }
+}
+
+impl AppConfig {
+    pub const USAGE: &'static str = "\
+Usage: wgpu-instancing [OPTIONS]
+
+Options:
+  --preset <NAME>    Benchmark scene preset (1m-static, 4m-simulated, 8m-culled,
+                     transparent)
+  --count <N>        Number of instances, rounded up to whole square slices
+                     of the simulation grid of up to 1024x1024. Overrides
+                     the preset size, change it later with the count
+                     console command
+  --demo <NAME>      Scene to generate and simulate: cube-storm, boids,
+                     galaxy, grass or fluid. Defaults to cube-storm
+  --kernel <NAME>    Simulate with attract, drift, vortex, flock, wave,
+                     nbody, nbody-tiled, curl, boids or sph instead of the
+                     demo's kernel. nbody pulls every instance towards every
+                     other one in its chunk, nbody-tiled does the same
+                     through workgroup memory, both are quadratic in the
+                     instances. sph is a fluid of spheres resting on the
+                     ground plane. K cycles through the kernels
+  --trace <DIR>      Record a wgpu API trace (requires the `trace` feature)
+  --debug-labels     Label passes with debug groups for RenderDoc, Xcode or
+                     Nsight captures. On by default in debug builds
+  --no-debug-labels  Leave the debug groups out
+  --gpu-profile      Log the GPU time of the simulation and render passes
+                     every second. Needs timestamp queries
+  --backend <NAME>   Graphics backend: vulkan, dx12, metal or gl.
+                     Defaults to WGPU_BACKEND or the primary backends
+  --compat           Downlevel mode for GL/WebGL2-class hardware
+  --no-push-constants
+                     Upload the kernel settings through a uniform buffer
+                     as on adapters without push constants, like WebGPU
+  --low-power        Prefer the integrated GPU, cap instances and frame rate
+  --frames-in-flight <N>
+                     Frames queued ahead of the GPU, 1 to 3. Lower reduces
+                     latency, higher smooths out frame times. Defaults to 2
+  --low-latency      Sample input only after the previous frame finished, with
+                     one frame in flight and mailbox presentation if available
+  --max-fps <N>      Cap the frame rate
+  --background <MODE>
+                     While unfocused: full keeps rendering, pause stops, and
+                     a number caps the frame rate. Defaults to 5
+  --alpha-mode <MODE>
+                     Surface alpha mode: opaque, premultiplied, postmultiplied
+                     or inherit. Must be supported by the surface
+  --msaa <N>         Samples per pixel of the scene, 1 to 16, lowered to what
+                     the adapter supports. 1 turns multisampling off, the msaa
+                     console parameter changes it while running. Defaults to 8
+  --reversed-z       Run depth from 1 at the near plane to 0 infinitely far
+                     away, for even precision over any distance
+  --render-scale <F> Render the scene at F times the window resolution, 0.25
+                     to 1, and upscale it. Defaults to 1
+  --upscale <NAME>   How the scene is upscaled: bilinear, or fsr for edge
+                     adaptive upsampling and sharpening. fsr sharpens even at
+                     a render scale of 1. Defaults to bilinear
+  --sharpness <STOPS>
+                     Sharpening of fsr in stops below the strongest, 0 to 2.
+                     Defaults to 0.2
+  --tone-mapping <NAME>
+                     Render the scene in HDR and tone map it with aces or
+                     reinhard, or off to render straight to the surface.
+                     Defaults to aces
+  --bloom            Make the bright parts of the scene glow, needs HDR
+  --bloom-threshold <T>
+                     Brightness glowing starts at, implies --bloom. Defaults
+                     to 1
+  --bloom-intensity <I>
+                     Share of the glow added onto the scene, implies --bloom.
+                     Defaults to 0.1
+  --material <FEATURES>
+                     Comma separated shader features: textured, lit, fogged,
+                     instanced-color, point-color, color-attribute, pulse,
+                     materials, shadowed. Defaults to instanced-color.
+                     voxel-runs is enabled by .vox datasets
+  --light <X,Y,Z>    Direction towards the light of the lit feature.
+                     Defaults to 0.4,0.8,0.45
+  --ambient <A>      Share of the color lit from every side, 0 to 1.
+                     Defaults to 0.25
+  --fog-color <R,G,B>
+                     Color of the fogged feature and the background while
+                     it's on, components from 0 to 1. Defaults to 0,0,0
+  --fog-density <D>  How quickly the fog thickens with distance. Defaults
+                     to 0.0001
+  --exposure <E>     Scales the shaded colors, before tone mapping. Defaults
+                     to 1
+  --gamma <G>        Gamma of the output, whatever the surface format.
+                     Defaults to 2.2
+  --background-color <R,G,B>
+                     Color the scene is drawn over while the fog is off,
+                     components from 0 to 1. Defaults to 0,0,0
+  --shadow-cascades <N>
+                     Cascades the view is split into for shadows, 2 to 4.
+                     Defaults to 3
+  --shadow-resolution <PX>
+                     Size of the shadow map of every cascade. Defaults to
+                     1024
+  --shadow-distance <D>
+                     Distance along the view shadows end at. Defaults to
+                     2000
+  --deferred         Render the opaque instances into a G-buffer and light
+                     them in one pass per pixel. B switches back to forward
+  --point-lights <N> Scatter N point lights over the instances, lit by the
+                     deferred path only, implies --deferred. Defaults to 0
+  --point-light-radius <D>
+                     Distance the point lights reach, implies --deferred.
+                     Defaults to 300
+  --shader-dir <DIR> Load shaders from DIR when present there, falling back
+                     to the embedded copies. With the `spirv` feature,
+                     <name>.spv files there replace the WGSL, with the
+                     `glsl` feature <name>.vert/.frag/.comp files do
+  --hot-reload       Rebuild pipelines when their WGSL changes in the shader
+                     directory, src/shaders of the source tree without
+                     --shader-dir. Invalid shaders keep the previous ones
+  --spirv-passthrough
+                     Hand SPIR-V to the driver without naga validation
+  --dataset <FILE>   Stream points from FILE around the camera instead of
+                     generating instances, or alongside them with --count or
+                     --preset. PLY and uncompressed LAS files are imported
+                     with their colors, as are MagicaVoxel .vox models.
+                     Anything else is read as little-endian f32 x, y, z, w
+                     records, with block bounds cached in FILE.bounds
+  --merge-voxels     Draw runs of same-colored voxels as single instances
+  --isosurface       Draw an animated noise isosurface extracted with compute
+                     shaders each frame
+  --sdf              Raymarch a signed distance field scene, depth tested
+                     against the instances
+  --multi-draw       Draw the visible instance ranges of each chunk with a
+                     single indirect multi-draw, when the adapter supports
+                     it
+  --occlusion        Cull instances hidden behind what was drawn the frame
+                     before, tested against a depth pyramid in a compute
+                     pass and drawn indirectly. C cycles through the culling
+                     modes. Needs compute shaders and indirect draws, and
+                     can't be combined with --lod or transparent instances,
+                     which cull chunks instead
+  --lod <LOW_POLY,POINTS>
+                     Draw instances farther than LOW_POLY as octahedra and
+                     farther than POINTS as points, bucketed by distance in a
+                     compute pass. The pass also picks out the instances
+                     small on screen for the impostors, I toggles them.
+                     Presets drawing impostors enable it at 1000,4000
+  --group <SHAPE:COUNT[,X,Y,Z,SPREAD][/ALPHA]>
+                     Scatter COUNT static cube, sphere or quad instances
+                     within SPREAD of a point, 1000 around the origin by
+                     default. An ALPHA below 1 makes them translucent,
+                     blended without sorting. Repeat to mix shapes
+  --clusters <COUNT[,RADIUS,SPEED]>
+                     Split the simulated instances into COUNT clusters, each
+                     simulated around its own center. The centers orbit the
+                     origin RADIUS away at SPEED radians per second,
+                     20000 and 0.02 by default. Needs compute shaders and
+                     can't be combined with --lod, --lifetime or
+                     transparent instances
+  --capture <FRAMES> Orbit the camera around the scene over FRAMES frames at
+                     a fixed time step, save each frame as a PNG and exit
+  --capture-dir <DIR>
+                     Directory for captured frames. Defaults to capture
+  --capture-fps <N>  Time steps per second of captured frames. Defaults to 30
+  --capture-focus <X,Y,Z>
+                     Orbit center. Defaults to the center of the instances
+  --capture-radius <R>
+                     Orbit radius. Defaults to fit the instances
+  --bookmarks <FILE> Camera bookmarks, saved with Ctrl+1 to Ctrl+9 and
+                     recalled with 1 to 9. Defaults to
+                     wgpu-instancing/bookmarks.txt in the user config
+                     directory, ~/.config or %APPDATA%
+  --exec <FILE>      Run console commands from FILE at startup, one per
+                     line. The console opens with the ` key, try help
+  --history <TICKS>  Snapshot the simulation every TICKS ticks so it can be
+                     rewound with the , key
+  --history-budget <MIB>
+                     Memory kept for snapshots. Defaults to 256
+  --integrator <NAME>
+                     Time integration of the attractor: euler, semi-implicit
+                     or verlet. Defaults to semi-implicit
+  --timestep <SECONDS>
+                     Time simulated by every step, frames run as many steps
+                     as fit in their time. Defaults to 1/60
+  --max-substeps <N> Most steps run in one frame, a slower simulation falls
+                     behind real time. Defaults to 8
+  --seed <N>         Generate the scene from seed N, the same every run.
+                     R scatters the instances again while running
+  --replay <STEPS>   Simulate one step per frame whatever the frame time,
+                     then log a digest of the positions and exit. With
+                     --seed, runs on the same GPU digest the same, unless
+                     the emitter or the sph kernel order work with atomics
+  --random-rotation  Turn every instance to a random orientation
+  --scale <MIN,MAX>  Scale every instance axis by a random factor in the
+                     range. Defaults to 1,1
+  --spin <SPEED>     Spin instances about their own axis at up to SPEED
+                     radians per second while simulating. Defaults to 0
+  --instance-colors <MODE>
+                     Color every instance randomly (random) or by its
+                     initial speed (speed), drawn with color-attribute in
+                     place of instanced-color
+  --instance-alpha <A>
+                     Opacity of the instances, 0 to 1. Below 1 they are
+                     sorted back to front on the GPU and blended, drawn with
+                     color-attribute. Needs compute shaders. Defaults to 1
+  --lifetime <SECONDS>
+                     Let every instance expire after a random time of up to
+                     SECONDS of simulation, after which it's left out of the
+                     draw, also when sorted, culled or picked a level of
+                     detail. Needs compute shaders and indirect draws
+  --emitter <X,Y,Z>  Respawn expired instances at X,Y,Z with a fresh
+                     lifetime, flying off in random directions. Needs
+                     --lifetime
+  --emit-rate <N>    Instances the emitter respawns per second at most.
+                     Defaults to 2000
+  --emit-speed <S>   Speed instances leave the emitter at. Defaults to 50
+  --collider <SHAPE> Static obstacle instances bounce off, sphere:x,y,z,r or
+                     box:x,y,z,hx,hy,hz. Repeat for up to 8 obstacles
+  --sdf-volume <FILE>
+                     Obstacle from a signed distance volume baked by SDFGen,
+                     in world units
+  --restitution <R>  Fraction of the speed kept bouncing off obstacles, 0 to
+                     1. Defaults to 0.5
+  --friction <F>     Fraction of the speed along obstacles lost on contact, 0
+                     to 1. Defaults to 0.1
+  --bounds <SHAPE>   Keep instances within sphere:x,y,z,r or
+                     box:x,y,z,hx,hy,hz. Change with the bounds console
+                     command
+  --bounds-behavior <NAME>
+                     What instances leaving the bounds do: bounce or wrap.
+                     Defaults to bounce
+  --follow-camera    Pull the instances towards a point ahead of the camera,
+                     toggled with T
+  --follow-distance <D>
+                     Distance of that point ahead of the camera. Defaults to
+                     500
+  -h, --help         Print this help";
+
+    pub fn apply_preset(&mut self, preset: &ScenePreset) {
+        self.preset = Some(preset.name);
+        self.dimensions = preset.dimensions;
+        self.simulate = preset.simulate;
+        self.culling_mode = preset.culling_mode;
+        self.impostors = preset.impostors;
+        self.max_draw_distance = preset.max_draw_distance;
+        self.instance_alpha = preset.instance_alpha;
+        if preset.instance_alpha < 1.0 {
+            self.material.features = self.material.features.without(ShaderFeatures::INSTANCED_COLOR)
+                | ShaderFeatures::COLOR_ATTRIBUTE;
+        }
+    }
+
+    /// Simulation grid holding at least `count` instances: square slices of
+    /// up to `MAX_SLICE_SIDE` squared instances, stacked along z.
+    pub fn grid_dimensions(count: u32) -> (u32, u32, u32) {
+        const MAX_SLICE_SIDE: u32 = 1024;
+
+        let side = (count as f64).sqrt().ceil().clamp(1.0, MAX_SLICE_SIDE as f64) as u32;
+        (side, side, count.div_ceil(side * side).max(1))
+    }
+
+    pub fn apply_low_power(&mut self) {
+        const MAX_DIMENSIONS: (u32, u32, u32) = (256, 256, 4);
+
+        self.power_preference = wgpu::PowerPreference::LowPower;
+        self.dimensions = (
+            self.dimensions.0.min(MAX_DIMENSIONS.0),
+            self.dimensions.1.min(MAX_DIMENSIONS.1),
+            self.dimensions.2.min(MAX_DIMENSIONS.2),
+        );
+        self.max_fps = Some(self.max_fps.unwrap_or(30).min(30));
+        // Impostors keep another list of every instance
+        self.impostors = false;
+        self.max_draw_distance = self.max_draw_distance.min(15000.0);
+    }
+
+    /// Parses command line arguments, excluding the program name.
+    /// Returns `Ok(None)` when help was requested.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> ConfigResult<Option<Self>> {
+        let mut config = Self::default();
+        let mut low_power = false;
+        // Datasets replace the default grid, not one asked for
+        let mut dimensions_given = false;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| ConfigError::new(format!("Missing value for {name}")))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "--preset" => {
+                    let name = value("--preset")?;
+                    let preset = PRESETS
+                        .iter()
+                        .find(|p| p.name == name)
+                        .ok_or_else(|| ConfigError::new(format!("Unknown preset: {name}")))?;
+                    config.apply_preset(preset);
+                    dimensions_given = true;
+                }
+                "--count" => {
+                    let count = value("--count")?;
+                    let count = count
+                        .parse()
+                        .ok()
+                        .filter(|&c: &u32| c > 0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid instance count: {count}")))?;
+                    config.dimensions = Self::grid_dimensions(count);
+                    dimensions_given = true;
+                }
+                "--demo" => {
+                    let name = value("--demo")?;
+                    config.demo = demo::find(&name)
+                        .ok_or_else(|| ConfigError::new(format!("Unknown demo: {name}")))?
+                        .name;
+                }
+                "--kernel" => {
+                    let name = value("--kernel")?;
+                    config.kernel = Some(
+                        KERNELS
+                            .iter()
+                            .find(|kernel| kernel.name == name)
+                            .ok_or_else(|| ConfigError::new(format!("Unknown kernel: {name}")))?
+                            .name,
+                    );
+                }
+                "--shader-dir" => config.shader_dir = Some(PathBuf::from(value("--shader-dir")?)),
+                "--hot-reload" => config.hot_reload = true,
+                "--spirv-passthrough" => config.spirv_passthrough = true,
+                "--debug-labels" => config.debug_labels = true,
+                "--no-debug-labels" => config.debug_labels = false,
+                "--gpu-profile" => config.gpu_profile = true,
+                "--trace" => config.trace_dir = Some(PathBuf::from(value("--trace")?)),
+                "--backend" => config.backends = parse_backend(&value("--backend")?)?,
+                "--compat" => config.compat = true,
+                "--no-push-constants" => config.push_constants = false,
+                "--low-power" => low_power = true,
+                "--material" => {
+                    config.material.features = ShaderFeatures::parse(&value("--material")?)
+                        .map_err(|e| ConfigError::new(e.message))?;
+                    // Only .vox imports keep run lengths in w
+                    if config.material.features.contains(ShaderFeatures::VOXEL_RUNS) {
+                        return Err(ConfigError::new(
+                            "The voxel-runs feature is only available for .vox datasets, which enable it".to_string(),
+                        ));
+                    }
+                }
+                "--light" => {
+                    let direction = parse_point(&value("--light")?)?;
+                    if direction == Point3::new(0.0, 0.0, 0.0) {
+                        return Err(ConfigError::new("The light needs a direction".to_string()));
+                    }
+                    config.light.direction = Vector3::new(direction.x, direction.y, direction.z);
+                }
+                "--ambient" => config.light.ambient = parse_fraction(&value("--ambient")?)?,
+                "--fog-color" => config.fog.color = parse_color(&value("--fog-color")?)?,
+                "--fog-density" => {
+                    let density = value("--fog-density")?;
+                    config.fog.density = density
+                        .parse()
+                        .ok()
+                        .filter(|&d: &f32| d >= 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid fog density: {density}")))?;
+                }
+                "--exposure" => {
+                    let exposure = value("--exposure")?;
+                    config.exposure = exposure
+                        .parse()
+                        .ok()
+                        .filter(|&e: &f32| e > 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid exposure: {exposure}")))?;
+                }
+                "--gamma" => {
+                    let gamma = value("--gamma")?;
+                    config.gamma = gamma
+                        .parse()
+                        .ok()
+                        .filter(|&g: &f32| g > 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid gamma: {gamma}")))?;
+                }
+                "--background-color" => config.background_color = parse_color(&value("--background-color")?)?,
+                "--shadow-cascades" => {
+                    let cascades = value("--shadow-cascades")?;
+                    config.shadows.cascades = cascades
+                        .parse()
+                        .ok()
+                        .filter(|n| (ShadowMaps::MIN_CASCADES..=ShadowMaps::MAX_CASCADES as u32).contains(n))
+                        .ok_or_else(|| ConfigError::new(format!("Invalid shadow cascade count: {cascades}")))?;
+                }
+                "--shadow-resolution" => {
+                    let resolution = value("--shadow-resolution")?;
+                    config.shadows.resolution = resolution
+                        .parse()
+                        .ok()
+                        .filter(|&n: &u32| n.is_power_of_two() && (256..=8192).contains(&n))
+                        .ok_or_else(|| ConfigError::new(format!("Invalid shadow resolution: {resolution}")))?;
+                }
+                "--shadow-distance" => {
+                    let distance = value("--shadow-distance")?;
+                    config.shadows.distance = distance
+                        .parse()
+                        .ok()
+                        .filter(|&d: &f32| d > 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid shadow distance: {distance}")))?;
+                }
+                "--deferred" => {
+                    config.deferred.get_or_insert_default();
+                }
+                "--point-lights" => {
+                    let count = value("--point-lights")?;
+                    config.deferred.get_or_insert_default().point_lights = count
+                        .parse()
+                        .map_err(|_| ConfigError::new(format!("Invalid point light count: {count}")))?;
+                }
+                "--point-light-radius" => {
+                    let radius = value("--point-light-radius")?;
+                    config.deferred.get_or_insert_default().point_light_radius = radius
+                        .parse()
+                        .ok()
+                        .filter(|&r: &f32| r > 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid point light radius: {radius}")))?;
+                }
+                "--frames-in-flight" => {
+                    let frames = value("--frames-in-flight")?;
+                    config.frames_in_flight = frames
+                        .parse()
+                        .ok()
+                        .filter(|n| (1..=FrameRing::MAX_FRAMES_IN_FLIGHT).contains(n))
+                        .ok_or_else(|| ConfigError::new(format!("Invalid frames in flight: {frames}")))?;
+                }
+                "--low-latency" => config.low_latency = true,
+                "--dataset" => config.dataset = Some(PathBuf::from(value("--dataset")?)),
+                "--merge-voxels" => config.merge_voxels = true,
+                "--isosurface" => config.isosurface = true,
+                "--sdf" => config.sdf = true,
+                "--multi-draw" => config.multi_draw = true,
+                "--occlusion" => config.culling_mode = CullingMode::GpuOcclusion,
+                "--lod" => config.lod = Some(parse_lod_distances(&value("--lod")?)?),
+                "--group" => config.groups.push(parse_group(&value("--group")?)?),
+                "--clusters" => config.hierarchy = Some(parse_clusters(&value("--clusters")?)?),
+                "--capture" => {
+                    let frames = value("--capture")?;
+                    config.capture.frames = frames
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid frame count: {frames}")))?;
+                }
+                "--bookmarks" => config.bookmarks = PathBuf::from(value("--bookmarks")?),
+                "--exec" => config.script = Some(PathBuf::from(value("--exec")?)),
+                "--history" => {
+                    let ticks = value("--history")?;
+                    config.history.interval = ticks
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid tick count: {ticks}")))?;
+                }
+                "--integrator" => {
+                    let name = value("--integrator")?;
+                    config.integrator = Integrator::from_name(&name)
+                        .ok_or_else(|| ConfigError::new(format!("Unknown integrator: {name}")))?;
+                }
+                "--timestep" => {
+                    let timestep = value("--timestep")?;
+                    config.timestep = timestep
+                        .parse()
+                        .ok()
+                        .filter(|&t: &f64| t > 0.0 && t <= 1.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid timestep: {timestep}")))?;
+                }
+                "--max-substeps" => {
+                    let substeps = value("--max-substeps")?;
+                    config.max_substeps = substeps
+                        .parse()
+                        .ok()
+                        .filter(|&n: &u32| n > 0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid substep count: {substeps}")))?;
+                }
+                "--seed" => {
+                    let seed = value("--seed")?;
+                    config.seed = Some(seed.parse().map_err(|_| ConfigError::new(format!("Invalid seed: {seed}")))?);
+                }
+                "--replay" => {
+                    let steps = value("--replay")?;
+                    config.replay = Some(
+                        steps
+                            .parse()
+                            .ok()
+                            .filter(|&n: &u32| n > 0)
+                            .ok_or_else(|| ConfigError::new(format!("Invalid step count: {steps}")))?,
+                    );
+                }
+                "--random-rotation" => config.transforms.random_rotation = true,
+                "--scale" => config.transforms.scale = parse_scale_range(&value("--scale")?)?,
+                "--spin" => {
+                    let spin = value("--spin")?;
+                    config.transforms.spin = spin
+                        .parse()
+                        .ok()
+                        .filter(|&s: &f32| s >= 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid spin: {spin}")))?;
+                }
+                "--instance-alpha" => {
+                    let alpha = value("--instance-alpha")?;
+                    config.instance_alpha = alpha
+                        .parse()
+                        .ok()
+                        .filter(|a| (0.0..=1.0).contains(a))
+                        .ok_or_else(|| ConfigError::new(format!("Invalid instance alpha: {alpha}")))?;
+                    config.material.features = config.material.features.without(ShaderFeatures::INSTANCED_COLOR)
+                        | ShaderFeatures::COLOR_ATTRIBUTE;
+                }
+                "--lifetime" => {
+                    let lifetime = value("--lifetime")?;
+                    config.lifetime = Some(
+                        lifetime
+                            .parse()
+                            .ok()
+                            .filter(|&l: &f32| l > 0.0)
+                            .ok_or_else(|| ConfigError::new(format!("Invalid lifetime: {lifetime}")))?,
+                    );
+                }
+                "--emitter" => config.emitter.position = Some(parse_point(&value("--emitter")?)?),
+                "--emit-rate" => {
+                    let rate = value("--emit-rate")?;
+                    config.emitter.rate = rate
+                        .parse()
+                        .ok()
+                        .filter(|&r: &f32| r >= 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid emit rate: {rate}")))?;
+                }
+                "--emit-speed" => {
+                    let speed = value("--emit-speed")?;
+                    config.emitter.speed = speed
+                        .parse()
+                        .ok()
+                        .filter(|&s: &f32| s >= 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid emit speed: {speed}")))?;
+                }
+                "--instance-colors" => {
+                    let name = value("--instance-colors")?;
+                    config.coloring = InstanceColoring::from_name(&name)
+                        .ok_or_else(|| ConfigError::new(format!("Unknown instance coloring: {name}")))?;
+                    config.material.features = config.material.features.without(ShaderFeatures::INSTANCED_COLOR)
+                        | ShaderFeatures::COLOR_ATTRIBUTE;
+                }
+                "--collider" => {
+                    let collider = Collider::parse(&value("--collider")?).map_err(|e| ConfigError::new(e.message))?;
+                    config.collision.colliders.push(collider);
+                }
+                "--sdf-volume" => config.collision.volume = Some(PathBuf::from(value("--sdf-volume")?)),
+                "--restitution" => config.collision.restitution = parse_fraction(&value("--restitution")?)?,
+                "--friction" => config.collision.friction = parse_fraction(&value("--friction")?)?,
+                "--bounds" => {
+                    let shape = Collider::parse(&value("--bounds")?).map_err(|e| ConfigError::new(e.message))?;
+                    config.bounds.shape = Some(shape);
+                }
+                "--bounds-behavior" => {
+                    let name = value("--bounds-behavior")?;
+                    config.bounds.behavior = BoundsBehavior::from_name(&name)
+                        .ok_or_else(|| ConfigError::new(format!("Unknown bounds behavior: {name}")))?;
+                }
+                "--follow-camera" => config.follow.enabled = true,
+                "--follow-distance" => {
+                    let distance = value("--follow-distance")?;
+                    config.follow.distance = distance
+                        .parse()
+                        .ok()
+                        .filter(|&d: &f32| d >= 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid distance: {distance}")))?;
+                }
+                "--history-budget" => {
+                    let budget = value("--history-budget")?;
+                    config.history.budget = budget
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|&mib| mib > 0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid budget: {budget}")))?
+                        << 20;
+                }
+                "--capture-dir" => config.capture.directory = PathBuf::from(value("--capture-dir")?),
+                "--capture-fps" => {
+                    let fps = value("--capture-fps")?;
+                    config.capture.fps = fps
+                        .parse()
+                        .ok()
+                        .filter(|&fps| fps > 0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid frame rate: {fps}")))?;
+                }
+                "--capture-focus" => config.capture.focus = Some(parse_point(&value("--capture-focus")?)?),
+                "--capture-radius" => {
+                    let radius = value("--capture-radius")?;
+                    config.capture.radius = Some(
+                        radius
+                            .parse()
+                            .ok()
+                            .filter(|&r: &f32| r > 0.0)
+                            .ok_or_else(|| ConfigError::new(format!("Invalid radius: {radius}")))?,
+                    );
+                }
+                "--max-fps" => {
+                    let fps = value("--max-fps")?;
+                    config.max_fps = Some(
+                        fps.parse()
+                            .ok()
+                            .filter(|&fps| fps > 0)
+                            .ok_or_else(|| ConfigError::new(format!("Invalid frame rate: {fps}")))?,
+                    );
+                }
+                "--background" => config.background = parse_background(&value("--background")?)?,
+                "--alpha-mode" => config.alpha_mode = Some(parse_alpha_mode(&value("--alpha-mode")?)?),
+                "--msaa" => {
+                    let samples = value("--msaa")?;
+                    config.msaa = samples
+                        .parse()
+                        .ok()
+                        .filter(|samples: &u32| samples.is_power_of_two() && *samples <= 16)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid sample count: {samples}")))?;
+                }
+                "--reversed-z" => config.depth_order = DepthOrder::Reversed,
+                "--render-scale" => {
+                    let scale = value("--render-scale")?;
+                    config.upscale.render_scale = scale
+                        .parse()
+                        .ok()
+                        .filter(|s| (UpscaleSettings::MIN_RENDER_SCALE..=1.0).contains(s))
+                        .ok_or_else(|| ConfigError::new(format!("Invalid render scale: {scale}")))?;
+                }
+                "--tone-mapping" => {
+                    let name = value("--tone-mapping")?;
+                    config.tone_mapping = match name.as_str() {
+                        "off" => None,
+                        _ => Some(
+                            ToneMapper::from_name(&name)
+                                .ok_or_else(|| ConfigError::new(format!("Unknown tone mapping: {name}")))?,
+                        ),
+                    };
+                }
+                "--bloom" => {
+                    config.bloom.get_or_insert_default();
+                }
+                "--bloom-threshold" => {
+                    let threshold = value("--bloom-threshold")?;
+                    config.bloom.get_or_insert_default().threshold = threshold
+                        .parse()
+                        .ok()
+                        .filter(|&t: &f32| t >= 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid bloom threshold: {threshold}")))?;
+                }
+                "--bloom-intensity" => {
+                    let intensity = value("--bloom-intensity")?;
+                    config.bloom.get_or_insert_default().intensity = intensity
+                        .parse()
+                        .ok()
+                        .filter(|&i: &f32| i >= 0.0)
+                        .ok_or_else(|| ConfigError::new(format!("Invalid bloom intensity: {intensity}")))?;
+                }
+                "--upscale" => {
+                    let name = value("--upscale")?;
+                    config.upscale.upscaler = Upscaler::from_name(&name)
+                        .ok_or_else(|| ConfigError::new(format!("Unknown upscaler: {name}")))?;
+                }
+                "--sharpness" => {
+                    let stops = value("--sharpness")?;
+                    config.upscale.sharpness = stops
+                        .parse()
+                        .ok()
+                        .filter(|s| (0.0..=2.0).contains(s))
+                        .ok_or_else(|| ConfigError::new(format!("Invalid sharpness: {stops}")))?;
+                }
+                _ => return Err(ConfigError::new(format!("Unknown argument: {arg}"))),
+            }
+        }
+
+        // Applied last so presets can't undo the caps
+        if low_power {
+            config.apply_low_power();
+        }
+        if config.low_latency {
+            config.frames_in_flight = 1;
+        }
+        if config.dataset.is_some() && !dimensions_given {
+            config.dimensions = (0, 0, 0);
+            config.simulate = false;
+        }
+
+        Ok(Some(config))
+    }
+}
+
+fn parse_backend(name: &str) -> ConfigResult<wgpu::Backends> {
+    match name.to_lowercase().as_str() {
+        "vulkan" | "vk" => Ok(wgpu::Backends::VULKAN),
+        "dx12" | "d3d12" => Ok(wgpu::Backends::DX12),
+        "metal" | "mtl" => Ok(wgpu::Backends::METAL),
+        "gl" | "gles" | "opengl" => Ok(wgpu::Backends::GL),
+        _ => Err(ConfigError::new(format!("Unknown backend: {name}"))),
+    }
+}
+
+fn parse_point(value: &str) -> ConfigResult<Point3<f32>> {
+    let coordinates: Vec<f32> = value
+        .split(',')
+        .map(|c| c.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| ConfigError::new(format!("Invalid point: {value}")))?;
+
+    match coordinates[..] {
+        [x, y, z] => Ok(Point3::new(x, y, z)),
+        _ => Err(ConfigError::new(format!("Expected three coordinates: {value}"))),
+    }
+}
+
+fn parse_color(value: &str) -> ConfigResult<[f32; 3]> {
+    let components: Vec<f32> = value
+        .split(',')
+        .map(|c| c.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| ConfigError::new(format!("Invalid color: {value}")))?;
+
+    match components[..] {
+        [r, g, b] if [r, g, b].iter().all(|c| (0.0..=1.0).contains(c)) => Ok([r, g, b]),
+        _ => Err(ConfigError::new(format!("Expected three components from 0 to 1: {value}"))),
+    }
+}
+
+fn parse_scale_range(value: &str) -> ConfigResult<(f32, f32)> {
+    let scales: Vec<f32> = value
+        .split(',')
+        .map(|s| s.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| ConfigError::new(format!("Invalid scale range: {value}")))?;
+
+    match scales[..] {
+        [min, max] if 0.0 < min && min <= max => Ok((min, max)),
+        _ => Err(ConfigError::new(format!("Expected a positive MIN,MAX: {value}"))),
+    }
+}
+
+fn parse_lod_distances(value: &str) -> ConfigResult<LodSettings> {
+    let distances: Vec<f32> = value
+        .split(',')
+        .map(|s| s.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| ConfigError::new(format!("Invalid LOD distances: {value}")))?;
+
+    match distances[..] {
+        [low_poly_distance, point_distance] if 0.0 < low_poly_distance && low_poly_distance <= point_distance => {
+            Ok(LodSettings {
+                low_poly_distance,
+                point_distance,
+            })
+        }
+        _ => Err(ConfigError::new(format!("Expected increasing positive distances: {value}"))),
+    }
+}
+
+fn parse_group(value: &str) -> ConfigResult<GroupSettings> {
+    let invalid = || {
+        ConfigError::new(format!("Invalid group: {value}, try sphere:1000 or quad:1000,x,y,z,spread/alpha"))
+    };
+
+    let (placement, alpha) = match value.split_once('/') {
+        Some((placement, alpha)) => (placement, alpha.trim().parse().map_err(|_| invalid())?),
+        None => (value, 1.0),
+    };
+    if !(0.0..=1.0).contains(&alpha) {
+        return Err(invalid());
+    }
+    let (shape, numbers) = placement.split_once(':').ok_or_else(invalid)?;
+    let shape = GroupShape::from_name(shape).ok_or_else(invalid)?;
+    let (count, numbers) = numbers.split_once(',').unwrap_or((numbers, ""));
+    let count = count.trim().parse().map_err(|_| invalid())?;
+    let numbers: Vec<f32> = numbers
+        .split(',')
+        .filter(|n| !n.is_empty())
+        .map(|n| n.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+
+    let (center, spread) = match numbers[..] {
+        [] => (Point3::new(0.0, 0.0, 0.0), 1000.0),
+        [x, y, z, spread] if spread > 0.0 => (Point3::new(x, y, z), spread),
+        _ => return Err(invalid()),
+    };
+    Ok(GroupSettings {
+        shape,
+        count,
+        center,
+        spread,
+        alpha,
+    })
+}
+
+fn parse_clusters(value: &str) -> ConfigResult<HierarchySettings> {
+    let invalid = || ConfigError::new(format!("Invalid clusters: {value}, try 8 or 8,20000,0.02"));
+
+    let (clusters, numbers) = value.split_once(',').unwrap_or((value, ""));
+    let clusters = clusters.trim().parse().ok().filter(|&c: &u32| c > 0).ok_or_else(invalid)?;
+    let numbers: Vec<f32> = numbers
+        .split(',')
+        .filter(|n| !n.is_empty())
+        .map(|n| n.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+
+    let (radius, orbit_speed) = match numbers[..] {
+        [] => (HierarchySettings::DEFAULT_RADIUS, HierarchySettings::DEFAULT_ORBIT_SPEED),
+        [radius, orbit_speed] if radius >= 0.0 => (radius, orbit_speed),
+        _ => return Err(invalid()),
+    };
+    Ok(HierarchySettings {
+        clusters,
+        radius,
+        orbit_speed,
+    })
+}
+
+fn parse_background(value: &str) -> ConfigResult<BackgroundMode> {
+    match value {
+        "full" => Ok(BackgroundMode::Full),
+        "pause" => Ok(BackgroundMode::Pause),
+        _ => value
+            .parse()
+            .ok()
+            .filter(|&fps| fps > 0)
+            .map(BackgroundMode::Throttle)
+            .ok_or_else(|| ConfigError::new(format!("Invalid background mode: {value}"))),
+    }
+}
+
+fn parse_fraction(value: &str) -> ConfigResult<f32> {
+    value
+        .parse()
+        .ok()
+        .filter(|f| (0.0..=1.0).contains(f))
+        .ok_or_else(|| ConfigError::new(format!("Expected a fraction from 0 to 1: {value}")))
+}
+
+fn parse_alpha_mode(name: &str) -> ConfigResult<wgpu::CompositeAlphaMode> {
+    match name.to_lowercase().as_str() {
+        "opaque" => Ok(wgpu::CompositeAlphaMode::Opaque),
+        "premultiplied" => Ok(wgpu::CompositeAlphaMode::PreMultiplied),
+        "postmultiplied" => Ok(wgpu::CompositeAlphaMode::PostMultiplied),
+        "inherit" => Ok(wgpu::CompositeAlphaMode::Inherit),
+        _ => Err(ConfigError::new(format!("Unknown alpha mode: {name}"))),
+    }
+}
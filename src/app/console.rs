@@ -0,0 +1,277 @@
+use std::{collections::VecDeque, fmt::Display, path::PathBuf};
+
+use cgmath::Point3;
+use winit::{
+    event::KeyEvent,
+    keyboard::{Key, NamedKey},
+};
+
+use super::label::{Label, LabelAnchor};
+
+#[derive(Debug, Clone)]
+pub struct ConsoleError {
+    pub message: String,
+}
+
+impl ConsoleError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Display for ConsoleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ConsoleError {}
+pub type ConsoleResult<T> = Result<T, ConsoleError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    /// Lists every parameter with its value.
+    Params,
+    Get(String),
+    Set(String, f32),
+    /// Switches the simulation kernel by name.
+    Kernel(String),
+    Integrator(String),
+    /// Switches the curve the HDR scene is tone mapped with.
+    ToneMapping(String),
+    /// Switches the world bounds off, to a shape or to a behavior.
+    Bounds(String),
+    /// Moves the emitter, `None` switches it off.
+    Emitter(Option<Point3<f32>>),
+    /// Pauses the simulation and runs this many steps.
+    Step(u32),
+    /// Adds at least this many instances.
+    Spawn(u32),
+    /// Removes at least this many of the newest instances.
+    Despawn(u32),
+    /// Removes this many z slices from the first one on, the last slices
+    /// of the grid take their place.
+    Remove(u32, u32),
+    /// Replaces the scene with a fresh one of at least this many instances.
+    Count(u32),
+    Teleport(Point3<f32>),
+    Preset(String),
+    /// Switches to another demo with a fresh scene.
+    Demo(String),
+    /// Restores the simulation this many snapshots back.
+    Rewind(usize),
+    /// Runs the commands of a script file, one per line.
+    Exec(PathBuf),
+    /// Writes the instance positions to a CSV file.
+    Export(PathBuf),
+    Clear,
+}
+
+impl Command {
+    pub const HELP: &'static str = "\
+help                 Show this help
+params               List parameters
+get <param>          Print a parameter
+set <param> <value>  Change a parameter
+kernel <name>        Switch the simulation kernel
+integrator <name>    Switch between euler, semi-implicit and verlet
+tonemap <name>       Switch tone mapping between aces and reinhard
+bounds <setting>     Set world bounds: off, bounce, wrap, sphere:x,y,z,r or
+                     box:x,y,z,hx,hy,hz
+emitter <x> <y> <z>  Respawn expired instances at a point, or off
+step [count]         Pause and run single simulation steps
+spawn <count>        Add instances around the camera
+despawn <count>      Remove the newest instances
+remove <z> [slices]  Remove z slices anywhere, the last ones fill the gap
+count <count>        Regenerate the scene with a new instance count
+teleport <x> <y> <z> Move the camera
+preset <name>        Switch to a benchmark preset
+demo <name>          Switch to another demo
+rewind [snapshots]   Restore an earlier simulation state
+exec <file>          Run commands from a file
+export <file>        Write instance positions as CSV
+clear                Clear the console";
+
+    /// Parses one line of input, `None` for blank lines and `#` comments.
+    pub fn parse(line: &str) -> ConsoleResult<Option<Self>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |word: &str| {
+            word.parse::<f32>()
+                .map_err(|_| ConsoleError::new(format!("Not a number: {word}")))
+        };
+
+        let command = match words[..] {
+            ["help"] => Self::Help,
+            ["params"] => Self::Params,
+            ["get", name] => Self::Get(name.to_string()),
+            ["set", name, value] => Self::Set(name.to_string(), number(value)?),
+            ["kernel", name] => Self::Kernel(name.to_string()),
+            ["integrator", name] => Self::Integrator(name.to_string()),
+            ["tonemap", name] => Self::ToneMapping(name.to_string()),
+            ["bounds", setting] => Self::Bounds(setting.to_string()),
+            ["emitter", "off"] => Self::Emitter(None),
+            ["emitter", x, y, z] => Self::Emitter(Some(Point3::new(number(x)?, number(y)?, number(z)?))),
+            ["step"] => Self::Step(1),
+            ["step", count] => Self::Step(
+                count
+                    .parse()
+                    .map_err(|_| ConsoleError::new(format!("Not a count: {count}")))?,
+            ),
+            ["spawn", count] => Self::Spawn(
+                count
+                    .parse()
+                    .map_err(|_| ConsoleError::new(format!("Not a count: {count}")))?,
+            ),
+            ["despawn", count] => Self::Despawn(
+                count
+                    .parse()
+                    .map_err(|_| ConsoleError::new(format!("Not a count: {count}")))?,
+            ),
+            ["remove", first] => Self::Remove(
+                first
+                    .parse()
+                    .map_err(|_| ConsoleError::new(format!("Not a z slice: {first}")))?,
+                1,
+            ),
+            ["remove", first, count] => Self::Remove(
+                first
+                    .parse()
+                    .map_err(|_| ConsoleError::new(format!("Not a z slice: {first}")))?,
+                count
+                    .parse()
+                    .map_err(|_| ConsoleError::new(format!("Not a count: {count}")))?,
+            ),
+            ["count", count] => Self::Count(
+                count
+                    .parse()
+                    .ok()
+                    .filter(|&c: &u32| c > 0)
+                    .ok_or_else(|| ConsoleError::new(format!("Not a count: {count}")))?,
+            ),
+            ["teleport", x, y, z] => Self::Teleport(Point3::new(number(x)?, number(y)?, number(z)?)),
+            ["preset", name] => Self::Preset(name.to_string()),
+            ["demo", name] => Self::Demo(name.to_string()),
+            ["rewind"] => Self::Rewind(1),
+            ["rewind", count] => Self::Rewind(
+                count
+                    .parse()
+                    .map_err(|_| ConsoleError::new(format!("Not a count: {count}")))?,
+            ),
+            ["exec", path] => Self::Exec(PathBuf::from(path)),
+            ["export", path] => Self::Export(PathBuf::from(path)),
+            ["clear"] => Self::Clear,
+            _ => return Err(ConsoleError::new(format!("Unknown command: {line}, try help"))),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Drop-down command line drawn as a screen overlay. The console only edits
+/// text, running commands is left to the app.
+#[derive(Default)]
+pub struct Console {
+    open: bool,
+    input: String,
+    output: VecDeque<String>,
+    history: Vec<String>,
+    /// Entry of `history` being edited, when browsing it.
+    history_index: Option<usize>,
+}
+
+impl Console {
+    const VISIBLE_LINES: usize = 12;
+    const MAX_OUTPUT: usize = 256;
+    const COLUMNS: usize = 80;
+    const TEXT_COLOR: [u8; 4] = [220, 220, 220, 255];
+    const BACKGROUND: [u8; 4] = [16, 16, 24, 200];
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    /// Adds a line of output, which is also logged.
+    pub fn print(&mut self, text: &str) {
+        for line in text.lines() {
+            log::info!("{line}");
+            self.output.push_back(line.to_string());
+        }
+        while self.output.len() > Self::MAX_OUTPUT {
+            self.output.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.output.clear();
+    }
+
+    /// Edits the input line, returning it once submitted with enter.
+    pub fn handle_key(&mut self, event: &KeyEvent) -> Option<String> {
+        if !event.state.is_pressed() {
+            return None;
+        }
+
+        match &event.logical_key {
+            Key::Named(NamedKey::Enter) => {
+                let line = std::mem::take(&mut self.input);
+                self.history_index = None;
+                if !line.trim().is_empty() {
+                    self.history.push(line.clone());
+                }
+                self.print(&format!("> {line}"));
+                return Some(line);
+            }
+            Key::Named(NamedKey::Backspace) => {
+                self.input.pop();
+            }
+            Key::Named(NamedKey::Escape) => self.open = false,
+            Key::Named(NamedKey::ArrowUp) if !self.history.is_empty() => {
+                let index = self.history_index.map_or(self.history.len() - 1, |i| i.saturating_sub(1));
+                self.history_index = Some(index);
+                self.input = self.history[index].clone();
+            }
+            Key::Named(NamedKey::ArrowDown) => {
+                if let Some(index) = self.history_index {
+                    self.history_index = (index + 1 < self.history.len()).then_some(index + 1);
+                    self.input = self.history_index.map_or_else(String::new, |i| self.history[i].clone());
+                }
+            }
+            _ => {
+                // The toggle key itself shouldn't end up in the input
+                let text = event.text.as_deref().unwrap_or_default();
+                self.input.extend(text.chars().filter(|c| !c.is_control() && *c != '`'));
+            }
+        }
+        None
+    }
+
+    /// Overlay with the latest output and the input line, `None` when closed.
+    pub fn label(&self) -> Option<Label> {
+        if !self.open {
+            return None;
+        }
+
+        let skip = self.output.len().saturating_sub(Self::VISIBLE_LINES);
+        let mut text = String::new();
+        for line in self.output.iter().skip(skip) {
+            text += &format!("{line:<width$.width$}\n", width = Self::COLUMNS);
+        }
+        text += &format!("{:<width$.width$}", format!("> {}_", self.input), width = Self::COLUMNS);
+
+        Some(Label {
+            anchor: LabelAnchor::Screen([0.0, 0.0]),
+            text,
+            color: Self::TEXT_COLOR,
+            background: Some(Self::BACKGROUND),
+        })
+    }
+}
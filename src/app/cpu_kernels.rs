@@ -0,0 +1,918 @@
+//! CPU reference implementations of the compute kernels in `src/shaders`.
+//!
+//! These mirror the WGSL line by line and exist to verify shader changes.
+
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Point3, Vector3};
+
+use super::{
+    Integrator, SimulationConstants,
+    collision::{BoundsBehavior, Collider, Obstacles, WorldBounds},
+    instances::InstanceTransform,
+    params::SimParams,
+    wells::GravityWell,
+};
+
+/// Mirror the constants in `compute.wgsl`.
+const INSTANCE_RADIUS: f32 = 0.5;
+const NORMAL_EPSILON: f32 = 0.5;
+const CURL_EPSILON: f32 = 0.05;
+const CURL_RESPONSE: f32 = 2.0;
+
+/// Mirrors `force` in `compute.wgsl`.
+pub fn force(p: Vector3<f32>, gravity: f32) -> Vector3<f32> {
+    let l = p.magnitude();
+    let d = -p / l;
+
+    gravity * d / (l * l)
+}
+
+/// Mirrors `swirl` in `compute.wgsl`.
+pub fn swirl(p: Vector3<f32>, gravity: f32) -> Vector3<f32> {
+    let radial = Vector3::new(p.x, 0.0, p.z);
+    let l = radial.magnitude().max(1.0);
+    let tangent = Vector3::new(-p.z, 0.0, p.x) / l;
+
+    gravity * (tangent - 0.5 * radial / l) / (l * l)
+}
+
+/// Mirrors `flock_velocity` in `compute.wgsl`.
+pub fn flock_velocity(p: Vector3<f32>, params: &SimParams) -> Vector3<f32> {
+    let l = p.magnitude().max(1.0);
+    let tangent = Vector3::new(-p.z, 0.0, p.x) / Vector3::new(p.x, 0.0, p.z).magnitude().max(1.0);
+
+    params.cruise_speed * (tangent + (params.flock_radius - l) / params.flock_radius * p / l)
+}
+
+/// Mirrors `attraction` in `compute.wgsl` for a body of unit mass.
+pub fn attraction(p: Vector3<f32>, body: Vector3<f32>, softening: f32) -> Vector3<f32> {
+    let d = body - p;
+    let r2 = d.magnitude2() + softening * softening;
+
+    d / (r2 * r2 * r2).sqrt()
+}
+
+/// Mirrors `pcg_hash` in `compute.wgsl`.
+fn pcg_hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Mirrors `lattice` in `compute.wgsl`.
+fn lattice(x: i32, y: i32, z: i32) -> f32 {
+    let h = pcg_hash(x as u32 ^ pcg_hash(y as u32 ^ pcg_hash(z as u32)));
+    (h >> 8) as f32 / 16777215.0 * 2.0 - 1.0
+}
+
+/// Mirrors `value_noise` in `compute.wgsl`.
+pub fn value_noise(p: Vector3<f32>) -> f32 {
+    // WGSL's mix
+    let mix = |a: f32, b: f32, t: f32| a * (1.0 - t) + b * t;
+    let cell = p.map(f32::floor);
+    let f = p - cell;
+    let u = f.map(|f| f * f * (3.0 - 2.0 * f));
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let x00 = mix(lattice(x, y, z), lattice(x + 1, y, z), u.x);
+    let x10 = mix(lattice(x, y + 1, z), lattice(x + 1, y + 1, z), u.x);
+    let x01 = mix(lattice(x, y, z + 1), lattice(x + 1, y, z + 1), u.x);
+    let x11 = mix(lattice(x, y + 1, z + 1), lattice(x + 1, y + 1, z + 1), u.x);
+    mix(mix(x00, x10, u.y), mix(x01, x11, u.y), u.z)
+}
+
+/// Mirrors `potential` in `compute.wgsl`.
+fn potential(p: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(
+        value_noise(p),
+        value_noise(p + Vector3::new(31.416, -47.853, 12.793)),
+        value_noise(p + Vector3::new(-19.321, 5.127, 61.419)),
+    )
+}
+
+/// Mirrors `curl_noise` in `compute.wgsl`.
+pub fn curl_noise(p: Vector3<f32>) -> Vector3<f32> {
+    let e = CURL_EPSILON;
+    let dx = potential(p + Vector3::new(e, 0.0, 0.0)) - potential(p - Vector3::new(e, 0.0, 0.0));
+    let dy = potential(p + Vector3::new(0.0, e, 0.0)) - potential(p - Vector3::new(0.0, e, 0.0));
+    let dz = potential(p + Vector3::new(0.0, 0.0, e)) - potential(p - Vector3::new(0.0, 0.0, e));
+
+    Vector3::new(dy.z - dz.y, dz.x - dx.z, dx.y - dy.x) / (2.0 * e)
+}
+
+/// Mirrors `integrate` in `compute.wgsl`, returns the position and velocity.
+pub fn integrate(
+    p: Vector3<f32>,
+    v: Vector3<f32>,
+    dt: f32,
+    simulation: &SimulationConstants,
+) -> (Vector3<f32>, Vector3<f32>) {
+    let gravity = simulation.params.gravity;
+    match simulation.integrator {
+        Integrator::Euler => (p + v * dt, v + force(p, gravity) * dt),
+        Integrator::Verlet => {
+            let half = v + force(p, gravity) * dt * 0.5;
+            let next = p + half * dt;
+            (next, half + force(next, gravity) * dt * 0.5)
+        }
+        Integrator::SemiImplicitEuler => {
+            let next_v = v + force(p, gravity) * dt;
+            (p + next_v * dt, next_v)
+        }
+    }
+}
+
+/// Mirrors `compute_main` in `compute.wgsl` for every instance.
+pub fn compute_main(
+    positions: &mut [[f32; 4]],
+    velocities: &mut [[f32; 4]],
+    delta: f32,
+    simulation: &SimulationConstants,
+) {
+    for (position, velocity) in positions.iter_mut().zip(velocities.iter_mut()) {
+        let p = Vector3::new(position[0], position[1], position[2]);
+        let v = Vector3::new(velocity[0], velocity[1], velocity[2]);
+        let (p, v) = if simulation.attract {
+            integrate(p, v, delta, simulation)
+        } else {
+            (p + v * delta, v)
+        };
+
+        *velocity = [v.x, v.y, v.z, velocity[3] - delta];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `vortex_main` in `compute.wgsl` for every instance.
+pub fn vortex_main(
+    positions: &mut [[f32; 4]],
+    velocities: &mut [[f32; 4]],
+    delta: f32,
+    simulation: &SimulationConstants,
+) {
+    for (position, velocity) in positions.iter_mut().zip(velocities.iter_mut()) {
+        let p = Vector3::new(position[0], position[1], position[2]);
+        let v = Vector3::new(velocity[0], velocity[1], velocity[2]) + swirl(p, simulation.params.gravity) * delta;
+        let p = p + v * delta;
+
+        *velocity = [v.x, v.y, v.z, velocity[3] - delta];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `flock_main` in `compute.wgsl` for every instance.
+pub fn flock_main(
+    positions: &mut [[f32; 4]],
+    velocities: &mut [[f32; 4]],
+    delta: f32,
+    simulation: &SimulationConstants,
+) {
+    for (position, velocity) in positions.iter_mut().zip(velocities.iter_mut()) {
+        let p = Vector3::new(position[0], position[1], position[2]);
+        let v = Vector3::new(velocity[0], velocity[1], velocity[2]);
+        let steering = (simulation.params.steering * delta).min(1.0);
+        let v = v + (flock_velocity(p, &simulation.params) - v) * steering;
+        let p = p + v * delta;
+
+        *velocity = [v.x, v.y, v.z, velocity[3] - delta];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `wave_main` in `compute.wgsl` for every instance.
+pub fn wave_main(
+    positions: &mut [[f32; 4]],
+    velocities: &mut [[f32; 4]],
+    delta: f32,
+    simulation: &SimulationConstants,
+) {
+    let stiffness = simulation.params.wave_stiffness;
+    for (position, velocity) in positions.iter_mut().zip(velocities.iter_mut()) {
+        let p = Vector3::new(position[0], position[1], position[2]);
+        let v = Vector3::new(velocity[0], velocity[1] - stiffness * p.y * delta, velocity[2]);
+        let p = p + v * delta;
+
+        *velocity = [v.x, v.y, v.z, velocity[3] - delta];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `curl_main` in `compute.wgsl` for every instance.
+pub fn curl_main(
+    positions: &mut [[f32; 4]],
+    velocities: &mut [[f32; 4]],
+    delta: f32,
+    simulation: &SimulationConstants,
+) {
+    let params = &simulation.params;
+    for (position, velocity) in positions.iter_mut().zip(velocities.iter_mut()) {
+        let p = Vector3::new(position[0], position[1], position[2]);
+        let v = Vector3::new(velocity[0], velocity[1], velocity[2]);
+        let flow = params.noise_speed * curl_noise(p * params.noise_scale);
+        let v = v + (flow - v) * (CURL_RESPONSE * delta).min(1.0);
+        let p = p + v * delta;
+
+        *velocity = [v.x, v.y, v.z, velocity[3] - delta];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `nbody_main` and `nbody_tiled_main` in `compute.wgsl` for every
+/// instance, reading the state before the step.
+pub fn nbody_main(
+    positions: &mut [[f32; 4]],
+    velocities: &mut [[f32; 4]],
+    delta: f32,
+    simulation: &SimulationConstants,
+) {
+    let bodies: Vec<Vector3<f32>> = positions.iter().map(|p| Vector3::new(p[0], p[1], p[2])).collect();
+    let mass = simulation.params.gravity / bodies.len() as f32;
+    let softening = simulation.params.softening;
+
+    for ((position, velocity), &p) in positions.iter_mut().zip(velocities.iter_mut()).zip(&bodies) {
+        let a = bodies
+            .iter()
+            .fold(Vector3::new(0.0, 0.0, 0.0), |a, &body| a + attraction(p, body, softening));
+        let v = Vector3::new(velocity[0], velocity[1], velocity[2]) + mass * a * delta;
+        let p = p + v * delta;
+
+        *velocity = [v.x, v.y, v.z, velocity[3] - delta];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `boids_main` in `compute.wgsl` for every instance, reading the
+/// state before the step.
+pub fn boids_main(
+    positions: &mut [[f32; 4]],
+    velocities: &mut [[f32; 4]],
+    delta: f32,
+    simulation: &SimulationConstants,
+) {
+    let boids = &simulation.boids;
+    let (positions_in, velocities_in) = (positions.to_vec(), velocities.to_vec());
+    let vector = |v: &[f32; 4]| Vector3::new(v[0], v[1], v[2]);
+    let neighbors = boids.neighbors as usize;
+
+    for (i, (position, velocity)) in positions.iter_mut().zip(velocities.iter_mut()).enumerate() {
+        let p = vector(&positions_in[i]);
+        let v = vector(&velocities_in[i]);
+        let mut separation = Vector3::new(0.0, 0.0, 0.0);
+        let mut heading = Vector3::new(0.0, 0.0, 0.0);
+        let mut center = Vector3::new(0.0, 0.0, 0.0);
+        let mut seen = 0;
+        for j in i.saturating_sub(neighbors)..=(i + neighbors).min(positions_in.len() - 1) {
+            let offset = vector(&positions_in[j]) - p;
+            let d = offset.magnitude();
+            if j == i || d >= boids.radius || d == 0.0 {
+                continue;
+            }
+            separation -= offset / d * (1.0 - d / boids.radius);
+            heading += vector(&velocities_in[j]);
+            center += vector(&positions_in[j]);
+            seen += 1;
+        }
+
+        let mut steer = (flock_velocity(p, &simulation.params) - v) * simulation.params.steering * 0.1;
+        if seen > 0 {
+            let n = seen as f32;
+            steer += boids.separation * separation + boids.alignment * (heading / n - v) + boids.cohesion * (center / n - p);
+        }
+        let mut next = v + steer * delta;
+        let speed = next.magnitude();
+        if speed > 0.0 {
+            let t = delta.min(1.0);
+            next *= (speed + (boids.speed - speed) * t) / speed;
+        }
+        let p = p + next * delta;
+
+        *velocity = [next.x, next.y, next.z, velocity[3] - delta];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `sph_cell` in `compute.wgsl`.
+fn sph_cell(p: Vector3<f32>, radius: f32) -> [i32; 3] {
+    let cell = (p / radius).map(f32::floor);
+    [cell.x as i32, cell.y as i32, cell.z as i32]
+}
+
+/// Instances in the cells around `cell`, what the spatial hash of the
+/// fluid kernel finds without buckets shared by far away cells.
+fn sph_neighbors(cells: &HashMap<[i32; 3], Vec<usize>>, cell: [i32; 3]) -> impl Iterator<Item = usize> + '_ {
+    (0..27)
+        .filter_map(move |k| cells.get(&[cell[0] + k % 3 - 1, cell[1] + k / 3 % 3 - 1, cell[2] + k / 9 - 1]))
+        .flatten()
+        .copied()
+}
+
+/// Mirrors `sph_density_main` and `sph_force_main` in `compute.wgsl` for
+/// every instance, reading the state before the step. The cells are kept
+/// in a hash map in place of the spatial hash.
+pub fn sph_main(
+    positions: &mut [[f32; 4]],
+    velocities: &mut [[f32; 4]],
+    delta: f32,
+    simulation: &SimulationConstants,
+) {
+    let params = &simulation.params;
+    let radius = params.sph_radius;
+    let (positions_in, velocities_in) = (positions.to_vec(), velocities.to_vec());
+    let vector = |v: &[f32; 4]| Vector3::new(v[0], v[1], v[2]);
+    let weight = |r2: f32| (1.0 - r2 / (radius * radius)).max(0.0).powi(3);
+    let pressure = |density: f32| params.sph_stiffness * (density - params.sph_density).max(0.0);
+
+    let mut cells: HashMap<[i32; 3], Vec<usize>> = HashMap::new();
+    for (i, position) in positions_in.iter().enumerate() {
+        cells.entry(sph_cell(vector(position), radius)).or_default().push(i);
+    }
+    let densities: Vec<f32> = positions_in
+        .iter()
+        .map(|position| {
+            let p = vector(position);
+            sph_neighbors(&cells, sph_cell(p, radius))
+                .map(|j| weight((vector(&positions_in[j]) - p).magnitude2()))
+                .sum()
+        })
+        .collect();
+
+    for (i, (position, velocity)) in positions.iter_mut().zip(velocities.iter_mut()).enumerate() {
+        let p = vector(&positions_in[i]);
+        let v = vector(&velocities_in[i]);
+        let own_pressure = pressure(densities[i]);
+        let mut a = Vector3::new(0.0, -params.sph_gravity, 0.0);
+        for j in sph_neighbors(&cells, sph_cell(p, radius)) {
+            let offset = p - vector(&positions_in[j]);
+            let r = offset.magnitude();
+            if j == i || r >= radius || r == 0.0 {
+                continue;
+            }
+            let q = 1.0 - r / radius;
+            let density = densities[j];
+            a += (own_pressure + pressure(density)) / (2.0 * density) * q * q * offset / r;
+            a += params.sph_viscosity * (vector(&velocities_in[j]) - v) / density * q;
+        }
+
+        let mut v = v + a * delta;
+        let mut p = p + v * delta;
+        if p.y < 0.0 {
+            p.y = 0.0;
+            v.y = v.y.max(0.0);
+        }
+
+        *velocity = [v.x, v.y, v.z, velocity[3] - delta];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `scene_normal` in `compute.wgsl`.
+pub fn scene_normal(p: Point3<f32>, obstacles: &Obstacles) -> Vector3<f32> {
+    let e = NORMAL_EPSILON;
+    let gradient = Vector3::new(e, -e, -e) * obstacles.distance(p + Vector3::new(e, -e, -e))
+        + Vector3::new(-e, -e, e) * obstacles.distance(p + Vector3::new(-e, -e, e))
+        + Vector3::new(-e, e, -e) * obstacles.distance(p + Vector3::new(-e, e, -e))
+        + Vector3::new(e, e, e) * obstacles.distance(p + Vector3::new(e, e, e));
+    if gradient.magnitude2() == 0.0 {
+        return Vector3::unit_y();
+    }
+    gradient.normalize()
+}
+
+/// Mirrors `damp` in `compute.wgsl` for every instance, which the kernels
+/// above leave out like `collide`.
+pub fn damp(velocities: &mut [[f32; 4]], drag: f32, delta: f32) {
+    if drag == 0.0 {
+        return;
+    }
+
+    let factor = (-drag * delta).exp();
+    for velocity in velocities.iter_mut() {
+        *velocity = [velocity[0] * factor, velocity[1] * factor, velocity[2] * factor, velocity[3]];
+    }
+}
+
+/// Mirrors `collide` in `compute.wgsl` for every instance. The kernels
+/// above leave it out, every one of them ends with it on the GPU.
+pub fn collide(positions: &mut [[f32; 4]], velocities: &mut [[f32; 4]], obstacles: &Obstacles) {
+    if obstacles.is_empty() {
+        return;
+    }
+
+    for (position, velocity) in positions.iter_mut().zip(velocities.iter_mut()) {
+        let p = Point3::new(position[0], position[1], position[2]);
+        let d = obstacles.distance(p) - INSTANCE_RADIUS;
+        if d >= 0.0 {
+            continue;
+        }
+
+        let n = scene_normal(p, obstacles);
+        let p = p - n * d;
+        let v = Vector3::new(velocity[0], velocity[1], velocity[2]);
+        let normal_speed = v.dot(n);
+        let v = if normal_speed >= 0.0 {
+            v
+        } else {
+            (v - normal_speed * n) * (1.0 - obstacles.friction) - normal_speed * obstacles.restitution * n
+        };
+
+        *velocity = [v.x, v.y, v.z, velocity[3]];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `follow` in `compute.wgsl` for every instance, `attractor` is
+/// `CameraAttractor::uniform`.
+pub fn follow(positions: &[[f32; 4]], velocities: &mut [[f32; 4]], attractor: [f32; 4], delta: f32) {
+    let strength = attractor[3];
+    if strength == 0.0 {
+        return;
+    }
+
+    let point = Vector3::new(attractor[0], attractor[1], attractor[2]);
+    for (position, velocity) in positions.iter().zip(velocities.iter_mut()) {
+        let offset = point - Vector3::new(position[0], position[1], position[2]);
+        let pull = offset * (strength / offset.magnitude().max(1.0));
+        velocity[0] += pull.x * delta;
+        velocity[1] += pull.y * delta;
+        velocity[2] += pull.z * delta;
+    }
+}
+
+/// Mirrors `pull_wells` in `compute.wgsl` for every instance.
+pub fn pull_wells(positions: &[[f32; 4]], velocities: &mut [[f32; 4]], wells: &[GravityWell], softening: f32, delta: f32) {
+    if wells.is_empty() {
+        return;
+    }
+
+    for (position, velocity) in positions.iter().zip(velocities.iter_mut()) {
+        let p = Vector3::new(position[0], position[1], position[2]);
+        let acceleration = wells.iter().fold(Vector3::new(0.0, 0.0, 0.0), |a, well| {
+            a + well.strength * attraction(p, well.position.into(), softening)
+        });
+        velocity[0] += acceleration.x * delta;
+        velocity[1] += acceleration.y * delta;
+        velocity[2] += acceleration.z * delta;
+    }
+}
+
+/// Mirrors `confine` in `compute.wgsl` for every instance.
+pub fn confine(positions: &mut [[f32; 4]], velocities: &mut [[f32; 4]], bounds: &WorldBounds, restitution: f32) {
+    let Some(shape) = bounds.shape else {
+        return;
+    };
+
+    for (position, velocity) in positions.iter_mut().zip(velocities.iter_mut()) {
+        let p = Point3::new(position[0], position[1], position[2]);
+        let v = Vector3::new(velocity[0], velocity[1], velocity[2]);
+        let (p, v) = match shape {
+            Collider::Sphere { center, radius } => {
+                let offset = p - center;
+                let l = offset.magnitude();
+                if l <= radius {
+                    continue;
+                }
+                let n = offset / l;
+                match bounds.behavior {
+                    BoundsBehavior::Wrap => (center - n * (2.0 * radius - l).max(0.0), v),
+                    BoundsBehavior::Bounce => {
+                        let normal_speed = v.dot(n).max(0.0);
+                        (center + n * radius, v - normal_speed * (1.0 + restitution) * n)
+                    }
+                }
+            }
+            Collider::Box { center, half_extent } => {
+                let offset = p - center;
+                if (0..3).all(|axis| offset[axis].abs() <= half_extent[axis]) {
+                    continue;
+                }
+                let (mut p, mut v) = (p, v);
+                for axis in 0..3 {
+                    let (o, e) = (offset[axis], half_extent[axis]);
+                    match bounds.behavior {
+                        BoundsBehavior::Wrap => p[axis] = center[axis] + o - 2.0 * e * ((o + e) / (2.0 * e)).floor(),
+                        BoundsBehavior::Bounce => {
+                            if o.abs() > e && o.signum() * v[axis] > 0.0 {
+                                v[axis] *= -restitution;
+                            }
+                            p[axis] = center[axis] + o.clamp(-e, e);
+                        }
+                    }
+                }
+                (p, v)
+            }
+        };
+
+        *velocity = [v.x, v.y, v.z, velocity[3]];
+        *position = [p.x, p.y, p.z, position[3]];
+    }
+}
+
+/// Mirrors `spin` in `compute.wgsl` for every instance, also left out of
+/// the kernels above.
+pub fn spin(transforms: &mut [InstanceTransform], delta: f32) {
+    for transform in transforms.iter_mut().filter(|t| t.scale[3] != 0.0) {
+        let (sin, cos) = (0.5 * transform.scale[3] * delta).sin_cos();
+        let [x, y, z, w] = transform.rotation;
+        let turned = [x * cos + w * sin, y * cos + z * sin, z * cos - y * sin, w * cos - x * sin];
+        let length = turned.iter().map(|c| c * c).sum::<f32>().sqrt();
+        transform.rotation = turned.map(|c| c / length);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pollster::FutureExt;
+    use wgpu::util::DeviceExt;
+
+    use cgmath::{InnerSpace, Point3, Vector3};
+
+    use super::super::{
+        camera::Camera,
+        collision::{BoundsBehavior, Collider, Collision, CollisionSettings, Obstacles, WorldBounds},
+        emitter::EmitterUniform,
+        instances::{InstanceTransform, TransformSettings},
+        params::{SimParams, SimParamsBuffer},
+        shader::ShaderLoader,
+        statistics::SimulationStats,
+        App, ComputePushConstants, FrameUniform, Integrator, SimulationConstants, SimulationKernel, KERNELS,
+    };
+
+    const DIMENSIONS: [u32; 4] = [8, 8, 4, 0];
+    const COUNT: usize = (DIMENSIONS[0] * DIMENSIONS[1] * DIMENSIONS[2]) as usize;
+    const DELTA: f32 = 1.0 / 60.0;
+    const STEPS: usize = 16;
+
+    fn initial_state() -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+        let positions = App::generate_random_vectors(
+            COUNT,
+            cgmath::Point3::new(-10000.0, -10000.0, -10000.0),
+            cgmath::Point3::new(10000.0, 10000.0, 10000.0),
+        );
+        let velocities = App::generate_random_vectors(
+            COUNT,
+            cgmath::Point3::new(-20.0, -20.0, -20.0),
+            cgmath::Point3::new(20.0, 20.0, 20.0),
+        );
+
+        (positions, velocities)
+    }
+
+    /// The kernels read the settings beyond `push_constant_size` bytes from
+    /// the uniform buffer, like on adapters with less room for push constants.
+    fn request_device(push_constant_size: u32) -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .block_on()?;
+
+        adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("test_device"),
+                required_features: if push_constant_size > 0 {
+                    adapter.features() & wgpu::Features::PUSH_CONSTANTS
+                } else {
+                    wgpu::Features::empty()
+                },
+                required_limits: wgpu::Limits {
+                    max_push_constant_size: push_constant_size.min(adapter.limits().max_push_constant_size),
+                    ..Default::default()
+                },
+                memory_hints: wgpu::MemoryHints::Performance,
+            }, None)
+            .block_on()
+            .ok()
+    }
+
+    fn run_gpu(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        kernel: &SimulationKernel,
+        positions: &[[f32; 4]],
+        velocities: &[[f32; 4]],
+    ) -> Vec<[f32; 4]> {
+        // Both sides of the state, stepped back and forth like in `App::update`
+        let state = |label, data: &[[f32; 4]]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(data),
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            })
+        };
+        let positions_buffers = [state("positions_buffer", positions), state("positions_buffer", positions)];
+        let velocities_buffers = [state("velocities_buffer", velocities), state("velocities_buffer", velocities)];
+        // Spinning, which mustn't change the positions
+        let transforms = InstanceTransform::generate(positions.len(), &TransformSettings {
+            random_rotation: true,
+            spin: 1.0,
+            ..Default::default()
+        });
+        let transforms_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("transforms_buffer"),
+            contents: bytemuck::cast_slice(&transforms),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging_buffer"),
+            size: positions_buffers[0].size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let frame_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frame_buffer"),
+            size: std::mem::size_of::<FrameUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let frame_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("frame"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let frame_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("frame"),
+            layout: &frame_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: frame_buffer.as_entire_binding(),
+            }],
+        });
+
+        let sph_scratch = App::sph_scratch(device, positions.len() as u32);
+        let layout = App::pv_bind_group_layout(device);
+        let bind_groups = [0, 1].map(|side| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("pv_bind_group"),
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: positions_buffers[1 - side].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: velocities_buffers[1 - side].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: transforms_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: positions_buffers[side].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: velocities_buffers[side].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: sph_scratch.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        let collision = Collision::new(device, queue, Obstacles::load(&CollisionSettings::default()).unwrap());
+        let mut params = SimParamsBuffer::new(device, &SimParams::default());
+        let constants = ComputePushConstants::new(&SimulationConstants::default(), EmitterUniform::default(), &Camera::new(1.0));
+        params.write_constants(device, queue, &[constants]);
+        let layouts = [&frame_layout, &layout, collision.layout(), params.layout()];
+        let shaders = ShaderLoader::default();
+        let pipeline = App::compute_pipeline(device, &layouts, kernel, &SimulationConstants::default(), &shaders);
+        let prepass_pipelines: Vec<_> = kernel
+            .prepasses
+            .iter()
+            .map(|&prepass| {
+                App::compute_entry_pipeline(device, &layouts, prepass, &SimulationConstants::default(), &shaders)
+            })
+            .collect();
+        let workgroups = [0, 1, 2].map(|axis| {
+            let dims = SimulationConstants::default().workgroup_dims;
+            DIMENSIONS[axis].div_ceil([dims.0, dims.1, dims.2][axis])
+        });
+
+        // The frame uniform changes every step, so each step is its own submission
+        for step in 0..STEPS {
+            let frame = FrameUniform {
+                dimensions: DIMENSIONS,
+                time: step as f32 * DELTA,
+                delta: DELTA,
+                frame_index: step as u32,
+                ..Default::default()
+            };
+            queue.write_buffer(&frame_buffer, 0, bytemuck::bytes_of(&frame));
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            {
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+                compute_pass.set_bind_group(0, &frame_bind_group, &[]);
+                compute_pass.set_bind_group(1, &bind_groups[step % 2], &[]);
+                compute_pass.set_bind_group(2, collision.bind_group(), &[]);
+                // Prepasses first, the kernel reads what they wrote
+                for pipeline in prepass_pipelines.iter().chain(std::iter::once(&pipeline)) {
+                    compute_pass.set_pipeline(pipeline);
+                    params.bind(&mut compute_pass, 0);
+                    compute_pass.dispatch_workgroups(workgroups[0], workgroups[1], workgroups[2]);
+                }
+            }
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        let positions_buffer = &positions_buffers[STEPS % 2];
+        encoder.copy_buffer_to_buffer(positions_buffer, 0, &staging_buffer, 0, positions_buffer.size());
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::Maintain::Wait);
+
+        let result = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging_buffer.unmap();
+        result
+    }
+
+    #[test]
+    fn cpu_kernel_pulls_towards_origin() {
+        let mut positions = vec![[1000.0, 0.0, 0.0, 1.0]];
+        let mut velocities = vec![[0.0, 0.0, 0.0, 1.0]];
+
+        super::compute_main(&mut positions, &mut velocities, DELTA, &SimulationConstants::default());
+
+        assert!(velocities[0][0] < 0.0);
+        assert!(positions[0][0] < 1000.0);
+        assert_eq!(positions[0][1], 0.0);
+    }
+
+    #[test]
+    fn collision_bounces_off_sphere() {
+        let obstacles = Obstacles {
+            colliders: vec![Collider::Sphere {
+                center: Point3::new(0.0, 0.0, 0.0),
+                radius: 100.0,
+            }],
+            volume: None,
+            restitution: 0.5,
+            friction: 0.0,
+        };
+        let mut positions = vec![[0.0, 99.0, 0.0, 1.0], [0.0, 200.0, 0.0, 1.0]];
+        let mut velocities = vec![[0.0, -10.0, 0.0, 1.0], [0.0, -10.0, 0.0, 1.0]];
+
+        super::collide(&mut positions, &mut velocities, &obstacles);
+
+        // Pushed out to the surface, bouncing back at half the speed
+        assert!((positions[0][1] - 100.5).abs() < 1.0e-3);
+        assert!((velocities[0][1] - 5.0).abs() < 1.0e-3);
+        assert_eq!(positions[1], [0.0, 200.0, 0.0, 1.0]);
+        assert_eq!(velocities[1], [0.0, -10.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn bounds_bounce_and_wrap() {
+        let sphere = WorldBounds {
+            shape: Some(Collider::Sphere {
+                center: Point3::new(0.0, 0.0, 0.0),
+                radius: 100.0,
+            }),
+            behavior: BoundsBehavior::Bounce,
+        };
+        let mut positions = vec![[0.0, 110.0, 0.0, 1.0], [0.0, 50.0, 0.0, 1.0]];
+        let mut velocities = vec![[0.0, 10.0, 0.0, 1.0], [0.0, 10.0, 0.0, 1.0]];
+
+        super::confine(&mut positions, &mut velocities, &sphere, 0.5);
+
+        // Back on the boundary, heading in at half the speed
+        assert!((positions[0][1] - 100.0).abs() < 1.0e-3);
+        assert!((velocities[0][1] + 5.0).abs() < 1.0e-3);
+        assert_eq!(positions[1], [0.0, 50.0, 0.0, 1.0]);
+        assert_eq!(velocities[1], [0.0, 10.0, 0.0, 1.0]);
+
+        let cube = WorldBounds {
+            shape: Some(Collider::Box {
+                center: Point3::new(0.0, 0.0, 0.0),
+                half_extent: cgmath::Vector3::new(100.0, 100.0, 100.0),
+            }),
+            behavior: BoundsBehavior::Wrap,
+        };
+        let mut positions = vec![[110.0, 20.0, -30.0, 1.0]];
+        let mut velocities = vec![[10.0, 0.0, 0.0, 1.0]];
+
+        super::confine(&mut positions, &mut velocities, &cube, 0.5);
+
+        // Over on the opposite side, keeping the velocity
+        assert!((positions[0][0] + 90.0).abs() < 1.0e-3);
+        assert_eq!(&positions[0][1..], &[20.0, -30.0, 1.0]);
+        assert_eq!(velocities[0], [10.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn curl_noise_is_divergence_free() {
+        // Differences of the same step as the curl cancel exactly
+        let h = super::CURL_EPSILON;
+        for i in 0..64 {
+            let p = Vector3::new(i as f32 * 0.37, (i % 7) as f32 * 0.61 - 2.0, (i % 5) as f32 * -0.83);
+            let flow = super::curl_noise(p);
+            let divergence = (super::curl_noise(p + Vector3::new(h, 0.0, 0.0)).x
+                - super::curl_noise(p - Vector3::new(h, 0.0, 0.0)).x
+                + super::curl_noise(p + Vector3::new(0.0, h, 0.0)).y
+                - super::curl_noise(p - Vector3::new(0.0, h, 0.0)).y
+                + super::curl_noise(p + Vector3::new(0.0, 0.0, h)).z
+                - super::curl_noise(p - Vector3::new(0.0, 0.0, h)).z)
+                / (2.0 * h);
+
+            assert!(divergence.abs() < 1.0e-3 * flow.magnitude().max(1.0), "divergence {divergence} at {p:?}");
+        }
+    }
+
+    #[test]
+    fn spin_turns_about_local_x_axis() {
+        let mut transforms = vec![
+            InstanceTransform {
+                scale: [1.0, 1.0, 1.0, std::f32::consts::PI],
+                ..InstanceTransform::IDENTITY
+            },
+            InstanceTransform::IDENTITY,
+        ];
+
+        // Half a turn in a hundred steps
+        for _ in 0..100 {
+            super::spin(&mut transforms, 0.01);
+        }
+
+        let [x, y, z, w] = transforms[0].rotation;
+        assert!((x.abs() - 1.0).abs() < 1.0e-3 && y.abs() < 1.0e-3 && z.abs() < 1.0e-3 && w.abs() < 1.0e-3);
+        assert_eq!(transforms[1], InstanceTransform::IDENTITY);
+    }
+
+    #[test]
+    fn verlet_conserves_orbital_energy_better_than_euler() {
+        const RADIUS: f32 = 1000.0;
+        const STEPS: usize = 1000;
+
+        let drift = |integrator| {
+            let simulation = SimulationConstants {
+                integrator,
+                ..Default::default()
+            };
+            // Circular orbit, a hundred steps per revolution
+            let speed = (simulation.params.gravity / RADIUS).sqrt();
+            let delta = std::f32::consts::TAU * RADIUS / speed / 100.0;
+            let mut positions = vec![[RADIUS, 0.0, 0.0, 1.0]];
+            let mut velocities = vec![[0.0, 0.0, speed, 1.0]];
+
+            let energy = |positions: &[[f32; 4]], velocities: &[[f32; 4]]| {
+                SimulationStats::from_state(positions, velocities, 1.0, simulation.params.gravity).energy()
+            };
+            let initial = energy(&positions, &velocities);
+            for _ in 0..STEPS {
+                super::compute_main(&mut positions, &mut velocities, delta, &simulation);
+            }
+            ((energy(&positions, &velocities) - initial) / initial).abs()
+        };
+
+        assert!(drift(Integrator::Verlet) < drift(Integrator::Euler));
+        assert!(drift(Integrator::SemiImplicitEuler) < drift(Integrator::Euler));
+    }
+
+    #[test]
+    fn gpu_kernel_matches_cpu_reference() {
+        // With all of the settings pushed, the hot ones and none
+        for push_constant_size in [ComputePushConstants::SIZE, ComputePushConstants::HOT_SIZE, 0] {
+            let Some((device, queue)) = request_device(push_constant_size) else {
+                eprintln!("No adapter available, skipping GPU comparison.");
+                return;
+            };
+
+            for kernel in KERNELS {
+                let (mut positions, mut velocities) = initial_state();
+                let gpu_positions = run_gpu(&device, &queue, kernel, &positions, &velocities);
+
+                let simulation = kernel.constants(&SimulationConstants::default());
+                for _ in 0..STEPS {
+                    (kernel.cpu)(&mut positions, &mut velocities, DELTA, &simulation);
+                }
+
+                for (i, (gpu, cpu)) in gpu_positions.iter().zip(&positions).enumerate() {
+                    for axis in 0..3 {
+                        let tolerance = 1.0e-3 * cpu[axis].abs().max(1.0);
+                        assert!(
+                            (gpu[axis] - cpu[axis]).abs() <= tolerance,
+                            "Kernel {} instance {i} axis {axis}, {push_constant_size} bytes pushed: GPU {} vs CPU {}",
+                            kernel.name,
+                            gpu[axis],
+                            cpu[axis],
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use std::{
+    ops::Range,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{ElementWise, EuclideanSpace, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::{camera::Frustum, debug_labels, instances::InstanceBuffers, octree::Octree, shader::ShaderLoader};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullingMode {
+    Disabled,
+    CpuChunks,
+    /// Hi-Z occlusion culling of every instance in a compute pass.
+    GpuOcclusion,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Self {
+            min: Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Point3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    pub fn extend(&mut self, point: [f32; 4]) {
+        self.min.x = self.min.x.min(point[0]);
+        self.min.y = self.min.y.min(point[1]);
+        self.min.z = self.min.z.min(point[2]);
+        self.max.x = self.max.x.max(point[0]);
+        self.max.y = self.max.y.max(point[1]);
+        self.max.z = self.max.z.max(point[2]);
+    }
+
+    pub fn inflate(&mut self, amount: f32) {
+        self.min -= Vector3::new(amount, amount, amount);
+        self.max += Vector3::new(amount, amount, amount);
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && self.min.z <= other.min.z
+            && self.max.x >= other.max.x
+            && self.max.y >= other.max.y
+            && self.max.z >= other.max.z
+    }
+
+    pub fn distance2(&self, point: Point3<f32>) -> f32 {
+        let dx = (self.min.x - point.x).max(point.x - self.max.x).max(0.0);
+        let dy = (self.min.y - point.y).max(point.y - self.max.y).max(0.0);
+        let dz = (self.min.z - point.z).max(point.z - self.max.z).max(0.0);
+
+        dx * dx + dy * dy + dz * dz
+    }
+
+    /// Slab test. Returns the entry distance along the ray if it hits the box.
+    pub fn intersect_ray(&self, origin: Point3<f32>, inv_direction: Vector3<f32>) -> Option<f32> {
+        let t1 = (self.min - origin).mul_element_wise(inv_direction);
+        let t2 = (self.max - origin).mul_element_wise(inv_direction);
+
+        let t_near = t1.x.min(t2.x).max(t1.y.min(t2.y)).max(t1.z.min(t2.z));
+        let t_far = t1.x.max(t2.x).min(t1.y.max(t2.y)).min(t1.z.max(t2.z));
+
+        (t_far >= t_near.max(0.0)).then_some(t_near.max(0.0))
+    }
+}
+
+/// Contiguous range of instances together with their bounds.
+pub struct InstanceChunk {
+    pub range: Range<u32>,
+    pub bounds: Aabb,
+}
+
+pub struct ChunkCuller {
+    chunks: Vec<InstanceChunk>,
+    octree: Octree,
+    visible_chunks: Vec<usize>,
+    visible: Vec<Range<u32>>,
+    /// Farthest any instance reaches from its center.
+    instance_extent: f32,
+    /// Speed of the fastest instance when the bounds were last computed.
+    max_speed: f32,
+    /// Simulated seconds so far, see [`Self::advance`].
+    simulated: f64,
+    /// The instances moved somewhere the bounds don't know of, so nothing
+    /// is culled until new ones come in.
+    stale: bool,
+}
+
+impl ChunkCuller {
+    pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+    /// Bounds are grown by `instance_extent`, the farthest any instance
+    /// reaches from its center, so they cover geometry and not just centers.
+    pub fn build(positions: &[[f32; 4]], chunk_size: usize, instance_extent: f32) -> Self {
+        let chunks = positions
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut bounds = Aabb::empty();
+                chunk.iter().for_each(|p| bounds.extend(*p));
+
+                let start = (i * chunk_size) as u32;
+                InstanceChunk {
+                    range: start..start + chunk.len() as u32,
+                    bounds,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        log::debug!("Built {} culling chunks of {} instances.", chunks.len(), chunk_size);
+
+        Self::from_chunks(chunks, instance_extent)
+    }
+
+    /// Culls chunks whose bounds of instance centers are known up front,
+    /// like the ones the GPU reset scatters instances into, without reading
+    /// any positions back.
+    pub fn from_chunks(mut chunks: Vec<InstanceChunk>, instance_extent: f32) -> Self {
+        chunks.iter_mut().for_each(|c| c.bounds.inflate(instance_extent));
+        let octree = Octree::build(chunks.iter().map(|c| c.bounds).collect());
+
+        Self {
+            chunks,
+            octree,
+            visible_chunks: Vec::new(),
+            visible: Vec::new(),
+            instance_extent,
+            max_speed: 0.0,
+            simulated: 0.0,
+            stale: false,
+        }
+    }
+
+    /// Grows every chunk by as far as the fastest instance of the last
+    /// bounds moves in `time`, to stay conservative while the simulation
+    /// moves instances on until the next bounds come in.
+    pub fn advance(&mut self, time: f32) {
+        let amount = time * self.max_speed;
+        self.simulated += time as f64;
+        self.chunks.iter_mut().for_each(|c| c.bounds.inflate(amount));
+        self.octree.inflate(amount);
+    }
+
+    /// Recomputes the bounds from the current `positions`, which the
+    /// simulation on the CPU keeps up to date.
+    pub fn refit(&mut self, positions: &[[f32; 4]]) {
+        for chunk in &mut self.chunks {
+            let mut bounds = Aabb::empty();
+            let range = chunk.range.start as usize..chunk.range.end as usize;
+            positions[range].iter().for_each(|p| bounds.extend(*p));
+            bounds.inflate(self.instance_extent);
+            chunk.bounds = bounds;
+        }
+        self.max_speed = 0.0;
+        self.octree = Octree::build(self.chunks.iter().map(|c| c.bounds).collect());
+        self.stale = false;
+    }
+
+    /// Takes bounds of instance centers and the speed of the fastest
+    /// instance for every chunk, as reduced when `simulated` seconds were
+    /// simulated, growing them by the instance extent and by how far their
+    /// instances moved since.
+    fn update(&mut self, bounds: impl Iterator<Item = (Aabb, f32)>, simulated: f64) {
+        let elapsed = (self.simulated - simulated).max(0.0) as f32;
+        self.max_speed = 0.0;
+        for (chunk, (mut bounds, speed)) in self.chunks.iter_mut().zip(bounds) {
+            bounds.inflate(self.instance_extent + speed * elapsed);
+            chunk.bounds = bounds;
+            self.max_speed = self.max_speed.max(speed);
+        }
+        self.octree = Octree::build(self.chunks.iter().map(|c| c.bounds).collect());
+        self.stale = false;
+    }
+
+    /// Stops culling until new bounds come in, for when the instances were
+    /// moved other than by simulating them, like rewinding the history.
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Returns instance ranges of the chunks intersecting `frustum`.
+    /// Adjacent visible chunks are merged into a single range.
+    pub fn cull(&mut self, frustum: &Frustum) -> &[Range<u32>] {
+        self.visible.clear();
+        self.visible_chunks.clear();
+
+        if self.stale {
+            let len = self.chunks.last().map_or(0, |c| c.range.end);
+            self.visible.extend((len > 0).then_some(0..len));
+            return &self.visible;
+        }
+
+        self.octree.query_frustum(frustum, &mut self.visible_chunks);
+        self.visible_chunks.sort_unstable();
+
+        for chunk in self.visible_chunks.iter().map(|&i| &self.chunks[i]) {
+            match self.visible.last_mut() {
+                Some(last) if last.end == chunk.range.start => last.end = chunk.range.end,
+                _ => self.visible.push(chunk.range.clone()),
+            }
+        }
+
+        &self.visible
+    }
+}
+
+/// Mirrors `Piece` in `bounds.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct BoundsPiece {
+    first: u32,
+    count: u32,
+    slot: u32,
+}
+
+/// Mirrors `Bounds` in `bounds.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct ReducedBounds {
+    min: [f32; 3],
+    speed: f32,
+    max: [f32; 3],
+    _padding: f32,
+}
+
+/// Reduces the simulated positions of every [`ChunkCuller`] chunk to its
+/// bounds on the GPU, along with the speed of its fastest instance, so the
+/// bounds follow the instances instead of only ever growing. Culling chunks
+/// can straddle instance chunks, so each workgroup reduces the part of one
+/// held by a single instance chunk. Readbacks complete asynchronously
+/// during device polls.
+pub struct ChunkBounds {
+    pipeline: wgpu::ComputePipeline,
+    /// One pair per instance chunk holding any instances, for either side
+    /// of the state, with its number of pieces.
+    bind_groups: Vec<([wgpu::BindGroup; 2], u32)>,
+    /// Culling chunk of every piece.
+    chunks: Vec<usize>,
+    chunk_count: usize,
+    bounds_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Set by the map callback once the readback buffer can be read.
+    mapped: Arc<AtomicBool>,
+    /// Set by the map callback when the readback buffer couldn't be mapped.
+    failed: Arc<AtomicBool>,
+    /// A reduction was recorded and its result not read yet.
+    pending: bool,
+    /// The pending result is of instances moved since, see [`Self::discard`].
+    discarded: bool,
+    /// [`ChunkCuller::simulated`] when the pending reduction was recorded.
+    simulated: f64,
+}
+
+impl ChunkBounds {
+    const BOUNDS_SIZE: u64 = std::mem::size_of::<ReducedBounds>() as u64;
+
+    /// Reduces chunks of `chunk_size` instances, as [`ChunkCuller::build`] makes them.
+    pub fn new(device: &wgpu::Device, instances: &InstanceBuffers, chunk_size: usize, shaders: &ShaderLoader) -> Self {
+        let module = shaders.module(device, "bounds.wgsl", include_str!("../shaders/bounds.wgsl"));
+        let chunk_size = chunk_size as u32;
+
+        let mut chunks = Vec::new();
+        let pieces: Vec<_> = instances
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let mut pieces = Vec::new();
+                let mut start = chunk.range.start;
+                while start < chunk.range.end {
+                    let culling_chunk = start / chunk_size;
+                    let end = chunk.range.end.min((culling_chunk + 1) * chunk_size);
+                    pieces.push(BoundsPiece {
+                        first: start - chunk.range.start,
+                        count: end - start,
+                        slot: chunks.len() as u32,
+                    });
+                    chunks.push(culling_chunk as usize);
+                    start = end;
+                }
+                pieces
+            })
+            .collect();
+        let chunk_count = instances.len().div_ceil(chunk_size) as usize;
+
+        let bounds_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk_bounds"),
+            size: chunks.len().max(1) as u64 * Self::BOUNDS_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk_bounds_readback"),
+            size: chunks.len().max(1) as u64 * Self::BOUNDS_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("chunk_bounds"),
+            entries: &[entry(0, true), entry(1, true), entry(2, true), entry(3, false)],
+        });
+        let bind_groups = instances
+            .chunks
+            .iter()
+            .zip(pieces)
+            .filter(|(_, pieces)| !pieces.is_empty())
+            .map(|(chunk, pieces)| {
+                let pieces_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("chunk_bounds_pieces"),
+                    contents: bytemuck::cast_slice(&pieces),
+                    usage: wgpu::BufferUsages::STORAGE,
+                });
+                let bind_groups = [0, 1].map(|side| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("chunk_bounds"),
+                        layout: &bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: chunk.positions[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: chunk.velocities[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: pieces_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: bounds_buffer.as_entire_binding(),
+                            },
+                        ],
+                    })
+                });
+                (bind_groups, pieces.len() as u32)
+            })
+            .collect();
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("chunk_bounds_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("reduce_bounds"),
+            layout: Some(&layout),
+            module: &module,
+            entry_point: Some("reduce_bounds"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_groups,
+            chunks,
+            chunk_count,
+            bounds_buffer,
+            readback_buffer,
+            mapped: Arc::new(AtomicBool::new(false)),
+            failed: Arc::new(AtomicBool::new(false)),
+            pending: false,
+            discarded: false,
+            simulated: 0.0,
+        }
+    }
+
+    /// Records the reduction of the `front` side of the state and the copy
+    /// of its result, unless the previous result hasn't been read yet.
+    /// Returns whether anything was recorded, in which case
+    /// [`Self::submitted`] has to follow the submission.
+    pub fn record(&mut self, encoder: &mut wgpu::CommandEncoder, front: usize, culler: &ChunkCuller) -> bool {
+        if self.pending || self.bind_groups.is_empty() {
+            return false;
+        }
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("chunk_bounds_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            for (index, (bind_groups, pieces)) in self.bind_groups.iter().enumerate() {
+                debug_labels::marker(&mut compute_pass, || format!("chunk bounds {index}: {pieces} pieces"));
+                compute_pass.set_bind_group(0, &bind_groups[front], &[]);
+                compute_pass.dispatch_workgroups(*pieces, 1, 1);
+            }
+        }
+        let size = self.chunks.len() as u64 * Self::BOUNDS_SIZE;
+        encoder.copy_buffer_to_buffer(&self.bounds_buffer, 0, &self.readback_buffer, 0, size);
+
+        self.pending = true;
+        self.discarded = false;
+        self.simulated = culler.simulated;
+        true
+    }
+
+    /// Starts mapping the result once the recorded reduction was submitted.
+    pub fn submitted(&self) {
+        let (mapped, failed) = (self.mapped.clone(), self.failed.clone());
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| match result {
+                Ok(()) => mapped.store(true, Ordering::Release),
+                Err(error) => {
+                    log::error!("Failed to map the chunk bounds readback: {error}");
+                    failed.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Drops the pending result, for when the instances were moved other
+    /// than by simulating them after it was recorded.
+    pub fn discard(&mut self) {
+        self.discarded = true;
+    }
+
+    /// Hands the latest result to `culler` once its readback finished.
+    /// Completion is noticed during device polls, so call this after polling.
+    pub fn apply(&mut self, culler: &mut ChunkCuller) {
+        if self.failed.swap(false, Ordering::Acquire) {
+            // The next reduction tries again
+            self.pending = false;
+            return;
+        }
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return;
+        }
+
+        let mut bounds = vec![(Aabb::empty(), 0.0); self.chunk_count];
+        {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let pieces: &[ReducedBounds] = bytemuck::cast_slice(&data);
+            for (&chunk, piece) in self.chunks.iter().zip(pieces) {
+                let (chunk, speed) = &mut bounds[chunk];
+                chunk.extend([piece.min[0], piece.min[1], piece.min[2], 0.0]);
+                chunk.extend([piece.max[0], piece.max[1], piece.max[2], 0.0]);
+                *speed = piece.speed.max(*speed);
+            }
+        }
+        self.readback_buffer.unmap();
+        self.pending = false;
+
+        if !std::mem::take(&mut self.discarded) {
+            culler.update(bounds.into_iter(), self.simulated);
+        }
+    }
+}
+
+/// Reorders positions along a Morton curve so contiguous chunks are spatially compact.
+pub fn sort_spatially(positions: &mut [[f32; 4]], min: Point3<f32>, max: Point3<f32>) {
+    fn spread_bits(mut v: u32) -> u32 {
+        v &= 0x3ff;
+        v = (v | (v << 16)) & 0x030000ff;
+        v = (v | (v << 8)) & 0x0300f00f;
+        v = (v | (v << 4)) & 0x030c30c3;
+        v = (v | (v << 2)) & 0x09249249;
+        v
+    }
+
+    let cell = |value: f32, min: f32, max: f32| {
+        (((value - min) / (max - min)).clamp(0.0, 1.0) * 1023.0) as u32
+    };
+
+    positions.sort_by_cached_key(|p| {
+        spread_bits(cell(p[0], min.x, max.x))
+            | (spread_bits(cell(p[1], min.y, max.y)) << 1)
+            | (spread_bits(cell(p[2], min.z, max.z)) << 2)
+    });
+}
@@ -0,0 +1,87 @@
+//! Debug groups and markers naming the frame, the pass and what is drawn or
+//! dispatched, so captures in RenderDoc, Xcode or Nsight are navigable.
+//!
+//! Labels are formatted every frame, so they can be switched off. They are
+//! on by default in debug builds only.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(cfg!(debug_assertions));
+
+/// Encoders and passes, which all record debug groups and markers.
+pub trait DebugScope {
+    fn push_group(&mut self, label: &str);
+    fn pop_group(&mut self);
+    fn marker(&mut self, label: &str);
+}
+
+impl DebugScope for wgpu::CommandEncoder {
+    fn push_group(&mut self, label: &str) {
+        self.push_debug_group(label);
+    }
+
+    fn pop_group(&mut self) {
+        self.pop_debug_group();
+    }
+
+    fn marker(&mut self, label: &str) {
+        self.insert_debug_marker(label);
+    }
+}
+
+impl DebugScope for wgpu::RenderPass<'_> {
+    fn push_group(&mut self, label: &str) {
+        self.push_debug_group(label);
+    }
+
+    fn pop_group(&mut self) {
+        self.pop_debug_group();
+    }
+
+    fn marker(&mut self, label: &str) {
+        self.insert_debug_marker(label);
+    }
+}
+
+impl DebugScope for wgpu::ComputePass<'_> {
+    fn push_group(&mut self, label: &str) {
+        self.push_debug_group(label);
+    }
+
+    fn pop_group(&mut self) {
+        self.pop_debug_group();
+    }
+
+    fn marker(&mut self, label: &str) {
+        self.insert_debug_marker(label);
+    }
+}
+
+/// Only meant to be changed at startup, groups opened before a change would
+/// be left unbalanced.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Opens a group, to be closed with [`pop`] in the same scope.
+pub fn push(scope: &mut impl DebugScope, label: impl FnOnce() -> String) {
+    if enabled() {
+        scope.push_group(&label());
+    }
+}
+
+pub fn pop(scope: &mut impl DebugScope) {
+    if enabled() {
+        scope.pop_group();
+    }
+}
+
+pub fn marker(scope: &mut impl DebugScope, label: impl FnOnce() -> String) {
+    if enabled() {
+        scope.marker(&label());
+    }
+}
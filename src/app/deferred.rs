@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+use rand::Rng;
+use wgpu::util::DeviceExt;
+
+use super::{Pipeline, PipelineSelector, camera::DepthOrder, random, shader::RenderModules, texture::Texture2d};
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeferredSettings {
+    /// Point lights scattered over the instances, only the deferred path
+    /// lights with them.
+    pub point_lights: u32,
+    /// Distance every point light reaches.
+    pub point_light_radius: f32,
+}
+
+impl Default for DeferredSettings {
+    fn default() -> Self {
+        Self {
+            point_lights: 0,
+            point_light_radius: 300.0,
+        }
+    }
+}
+
+/// Mirrors `PointLight` in `default.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct PointLightUniform {
+    position: [f32; 3],
+    radius: f32,
+    /// Scaled by the intensity.
+    color: [f32; 3],
+    _padding: f32,
+}
+
+impl PointLightUniform {
+    /// `count` lights of random hues, each near one of `positions`, off its
+    /// center so it isn't buried in the instance there.
+    fn scatter(count: u32, positions: &[[f32; 4]], radius: f32) -> Vec<Self> {
+        if positions.is_empty() {
+            return Vec::new();
+        }
+        let mut rng = random::rng();
+        (0..count)
+            .map(|_| {
+                let [x, y, z, _] = positions[rng.random_range(0..positions.len())];
+                let mut offset = || rng.random_range(-0.5..0.5) * radius;
+                let position = [x + offset(), y + offset(), z + offset()];
+                let color: [f32; 3] = [rng.random(), rng.random(), rng.random()];
+                let brightest = color.into_iter().fold(f32::EPSILON, f32::max);
+                Self {
+                    position,
+                    radius,
+                    color: color.map(|c| c / brightest),
+                    _padding: 0.0,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Targets the opaque instances are rendered to, at the internal
+/// resolution without multisampling.
+struct GBuffer {
+    /// Base color, roughness in alpha.
+    albedo: Texture2d,
+    normal: Texture2d,
+    /// Kept apart from the albedo, it can be brighter than 1.
+    emissive: Texture2d,
+    depth: Texture2d,
+}
+
+impl GBuffer {
+    const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const EMISSIVE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    fn new(device: &wgpu::Device, size: (u32, u32)) -> Self {
+        Self {
+            albedo: Texture2d::create_render_target(device, size, Self::ALBEDO_FORMAT, Some("g_buffer_albedo")),
+            normal: Texture2d::create_render_target(device, size, Self::NORMAL_FORMAT, Some("g_buffer_normal")),
+            emissive: Texture2d::create_render_target(device, size, Self::EMISSIVE_FORMAT, Some("g_buffer_emissive")),
+            depth: Texture2d::create_sized_depth_texture(device, size, 1, Some("g_buffer_depth")),
+        }
+    }
+}
+
+/// Deferred shading of the opaque instances. They are rendered into a
+/// G-buffer of what lighting needs to know of them, which a single
+/// fullscreen pass then shades into the scene along with the point lights,
+/// once per pixel however many instances overlap it. The lighting pass also
+/// writes the depth of the instances, so everything still drawn forward
+/// after it, like transparent instances and groups, is hidden behind them.
+pub struct Deferred {
+    pub settings: DeferredSettings,
+    /// Shading with the G-buffer rather than forward, switchable while
+    /// running.
+    pub enabled: bool,
+    g_buffer: GBuffer,
+    layout: wgpu::BindGroupLayout,
+    light_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Opaque variants of the default pipelines, writing the G-buffer.
+    pub pipelines: HashMap<PipelineSelector, Pipeline>,
+    lighting_pipeline: Option<wgpu::RenderPipeline>,
+    depth_order: DepthOrder,
+}
+
+impl Deferred {
+    pub const FRAGMENT_ENTRY: &'static str = "fs_gbuffer";
+
+    /// `positions` are the instances the point lights are placed at.
+    pub fn new(
+        device: &wgpu::Device,
+        settings: DeferredSettings,
+        positions: &[[f32; 4]],
+        size: (u32, u32),
+        depth_order: DepthOrder,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("g_buffer"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                texture_entry(2),
+                // Depth too, loaded rather than compared
+                texture_entry(3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let mut lights = PointLightUniform::scatter(settings.point_lights, positions, settings.point_light_radius);
+        log::info!("Deferred shading with {} point lights.", lights.len());
+        if lights.is_empty() {
+            // A light reaching nowhere keeps the buffer from being empty
+            lights.push(PointLightUniform::zeroed());
+        }
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("point_lights"),
+            contents: bytemuck::cast_slice(&lights),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let g_buffer = GBuffer::new(device, size);
+        let bind_group = Self::create_bind_group(device, &layout, &light_buffer, &g_buffer);
+
+        Self {
+            settings,
+            enabled: true,
+            g_buffer,
+            layout,
+            light_buffer,
+            bind_group,
+            pipelines: HashMap::new(),
+            lighting_pipeline: None,
+            depth_order,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        light_buffer: &wgpu::Buffer,
+        g_buffer: &GBuffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("g_buffer"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&g_buffer.albedo.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&g_buffer.normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&g_buffer.emissive.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&g_buffer.depth.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: light_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Read by the lighting pass after the frame, scene and camera groups.
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    /// Color targets of the G-buffer pipelines.
+    pub fn color_targets() -> [Option<wgpu::ColorTargetState>; 3] {
+        [GBuffer::ALBEDO_FORMAT, GBuffer::NORMAL_FORMAT, GBuffer::EMISSIVE_FORMAT].map(|format| {
+            Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) {
+        self.g_buffer = GBuffer::new(device, size);
+        self.bind_group = Self::create_bind_group(device, &self.layout, &self.light_buffer, &self.g_buffer);
+    }
+
+    /// Creates the pipeline shading the G-buffer into the multisampled
+    /// scene with the `fs_lighting` entry point of `modules`.
+    pub fn create_lighting_pipeline(
+        &self,
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        modules: &RenderModules,
+    ) -> wgpu::RenderPipeline {
+        let constants = self.depth_order.override_constants();
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("lighting_pipeline_layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("lighting_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &modules.vertex,
+                entry_point: Some("vs_lighting"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &modules.fragment,
+                entry_point: Some("fs_lighting"),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Replaces the pipelines after the material changed.
+    pub fn set_pipelines(&mut self, pipelines: Vec<(PipelineSelector, wgpu::RenderPipeline)>, lighting: wgpu::RenderPipeline) {
+        self.pipelines = pipelines
+            .into_iter()
+            .map(|(selector, pipeline)| (selector, Pipeline::Render(pipeline)))
+            .collect();
+        self.lighting_pipeline = Some(lighting);
+    }
+
+    /// Render pass writing the G-buffer, cleared.
+    pub fn begin_geometry_pass<'a>(&self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        let clear = wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            store: wgpu::StoreOp::Store,
+        };
+        let g_buffer = &self.g_buffer;
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("g_buffer_pass"),
+            color_attachments: &[&g_buffer.albedo, &g_buffer.normal, &g_buffer.emissive].map(|target| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &target.view,
+                    resolve_target: None,
+                    ops: clear,
+                })
+            }),
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &g_buffer.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.depth_order.far()),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
+
+    /// Shades the G-buffer into the scene pass, which has the frame, camera
+    /// and scene groups bound.
+    pub fn draw_lighting(&self, render_pass: &mut wgpu::RenderPass) {
+        let Some(pipeline) = &self.lighting_pipeline else {
+            return;
+        };
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(3, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,270 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+use rand::Rng;
+
+use super::{culling, random, App, SimulationConstants};
+
+/// What a demo gets to set itself up with.
+pub struct DemoContext<'a> {
+    pub simulation: &'a SimulationConstants,
+    /// Instances to generate.
+    pub count: usize,
+}
+
+/// Self-contained scene sharing the renderer, the instance buffers and the
+/// window with every other demo. Demos provide the initial instance state
+/// and pick the simulation kernel that drives it.
+pub trait Demo {
+    /// Name of the kernel in `KERNELS` that simulates this demo.
+    fn kernel(&self) -> &'static str;
+
+    /// Generates the positions and velocities of the instances.
+    fn init(&mut self, context: &DemoContext) -> (Vec<[f32; 4]>, Vec<[f32; 4]>);
+
+    /// Called every tick before the simulation step.
+    fn update(&mut self, _time: f64, _delta: f64) {}
+
+    /// Records extra draws into the main render pass, after the instances.
+    fn render(&self, _render_pass: &mut wgpu::RenderPass) {}
+
+    /// Status shown in the window title.
+    fn ui(&self) -> Option<String> {
+        None
+    }
+
+    /// Box `init` scatters the instances over, when it does nothing else.
+    /// These scenes are reset on the GPU, see `reset_main` in `compute.wgsl`.
+    fn scatter(&self) -> Option<ScatterVolume> {
+        None
+    }
+}
+
+/// Box instances are scattered over uniformly, with random velocities.
+#[derive(Clone, Copy, Debug)]
+pub struct ScatterVolume {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+    /// Largest velocity along each axis.
+    pub speed: f32,
+}
+
+impl ScatterVolume {
+    /// Positions sorted spatially, so chunks of them are compact.
+    pub fn generate(&self, count: usize) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+        let mut positions = App::generate_random_vectors(count, self.min, self.max);
+        culling::sort_spatially(&mut positions, self.min, self.max);
+        let velocities = App::generate_random_vectors(
+            count,
+            Point3::new(-self.speed, -self.speed, -self.speed),
+            Point3::new(self.speed, self.speed, self.speed),
+        );
+
+        (positions, velocities)
+    }
+}
+
+pub struct DemoEntry {
+    pub name: &'static str,
+    pub create: fn() -> Box<dyn Demo>,
+}
+
+pub const DEMOS: &[DemoEntry] = &[
+    DemoEntry {
+        name: "cube-storm",
+        create: || Box::new(CubeStorm),
+    },
+    DemoEntry {
+        name: "boids",
+        create: || Box::new(Boids),
+    },
+    DemoEntry {
+        name: "galaxy",
+        create: || Box::new(Galaxy),
+    },
+    DemoEntry {
+        name: "grass",
+        create: || Box::new(Grass),
+    },
+    DemoEntry {
+        name: "fluid",
+        create: || Box::new(Fluid),
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static DemoEntry> {
+    DEMOS.iter().find(|demo| demo.name == name)
+}
+
+/// Instances spread over the world, pulled towards the origin.
+pub struct CubeStorm;
+
+impl CubeStorm {
+    const SCATTER: ScatterVolume = ScatterVolume {
+        min: Point3::new(-10000.0, -10000.0, -10000.0),
+        max: Point3::new(10000.0, 10000.0, 10000.0),
+        speed: 20.0,
+    };
+}
+
+impl Demo for CubeStorm {
+    fn kernel(&self) -> &'static str {
+        "attract"
+    }
+
+    fn init(&mut self, context: &DemoContext) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+        Self::SCATTER.generate(context.count)
+    }
+
+    fn scatter(&self) -> Option<ScatterVolume> {
+        Some(Self::SCATTER)
+    }
+}
+
+/// A flock circling the y axis, see `flock_main` in `compute.wgsl`.
+pub struct Boids;
+
+impl Boids {
+    const SCATTER: ScatterVolume = ScatterVolume {
+        min: Point3::new(-6000.0, -2000.0, -6000.0),
+        max: Point3::new(6000.0, 2000.0, 6000.0),
+        speed: 40.0,
+    };
+}
+
+impl Demo for Boids {
+    fn kernel(&self) -> &'static str {
+        "flock"
+    }
+
+    fn init(&mut self, context: &DemoContext) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+        Self::SCATTER.generate(context.count)
+    }
+
+    fn scatter(&self) -> Option<ScatterVolume> {
+        Some(Self::SCATTER)
+    }
+}
+
+/// Spiral disk on circular orbits around the attractor at the origin.
+pub struct Galaxy;
+
+impl Galaxy {
+    const ARMS: u32 = 4;
+    const INNER_RADIUS: f32 = 500.0;
+    const OUTER_RADIUS: f32 = 12000.0;
+    /// Radians the arms wind per unit of radius.
+    const TWIST: f32 = 4.0e-4;
+    const SPREAD: f32 = 0.4;
+    const THICKNESS: f32 = 300.0;
+}
+
+impl Demo for Galaxy {
+    fn kernel(&self) -> &'static str {
+        "attract"
+    }
+
+    fn init(&mut self, context: &DemoContext) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+        let mut rng = random::rng();
+        let mut positions = Vec::with_capacity(context.count);
+        for _ in 0..context.count {
+            // Squaring crowds the core
+            let radius = Self::INNER_RADIUS
+                + rng.random_range(0.0f32..1.0).powi(2) * (Self::OUTER_RADIUS - Self::INNER_RADIUS);
+            let arm = rng.random_range(0..Self::ARMS) as f32 * std::f32::consts::TAU / Self::ARMS as f32;
+            let angle = arm + radius * Self::TWIST + rng.random_range(-Self::SPREAD..Self::SPREAD);
+            let height = rng.random_range(-Self::THICKNESS..Self::THICKNESS) * (1.0 - radius / Self::OUTER_RADIUS);
+            positions.push([radius * angle.cos(), height, radius * angle.sin(), 1.0]);
+        }
+        culling::sort_spatially(
+            &mut positions,
+            Point3::new(-Self::OUTER_RADIUS, -Self::THICKNESS, -Self::OUTER_RADIUS),
+            Point3::new(Self::OUTER_RADIUS, Self::THICKNESS, Self::OUTER_RADIUS),
+        );
+
+        // Orbital speed balances the pull of `force` in `compute.wgsl`
+        let velocities = positions
+            .iter()
+            .map(|p| {
+                let position = Vector3::new(p[0], p[1], p[2]);
+                let tangent = Vector3::new(-p[2], 0.0, p[0]).normalize();
+                let v = tangent * (context.simulation.params.gravity / position.magnitude()).sqrt();
+                [v.x, v.y, v.z, 1.0]
+            })
+            .collect();
+
+        (positions, velocities)
+    }
+}
+
+/// Field of blades on the ground plane, rippled by a travelling wave.
+pub struct Grass;
+
+impl Grass {
+    const SPACING: f32 = 8.0;
+    const WAVE_HEIGHT: f32 = 6.0;
+    const WAVE_LENGTH: f32 = 400.0;
+}
+
+impl Demo for Grass {
+    fn kernel(&self) -> &'static str {
+        "wave"
+    }
+
+    fn init(&mut self, context: &DemoContext) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+        let side = (context.count as f64).sqrt().ceil().max(1.0) as usize;
+        let offset = side as f32 * Self::SPACING / 2.0;
+        // Angular frequency of the blades swinging under `wave_stiffness`
+        let frequency = context.simulation.params.wave_stiffness.sqrt();
+
+        // Rows in order are spatially compact already
+        let (positions, velocities) = (0..context.count)
+            .map(|i| {
+                let x = (i % side) as f32 * Self::SPACING - offset;
+                let z = (i / side) as f32 * Self::SPACING - offset;
+                let phase = (x + z) * std::f32::consts::TAU / Self::WAVE_LENGTH;
+                (
+                    [x, Self::WAVE_HEIGHT * phase.cos(), z, 1.0],
+                    [0.0, Self::WAVE_HEIGHT * frequency * phase.sin(), 0.0, 1.0],
+                )
+            })
+            .unzip();
+
+        (positions, velocities)
+    }
+}
+
+/// Column of fluid collapsing onto the ground plane, see `sph_force_main`
+/// in `compute.wgsl`.
+pub struct Fluid;
+
+impl Fluid {
+    /// About the spacing of fluid at rest with the default parameters.
+    const SPACING: f32 = 8.0;
+    /// Breaks up the lattice, which would otherwise collapse symmetrically.
+    const JITTER: f32 = 0.5;
+}
+
+impl Demo for Fluid {
+    fn kernel(&self) -> &'static str {
+        "sph"
+    }
+
+    fn init(&mut self, context: &DemoContext) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+        let mut rng = random::rng();
+        // Twice as tall as wide
+        let side = ((context.count as f64 / 2.0).cbrt().ceil() as usize).max(1);
+        let offset = side as f32 * Self::SPACING / 2.0;
+
+        // Layer by layer, rows in order are spatially compact already
+        let (positions, velocities) = (0..context.count)
+            .map(|i| {
+                let mut jitter = || rng.random_range(-Self::JITTER..Self::JITTER);
+                let x = (i % side) as f32 * Self::SPACING - offset + jitter();
+                let z = (i / side % side) as f32 * Self::SPACING - offset + jitter();
+                let y = (i / (side * side)) as f32 * Self::SPACING + Self::SPACING / 2.0 + jitter();
+                ([x, y, z, 1.0], [0.0, 0.0, 0.0, 1.0])
+            })
+            .unzip();
+
+        (positions, velocities)
+    }
+}
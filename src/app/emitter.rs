@@ -0,0 +1,89 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::Point3;
+
+/// Particle emitter options, disabled while `position` is `None`.
+#[derive(Debug, Clone)]
+pub struct EmitterSettings {
+    /// Where expired instances come back to life.
+    pub position: Option<Point3<f32>>,
+    /// Instances respawned per second, at most.
+    pub rate: f32,
+    /// Speed instances leave the emitter at, in random directions.
+    pub speed: f32,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        Self {
+            position: None,
+            rate: 2000.0,
+            speed: 50.0,
+        }
+    }
+}
+
+/// Respawns expired instances at a point, turning the instances into a
+/// particle system. The kernels count the lifetimes down, `respawn_main` in
+/// `compute.wgsl` brings instances back with fresh ones after every step, as
+/// many as the emitter's rate allows.
+pub struct Emitter {
+    pub settings: EmitterSettings,
+    /// Fraction of an instance left over from the steps so far.
+    carry: f32,
+    /// Instances the steps of the current frame may respawn in total.
+    limit: u32,
+}
+
+impl Emitter {
+    pub fn new(settings: &EmitterSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+            carry: 0.0,
+            limit: 0,
+        }
+    }
+
+    /// Starts a frame, the GPU counter of respawned instances is cleared
+    /// along with it.
+    pub fn begin_frame(&mut self) {
+        self.limit = 0;
+    }
+
+    /// Adds the instances a step of `delta` seconds may respawn to the limit
+    /// of the frame. Whatever a step doesn't use is left to the later steps
+    /// of the same frame, but not carried over to the next one.
+    pub fn step(&mut self, delta: f32) {
+        self.carry += self.settings.rate.max(0.0) * delta;
+        let whole = self.carry.floor();
+        self.carry -= whole;
+        self.limit = self.limit.saturating_add(whole as u32);
+    }
+
+    /// Settings of the next step, with the longest lifetime respawned
+    /// instances get and a seed of their random directions and lifetimes.
+    pub fn uniform(&self, lifetime: f32, seed: u32) -> EmitterUniform {
+        let Some(position) = self.settings.position else {
+            return EmitterUniform::default();
+        };
+        EmitterUniform {
+            position: position.into(),
+            speed: self.settings.speed,
+            lifetime,
+            limit: self.limit,
+            seed,
+            _padding: 0,
+        }
+    }
+}
+
+/// Mirrors `EmitterParams` in `compute.wgsl`. A `limit` of 0 respawns nothing.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+pub struct EmitterUniform {
+    position: [f32; 3],
+    speed: f32,
+    lifetime: f32,
+    limit: u32,
+    seed: u32,
+    _padding: u32,
+}
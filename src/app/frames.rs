@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use bytemuck::Pod;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReadbackState {
+    Idle,
+    /// A copy into the buffer was recorded but not submitted yet.
+    Recorded,
+    Mapping,
+}
+
+/// Staging buffer a slot's results are copied into for the CPU to read.
+struct Readback {
+    buffer: wgpu::Buffer,
+    state: ReadbackState,
+    /// Frame the copy was recorded in, the latest result wins.
+    frame: u64,
+    /// Set by the map callback once the buffer can be read.
+    mapped: Arc<AtomicBool>,
+    /// Set by the map callback if mapping failed.
+    failed: Arc<AtomicBool>,
+}
+
+impl Readback {
+    fn new(device: &wgpu::Device, label: &'static str, size: u64) -> Self {
+        Self {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            state: ReadbackState::Idle,
+            frame: 0,
+            mapped: Arc::new(AtomicBool::new(false)),
+            failed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Makes a buffer whose mapping failed available again, the failure was
+    /// logged by the callback.
+    fn recover(&mut self) {
+        if self.failed.swap(false, Ordering::Acquire) {
+            self.state = ReadbackState::Idle;
+        }
+    }
+}
+
+struct FrameSlot {
+    submission: Option<wgpu::SubmissionIndex>,
+    /// Number of submissions made for this slot.
+    submitted: u64,
+    /// Raised from `on_submitted_work_done` as the slot's submissions finish.
+    completed: Arc<AtomicU64>,
+    /// Keyed by label, created on first use.
+    readbacks: HashMap<&'static str, Readback>,
+}
+
+impl FrameSlot {
+    fn is_done(&self) -> bool {
+        self.completed.load(Ordering::Acquire) >= self.submitted
+    }
+}
+
+/// Ring of per-frame slots. Each slot owns a region of the per-frame uniform
+/// buffers and its own readback buffers, and a slot is only reused once the
+/// GPU finished the frame that last used it, so CPU writes never race with
+/// GPU reads.
+///
+/// The ring length bounds how far the CPU may run ahead of the GPU.
+pub struct FrameRing {
+    slots: Vec<FrameSlot>,
+    current: usize,
+    /// Distance between slot regions in the uniform buffers.
+    stride: u64,
+    /// Number of times the ring advanced.
+    frame: u64,
+}
+
+impl FrameRing {
+    pub const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
+    pub const MAX_FRAMES_IN_FLIGHT: u32 = 3;
+
+    /// `uniform_size` is the size of one slot's uniform data, padded here to
+    /// the device's dynamic offset alignment.
+    pub fn new(device: &wgpu::Device, frames_in_flight: u32, uniform_size: u64) -> Self {
+        let frames_in_flight = frames_in_flight.clamp(1, Self::MAX_FRAMES_IN_FLIGHT);
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+
+        Self {
+            slots: (0..frames_in_flight)
+                .map(|_| FrameSlot {
+                    submission: None,
+                    submitted: 0,
+                    completed: Arc::new(AtomicU64::new(0)),
+                    readbacks: HashMap::new(),
+                })
+                .collect(),
+            current: 0,
+            stride: uniform_size.next_multiple_of(alignment),
+            frame: 0,
+        }
+    }
+
+    pub fn frames_in_flight(&self) -> u32 {
+        self.slots.len() as u32
+    }
+
+    /// Size of a uniform buffer holding one region per slot.
+    pub fn buffer_size(&self) -> u64 {
+        self.stride * self.slots.len() as u64
+    }
+
+    /// Dynamic offset of the current slot's uniform region.
+    pub fn uniform_offset(&self) -> u32 {
+        (self.stride * self.current as u64) as u32
+    }
+
+    /// Index of the current slot.
+    pub fn slot(&self) -> usize {
+        self.current
+    }
+
+    /// Dynamic offsets of the uniform regions of every slot, in order.
+    pub fn uniform_offsets(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.slots.len() as u64).map(|slot| (self.stride * slot) as u32)
+    }
+
+    /// Moves to the next slot, blocking until the GPU is done with the frame
+    /// that used it last.
+    pub fn advance(&mut self, device: &wgpu::Device) {
+        self.current = (self.current + 1) % self.slots.len();
+        self.frame += 1;
+
+        let slot = &mut self.slots[self.current];
+        if let Some(submission) = slot.submission.take().filter(|_| !slot.is_done()) {
+            device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+        }
+    }
+
+    /// Blocks until the GPU finished everything submitted so far.
+    pub fn wait_for_last(&self, device: &wgpu::Device) {
+        if let Some(submission) = &self.slots[self.current].submission {
+            device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission.clone()));
+        }
+    }
+
+    /// Records work submitted for the current slot. Later submissions of the
+    /// same frame replace earlier ones, since the queue completes them in order.
+    pub fn submitted(&mut self, queue: &wgpu::Queue, submission: wgpu::SubmissionIndex) {
+        let slot = &mut self.slots[self.current];
+        slot.submission = Some(submission);
+        slot.submitted += 1;
+
+        let generation = slot.submitted;
+        let completed = slot.completed.clone();
+        queue.on_submitted_work_done(move || {
+            completed.fetch_max(generation, Ordering::AcqRel);
+        });
+    }
+
+    /// Staging buffer of `size` bytes in the current slot to copy the results
+    /// named `label` into, with [`Self::map_readbacks`] following their
+    /// submission. Returns `None` while the slot's previous results of the
+    /// same name haven't been taken yet.
+    pub fn readback(&mut self, device: &wgpu::Device, label: &'static str, size: u64) -> Option<&wgpu::Buffer> {
+        let frame = self.frame;
+        let readback = self.slots[self.current]
+            .readbacks
+            .entry(label)
+            .or_insert_with(|| Readback::new(device, label, size));
+        readback.recover();
+        if readback.state != ReadbackState::Idle {
+            return None;
+        }
+        if readback.buffer.size() != size {
+            *readback = Readback::new(device, label, size);
+        }
+
+        readback.state = ReadbackState::Recorded;
+        readback.frame = frame;
+        Some(&readback.buffer)
+    }
+
+    /// Starts mapping every recorded readback once their copies were submitted.
+    pub fn map_readbacks(&mut self) {
+        for (label, readback) in self.slots.iter_mut().flat_map(|slot| &mut slot.readbacks) {
+            if readback.state != ReadbackState::Recorded {
+                continue;
+            }
+
+            readback.state = ReadbackState::Mapping;
+            let label = *label;
+            let mapped = readback.mapped.clone();
+            let failed = readback.failed.clone();
+            readback
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| match result {
+                    Ok(()) => mapped.store(true, Ordering::Release),
+                    Err(error) => {
+                        log::error!("Failed to map the {label} readback: {error}");
+                        failed.store(true, Ordering::Release);
+                    }
+                });
+        }
+    }
+
+    /// Returns the latest mapped results named `label` of any slot and
+    /// releases older ones. Mapping completes during device polls, so call
+    /// this after polling.
+    pub fn take_readback<T: Pod>(&mut self, label: &str) -> Option<T> {
+        let mut latest: Option<(u64, T)> = None;
+        for readback in self.slots.iter_mut().filter_map(|slot| slot.readbacks.get_mut(label)) {
+            readback.recover();
+            if !readback.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+
+            let value = {
+                let data = readback.buffer.slice(..).get_mapped_range();
+                bytemuck::pod_read_unaligned(&data[..std::mem::size_of::<T>()])
+            };
+            readback.buffer.unmap();
+            readback.state = ReadbackState::Idle;
+            if latest.is_none_or(|(frame, _)| readback.frame > frame) {
+                latest = Some((readback.frame, value));
+            }
+        }
+        latest.map(|(_, value)| value)
+    }
+}
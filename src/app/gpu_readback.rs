@@ -0,0 +1,127 @@
+use std::{
+    fmt::Display,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use bytemuck::Pod;
+
+#[derive(Debug, Clone)]
+pub struct ReadbackError {
+    pub message: String,
+}
+
+impl ReadbackError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Display for ReadbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ReadbackError {}
+pub type ReadbackResult<T> = Result<T, ReadbackError>;
+
+/// Shared with the map callback.
+#[derive(Default)]
+struct MapState {
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<Waker>,
+}
+
+/// Contents of GPU buffers copied into a staging buffer on the CPU. Await
+/// it to get the elements: it resolves once a device poll sees the copy
+/// finished, which the app does every frame.
+pub struct Readback<T> {
+    buffer: wgpu::Buffer,
+    size: u64,
+    state: Arc<Mutex<MapState>>,
+    element: PhantomData<fn() -> T>,
+}
+
+impl<T: Pod> Readback<T> {
+    /// Submits copies of the first bytes of each source, with their sizes,
+    /// back to back into one staging buffer and starts mapping it.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, sources: &[(&wgpu::Buffer, u64)]) -> Self {
+        let size: u64 = sources.iter().map(|(_, size)| size).sum();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback_staging"),
+            size: size.max(wgpu::COPY_BUFFER_ALIGNMENT),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let state = Arc::new(Mutex::new(MapState::default()));
+        if size == 0 {
+            state.lock().unwrap().result = Some(Ok(()));
+            return Self {
+                buffer,
+                size,
+                state,
+                element: PhantomData,
+            };
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("readback"),
+        });
+        let mut offset = 0;
+        for (source, size) in sources {
+            encoder.copy_buffer_to_buffer(source, 0, &buffer, offset, *size);
+            offset += size;
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let callback_state = state.clone();
+        buffer.slice(..size).map_async(wgpu::MapMode::Read, move |result| {
+            let mut state = callback_state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+
+        Self {
+            buffer,
+            size,
+            state,
+            element: PhantomData,
+        }
+    }
+
+    fn finish(&self, result: Result<(), wgpu::BufferAsyncError>) -> ReadbackResult<Vec<T>> {
+        result.map_err(|error| ReadbackError::new(format!("Failed to map the readback buffer: {error}")))?;
+        if self.size == 0 {
+            return Ok(Vec::new());
+        }
+
+        let elements = bytemuck::pod_collect_to_vec(&self.buffer.slice(..self.size).get_mapped_range());
+        self.buffer.unmap();
+        Ok(elements)
+    }
+}
+
+impl<T: Pod> Future for Readback<T> {
+    type Output = ReadbackResult<Vec<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = {
+            let mut state = self.state.lock().unwrap();
+            let result = state.result.take();
+            if result.is_none() {
+                state.waker = Some(cx.waker().clone());
+            }
+            result
+        };
+        match result {
+            Some(result) => Poll::Ready(self.finish(result)),
+            None => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::{collections::HashMap, fmt::{Debug, Display}};
+
+use super::texture::Texture2d;
+
+#[derive(Debug, Clone)]
+pub struct RenderGraphError {
+    pub message: String,
+}
+
+impl RenderGraphError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Display for RenderGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RenderGraphError {}
+type RenderGraphResult<T> = Result<T, RenderGraphError>;
+
+struct TransientTexture {
+    format: wgpu::TextureFormat,
+    /// Created by [`RenderGraph::compile`].
+    texture: Option<Texture2d>,
+}
+
+struct PassNode<P> {
+    pass: P,
+    reads: Vec<&'static str>,
+    writes: Vec<&'static str>,
+}
+
+/// Schedules the passes of a frame by the resources they read and write,
+/// and owns the transient textures they hand to each other.
+///
+/// Resources are named. The ones declared with [`Self::add_texture`] are
+/// created by the graph and recreated on resize, any other name stands for
+/// something owned elsewhere, like the surface or a buffer. Passes only
+/// writing a resource run first, then the ones drawing onto what's there,
+/// each in the order they were added, and its readers after all of them. The graph only orders passes, recording them is up to the caller
+/// walking [`Self::order`].
+pub struct RenderGraph<P> {
+    passes: Vec<PassNode<P>>,
+    textures: HashMap<&'static str, TransientTexture>,
+    order: Vec<P>,
+}
+
+impl<P: Copy + Debug + PartialEq> RenderGraph<P> {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+            textures: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    /// Declares a texture the graph creates at the internal resolution the
+    /// scene is rendered at, for passes to render to and sample.
+    pub fn add_texture(&mut self, name: &'static str, format: wgpu::TextureFormat) {
+        self.textures.insert(name, TransientTexture { format, texture: None });
+    }
+
+    /// A pass reading and writing the same resource, like an effect drawing
+    /// back onto the scene, lists it in both.
+    pub fn add_pass(&mut self, pass: P, reads: &[&'static str], writes: &[&'static str]) {
+        self.passes.push(PassNode {
+            pass,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+        });
+    }
+
+    /// Orders the passes and creates the transient textures. Fails when a
+    /// transient texture is read but never written, or the passes depend on
+    /// each other in a cycle.
+    pub fn compile(&mut self, device: &wgpu::Device, size: (u32, u32)) -> RenderGraphResult<()> {
+        let passes = &self.passes;
+        let writers = |name: &'static str| {
+            let mut writers: Vec<_> = (0..passes.len()).filter(|&index| passes[index].writes.contains(&name)).collect();
+            writers.sort_by_key(|&index| passes[index].reads.contains(&name));
+            writers
+        };
+
+        // Edges from every pass to the ones that have to wait for it
+        let mut dependents = vec![Vec::new(); self.passes.len()];
+        for (index, node) in self.passes.iter().enumerate() {
+            for &name in &node.reads {
+                if node.writes.contains(&name) {
+                    continue;
+                }
+                if self.textures.contains_key(name) && writers(name).is_empty() {
+                    return Err(RenderGraphError::new(format!(
+                        "{:?} reads {name}, which no pass writes",
+                        node.pass
+                    )));
+                }
+                for writer in writers(name) {
+                    dependents[writer].push(index);
+                }
+            }
+            for &name in &node.writes {
+                let writers = writers(name);
+                let position = writers.iter().position(|&writer| writer == index);
+                if let Some(&next) = position.and_then(|position| writers.get(position + 1)) {
+                    dependents[index].push(next);
+                }
+            }
+        }
+
+        // Kahn's algorithm, always taking the earliest added pass that's
+        // ready so independent passes keep their order
+        let mut waiting_on = vec![0; self.passes.len()];
+        for &dependent in dependents.iter().flatten() {
+            waiting_on[dependent] += 1;
+        }
+        let mut scheduled = vec![false; self.passes.len()];
+        let mut order = Vec::with_capacity(self.passes.len());
+        while let Some(index) = (0..self.passes.len()).find(|&index| !scheduled[index] && waiting_on[index] == 0) {
+            scheduled[index] = true;
+            order.push(self.passes[index].pass);
+            for &dependent in &dependents[index] {
+                waiting_on[dependent] -= 1;
+            }
+        }
+        if order.len() < self.passes.len() {
+            let cycle: Vec<_> = self
+                .passes
+                .iter()
+                .zip(&scheduled)
+                .filter(|(_, done)| !**done)
+                .map(|(node, _)| node.pass)
+                .collect();
+            return Err(RenderGraphError::new(format!("Passes {cycle:?} depend on each other")));
+        }
+
+        log::info!("Render graph order: {order:?}.");
+        self.order = order;
+        self.resize(device, size);
+        Ok(())
+    }
+
+    /// Recreates the transient textures, whatever binds them has to be
+    /// recreated after.
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32)) {
+        for (name, transient) in &mut self.textures {
+            transient.texture = Some(Texture2d::create_render_target(device, size, transient.format, Some(name)));
+        }
+    }
+
+    /// Passes in the order to record them in, empty until compiled.
+    pub fn order(&self) -> &[P] {
+        &self.order
+    }
+
+    /// Resources `pass` writes, in the order it was added with.
+    pub fn writes(&self, pass: P) -> &[&'static str] {
+        self.passes
+            .iter()
+            .find(|node| node.pass == pass)
+            .map_or(&[], |node| &node.writes)
+    }
+
+    /// View of a transient texture, or `imported` for anything owned
+    /// elsewhere.
+    pub fn view<'a>(&'a self, name: &str, imported: &'a wgpu::TextureView) -> &'a wgpu::TextureView {
+        self.textures
+            .get(name)
+            .and_then(|transient| transient.texture.as_ref())
+            .map_or(imported, |texture| &texture.view)
+    }
+
+    /// Panics for a texture that wasn't declared, or before compiling.
+    pub fn texture(&self, name: &str) -> &Texture2d {
+        self.textures
+            .get(name)
+            .and_then(|transient| transient.texture.as_ref())
+            .unwrap_or_else(|| panic!("Render graph texture {name} wasn't created"))
+    }
+}
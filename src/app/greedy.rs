@@ -0,0 +1,267 @@
+use std::collections::{HashMap, HashSet};
+
+use bytemuck::{Pod, Zeroable};
+
+use super::{instances::InstanceColor, mesh::{Mesh, Vertex, vertex_attributes}};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct GreedyVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl GreedyVertex {
+    const ATTRIBS: &'static [wgpu::VertexAttribute] = &vertex_attributes!(Self, 0 => {
+        position: Float32x3,
+        normal: Float32x3,
+        color: Float32x3,
+    });
+}
+
+impl Vertex for GreedyVertex {
+    fn attribs() -> &'static [wgpu::VertexAttribute] {
+        Self::ATTRIBS
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    Instanced,
+    GreedyMesh,
+}
+
+/// Colors of the instances and how their `w` components are interpreted
+/// when voxelizing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VoxelSource<'a> {
+    /// One per instance, otherwise they get the base color.
+    pub colors: Option<&'a [InstanceColor]>,
+    /// `w` holds a run length along x.
+    pub runs: bool,
+}
+
+/// Instances snapped to a unit grid and turned into greedy-meshed chunk
+/// geometry: hidden faces between neighbors are dropped and coplanar faces
+/// of the same color are merged into rectangles, then drawn without
+/// instancing. Meant for comparison with the per-cube instancing path.
+pub struct GreedyMesh {
+    batches: Vec<Mesh>,
+    pub voxels: usize,
+    pub triangles: usize,
+}
+
+/// Dense occupancy of one chunk, 0 for empty cells, otherwise an RGBA8 color.
+type ChunkCells = Vec<u32>;
+
+impl GreedyMesh {
+    const CHUNK_SIZE: i32 = 32;
+    /// Vertices per buffer, so huge inputs stay below `max_buffer_size`.
+    const MAX_BATCH_VERTICES: usize = 1 << 20;
+    /// Sparse scenes touch a chunk per instance, and every chunk is stored
+    /// and swept in full, so meshing them would take gigabytes and minutes.
+    const MAX_CHUNKS: usize = 16384;
+    /// Matches `BASE_COLOR` in the default shader.
+    const BASE_COLOR: [u8; 4] = [128, 26, 128, 255];
+
+    /// Returns `None` when the instances are too sparse to mesh in reasonable time.
+    pub fn build(device: &wgpu::Device, points: &[[f32; 4]], source: VoxelSource) -> Option<Self> {
+        // Cells covered by an instance, a whole run of them with voxel runs
+        let covered = |p: &[f32; 4]| {
+            let len = if source.runs { (p[3] as u32).max(1) } else { 1 };
+            let first_x = (p[0] - (len - 1) as f32 * 0.5).round() as i32;
+            let (y, z) = (p[1].round() as i32, p[2].round() as i32);
+            (first_x..first_x + len as i32).map(move |x| [x, y, z])
+        };
+
+        // Counted before any chunk is allocated
+        let mut occupied = HashSet::new();
+        for cell in points.iter().flat_map(covered) {
+            occupied.insert(Self::locate(cell).0);
+            if occupied.len() > Self::MAX_CHUNKS {
+                log::warn!("Instances span more than {} chunks, too sparse to greedy mesh.", Self::MAX_CHUNKS);
+                return None;
+            }
+        }
+
+        let mut chunks: HashMap<[i32; 3], ChunkCells> = HashMap::with_capacity(occupied.len());
+        let mut voxels = 0;
+        for (i, p) in points.iter().enumerate() {
+            let color = match source.colors {
+                Some(colors) => u32::from_le_bytes(colors[i].color) | 0xff00_0000,
+                None => u32::from_le_bytes(Self::BASE_COLOR),
+            };
+            for cell in covered(p) {
+                let (chunk, index) = Self::locate(cell);
+                let cells = chunks
+                    .entry(chunk)
+                    .or_insert_with(|| vec![0; Self::CHUNK_SIZE.pow(3) as usize]);
+                if cells[index] == 0 {
+                    voxels += 1;
+                }
+                cells[index] = color;
+            }
+        }
+
+        let cell = |cell: [i32; 3]| {
+            let (chunk, index) = Self::locate(cell);
+            chunks.get(&chunk).map_or(0, |cells| cells[index])
+        };
+
+        let mut batches = Vec::new();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut triangles = 0;
+        for (&chunk, cells) in &chunks {
+            Self::mesh_chunk(chunk, cells, &cell, &mut vertices, &mut indices);
+
+            if vertices.len() >= Self::MAX_BATCH_VERTICES {
+                triangles += indices.len() / 3;
+                batches.push(Mesh::create(device, &vertices, &indices));
+                vertices.clear();
+                indices.clear();
+            }
+        }
+        if !vertices.is_empty() {
+            triangles += indices.len() / 3;
+            batches.push(Mesh::create(device, &vertices, &indices));
+        }
+
+        log::info!(
+            "Greedy meshed {voxels} voxels in {} chunks into {triangles} triangles.",
+            chunks.len()
+        );
+
+        Some(Self {
+            batches,
+            voxels,
+            triangles,
+        })
+    }
+
+    fn locate(cell: [i32; 3]) -> ([i32; 3], usize) {
+        let chunk = cell.map(|c| c.div_euclid(Self::CHUNK_SIZE));
+        let [x, y, z] = cell.map(|c| c.rem_euclid(Self::CHUNK_SIZE) as usize);
+        let size = Self::CHUNK_SIZE as usize;
+
+        (chunk, x + y * size + z * size * size)
+    }
+
+    /// Sweeps the chunk along each axis and direction, merging the exposed
+    /// faces of every slice into maximal rectangles.
+    fn mesh_chunk(
+        chunk: [i32; 3],
+        cells: &ChunkCells,
+        cell: &impl Fn([i32; 3]) -> u32,
+        vertices: &mut Vec<GreedyVertex>,
+        indices: &mut Vec<u32>,
+    ) {
+        let size = Self::CHUNK_SIZE;
+        let origin = chunk.map(|c| c * size);
+        let mut mask = vec![0u32; (size * size) as usize];
+        // Only neighbors across the chunk border go through the chunk map
+        let cell = |position: [i32; 3]| {
+            let [x, y, z] = [0, 1, 2].map(|a| position[a] - origin[a]);
+            if [x, y, z].iter().all(|c| (0..size).contains(c)) {
+                cells[(x + y * size + z * size * size) as usize]
+            } else {
+                cell(position)
+            }
+        };
+
+        for d in 0..3 {
+            let (u, v) = ((d + 1) % 3, (d + 2) % 3);
+
+            for direction in [-1, 1] {
+                for slice in 0..size {
+                    // Faces of cells in this slice whose neighbor along the direction is empty
+                    for j in 0..size {
+                        for i in 0..size {
+                            let mut position = origin;
+                            position[d] += slice;
+                            position[u] += i;
+                            position[v] += j;
+                            let color = cell(position);
+
+                            position[d] += direction;
+                            let exposed = color != 0 && cell(position) == 0;
+                            mask[(i + j * size) as usize] = if exposed { color } else { 0 };
+                        }
+                    }
+
+                    for j in 0..size {
+                        let mut i = 0;
+                        while i < size {
+                            let color = mask[(i + j * size) as usize];
+                            if color == 0 {
+                                i += 1;
+                                continue;
+                            }
+
+                            let at = |i: i32, j: i32| mask[(i + j * size) as usize];
+                            let width = (i..size).take_while(|&k| at(k, j) == color).count() as i32;
+                            let height = (j..size)
+                                .take_while(|&l| (i..i + width).all(|k| at(k, l) == color))
+                                .count() as i32;
+
+                            for l in j..j + height {
+                                for k in i..i + width {
+                                    mask[(k + l * size) as usize] = 0;
+                                }
+                            }
+
+                            let mut corner = origin.map(|c| c as f32 - 0.5);
+                            corner[d] += slice as f32 + if direction > 0 { 1.0 } else { 0.0 };
+                            corner[u] += i as f32;
+                            corner[v] += j as f32;
+                            Self::push_quad(vertices, indices, corner, [d, u, v], [width, height], direction, color);
+
+                            i += width;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn push_quad(
+        vertices: &mut Vec<GreedyVertex>,
+        indices: &mut Vec<u32>,
+        corner: [f32; 3],
+        [d, u, v]: [usize; 3],
+        [width, height]: [i32; 2],
+        direction: i32,
+        color: u32,
+    ) {
+        let mut normal = [0.0; 3];
+        normal[d] = direction as f32;
+        let [r, g, b, _] = color.to_le_bytes();
+        let color = [r, g, b].map(|c| c as f32 / 255.0);
+
+        let base = vertices.len() as u32;
+        for (du, dv) in [(0, 0), (width, 0), (width, height), (0, height)] {
+            let mut position = corner;
+            position[u] += du as f32;
+            position[v] += dv as f32;
+            vertices.push(GreedyVertex {
+                position,
+                normal,
+                color,
+            });
+        }
+
+        // u x v points along +d, so flip the winding for faces pointing the other way
+        if direction > 0 {
+            indices.extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+        } else {
+            indices.extend([base, base + 2, base + 1, base, base + 3, base + 2]);
+        }
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        for batch in &self.batches {
+            batch.draw(render_pass);
+        }
+    }
+}
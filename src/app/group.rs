@@ -0,0 +1,173 @@
+use std::ops::Range;
+
+use cgmath::{Point3, Vector3};
+
+use super::{
+    App, PipelineSelector,
+    camera::Frustum,
+    culling::{self, ChunkCuller},
+    instances::{InstanceBuffer, InstanceColor, InstanceColoring, InstanceTransform, TransformSettings},
+    mesh::Mesh,
+};
+
+/// Mesh every instance of a group is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupShape {
+    Cube,
+    Sphere,
+    /// Square in the xy plane, drawn from both sides.
+    Quad,
+}
+
+impl GroupShape {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cube" => Some(Self::Cube),
+            "sphere" => Some(Self::Sphere),
+            "quad" => Some(Self::Quad),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cube => "cube",
+            Self::Sphere => "sphere",
+            Self::Quad => "quad",
+        }
+    }
+
+    fn mesh(&self, device: &wgpu::Device) -> Mesh {
+        match self {
+            Self::Cube => Mesh::cube(device),
+            Self::Sphere => Mesh::sphere(device, 0.5, 8, 16),
+            Self::Quad => Mesh::quad(device),
+        }
+    }
+
+    fn selector(&self) -> PipelineSelector {
+        match self {
+            Self::Quad => PipelineSelector::Custom { name: "double_sided" },
+            _ => PipelineSelector::Default,
+        }
+    }
+}
+
+/// Instances of one shape scattered around a point.
+#[derive(Debug, Clone)]
+pub struct GroupSettings {
+    pub shape: GroupShape,
+    pub count: u32,
+    pub center: Point3<f32>,
+    /// Half the side of the cube the instances are scattered in.
+    pub spread: f32,
+    /// Below 1 the group is translucent, blended order independently.
+    pub alpha: f32,
+}
+
+/// Static instances drawn with their own mesh and pipeline next to the
+/// simulated ones, using the same per-instance vertex streams.
+pub struct InstanceGroup {
+    pub shape: GroupShape,
+    pub mesh: Mesh,
+    pub selector: PipelineSelector,
+    /// Drawn with weighted blended transparency after the scene, see
+    /// [`super::oit::WeightedBlended`].
+    pub translucent: bool,
+    /// Bounding sphere of every instance, extent included.
+    center: Point3<f32>,
+    radius: f32,
+    /// Coarse chunks of the instances, culled when the group is only partly
+    /// in view.
+    culler: ChunkCuller,
+    /// Instances left to draw by the last [`Self::cull`].
+    visible: Vec<Range<u32>>,
+    positions: InstanceBuffer<[f32; 4]>,
+    transforms: InstanceBuffer<InstanceTransform>,
+    colors: InstanceBuffer<InstanceColor>,
+}
+
+impl InstanceGroup {
+    /// Instances per culling chunk, fewer than the simulation's since groups
+    /// are smaller.
+    pub const CHUNK_SIZE: usize = 512;
+
+    pub fn new(
+        device: &wgpu::Device,
+        settings: &GroupSettings,
+        transform_settings: &TransformSettings,
+        coloring: InstanceColoring,
+    ) -> Self {
+        let count = settings.count as usize;
+        let spread = Vector3::new(settings.spread, settings.spread, settings.spread);
+        let (min, max) = (settings.center - spread, settings.center + spread);
+        let mut positions = App::generate_random_vectors(count, min, max);
+        // Scattered at random anyway, sorted so the chunks are compact
+        culling::sort_spatially(&mut positions, min, max);
+        let extent = transform_settings.max_extent();
+        let all_instances = 0..count as u32;
+        let transforms = InstanceTransform::generate(count, transform_settings);
+        // Static, so speed coloring has nothing to go by
+        let colors = InstanceColor::generate(&vec![[0.0; 4]; count], coloring, settings.alpha);
+
+        Self {
+            shape: settings.shape,
+            mesh: settings.shape.mesh(device),
+            selector: settings.shape.selector(),
+            translucent: settings.alpha < 1.0,
+            center: settings.center,
+            radius: settings.spread * 3.0f32.sqrt() + extent,
+            culler: ChunkCuller::build(&positions, Self::CHUNK_SIZE, extent),
+            visible: vec![all_instances],
+            positions: InstanceBuffer::new(device, "group_positions", wgpu::BufferUsages::VERTEX, &positions),
+            transforms: InstanceBuffer::new(device, "group_transforms", wgpu::BufferUsages::VERTEX, &transforms),
+            colors: InstanceBuffer::new(device, "group_colors", wgpu::BufferUsages::VERTEX, &colors),
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.positions.len()
+    }
+
+    /// Instances left to draw by the last [`Self::cull`].
+    pub fn visible_count(&self) -> u32 {
+        self.visible.iter().map(|range| range.len() as u32).sum()
+    }
+
+    /// Whether the last [`Self::cull`] left every instance, so the group
+    /// can be drawn whole.
+    pub fn is_whole(&self) -> bool {
+        self.visible.len() == 1 && self.visible[0] == (0..self.count())
+    }
+
+    /// Culls the group against `frustum` by its bounding sphere, then by
+    /// chunk when it's only partly in view. Without a frustum every
+    /// instance is left to draw.
+    pub fn cull(&mut self, frustum: Option<&Frustum>) {
+        self.visible.clear();
+        match frustum {
+            Some(frustum) if !frustum.intersects_sphere(self.center, self.radius) => {}
+            Some(frustum) => self.visible.extend_from_slice(self.culler.cull(frustum)),
+            None => self.visible.push(0..self.count()),
+        }
+    }
+
+    /// Draws the instances left by the last [`Self::cull`], with the
+    /// group's pipeline already set.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_vertex_buffer(2, self.transforms.slice());
+        render_pass.set_vertex_buffer(3, self.colors.slice());
+        for range in &self.visible {
+            self.mesh.draw_instanced(render_pass, self.positions.buffer(), range.clone());
+        }
+    }
+
+    /// Records the draw of every instance into a render bundle, with the
+    /// group's pipeline already set. Nothing changes between frames, so the
+    /// bundle can be replayed for as long as the pipeline lives.
+    pub fn bundle<'a>(&'a self, encoder: &mut wgpu::RenderBundleEncoder<'a>) {
+        encoder.set_vertex_buffer(2, self.transforms.slice());
+        encoder.set_vertex_buffer(3, self.colors.slice());
+        self.mesh.bundle_instanced(encoder, self.positions.buffer(), 0..self.count());
+    }
+}
@@ -0,0 +1,272 @@
+use bytemuck::{Pod, Zeroable};
+use rand::Rng;
+use wgpu::util::DeviceExt;
+
+use super::{debug_labels, instances::InstanceBuffers, random, shader::ShaderLoader};
+
+/// Clusters the simulated instances are split into.
+#[derive(Debug, Clone)]
+pub struct HierarchySettings {
+    pub clusters: u32,
+    /// Distance of the cluster centers from the origin.
+    pub radius: f32,
+    /// Radians per second the clusters orbit the origin at.
+    pub orbit_speed: f32,
+}
+
+impl HierarchySettings {
+    pub const DEFAULT_RADIUS: f32 = 20000.0;
+    pub const DEFAULT_ORBIT_SPEED: f32 = 0.02;
+    /// Fastest turn of a cluster about its own center, relative to the orbit.
+    const CLUSTER_SPIN: f32 = 5.0;
+}
+
+/// Mirrors `Node` in `hierarchy.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct HierarchyNode {
+    offset: [f32; 4],
+    parent: u32,
+    _padding: [u32; 3],
+}
+
+/// Mirrors `Params` in `hierarchy.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct HierarchyParams {
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// Parent nodes of the instances of one chunk.
+struct HierarchyChunk {
+    count: u32,
+    /// For either side of the simulation state.
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+/// Instances placed relative to parent transforms. A root node turns at
+/// the orbit speed with a cluster node per cluster offset from it, each
+/// spinning about its own center. Instances are simulated around the
+/// origin as usual and every frame a compute pass resolves the world matrix
+/// of every node and moves each instance to its cluster, writing the vertex
+/// positions in place of the copy of the simulated ones.
+///
+/// Instances keep their own orientation. Passes reading the simulated
+/// positions, like raycasts, see them around the origin.
+pub struct Hierarchy {
+    node_count: u32,
+    clusters: u32,
+    node_bind_group: wgpu::BindGroup,
+    chunk_layout: wgpu::BindGroupLayout,
+    node_pipeline: wgpu::ComputePipeline,
+    instance_pipeline: wgpu::ComputePipeline,
+    chunks: Vec<HierarchyChunk>,
+}
+
+impl Hierarchy {
+    const ROOT: u32 = u32::MAX;
+    const WORKGROUP_SIZE: u32 = 256;
+    const NODE_WORKGROUP_SIZE: u32 = 64;
+
+    /// `frame_layout` is the layout of the frame uniform.
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLoader,
+        frame_layout: &wgpu::BindGroupLayout,
+        settings: &HierarchySettings,
+        instances: &InstanceBuffers,
+    ) -> Self {
+        let module = shaders.module(device, "hierarchy.wgsl", include_str!("../shaders/hierarchy.wgsl"));
+
+        let mut rng = random::rng();
+        let mut nodes = vec![HierarchyNode {
+            offset: [0.0, 0.0, 0.0, settings.orbit_speed],
+            parent: Self::ROOT,
+            _padding: [0; 3],
+        }];
+        for cluster in 0..settings.clusters {
+            let angle = std::f32::consts::TAU * cluster as f32 / settings.clusters as f32;
+            let spin = settings.orbit_speed * HierarchySettings::CLUSTER_SPIN * rng.random_range(-1.0..1.0);
+            nodes.push(HierarchyNode {
+                offset: [settings.radius * angle.cos(), 0.0, settings.radius * angle.sin(), spin],
+                parent: 0,
+                _padding: [0; 3],
+            });
+        }
+        let nodes_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("hierarchy_nodes"),
+            contents: bytemuck::cast_slice(&nodes),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let world = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("hierarchy_world"),
+            size: (nodes.len() * std::mem::size_of::<[[f32; 4]; 4]>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let node_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("hierarchy_nodes"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(1, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+        let chunk_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("hierarchy_chunk"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Uniform),
+                entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(2, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+        let node_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("hierarchy_nodes"),
+            layout: &node_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: nodes_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: world.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline = |label, entry_point, layouts: &[&wgpu::BindGroupLayout]| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let node_pipeline = pipeline("hierarchy_nodes_pipeline", "resolve_nodes", &[frame_layout, &node_layout]);
+        let instance_pipeline = pipeline(
+            "hierarchy_instances_pipeline",
+            "resolve_instances",
+            &[frame_layout, &node_layout, &chunk_layout],
+        );
+
+        log::info!("{} clusters {} from the origin.", settings.clusters, settings.radius);
+        let mut hierarchy = Self {
+            node_count: nodes.len() as u32,
+            clusters: settings.clusters,
+            node_bind_group,
+            chunk_layout,
+            node_pipeline,
+            instance_pipeline,
+            chunks: Vec::new(),
+        };
+        hierarchy.resize(device, instances);
+        hierarchy
+    }
+
+    /// Reassigns the parents after the instance buffers changed, instances
+    /// taking turns between the clusters.
+    pub fn resize(&mut self, device: &wgpu::Device, instances: &InstanceBuffers) {
+        self.chunks = instances
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let parents: Vec<u32> = chunk.range.clone().map(|i| 1 + i % self.clusters).collect();
+                let parents_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("hierarchy_parents"),
+                    contents: bytemuck::cast_slice(&parents),
+                    usage: wgpu::BufferUsages::STORAGE,
+                });
+                let params = HierarchyParams {
+                    count: parents.len() as u32,
+                    _padding: [0; 3],
+                };
+                let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("hierarchy_params"),
+                    contents: bytemuck::bytes_of(&params),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+
+                let bind_groups = [0, 1].map(|side| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("hierarchy_chunk"),
+                        layout: &self.chunk_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: params_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: parents_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: chunk.positions[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: chunk.positions_vsh.as_binding(),
+                            },
+                        ],
+                    })
+                });
+
+                HierarchyChunk {
+                    count: params.count,
+                    bind_groups,
+                }
+            })
+            .collect();
+    }
+
+    /// Records resolving the nodes and then the vertex positions of every
+    /// chunk from the `front` side of the simulation state.
+    pub fn record(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        front: usize,
+        frame_bind_group: &wgpu::BindGroup,
+        frame_offset: u32,
+    ) {
+        let max_groups = device.limits().max_compute_workgroups_per_dimension;
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("hierarchy_pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, frame_bind_group, &[frame_offset]);
+        compute_pass.set_bind_group(1, &self.node_bind_group, &[]);
+
+        debug_labels::marker(&mut compute_pass, || format!("{} nodes", self.node_count));
+        compute_pass.set_pipeline(&self.node_pipeline);
+        compute_pass.dispatch_workgroups(self.node_count.div_ceil(Self::NODE_WORKGROUP_SIZE), 1, 1);
+
+        compute_pass.set_pipeline(&self.instance_pipeline);
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            debug_labels::marker(&mut compute_pass, || format!("chunk {index}: {} instances", chunk.count));
+            compute_pass.set_bind_group(2, &chunk.bind_groups[front], &[]);
+            let groups = chunk.count.div_ceil(Self::WORKGROUP_SIZE);
+            compute_pass.dispatch_workgroups(groups.clamp(1, max_groups), groups.div_ceil(max_groups).max(1), 1);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+
+use super::instances::InstanceBuffers;
+
+/// Rewind history options, recording is disabled while `interval` is 0.
+#[derive(Debug, Clone)]
+pub struct HistorySettings {
+    /// Simulation ticks between snapshots.
+    pub interval: u32,
+    /// Upper bound on the memory held by snapshots, in bytes.
+    pub budget: u64,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            interval: 0,
+            budget: 256 << 20,
+        }
+    }
+}
+
+/// Copy of the simulated state of every instance chunk.
+struct Snapshot {
+    time: f64,
+    /// Positions and velocities buffers, one pair per chunk.
+    chunks: Vec<[wgpu::Buffer; 2]>,
+}
+
+/// Ring of snapshots of the simulation taken on the GPU every few ticks.
+/// Velocities are kept along with positions, so the simulation resumes from
+/// a restored snapshot exactly as it ran the first time. The oldest
+/// snapshots are reused once the memory budget is reached.
+pub struct History {
+    interval: u32,
+    budget: u64,
+    /// Snapshots that fit in the budget for the current instance count.
+    capacity: usize,
+    ticks: u32,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl History {
+    pub fn new(settings: &HistorySettings, instance_buffers: &InstanceBuffers) -> Self {
+        let mut history = Self {
+            interval: settings.interval.max(1),
+            budget: settings.budget,
+            capacity: 0,
+            ticks: 0,
+            snapshots: VecDeque::new(),
+        };
+        history.reset(instance_buffers);
+        history
+    }
+
+    /// Drops every snapshot, for when the instances were replaced.
+    pub fn reset(&mut self, instance_buffers: &InstanceBuffers) {
+        let snapshot_size: u64 = instance_buffers
+            .chunks
+            .iter()
+            .map(|chunk| chunk.positions[0].size() + chunk.velocities[0].size())
+            .sum();
+        self.capacity = (self.budget / snapshot_size.max(1)) as usize;
+        self.ticks = 0;
+        self.snapshots.clear();
+
+        if self.capacity == 0 {
+            log::warn!(
+                "A snapshot takes {} MiB, more than the history budget of {} MiB.",
+                snapshot_size >> 20,
+                self.budget >> 20
+            );
+        } else {
+            log::info!("Keeping up to {} snapshots, one every {} ticks.", self.capacity, self.interval);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Counts a simulation tick and records a snapshot of the state after it
+    /// every `interval` ticks.
+    pub fn tick(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        instance_buffers: &InstanceBuffers,
+        time: f64,
+    ) {
+        self.ticks += 1;
+        if self.ticks < self.interval || self.capacity == 0 {
+            return;
+        }
+        self.ticks = 0;
+
+        let mut snapshot = if self.snapshots.len() >= self.capacity {
+            self.snapshots.pop_front().unwrap()
+        } else {
+            Snapshot {
+                time,
+                chunks: instance_buffers
+                    .chunks
+                    .iter()
+                    .map(|chunk| {
+                        [chunk.positions[0].size(), chunk.velocities[0].size()].map(|size| {
+                            device.create_buffer(&wgpu::BufferDescriptor {
+                                label: Some("history_snapshot"),
+                                size,
+                                usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+                                mapped_at_creation: false,
+                            })
+                        })
+                    })
+                    .collect(),
+            }
+        };
+
+        snapshot.time = time;
+        let front = instance_buffers.front();
+        for (chunk, [positions, velocities]) in instance_buffers.chunks.iter().zip(&snapshot.chunks) {
+            encoder.copy_buffer_to_buffer(chunk.positions[front].buffer(), 0, positions, 0, positions.size());
+            encoder.copy_buffer_to_buffer(chunk.velocities[front].buffer(), 0, velocities, 0, velocities.size());
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Records copies restoring the snapshot `steps` back, counting the
+    /// latest as one and clamped to the oldest. The restored snapshot and
+    /// everything after it are dropped, so rewinding again goes further
+    /// back. Returns the time the restored snapshot was taken at.
+    pub fn rewind(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        instance_buffers: &InstanceBuffers,
+        steps: usize,
+    ) -> Option<f64> {
+        let keep = self.snapshots.len().saturating_sub(steps.max(1) - 1).max(1);
+        self.snapshots.truncate(keep);
+        let snapshot = self.snapshots.pop_back()?;
+        self.ticks = 0;
+
+        // Into the front, which the next step reads
+        let front = instance_buffers.front();
+        for (chunk, [positions, velocities]) in instance_buffers.chunks.iter().zip(&snapshot.chunks) {
+            encoder.copy_buffer_to_buffer(positions, 0, chunk.positions[front].buffer(), 0, positions.size());
+            encoder.copy_buffer_to_buffer(velocities, 0, chunk.velocities[front].buffer(), 0, velocities.size());
+        }
+        Some(snapshot.time)
+    }
+}
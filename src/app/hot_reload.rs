@@ -0,0 +1,58 @@
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::mpsc::{self, Receiver},
+};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use super::shader::{ShaderError, ShaderResult};
+
+/// Watches the shader directory for changed WGSL files, so the pipelines
+/// built from them can be rebuilt while the app runs.
+pub struct ShaderWatcher {
+    // Stops watching when dropped
+    _watcher: notify::RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: &Path) -> ShaderResult<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)
+            .map_err(|e| ShaderError::new(format!("Failed to watch shaders: {e}")))?;
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| ShaderError::new(format!("Failed to watch {}: {e}", dir.display())))?;
+        log::info!("Watching {} for shader changes.", dir.display());
+
+        Ok(Self { _watcher: watcher, events })
+    }
+
+    /// File names of the WGSL shaders written since the last call. Editors
+    /// write a file in several events, each name is only returned once.
+    pub fn changed(&self) -> BTreeSet<String> {
+        let mut changed = BTreeSet::new();
+        for event in self.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(error) => {
+                    log::warn!("Shader watcher error: {error}.");
+                    continue;
+                }
+            };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+            changed.extend(
+                event
+                    .paths
+                    .iter()
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "wgsl"))
+                    .filter_map(|path| path.file_name()?.to_str().map(str::to_string)),
+            );
+        }
+
+        changed
+    }
+}
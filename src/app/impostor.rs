@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{
+    debug_labels,
+    mesh::{DefaultVertex3d, Mesh, Vertex},
+    shader::{self, ShaderLoader},
+    texture::Texture2d,
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct BakeConstants {
+    tile: [u32; 2],
+}
+
+/// Atlas of a mesh pre-rendered from a ring of view directions, holding
+/// its normals so impostors are lit like the instances.
+///
+/// Tiles are laid out with azimuth along X and elevation along Y. The layout
+/// is passed to `impostor.wgsl` and `impostor_bake.wgsl` as override constants.
+pub struct ImpostorAtlas {
+    pub texture: Texture2d,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    bake_pipeline: wgpu::RenderPipeline,
+    /// [`BakeConstants`] of every tile, bound at the tile's offset.
+    bake_bind_group: wgpu::BindGroup,
+    bake_stride: u32,
+}
+
+impl ImpostorAtlas {
+    pub const AZIMUTH_TILES: u32 = 8;
+    pub const ELEVATION_TILES: u32 = 4;
+    pub const TILE_SIZE: u32 = 64;
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    const BAKE_CONSTANTS_SIZE: u64 = std::mem::size_of::<BakeConstants>() as u64;
+
+    const SIZE: (u32, u32) = (Self::AZIMUTH_TILES * Self::TILE_SIZE, Self::ELEVATION_TILES * Self::TILE_SIZE);
+
+    pub fn bake(device: &wgpu::Device, queue: &wgpu::Queue, mesh: &Mesh, shaders: &ShaderLoader) -> Self {
+        let texture = Texture2d::create_render_target(device, Self::SIZE, Self::FORMAT, Some("impostor_atlas"));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("impostor_atlas"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("impostor_atlas"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+        });
+
+        // Uploaded once rather than pushed, so baking works without push constants
+        let bake_stride = (Self::BAKE_CONSTANTS_SIZE as u32).next_multiple_of(device.limits().min_uniform_buffer_offset_alignment);
+        let mut bake_constants = vec![0; (Self::AZIMUTH_TILES * Self::ELEVATION_TILES * bake_stride) as usize];
+        for y in 0..Self::ELEVATION_TILES {
+            for x in 0..Self::AZIMUTH_TILES {
+                let offset = ((y * Self::AZIMUTH_TILES + x) * bake_stride) as usize;
+                bake_constants[offset..offset + Self::BAKE_CONSTANTS_SIZE as usize]
+                    .copy_from_slice(bytemuck::bytes_of(&BakeConstants { tile: [x, y] }));
+            }
+        }
+        let bake_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("impostor_bake_constants"),
+            contents: &bake_constants,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bake_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("impostor_bake"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(Self::BAKE_CONSTANTS_SIZE),
+                },
+                count: None,
+            }],
+        });
+        let bake_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("impostor_bake"),
+            layout: &bake_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &bake_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(Self::BAKE_CONSTANTS_SIZE),
+                }),
+            }],
+        });
+
+        let atlas = Self {
+            texture,
+            bind_group_layout,
+            bind_group,
+            bake_pipeline: Self::bake_pipeline(device, &bake_bind_group_layout, shaders),
+            bake_bind_group,
+            bake_stride,
+        };
+        atlas.rebake(device, queue, mesh);
+        atlas
+    }
+
+    /// Renders `mesh` into the atlas again, for when the instances change shape.
+    pub fn rebake(&self, device: &wgpu::Device, queue: &wgpu::Queue, mesh: &Mesh) {
+        let depth_texture = Texture2d::create_sized_depth_texture(device, Self::SIZE, 1, Some("impostor_depth"));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("impostor_bake"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("impostor_bake_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.texture.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            render_pass.set_pipeline(&self.bake_pipeline);
+
+            debug_labels::push(&mut render_pass, || {
+                format!("impostor bake, {}x{} tiles", Self::AZIMUTH_TILES, Self::ELEVATION_TILES)
+            });
+            for y in 0..Self::ELEVATION_TILES {
+                for x in 0..Self::AZIMUTH_TILES {
+                    render_pass.set_viewport(
+                        (x * Self::TILE_SIZE) as f32,
+                        (y * Self::TILE_SIZE) as f32,
+                        Self::TILE_SIZE as f32,
+                        Self::TILE_SIZE as f32,
+                        0.0,
+                        1.0,
+                    );
+                    render_pass.set_bind_group(
+                        0,
+                        &self.bake_bind_group,
+                        &[(y * Self::AZIMUTH_TILES + x) * self.bake_stride],
+                    );
+                    mesh.draw(&mut render_pass);
+                }
+            }
+            debug_labels::pop(&mut render_pass);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        log::debug!(
+            "Baked {}x{} impostor atlas.",
+            Self::AZIMUTH_TILES,
+            Self::ELEVATION_TILES
+        );
+    }
+
+    /// Tile layout for the `override` constants of the impostor shaders.
+    pub fn override_constants() -> HashMap<String, f64> {
+        shader::override_constants([
+            ("AZIMUTH_TILES", Self::AZIMUTH_TILES as f64),
+            ("ELEVATION_TILES", Self::ELEVATION_TILES as f64),
+        ])
+    }
+
+    fn bake_pipeline(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        shaders: &ShaderLoader,
+    ) -> wgpu::RenderPipeline {
+        let module = shaders.module(device, "impostor_bake.wgsl", include_str!("../shaders/impostor_bake.wgsl"));
+        let constants = Self::override_constants();
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("impostor_bake_pipeline_layout"),
+            bind_group_layouts: &[bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("impostor_bake_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+                buffers: &[DefaultVertex3d::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+                targets: &[
+                    Some(wgpu::ColorTargetState {
+                        format: Self::FORMAT,
+                        write_mask: wgpu::ColorWrites::ALL,
+                        blend: None,
+                    })
+                ]
+            }),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
@@ -0,0 +1,167 @@
+use std::ops::Range;
+
+use super::{instances::InstanceChunk, mesh::Mesh};
+
+/// Instanced draws of a mesh issued from an argument buffer, with one
+/// `multi_draw_indexed_indirect` per batch in place of a draw call per
+/// instance range. Batches are draws sharing vertex buffers, here the
+/// instance chunks.
+///
+/// The arguments are written from the CPU culling results for now. The
+/// buffer is a storage buffer too, so a culling kernel can later fill it
+/// on the GPU without changing how it's drawn.
+pub struct MultiDraw {
+    buffer: wgpu::Buffer,
+    /// Arguments of each batch, indices into `buffer`.
+    batches: Vec<Range<u32>>,
+}
+
+impl MultiDraw {
+    /// Culled ranges start past the first instance of their chunk.
+    pub const FEATURES: wgpu::Features =
+        wgpu::Features::MULTI_DRAW_INDIRECT.union(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+    const STRIDE: u64 = std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64;
+    const INITIAL_CAPACITY: u64 = 256;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            buffer: Self::create_buffer(device, Self::INITIAL_CAPACITY),
+            batches: Vec::new(),
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("multi_draw_arguments"),
+            size: capacity * Self::STRIDE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Writes the arguments drawing `mesh` for every instance range of each
+    /// batch, growing the buffer when they don't fit.
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, mesh: &Mesh, batches: &[Vec<Range<u32>>]) {
+        let mut arguments = Vec::new();
+        self.batches.clear();
+        for instances in batches {
+            let first = self.batches.last().map_or(0, |batch| batch.end);
+            for range in instances {
+                let draw = wgpu::util::DrawIndexedIndirectArgs {
+                    index_count: mesh.element_count(),
+                    instance_count: range.len() as u32,
+                    first_index: 0,
+                    base_vertex: 0,
+                    first_instance: range.start,
+                };
+                arguments.extend_from_slice(draw.as_bytes());
+            }
+            self.batches.push(first..first + instances.len() as u32);
+        }
+        if arguments.is_empty() {
+            return;
+        }
+
+        let size = arguments.len() as u64;
+        if size > self.buffer.size() {
+            self.buffer = Self::create_buffer(device, (size / Self::STRIDE).next_power_of_two());
+            log::debug!("Multi-draw arguments grown to {} draws.", self.buffer.size() / Self::STRIDE);
+        }
+        queue.write_buffer(&self.buffer, 0, &arguments);
+    }
+
+    pub fn draw_count(&self) -> u32 {
+        self.batches.last().map_or(0, |batch| batch.end)
+    }
+
+    /// Draws one batch with the instances in `instance_buffer`, the other
+    /// per-instance streams must be set already.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, mesh: &Mesh, instance_buffer: &wgpu::Buffer, batch: usize) {
+        let Some(draws) = self.batches.get(batch).filter(|draws| !draws.is_empty()) else {
+            return;
+        };
+        mesh.draw_multi_indirect(
+            render_pass,
+            instance_buffer,
+            &self.buffer,
+            draws.start as u64 * Self::STRIDE,
+            draws.len() as u32,
+        );
+    }
+}
+
+/// Workgroup counts of the simulation kernels, one dispatch per instance
+/// chunk. They're written whenever the chunks or the workgroup size change
+/// and read by indirect dispatches, so the recorded passes don't depend on
+/// the instance count. Without indirect execution they're dispatched
+/// directly.
+pub struct KernelDispatch {
+    indirect: bool,
+    /// Created with the first counts, grown when more chunks don't fit.
+    buffer: Option<wgpu::Buffer>,
+    counts: Vec<[u32; 3]>,
+}
+
+impl KernelDispatch {
+    const STRIDE: u64 = std::mem::size_of::<wgpu::util::DispatchIndirectArgs>() as u64;
+
+    pub fn new(indirect: bool) -> Self {
+        Self {
+            indirect,
+            buffer: None,
+            counts: Vec::new(),
+        }
+    }
+
+    /// Covers every instance of each chunk, a grid of `dimensions` with the
+    /// chunk's z slices, with workgroups of `workgroup_dims`.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        chunks: &[InstanceChunk],
+        dimensions: [u32; 4],
+        workgroup_dims: (u32, u32, u32),
+    ) {
+        self.counts = chunks
+            .iter()
+            .map(|chunk| {
+                [
+                    dimensions[0].div_ceil(workgroup_dims.0),
+                    dimensions[1].div_ceil(workgroup_dims.1),
+                    chunk.slices.div_ceil(workgroup_dims.2),
+                ]
+            })
+            .collect();
+        if !self.indirect || self.counts.is_empty() {
+            return;
+        }
+
+        let size = self.counts.len() as u64 * Self::STRIDE;
+        if self.buffer.as_ref().is_none_or(|buffer| buffer.size() < size) {
+            self.buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("kernel_dispatch_arguments"),
+                size: size.next_power_of_two(),
+                usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        if let Some(buffer) = &self.buffer {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&self.counts));
+        }
+    }
+
+    /// Dispatches the kernel set on `compute_pass` over the instances of
+    /// one chunk.
+    pub fn dispatch(&self, compute_pass: &mut wgpu::ComputePass, chunk: usize) {
+        match &self.buffer {
+            Some(buffer) => {
+                compute_pass.dispatch_workgroups_indirect(buffer, chunk as u64 * Self::STRIDE);
+            }
+            _ => {
+                let [x, y, z] = self.counts[chunk];
+                compute_pass.dispatch_workgroups(x, y, z);
+            }
+        }
+    }
+}
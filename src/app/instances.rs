@@ -0,0 +1,755 @@
+use std::{fmt::Display, marker::PhantomData, ops::Range};
+
+use bytemuck::{Pod, Zeroable};
+use rand::Rng;
+use wgpu::util::DeviceExt;
+
+use super::{material::MaterialParams, mesh::{Instance, vertex_attributes}, random};
+
+#[derive(Debug, Clone)]
+pub struct InstanceError {
+    pub message: String,
+}
+
+impl InstanceError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Display for InstanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for InstanceError {}
+pub type InstanceResult<T> = Result<T, InstanceError>;
+
+/// Orientation and size of an instance, the second per-instance vertex
+/// stream of `default.wgsl` and `impostor.wgsl`. Kept apart from the
+/// positions so simulated positions are still copied straight into the
+/// vertex buffers, and streamed points keep their layout.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct InstanceTransform {
+    /// Unit quaternion, xyzw.
+    pub rotation: [f32; 4],
+    /// Scale along the local axes in xyz, spin about the local x axis in
+    /// radians per second in w.
+    pub scale: [f32; 4],
+}
+
+impl InstanceTransform {
+    pub const IDENTITY: Self = Self {
+        rotation: [0.0, 0.0, 0.0, 1.0],
+        scale: [1.0, 1.0, 1.0, 0.0],
+    };
+    const ATTRIBS: &'static [wgpu::VertexAttribute] = &vertex_attributes!(Self, 2 => {
+        rotation: Float32x4,
+        scale: Float32x4,
+    });
+
+    /// Transforms for `count` instances, identity with the default settings.
+    pub fn generate(count: usize, settings: &TransformSettings) -> Vec<Self> {
+        let mut rng = random::rng();
+        let (min_scale, max_scale) = settings.scale;
+
+        (0..count)
+            .map(|_| {
+                let rotation = if settings.random_rotation {
+                    // Uniformly distributed, after Shoemake
+                    let (u, v, w): (f32, f32, f32) = (rng.random(), rng.random(), rng.random());
+                    let (a, b) = ((1.0 - u).sqrt(), u.sqrt());
+                    let (v, w) = (v * std::f32::consts::TAU, w * std::f32::consts::TAU);
+                    [a * v.sin(), a * v.cos(), b * w.sin(), b * w.cos()]
+                } else {
+                    Self::IDENTITY.rotation
+                };
+                let mut scale = || rng.random_range(min_scale..=max_scale);
+                let scale = [scale(), scale(), scale()];
+                let spin = rng.random_range(-settings.spin..=settings.spin);
+
+                Self {
+                    rotation,
+                    scale: [scale[0], scale[1], scale[2], spin],
+                }
+            })
+            .collect()
+    }
+}
+
+impl Instance for InstanceTransform {
+    fn attribs() -> &'static [wgpu::VertexAttribute] {
+        Self::ATTRIBS
+    }
+}
+
+/// How instance transforms are generated.
+#[derive(Debug, Clone)]
+pub struct TransformSettings {
+    pub random_rotation: bool,
+    /// Range every axis is scaled within.
+    pub scale: (f32, f32),
+    /// Largest spin in radians per second, instances only spin when the
+    /// simulation runs.
+    pub spin: f32,
+}
+
+impl Default for TransformSettings {
+    fn default() -> Self {
+        Self {
+            random_rotation: false,
+            scale: (1.0, 1.0),
+            spin: 0.0,
+        }
+    }
+}
+
+impl TransformSettings {
+    /// Farthest any point of an instance cube gets from its center.
+    pub fn max_extent(&self) -> f32 {
+        let turned = self.random_rotation || self.spin > 0.0;
+        0.5 * self.scale.1 * if turned { 3.0f32.sqrt() } else { 1.0 }
+    }
+}
+
+/// RGBA color and material of an instance, the third per-instance vertex
+/// stream of `default.wgsl`. The color is only drawn with the
+/// `COLOR_ATTRIBUTE` shader feature and the material with `MATERIALS`, the
+/// alpha always, though only the transparent pipeline blends.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct InstanceColor {
+    pub color: [u8; 4],
+    /// Index into [`MaterialParams::PALETTE`].
+    pub material: u32,
+}
+
+impl InstanceColor {
+    pub const WHITE: Self = Self {
+        color: [255; 4],
+        material: 0,
+    };
+    const ATTRIBS: &'static [wgpu::VertexAttribute] = &vertex_attributes!(Self, 4 => {
+        color: Unorm8x4,
+        material: Uint32,
+    });
+
+    /// Colors for the instances moving at `velocities`, all with the same
+    /// opacity, each with a random material.
+    pub fn generate(velocities: &[[f32; 4]], coloring: InstanceColoring, alpha: f32) -> Vec<Self> {
+        let speed = |v: &[f32; 4]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        let alpha = (alpha.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let mut rng = random::rng();
+        let mut color = |color| Self {
+            color,
+            material: rng.random_range(0..MaterialParams::PALETTE.len() as u32),
+        };
+
+        match coloring {
+            InstanceColoring::Random => {
+                let mut rng = random::rng();
+                velocities.iter().map(|_| color([rng.random(), rng.random(), rng.random(), alpha])).collect()
+            }
+            InstanceColoring::Speed => {
+                let max_speed = velocities.iter().map(speed).fold(0.0, f32::max).max(f32::EPSILON);
+                velocities
+                    .iter()
+                    .map(|v| {
+                        // Blue through green to red
+                        let t = speed(v) / max_speed;
+                        let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0) as u8;
+                        color([channel(2.0 * t - 1.0), channel(1.0 - (2.0 * t - 1.0).abs()), channel(1.0 - 2.0 * t), alpha])
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Instance for InstanceColor {
+    fn attribs() -> &'static [wgpu::VertexAttribute] {
+        Self::ATTRIBS
+    }
+}
+
+/// Where instance colors come from.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum InstanceColoring {
+    #[default]
+    Random,
+    /// Initial speed, relative to the fastest instance.
+    Speed,
+}
+
+impl InstanceColoring {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "random" => Some(Self::Random),
+            "speed" => Some(Self::Speed),
+            _ => None,
+        }
+    }
+}
+
+/// Growable GPU array, a `Vec` whose elements live in a buffer. The buffer
+/// holds `capacity` elements of which the first `len` are instances, the
+/// rest is room to append into without reallocating.
+pub struct InstanceBuffer<T> {
+    buffer: wgpu::Buffer,
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    len: u32,
+    element: PhantomData<T>,
+}
+
+impl<T: Pod> InstanceBuffer<T> {
+    const STRIDE: u64 = std::mem::size_of::<T>() as u64;
+
+    pub fn new(device: &wgpu::Device, label: &'static str, usage: wgpu::BufferUsages, data: &[T]) -> Self {
+        // Growing copies the old contents over, appending writes into it
+        let usage = usage | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+        Self {
+            buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: bytemuck::cast_slice(data),
+                usage,
+            }),
+            label,
+            usage,
+            len: data.len() as u32,
+            element: PhantomData,
+        }
+    }
+
+    /// Replaced when the buffer grows, so bind groups made from it have to
+    /// be recreated then.
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn capacity(&self) -> u32 {
+        (self.buffer.size() / Self::STRIDE) as u32
+    }
+
+    /// Bytes taken by the instances, without the spare capacity.
+    pub fn size(&self) -> u64 {
+        self.len as u64 * Self::STRIDE
+    }
+
+    /// Binds the instances without the spare capacity, so `arrayLength` in
+    /// shaders is the instance count.
+    pub fn as_binding(&self) -> wgpu::BindingResource<'_> {
+        wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &self.buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(self.size()),
+        })
+    }
+
+    /// The instances, without the spare capacity.
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(..self.size())
+    }
+
+    /// Grows the buffer to hold at least `capacity` elements, recording a
+    /// copy of the instances into the new one. Returns whether the buffer
+    /// was reallocated.
+    pub fn reserve(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, capacity: u32) -> bool {
+        if capacity <= self.capacity() {
+            return false;
+        }
+        self.reallocate(device, encoder, capacity);
+        true
+    }
+
+    /// Shrinks the buffer to hold `capacity` elements, or just the instances
+    /// when there are more. Returns whether the buffer was reallocated.
+    pub fn shrink_to(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, capacity: u32) -> bool {
+        let capacity = capacity.max(self.len);
+        if capacity >= self.capacity() {
+            return false;
+        }
+        self.reallocate(device, encoder, capacity);
+        true
+    }
+
+    fn reallocate(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, capacity: u32) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(self.label),
+            size: capacity as u64 * Self::STRIDE,
+            usage: self.usage,
+            mapped_at_creation: false,
+        });
+        if self.len > 0 {
+            encoder.copy_buffer_to_buffer(&self.buffer, 0, &buffer, 0, self.size());
+        }
+        self.buffer = buffer;
+    }
+
+    /// Writes `data` after the instances, failing without writing anything
+    /// when it doesn't fit the capacity.
+    pub fn extend(&mut self, queue: &wgpu::Queue, data: &[T]) -> InstanceResult<()> {
+        if self.len as usize + data.len() > self.capacity() as usize {
+            return Err(InstanceError::new(format!(
+                "Appending {} elements to {} overflows its capacity of {}",
+                data.len(),
+                self.label,
+                self.capacity()
+            )));
+        }
+        queue.write_buffer(&self.buffer, self.size(), bytemuck::cast_slice(data));
+        self.len += data.len() as u32;
+        Ok(())
+    }
+
+    /// Records a copy of `count` instances of `source` from `from` over the
+    /// instances of this buffer from `to`. Buffers can't be copied into
+    /// themselves, so a copy within one buffer goes through a staging buffer.
+    pub fn copy_from(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &Self,
+        from: u32,
+        to: u32,
+        count: u32,
+    ) -> InstanceResult<()> {
+        if from as u64 + count as u64 > source.len as u64 || to as u64 + count as u64 > self.len as u64 {
+            return Err(InstanceError::new(format!(
+                "Copying {count} elements from {} at {from} to {} at {to} is out of bounds",
+                source.label, self.label
+            )));
+        }
+
+        let size = count as u64 * Self::STRIDE;
+        let (from, to) = (from as u64 * Self::STRIDE, to as u64 * Self::STRIDE);
+        if std::ptr::eq(self, source) {
+            let staging = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(self.label),
+                size,
+                usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            encoder.copy_buffer_to_buffer(&source.buffer, from, &staging, 0, size);
+            encoder.copy_buffer_to_buffer(&staging, 0, &self.buffer, to, size);
+        } else {
+            encoder.copy_buffer_to_buffer(&source.buffer, from, &self.buffer, to, size);
+        }
+        Ok(())
+    }
+
+    /// Drops instances past `len`, keeping the capacity.
+    pub fn truncate(&mut self, len: u32) {
+        self.len = self.len.min(len);
+    }
+}
+
+/// Per-instance data on the CPU, with one element per instance in each slice.
+#[derive(Clone, Copy)]
+pub struct InstanceData<'a> {
+    pub positions: &'a [[f32; 4]],
+    pub velocities: &'a [[f32; 4]],
+    pub transforms: &'a [InstanceTransform],
+    pub colors: &'a [InstanceColor],
+}
+
+impl<'a> InstanceData<'a> {
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn slice(&self, elements: Range<usize>) -> InstanceData<'a> {
+        InstanceData {
+            positions: &self.positions[elements.clone()],
+            velocities: &self.velocities[elements.clone()],
+            transforms: &self.transforms[elements.clone()],
+            colors: &self.colors[elements],
+        }
+    }
+}
+
+/// Instance positions, velocities, transforms and colors for a run of
+/// whole z slices of the simulation grid.
+pub struct InstanceChunk {
+    /// Instances held by this chunk.
+    pub range: Range<u32>,
+    /// Number of z slices held by this chunk.
+    pub slices: u32,
+    /// Both sides of the simulation state, the kernels read one and write
+    /// the other. Index with [`InstanceBuffers::front`] for the current one.
+    pub positions: [InstanceBuffer<[f32; 4]>; 2],
+    pub positions_vsh: InstanceBuffer<[f32; 4]>,
+    pub velocities: [InstanceBuffer<[f32; 4]>; 2],
+    /// Animated by the compute kernels and read as vertices.
+    pub transforms: InstanceBuffer<InstanceTransform>,
+    pub colors: InstanceBuffer<InstanceColor>,
+}
+
+impl InstanceChunk {
+    fn new(device: &wgpu::Device, data: InstanceData, start: u32, slices: u32) -> Self {
+        Self {
+            range: start..start + data.len() as u32,
+            slices,
+            positions: ["positions_buffer_a", "positions_buffer_b"]
+                .map(|label| InstanceBuffer::new(device, label, wgpu::BufferUsages::STORAGE, data.positions)),
+            // Written by the hierarchy pass when instances have parents
+            positions_vsh: InstanceBuffer::new(
+                device,
+                "positions_buffer_vsh",
+                wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+                data.positions,
+            ),
+            velocities: ["velocities_buffer_a", "velocities_buffer_b"]
+                .map(|label| InstanceBuffer::new(device, label, wgpu::BufferUsages::STORAGE, data.velocities)),
+            transforms: InstanceBuffer::new(
+                device,
+                "transforms_buffer",
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+                data.transforms,
+            ),
+            // Read by the sort as well
+            colors: InstanceBuffer::new(
+                device,
+                "colors_buffer",
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+                data.colors,
+            ),
+        }
+    }
+
+    /// Appends whole z slices, growing the buffers to at least twice their
+    /// capacity, up to `max_len`, when they don't fit. Both sides of the state
+    /// get the new instances, so it doesn't matter which one is current.
+    /// Returns whether the buffers were reallocated.
+    fn extend(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        data: InstanceData,
+        slices: u32,
+        max_len: u32,
+    ) -> InstanceResult<bool> {
+        let len = self.positions_vsh.len() + data.len() as u32;
+        let mut reallocated = false;
+        if len > self.positions_vsh.capacity() {
+            let capacity = len.max(self.positions_vsh.capacity().saturating_mul(2)).min(max_len.max(len));
+            for side in 0..2 {
+                reallocated |= self.positions[side].reserve(device, encoder, capacity);
+                reallocated |= self.velocities[side].reserve(device, encoder, capacity);
+            }
+            reallocated |= self.positions_vsh.reserve(device, encoder, capacity);
+            reallocated |= self.transforms.reserve(device, encoder, capacity);
+            reallocated |= self.colors.reserve(device, encoder, capacity);
+        }
+
+        for side in 0..2 {
+            self.positions[side].extend(queue, data.positions)?;
+            self.velocities[side].extend(queue, data.velocities)?;
+        }
+        self.positions_vsh.extend(queue, data.positions)?;
+        self.transforms.extend(queue, data.transforms)?;
+        self.colors.extend(queue, data.colors)?;
+        self.range.end = self.range.start + len;
+        self.slices += slices;
+        Ok(reallocated)
+    }
+
+    /// Records a copy of the z slice starting at instance `from` of `source`
+    /// over the one starting at `to`, both local to their chunk, on both
+    /// sides of the state and every other buffer.
+    fn copy_slice(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &InstanceChunk,
+        from: u32,
+        to: u32,
+        slice_len: u32,
+    ) -> InstanceResult<()> {
+        for side in 0..2 {
+            self.positions[side].copy_from(device, encoder, &source.positions[side], from, to, slice_len)?;
+            self.velocities[side].copy_from(device, encoder, &source.velocities[side], from, to, slice_len)?;
+        }
+        self.positions_vsh.copy_from(device, encoder, &source.positions_vsh, from, to, slice_len)?;
+        self.transforms.copy_from(device, encoder, &source.transforms, from, to, slice_len)?;
+        self.colors.copy_from(device, encoder, &source.colors, from, to, slice_len)
+    }
+
+    /// Drops the instances past `len`. Once they take up less than a quarter
+    /// of the capacity, the buffers shrink to twice the instances, which
+    /// leaves room for appending as much again before they grow back.
+    /// Returns whether the buffers were reallocated.
+    fn truncate(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, len: u32, slice_len: u32) -> bool {
+        for side in 0..2 {
+            self.positions[side].truncate(len);
+            self.velocities[side].truncate(len);
+        }
+        self.positions_vsh.truncate(len);
+        self.transforms.truncate(len);
+        self.colors.truncate(len);
+        self.range.end = self.range.start + len;
+        self.slices = len / slice_len;
+
+        let mut reallocated = false;
+        if len < self.positions_vsh.capacity() / 4 {
+            let capacity = len.saturating_mul(2);
+            for side in 0..2 {
+                reallocated |= self.positions[side].shrink_to(device, encoder, capacity);
+                reallocated |= self.velocities[side].shrink_to(device, encoder, capacity);
+            }
+            reallocated |= self.positions_vsh.shrink_to(device, encoder, capacity);
+            reallocated |= self.transforms.shrink_to(device, encoder, capacity);
+            reallocated |= self.colors.shrink_to(device, encoder, capacity);
+        }
+        reallocated
+    }
+}
+
+/// Removes the z slices in `slices` from per-instance data on the CPU the
+/// way [`InstanceBuffers::swap_remove_slices`] does on the GPU.
+pub fn swap_remove_slices<T: Copy>(data: &mut Vec<T>, slices: Range<u32>, slice_len: u32) {
+    let slice_len = slice_len as usize;
+    let depth = (data.len() / slice_len) as u32;
+    let tail = slices.end.max(depth - slices.len() as u32)..depth;
+    for (from, to) in tail.zip(slices.clone()) {
+        let (from, to) = (from as usize * slice_len, to as usize * slice_len);
+        data.copy_within(from..from + slice_len, to);
+    }
+    data.truncate((depth as usize - slices.len()) * slice_len);
+}
+
+/// Instance data split across as many buffers as needed to stay within
+/// `max_buffer_size` and `max_storage_buffer_binding_size`, so the instance
+/// count is bounded by memory rather than by a single binding.
+///
+/// Chunks hold whole z slices, which lets the compute shader index a chunk
+/// with the same grid coordinates as the full simulation. Slices can be
+/// appended and removed at runtime, the last chunk grows in place until it
+/// reaches the limits and then new chunks are added.
+///
+/// Positions and velocities are double buffered, so the simulation never
+/// reads state it's already overwritten. Every step reads the front side,
+/// writes the back one and then [`Self::swap`]s them. Frames draw the
+/// [`Self::shown`] side, which only catches up with the front once the steps
+/// recorded since are submitted.
+pub struct InstanceBuffers {
+    pub chunks: Vec<InstanceChunk>,
+    front: usize,
+    shown: usize,
+}
+
+impl InstanceBuffers {
+    const TRANSFORM_STRIDE: u64 = std::mem::size_of::<InstanceTransform>() as u64;
+
+    /// Largest number of instances a single chunk may hold.
+    pub fn max_chunk_len(limits: &wgpu::Limits, compat: bool) -> u64 {
+        // Compatibility mode only uses the positions as vertex buffers
+        let max_size = if compat {
+            limits.max_buffer_size
+        } else {
+            limits.max_buffer_size.min(limits.max_storage_buffer_binding_size as u64)
+        };
+        // Transforms are the largest per-instance buffer
+        max_size / Self::TRANSFORM_STRIDE
+    }
+
+    /// Whole z slices that fit a chunk. Chunks can't split a slice, so
+    /// fails when a single one exceeds the limits.
+    pub fn slices_per_chunk(limits: &wgpu::Limits, slice_len: u32, compat: bool) -> InstanceResult<u32> {
+        let max_chunk_len = Self::max_chunk_len(limits, compat);
+        if slice_len as u64 > max_chunk_len {
+            return Err(InstanceError::new(format!(
+                "A single z slice of {slice_len} instances exceeds the buffer limits of {max_chunk_len} instances"
+            )));
+        }
+        Ok((max_chunk_len / slice_len.max(1) as u64).min(u32::MAX as u64) as u32)
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        data: InstanceData,
+        dimensions: (u32, u32, u32),
+        compat: bool,
+    ) -> InstanceResult<Self> {
+        let slice_len = dimensions.0 * dimensions.1;
+        let slices_per_chunk = Self::slices_per_chunk(&device.limits(), slice_len, compat)?.min(dimensions.2.max(1));
+
+        let chunks: Vec<_> = (0..dimensions.2)
+            .step_by(slices_per_chunk as usize)
+            .map(|first_slice| {
+                let slices = slices_per_chunk.min(dimensions.2 - first_slice);
+                let range = first_slice * slice_len..(first_slice + slices) * slice_len;
+                InstanceChunk::new(device, data.slice(range.start as usize..range.end as usize), range.start, slices)
+            })
+            .collect();
+
+        if chunks.len() > 1 {
+            log::info!("Instance data split into {} chunks of up to {} instances.", chunks.len(), slices_per_chunk * slice_len);
+        }
+
+        Ok(Self {
+            chunks,
+            front: 0,
+            shown: 0,
+        })
+    }
+
+    pub fn len(&self) -> u32 {
+        self.chunks.last().map_or(0, |chunk| chunk.range.end)
+    }
+
+    /// Side of the positions and velocities holding the current state.
+    pub fn front(&self) -> usize {
+        self.front
+    }
+
+    /// Makes the side the last simulation step wrote into the front.
+    pub fn swap(&mut self) {
+        self.front = 1 - self.front;
+    }
+
+    /// Side frames draw from, the front as of the last submitted step.
+    pub fn shown(&self) -> usize {
+        self.shown
+    }
+
+    /// Shows the front side once every step recorded so far is submitted.
+    pub fn show_front(&mut self) {
+        self.shown = self.front;
+    }
+
+    /// Appends whole z slices of `slice_len` instances, filling the last
+    /// chunk before adding new ones. Returns whether a buffer was created or
+    /// reallocated, either of which invalidates the bind groups of the chunks,
+    /// and fails without appending anything when a slice exceeds the limits.
+    pub fn extend(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        data: InstanceData,
+        slice_len: u32,
+        compat: bool,
+    ) -> InstanceResult<bool> {
+        let slices_per_chunk = Self::slices_per_chunk(&device.limits(), slice_len, compat)?;
+        let mut reallocated = false;
+        let mut appended = 0;
+
+        while appended < data.len() {
+            let remaining = (data.len() - appended) as u32 / slice_len;
+            let start = self.len();
+            match self.chunks.last_mut().filter(|chunk| chunk.slices < slices_per_chunk) {
+                Some(chunk) => {
+                    let slices = remaining.min(slices_per_chunk - chunk.slices);
+                    let elements = appended..appended + (slices * slice_len) as usize;
+                    reallocated |= chunk.extend(
+                        device,
+                        queue,
+                        encoder,
+                        data.slice(elements.clone()),
+                        slices,
+                        slices_per_chunk * slice_len,
+                    )?;
+                    appended = elements.end;
+                }
+                None => {
+                    let slices = remaining.min(slices_per_chunk);
+                    let elements = appended..appended + (slices * slice_len) as usize;
+                    self.chunks.push(InstanceChunk::new(device, data.slice(elements.clone()), start, slices));
+                    reallocated = true;
+                    appended = elements.end;
+                }
+            }
+        }
+        Ok(reallocated)
+    }
+
+    /// Removes every instance from `len` on, which should be a whole number
+    /// of z slices. Emptied chunks are dropped and the last one shrinks when
+    /// it's mostly spare capacity. Returns whether chunks were dropped or
+    /// reallocated, either of which invalidates their bind groups.
+    pub fn truncate(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, len: u32, slice_len: u32) -> bool {
+        let chunks = self.chunks.len();
+        self.chunks.retain(|chunk| chunk.range.start < len);
+        let mut reallocated = self.chunks.len() != chunks;
+        if let Some(chunk) = self.chunks.last_mut() {
+            reallocated |= chunk.truncate(device, encoder, len.min(chunk.range.end) - chunk.range.start, slice_len);
+        }
+        reallocated
+    }
+
+    /// Removes the z slices in `slices` by recording copies of the last
+    /// slices of the grid over them, then truncating. The order of the
+    /// slices isn't kept, like `Vec::swap_remove`. Both sides of the state
+    /// are moved, so it doesn't matter which one is current. Returns whether
+    /// chunks were dropped or reallocated.
+    pub fn swap_remove_slices(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        slices: Range<u32>,
+        slice_len: u32,
+    ) -> InstanceResult<bool> {
+        let depth = self.len() / slice_len.max(1);
+        if slices.start > slices.end || slices.end > depth {
+            return Err(InstanceError::new(format!(
+                "Z slices {slices:?} are out of the {depth} in the grid"
+            )));
+        }
+
+        let tail = slices.end.max(depth - slices.len() as u32)..depth;
+        for (from, to) in tail.zip(slices.clone()) {
+            let (from, to) = (from * slice_len, to * slice_len);
+            let chunk = |instance: u32| {
+                self.chunks
+                    .iter()
+                    .find(|chunk| chunk.range.contains(&instance))
+                    .ok_or_else(|| InstanceError::new(format!("No instance chunk holds instance {instance}")))
+            };
+            let (source, target) = (chunk(from)?, chunk(to)?);
+            target.copy_slice(
+                device,
+                encoder,
+                source,
+                from - source.range.start,
+                to - target.range.start,
+                slice_len,
+            )?;
+        }
+        Ok(self.truncate(device, encoder, (depth - slices.len() as u32) * slice_len, slice_len))
+    }
+
+    /// Groups ranges of instances by chunk, with ranges local to their chunk.
+    pub fn batches(&self, ranges: &[Range<u32>]) -> Vec<Vec<Range<u32>>> {
+        self.chunks
+            .iter()
+            .map(|chunk| {
+                ranges
+                    .iter()
+                    .filter_map(|range| {
+                        let start = range.start.max(chunk.range.start);
+                        let end = range.end.min(chunk.range.end);
+                        (start < end).then(|| start - chunk.range.start..end - chunk.range.start)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Splits a range of instances into the parts held by each chunk, with
+    /// the chunk's index and ranges local to it.
+    pub fn split(&self, instances: Range<u32>) -> impl Iterator<Item = (usize, &InstanceChunk, Range<u32>)> {
+        self.chunks.iter().enumerate().filter_map(move |(index, chunk)| {
+            let start = instances.start.max(chunk.range.start);
+            let end = instances.end.min(chunk.range.end);
+            (start < end).then(|| (index, chunk, start - chunk.range.start..end - chunk.range.start))
+        })
+    }
+}
@@ -0,0 +1,376 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::Point3;
+
+use super::{
+    camera::DepthOrder,
+    mesh::{Instance, vertex_attributes},
+    shader::ShaderLoader,
+    texture::Texture2d,
+};
+
+/// 5x7 glyphs for ASCII `' '..='_'`, one byte per column with the top row
+/// in the lowest bit. Lowercase letters are drawn as uppercase.
+const FONT: [[u8; 5]; 64] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7f, 0x14, 0x7f, 0x14],
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1c, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1c, 0x00], [0x08, 0x2a, 0x1c, 0x2a, 0x08], [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x60, 0x60, 0x00, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00], [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4b, 0x31],
+    [0x18, 0x14, 0x12, 0x7f, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], [0x3c, 0x4a, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1e], [0x00, 0x36, 0x36, 0x00, 0x00], [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14], [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3e], [0x7e, 0x11, 0x11, 0x11, 0x7e], [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], [0x7f, 0x49, 0x49, 0x49, 0x41], [0x7f, 0x09, 0x09, 0x01, 0x01], [0x3e, 0x41, 0x41, 0x51, 0x32],
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00], [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41],
+    [0x7f, 0x40, 0x40, 0x40, 0x40], [0x7f, 0x02, 0x04, 0x02, 0x7f], [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e], [0x7f, 0x09, 0x19, 0x29, 0x46], [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7f, 0x01, 0x01], [0x3f, 0x40, 0x40, 0x40, 0x3f], [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x7f, 0x20, 0x18, 0x20, 0x7f],
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x03, 0x04, 0x78, 0x04, 0x03], [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7f, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7f, 0x00], [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40],
+];
+
+#[derive(Clone, Copy, Debug)]
+pub enum LabelAnchor {
+    Position(Point3<f32>),
+    /// Follows an instance, placed above it.
+    Instance(u32),
+    /// Overlay at a pixel position from the top left corner of the window.
+    Screen([f32; 2]),
+}
+
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub anchor: LabelAnchor,
+    /// Lines are separated by `\n`, characters outside the font show as `?`
+    /// and `█` fills the whole cell.
+    pub text: String,
+    pub color: [u8; 4],
+    /// Fills every character cell, including spaces, behind the text.
+    pub background: Option<[u8; 4]>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct GlyphInstance {
+    anchor: [f32; 3],
+    /// Position of the glyph within its label, in glyph cells.
+    offset: [f32; 2],
+    glyph: u32,
+    color: u32,
+    /// 1 when `anchor` is in pixels rather than world space.
+    screen: u32,
+}
+
+impl GlyphInstance {
+    const ATTRIBS: &'static [wgpu::VertexAttribute] = &vertex_attributes!(Self, 0 => {
+        anchor: Float32x3,
+        offset: Float32x2,
+        glyph: Uint32,
+        color: Uint32,
+        screen: Uint32,
+    });
+}
+
+impl Instance for GlyphInstance {
+    fn attribs() -> &'static [wgpu::VertexAttribute] {
+        Self::ATTRIBS
+    }
+}
+
+/// World-space text drawn as camera-facing glyph quads from a bitmap font
+/// atlas. Labels are scaled with distance to stay readable and fade out
+/// far away, see `label.wgsl`.
+pub struct LabelRenderer {
+    labels: Vec<Label>,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    glyph_buffer: wgpu::Buffer,
+    glyph_count: u32,
+}
+
+impl LabelRenderer {
+    const ATLAS_COLUMNS: u32 = 16;
+    /// Fully covered cell after the font, used for backgrounds.
+    const SOLID_GLYPH: u32 = FONT.len() as u32;
+    /// Glyph cells have a pixel of padding so neighbors don't bleed in.
+    const CELL_SIZE: (u32, u32) = (6, 8);
+    /// Distance of instance labels above the instance center.
+    const INSTANCE_OFFSET: f32 = 1.0;
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        default_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        shaders: &ShaderLoader,
+    ) -> Self {
+        let atlas = Self::create_atlas(device, queue);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("label_atlas"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("label_atlas"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&atlas.sampler),
+                },
+            ],
+        });
+
+        let mut bind_group_layouts = default_layouts.to_vec();
+        bind_group_layouts.push(&bind_group_layout);
+        let pipeline = Self::pipeline(device, &bind_group_layouts, color_format, sample_count, depth_order, shaders);
+
+        Self {
+            labels: Vec::new(),
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            glyph_buffer: Self::create_glyph_buffer(device, 256),
+            glyph_count: 0,
+        }
+    }
+
+    /// White glyphs with coverage in alpha, laid out in ASCII order.
+    fn create_atlas(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture2d {
+        let (cell_width, cell_height) = Self::CELL_SIZE;
+        let rows = (Self::SOLID_GLYPH + 1).div_ceil(Self::ATLAS_COLUMNS);
+        let size = (Self::ATLAS_COLUMNS * cell_width, rows * cell_height);
+
+        let mut pixels = vec![0u8; (size.0 * size.1 * 4) as usize];
+        for (index, columns) in FONT.iter().enumerate() {
+            let cell_x = index as u32 % Self::ATLAS_COLUMNS * cell_width;
+            let cell_y = index as u32 / Self::ATLAS_COLUMNS * cell_height;
+            for (x, column) in columns.iter().enumerate() {
+                for y in 0..7 {
+                    if column & (1 << y) == 0 {
+                        continue;
+                    }
+                    let pixel = ((cell_y + y) * size.0 + cell_x + x as u32) as usize * 4;
+                    pixels[pixel..pixel + 4].copy_from_slice(&[255; 4]);
+                }
+            }
+        }
+        let solid_x = Self::SOLID_GLYPH % Self::ATLAS_COLUMNS * cell_width;
+        let solid_y = Self::SOLID_GLYPH / Self::ATLAS_COLUMNS * cell_height;
+        for y in solid_y..solid_y + cell_height {
+            let row = ((y * size.0 + solid_x) * 4) as usize;
+            pixels[row..row + (cell_width * 4) as usize].fill(255);
+        }
+
+        Texture2d::from_bytes(
+            &pixels,
+            device,
+            queue,
+            size,
+            wgpu::TextureFormat::Rgba8Unorm,
+            Some("label_atlas"),
+        )
+    }
+
+    fn create_glyph_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("label_glyphs"),
+            size: (capacity * std::mem::size_of::<GlyphInstance>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        shaders: &ShaderLoader,
+    ) -> wgpu::RenderPipeline {
+        let module = shaders.module(device, "label.wgsl", include_str!("../shaders/label.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("label_pipeline_layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("label_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[
+                    GlyphInstance::desc(),
+                ]
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                targets: &[
+                    Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        write_mask: wgpu::ColorWrites::ALL,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    })
+                ]
+            }),
+            // Tested against the scene but not written, so overlapping labels all show
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: depth_order.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Rebuilds the pipeline for a scene with `sample_count` samples.
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        default_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        shaders: &ShaderLoader,
+    ) {
+        let mut bind_group_layouts = default_layouts.to_vec();
+        bind_group_layouts.push(&self.bind_group_layout);
+        self.pipeline = Self::pipeline(device, &bind_group_layouts, color_format, sample_count, depth_order, shaders);
+    }
+
+    pub fn add(&mut self, label: Label) {
+        self.labels.push(label);
+    }
+
+    /// Replaces the label on `instance`, if any.
+    pub fn set_instance_label(&mut self, instance: u32, text: String, color: [u8; 4]) {
+        self.labels
+            .retain(|label| !matches!(label.anchor, LabelAnchor::Instance(i) if i == instance));
+        self.add(Label {
+            anchor: LabelAnchor::Instance(instance),
+            text,
+            color,
+            background: None,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.labels.clear();
+    }
+
+    /// Lays out the glyphs of every label and of `overlays`, which are only
+    /// drawn this frame. Instance anchors are resolved against `positions`,
+    /// labels of instances that no longer exist are skipped.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, positions: &[[f32; 4]], overlays: &[Label]) {
+        let mut glyphs = Vec::new();
+        for label in self.labels.iter().chain(overlays) {
+            let (anchor, screen) = match label.anchor {
+                LabelAnchor::Position(position) => (position.into(), false),
+                LabelAnchor::Instance(instance) => match positions.get(instance as usize) {
+                    Some(p) => ([p[0], p[1] + Self::INSTANCE_OFFSET, p[2]], false),
+                    None => continue,
+                },
+                LabelAnchor::Screen([x, y]) => ([x, y, 0.0], true),
+            };
+            let color = u32::from_le_bytes(label.color);
+
+            let lines: Vec<&str> = label.text.lines().collect();
+            for (row, line) in lines.iter().enumerate() {
+                let width = line.chars().count() as f32;
+                for (column, c) in line.chars().enumerate() {
+                    let offset = if screen {
+                        // Left aligned, the first line hangs below the anchor
+                        [column as f32, -(row as f32) - 1.0]
+                    } else {
+                        // Centered horizontally, the last line sits on the anchor
+                        [column as f32 - width * 0.5, (lines.len() - 1 - row) as f32]
+                    };
+                    let mut glyph = GlyphInstance {
+                        anchor,
+                        offset,
+                        glyph: Self::SOLID_GLYPH,
+                        color,
+                        screen: screen as u32,
+                    };
+
+                    if let Some(background) = label.background {
+                        glyphs.push(GlyphInstance {
+                            color: u32::from_le_bytes(background),
+                            ..glyph
+                        });
+                    }
+                    glyph.glyph = match c.to_ascii_uppercase() {
+                        ' ' => continue,
+                        '\u{2588}' => Self::SOLID_GLYPH,
+                        c @ ' '..='_' => c as u32 - ' ' as u32,
+                        _ => '?' as u32 - ' ' as u32,
+                    };
+                    glyphs.push(glyph);
+                }
+            }
+        }
+
+        let size = std::mem::size_of_val(glyphs.as_slice()) as u64;
+        if size > self.glyph_buffer.size() {
+            self.glyph_buffer = Self::create_glyph_buffer(device, glyphs.len().next_power_of_two());
+        }
+        queue.write_buffer(&self.glyph_buffer, 0, bytemuck::cast_slice(&glyphs));
+        self.glyph_count = glyphs.len() as u32;
+    }
+
+    /// Expects the frame, camera and scene bind groups at groups 0 to 2.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
+        if self.glyph_count == 0 {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(3, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.glyph_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.glyph_count);
+    }
+}
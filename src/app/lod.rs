@@ -0,0 +1,401 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::Point3;
+use wgpu::util::DeviceExt;
+
+use super::{debug_labels, instances::{InstanceBuffers, InstanceColor, InstanceTransform}, mesh::Mesh, shader::ShaderLoader};
+
+/// Distances at which the default levels take over.
+#[derive(Debug, Clone)]
+pub struct LodSettings {
+    /// Where instances switch from the cube to the octahedron.
+    pub low_poly_distance: f32,
+    /// Where they switch to points.
+    pub point_distance: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            low_poly_distance: 1000.0,
+            point_distance: 4000.0,
+        }
+    }
+}
+
+/// Mesh drawn for the instances from `start` up to where the next level starts.
+pub struct LodLevel {
+    pub name: &'static str,
+    pub mesh: Mesh,
+    /// Drawn with a point list pipeline instead of triangles.
+    pub points: bool,
+    pub start: f32,
+}
+
+impl LodLevel {
+    /// Full cube, a low-poly octahedron and single points.
+    pub fn defaults(device: &wgpu::Device, settings: &LodSettings) -> Vec<Self> {
+        vec![
+            Self {
+                name: "cube",
+                mesh: Mesh::cube(device),
+                points: false,
+                start: 0.0,
+            },
+            Self {
+                name: "low-poly",
+                mesh: Mesh::octahedron(device, 0.7),
+                points: false,
+                start: settings.low_poly_distance,
+            },
+            Self {
+                name: "point",
+                mesh: Mesh::point(device),
+                points: true,
+                start: settings.point_distance,
+            },
+        ]
+    }
+}
+
+/// Mirrors `Params` in `lod.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct LodParams {
+    eye: [f32; 4],
+    thresholds: [f32; 4],
+    count: u32,
+    capacity: u32,
+    impostor_list: u32,
+    pixel_scale: f32,
+    impostor_threshold: f32,
+    _padding: [u32; 3],
+}
+
+/// Per-level instance lists of one chunk, laid out level after level.
+struct LodChunk {
+    params_buffer: wgpu::Buffer,
+    draws_buffer: wgpu::Buffer,
+    /// For either side of the simulation state.
+    bind_groups: [wgpu::BindGroup; 2],
+    positions: wgpu::Buffer,
+    transforms: wgpu::Buffer,
+    colors: wgpu::Buffer,
+    count: u32,
+}
+
+/// Level of detail selection on the GPU. Every frame a compute pass sorts
+/// the instances into a list per level by distance to the eye, counting each
+/// list into the arguments of an indirect draw, and each level is drawn with
+/// one instanced draw per chunk. With impostors, instances covering fewer
+/// pixels than the impostor threshold go to a list of their own instead,
+/// drawn as quads by the impostor pipeline. Expired instances, without
+/// lifetime left in the w of their velocity, go to no list.
+///
+/// Every list has room for the whole chunk, so the lists take as much
+/// memory as the instance data times the number of lists.
+pub struct Lod {
+    levels: Vec<LodLevel>,
+    /// Keeps a list of impostors after the levels.
+    impostors: bool,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    chunks: Vec<LodChunk>,
+    /// Indices of the six corners of an impostor quad.
+    quad_indices: wgpu::Buffer,
+}
+
+impl Lod {
+    pub const MAX_LEVELS: usize = 4;
+    const WORKGROUP_SIZE: u32 = 256;
+    const DRAW_SIZE: u64 = std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64;
+    /// The impostors' draw comes after one for every possible level.
+    const IMPOSTOR_DRAW: u64 = Self::MAX_LEVELS as u64;
+    const QUAD_INDICES: [u32; 6] = [0, 1, 2, 3, 4, 5];
+
+    /// `levels` start at increasing distances, the first at 0. `impostors`
+    /// keeps a list for them, see [`Self::draw_impostors`].
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLoader,
+        levels: Vec<LodLevel>,
+        impostors: bool,
+        instances: &InstanceBuffers,
+    ) -> Self {
+        assert!((1..=Self::MAX_LEVELS).contains(&levels.len()), "Between 1 and {} levels of detail", Self::MAX_LEVELS);
+        let module = shaders.module(device, "lod.wgsl", include_str!("../shaders/lod.wgsl"));
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("lod"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Uniform),
+                entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(2, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(3, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(4, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(5, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(6, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(7, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(8, wgpu::BufferBindingType::Storage { read_only: true }),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("lod_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("lod_pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: Some("bucket"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        log::info!(
+            "Levels of detail: {}.",
+            levels.iter().map(|level| format!("{} from {}", level.name, level.start)).collect::<Vec<_>>().join(", ")
+        );
+        let quad_indices = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("impostor_quad_indices"),
+            contents: bytemuck::cast_slice(&Self::QUAD_INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let mut lod = Self {
+            levels,
+            impostors,
+            layout,
+            pipeline,
+            chunks: Vec::new(),
+            quad_indices,
+        };
+        lod.resize(device, instances);
+        lod
+    }
+
+    /// Whether the lists of every chunk fit in a storage binding, with one
+    /// per level and another for impostors.
+    pub fn fits(limits: &wgpu::Limits, lists: usize, instances: &InstanceBuffers) -> bool {
+        let largest = instances.chunks.iter().map(|chunk| chunk.range.len() as u64).max().unwrap_or(0);
+        largest * lists as u64 * std::mem::size_of::<InstanceTransform>() as u64
+            <= limits.max_storage_buffer_binding_size as u64
+    }
+
+    pub fn levels(&self) -> &[LodLevel] {
+        &self.levels
+    }
+
+    pub fn impostors(&self) -> bool {
+        self.impostors
+    }
+
+    /// Lists every chunk keeps.
+    pub fn lists(&self) -> usize {
+        self.levels.len() + self.impostors as usize
+    }
+
+    /// Recreates the lists after the instance buffers changed.
+    pub fn resize(&mut self, device: &wgpu::Device, instances: &InstanceBuffers) {
+        let levels = self.lists() as u64;
+        self.chunks = instances
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let count = chunk.range.len() as u32;
+                let buffer = |label, size: u64, usage| {
+                    device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some(label),
+                        size,
+                        usage: wgpu::BufferUsages::STORAGE | usage,
+                        mapped_at_creation: false,
+                    })
+                };
+                let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("lod_params"),
+                    size: std::mem::size_of::<LodParams>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let draws_buffer = buffer(
+                    "lod_draws",
+                    (Self::IMPOSTOR_DRAW + 1) * Self::DRAW_SIZE,
+                    wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                );
+                let positions = buffer("lod_positions", chunk.positions[0].size() * levels, wgpu::BufferUsages::VERTEX);
+                let transforms = buffer("lod_transforms", chunk.transforms.size() * levels, wgpu::BufferUsages::VERTEX);
+                let colors = buffer("lod_colors", chunk.colors.size() * levels, wgpu::BufferUsages::VERTEX);
+
+                let bind_groups = [0, 1].map(|side| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("lod"),
+                        layout: &self.layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: params_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: chunk.positions[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: chunk.transforms.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: chunk.colors.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 4,
+                                resource: positions.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 5,
+                                resource: transforms.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 6,
+                                resource: colors.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 7,
+                                resource: draws_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 8,
+                                resource: chunk.velocities[side].as_binding(),
+                            },
+                        ],
+                    })
+                });
+
+                LodChunk {
+                    params_buffer,
+                    draws_buffer,
+                    bind_groups,
+                    positions,
+                    transforms,
+                    colors,
+                    count,
+                }
+            })
+            .collect();
+    }
+
+    /// Records the bucketing of every chunk for the eye, leaving out
+    /// instances past `draw_distance`, reading the `front` side of the
+    /// simulation state. `pixel_scale` is how many pixels tall an instance
+    /// of radius 1 is at distance 1, for the `impostor_threshold`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        front: usize,
+        eye: Point3<f32>,
+        draw_distance: f32,
+        pixel_scale: f32,
+        impostor_threshold: f32,
+    ) {
+        let max_groups = device.limits().max_compute_workgroups_per_dimension;
+        let mut thresholds = [f32::MAX; 4];
+        for (threshold, level) in thresholds.iter_mut().zip(&self.levels[1..]) {
+            *threshold = level.start;
+        }
+        let mut draws = Vec::new();
+        for index in 0..=Self::MAX_LEVELS {
+            let index_count = if index as u64 == Self::IMPOSTOR_DRAW {
+                Self::QUAD_INDICES.len() as u32
+            } else {
+                self.levels.get(index).map_or(0, |level| level.mesh.element_count())
+            };
+            let draw = wgpu::util::DrawIndexedIndirectArgs {
+                index_count,
+                instance_count: 0,
+                first_index: 0,
+                base_vertex: 0,
+                first_instance: 0,
+            };
+            draws.extend_from_slice(draw.as_bytes());
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("lod_pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let params = LodParams {
+                eye: [eye.x, eye.y, eye.z, draw_distance],
+                thresholds,
+                count: chunk.count,
+                capacity: chunk.count,
+                impostor_list: self.levels.len() as u32,
+                pixel_scale,
+                // Nothing is small enough without a list to put it in
+                impostor_threshold: if self.impostors { impostor_threshold } else { 0.0 },
+                _padding: [0; 3],
+            };
+            queue.write_buffer(&chunk.params_buffer, 0, bytemuck::bytes_of(&params));
+            queue.write_buffer(&chunk.draws_buffer, 0, &draws);
+
+            debug_labels::marker(&mut compute_pass, || format!("chunk {index}: {} instances", chunk.count));
+            compute_pass.set_bind_group(0, &chunk.bind_groups[front], &[]);
+            let groups = chunk.count.div_ceil(Self::WORKGROUP_SIZE);
+            compute_pass.dispatch_workgroups(groups.clamp(1, max_groups), groups.div_ceil(max_groups).max(1), 1);
+        }
+    }
+
+    /// Draws the instances of one level in every chunk, with a pipeline
+    /// using the default vertex streams.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, level: usize) {
+        let mesh = &self.levels[level].mesh;
+        for chunk in &self.chunks {
+            let list = |stride: u64| {
+                let size = chunk.count as u64 * stride;
+                level as u64 * size..(level as u64 + 1) * size
+            };
+            render_pass.set_vertex_buffer(2, chunk.transforms.slice(list(std::mem::size_of::<InstanceTransform>() as u64)));
+            render_pass.set_vertex_buffer(3, chunk.colors.slice(list(std::mem::size_of::<InstanceColor>() as u64)));
+            mesh.draw_indexed_indirect(
+                render_pass,
+                chunk.positions.slice(list(std::mem::size_of::<[f32; 4]>() as u64)),
+                &chunk.draws_buffer,
+                level as u64 * Self::DRAW_SIZE,
+            );
+        }
+    }
+
+    /// Draws the impostor list of every chunk, with a pipeline reading
+    /// positions and transforms from the first two vertex buffers and the
+    /// quad corner from the vertex index, see `impostor.wgsl`.
+    pub fn draw_impostors(&self, render_pass: &mut wgpu::RenderPass) {
+        if !self.impostors {
+            return;
+        }
+
+        let list = self.levels.len() as u64;
+        render_pass.set_index_buffer(self.quad_indices.slice(..), wgpu::IndexFormat::Uint32);
+        for chunk in &self.chunks {
+            let list = |stride: u64| {
+                let size = chunk.count as u64 * stride;
+                list * size..(list + 1) * size
+            };
+            render_pass.set_vertex_buffer(0, chunk.positions.slice(list(std::mem::size_of::<[f32; 4]>() as u64)));
+            render_pass.set_vertex_buffer(1, chunk.transforms.slice(list(std::mem::size_of::<InstanceTransform>() as u64)));
+            render_pass.draw_indexed_indirect(&chunk.draws_buffer, Self::IMPOSTOR_DRAW * Self::DRAW_SIZE);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::shader::ShaderFeatures;
+
+/// Surface appearance of a mesh. The feature set selects which permutation of
+/// the default shader draws it.
+#[derive(Clone, Copy, Debug)]
+pub struct Material {
+    pub features: ShaderFeatures,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            features: ShaderFeatures::INSTANCED_COLOR,
+        }
+    }
+}
+
+/// Entry of the material table instances index with their material id, drawn
+/// with the `MATERIALS` shader feature. Mirrors `MaterialParams` in
+/// `default.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct MaterialParams {
+    /// Linear RGB base color, alpha unused.
+    pub color: [f32; 4],
+    /// Linear RGB light given off regardless of lighting.
+    pub emissive: [f32; 3],
+    /// 0 for a sharp highlight, 1 for none.
+    pub roughness: f32,
+}
+
+impl MaterialParams {
+    /// Materials instances are spread across.
+    pub const PALETTE: [Self; 4] = [
+        // Matte clay
+        Self {
+            color: [0.8, 0.35, 0.2, 1.0],
+            emissive: [0.0; 3],
+            roughness: 1.0,
+        },
+        // Glossy plastic
+        Self {
+            color: [0.1, 0.4, 0.8, 1.0],
+            emissive: [0.0; 3],
+            roughness: 0.2,
+        },
+        // Polished metal
+        Self {
+            color: [0.75, 0.75, 0.7, 1.0],
+            emissive: [0.0; 3],
+            roughness: 0.05,
+        },
+        // Glowing
+        Self {
+            color: [0.2, 0.9, 0.4, 1.0],
+            emissive: [0.1, 0.6, 0.2],
+            roughness: 0.6,
+        },
+    ];
+}
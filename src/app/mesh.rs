@@ -3,6 +3,60 @@ use std::ops::Range;
 use bytemuck::{Pod, Zeroable};
 use wgpu::util::DeviceExt;
 
+/// Attributes of the fields of a `#[repr(C)]` vertex or instance struct, at
+/// their offsets within it and at consecutive shader locations from the
+/// first one given:
+///
+/// ```ignore
+/// const ATTRIBS: &'static [wgpu::VertexAttribute] = &vertex_attributes!(Self, 2 => {
+///     rotation: Float32x4,
+///     scale: Float32x4,
+/// });
+/// ```
+///
+/// A format may be wider than its field and read the fields after it, but
+/// attributes overlapping or running past the end of the struct fail to
+/// compile.
+macro_rules! vertex_attributes {
+    ($ty:ty, $first:expr => { $($field:ident: $format:ident),+ $(,)? }) => {
+        $crate::app::mesh::place_attributes(
+            $first,
+            std::mem::size_of::<$ty>(),
+            [$((std::mem::offset_of!($ty, $field), wgpu::VertexFormat::$format)),+],
+        )
+    };
+}
+pub(crate) use vertex_attributes;
+
+/// Places `fields`, offsets and formats, at consecutive shader locations
+/// from `first`, checking they fit in a struct of `size` bytes. Used through
+/// [`vertex_attributes`].
+pub const fn place_attributes<const N: usize>(
+    first: u32,
+    size: usize,
+    fields: [(usize, wgpu::VertexFormat); N],
+) -> [wgpu::VertexAttribute; N] {
+    let mut attributes = [wgpu::VertexAttribute {
+        format: wgpu::VertexFormat::Float32,
+        offset: 0,
+        shader_location: 0,
+    }; N];
+    let mut i = 0;
+    while i < N {
+        let (offset, format) = fields[i];
+        let end = offset as u64 + format.size();
+        assert!(end <= size as u64, "vertex attribute runs past the end of the struct");
+        assert!(i + 1 == N || end <= fields[i + 1].0 as u64, "vertex attributes overlap");
+        attributes[i] = wgpu::VertexAttribute {
+            format,
+            offset: offset as u64,
+            shader_location: first + i as u32,
+        };
+        i += 1;
+    }
+    attributes
+}
+
 pub trait Vertex: Pod + Zeroable {
     fn attribs() -> &'static [wgpu::VertexAttribute];
     fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -29,12 +83,20 @@ pub trait Instance: Pod + Zeroable {
 #[derive(Clone, Copy, Debug, Pod, Zeroable)]
 pub struct DefaultVertex3d {
     pub position: [f32; 3],
+    /// Outward normal of the surface, the same for every corner of a flat
+    /// face.
+    pub normal: [f32; 3],
 }
 
 impl DefaultVertex3d {
-    const ATTRIBS: &'static [wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
-        0 => Float32x3,
-    ];
+    /// After the instance attributes at locations 1 to 5, so shaders only
+    /// reading positions keep their locations.
+    pub const NORMAL_LOCATION: u32 = 6;
+    const ATTRIBS: &'static [wgpu::VertexAttribute] = &{
+        let [position] = vertex_attributes!(Self, 0 => { position: Float32x3 });
+        let [normal] = vertex_attributes!(Self, Self::NORMAL_LOCATION => { normal: Float32x3 });
+        [position, normal]
+    };
 }
 
 impl Vertex for DefaultVertex3d {
@@ -77,6 +139,113 @@ impl Mesh {
         }
     }
 
+    /// Unit cube centered on the origin, the instance shape. Every face has
+    /// corners of its own, 24 in all, so they light flat.
+    pub fn cube(device: &wgpu::Device) -> Self {
+        // Outward normal, then two axes along the face turning
+        // counter-clockwise about it
+        const FACES: [[[f32; 3]; 3]; 6] = [
+            [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            [[-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]],
+            [[0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]],
+            [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]],
+            [[0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            [[0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+        ];
+
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+        for [normal, u, v] in FACES {
+            let first = vertices.len() as u32;
+            for (s, t) in [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)] {
+                vertices.push(DefaultVertex3d {
+                    position: std::array::from_fn(|i| 0.5 * normal[i] + s * u[i] + t * v[i]),
+                    normal,
+                });
+            }
+            indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+        Self::create(device, &vertices, &indices)
+    }
+
+    /// Octahedron with its corners `radius` out along the axes, a coarse
+    /// stand-in for the cube at a distance. Faces have corners of their own
+    /// like the cube's.
+    pub fn octahedron(device: &wgpu::Device, radius: f32) -> Self {
+        let mut vertices = Vec::with_capacity(24);
+        for octant in 0..8 {
+            let sign = |bit: u32| if octant & (1 << bit) == 0 { 1.0 } else { -1.0 };
+            let [x, y, z] = [sign(0), sign(1), sign(2)];
+            let normal = [x, y, z].map(|s| s / 3.0f32.sqrt());
+            let corners = [[x * radius, 0.0, 0.0], [0.0, y * radius, 0.0], [0.0, 0.0, z * radius]];
+            // Mirrored octants turn the other way
+            let order = if x * y * z > 0.0 { [0, 1, 2] } else { [0, 2, 1] };
+            vertices.extend(order.map(|i| DefaultVertex3d {
+                position: corners[i],
+                normal,
+            }));
+        }
+        let indices: Vec<u32> = (0..vertices.len() as u32).collect();
+        Self::create(device, &vertices, &indices)
+    }
+
+    /// Sphere centered on the origin, with `rings` rows of `segments` quads
+    /// from pole to pole.
+    pub fn sphere(device: &wgpu::Device, radius: f32, rings: u32, segments: u32) -> Self {
+        let mut vertices = Vec::new();
+        for ring in 0..=rings {
+            let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+            for segment in 0..=segments {
+                let phi = std::f32::consts::TAU * segment as f32 / segments as f32;
+                let normal = [theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()];
+                vertices.push(DefaultVertex3d {
+                    position: normal.map(|n| radius * n),
+                    normal,
+                });
+            }
+        }
+
+        let mut indices = Vec::new();
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let a = ring * (segments + 1) + segment;
+                let b = a + segments + 1;
+                indices.extend_from_slice(&[a, a + 1, b, a + 1, b + 1, b]);
+            }
+        }
+        Self::create(device, &vertices, &indices)
+    }
+
+    /// Unit square in the xy plane facing +z, centered on the origin.
+    pub fn quad(device: &wgpu::Device) -> Self {
+        Self::create(
+            device,
+            &[
+                DefaultVertex3d { position: [-0.5, -0.5, 0.0], normal: [0.0, 0.0, 1.0]},
+                DefaultVertex3d { position: [0.5, -0.5, 0.0], normal: [0.0, 0.0, 1.0]},
+                DefaultVertex3d { position: [0.5, 0.5, 0.0], normal: [0.0, 0.0, 1.0]},
+                DefaultVertex3d { position: [-0.5, 0.5, 0.0], normal: [0.0, 0.0, 1.0]},
+            ],
+            &[
+                0, 1, 2,
+                0, 2, 3,
+            ]
+        )
+    }
+
+    /// Single vertex at the origin, drawn with a point list pipeline. Points
+    /// face up, lit like the tops of cubes.
+    pub fn point(device: &wgpu::Device) -> Self {
+        Self::create(
+            device,
+            &[DefaultVertex3d {
+                position: [0.0; 3],
+                normal: [0.0, 1.0, 0.0],
+            }],
+            &[0],
+        )
+    }
+
     pub fn draw_instanced(
         &self,
         render_pass: &mut wgpu::RenderPass,
@@ -90,6 +259,56 @@ impl Mesh {
         render_pass.draw_indexed(0..self.element_count as u32, 0, instances);
     }
 
+    /// Like [`Self::draw_instanced`], recorded into a render bundle.
+    pub fn bundle_instanced<'a>(
+        &'a self,
+        encoder: &mut wgpu::RenderBundleEncoder<'a>,
+        instance_buffer: &'a wgpu::Buffer,
+        instances: Range<u32>,
+    ) {
+        encoder.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        encoder.set_vertex_buffer(1, instance_buffer.slice(..));
+        encoder.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+        encoder.draw_indexed(0..self.element_count as u32, 0, instances);
+    }
+
+    /// Issues `count` draws whose arguments start at `offset` in `indirect_buffer`.
+    pub fn draw_multi_indirect(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        instance_buffer: &wgpu::Buffer,
+        indirect_buffer: &wgpu::Buffer,
+        offset: u64,
+        count: u32,
+    ) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+        render_pass.multi_draw_indexed_indirect(indirect_buffer, offset, count);
+    }
+
+    /// Draws with the arguments at `offset` in `indirect_buffer`, the instance
+    /// count usually written by a compute pass.
+    pub fn draw_indexed_indirect(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        instances: wgpu::BufferSlice,
+        indirect_buffer: &wgpu::Buffer,
+        offset: u64,
+    ) {
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instances);
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+        render_pass.draw_indexed_indirect(indirect_buffer, offset);
+    }
+
+    pub fn element_count(&self) -> u32 {
+        self.element_count as u32
+    }
+
     pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
         // TODO: Move to bundle?
         render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
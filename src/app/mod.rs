@@ -1,30 +1,139 @@
+mod bookmarks;
 mod camera;
+mod capture;
+mod collision;
+mod compaction;
+mod config;
+mod console;
+mod cpu_kernels;
+mod debug_labels;
+mod deferred;
+mod demo;
+mod emitter;
+mod frames;
+mod gpu_readback;
+mod graph;
+mod greedy;
+mod group;
+mod hierarchy;
+mod history;
+mod hot_reload;
+mod culling;
+mod impostor;
+mod indirect;
+mod instances;
+mod isosurface;
+mod label;
+mod lod;
+mod material;
 mod mesh;
+mod occlusion;
+mod octree;
+mod offsets;
+mod oit;
+mod params;
+mod picking;
+mod pointcloud;
+mod pool;
+mod post;
+mod profiler;
+mod random;
+mod raycast;
+mod registry;
+mod reset;
+mod scene;
+mod selection;
+mod shader;
+mod shadow;
+mod sort;
+mod statistics;
+mod stream;
 mod texture;
+mod timing;
+mod tonemap;
+mod upscale;
+mod voxel;
+mod wells;
 
-use std::{collections::HashMap, error::Error, sync::Arc, time::Instant};
+use std::{collections::HashMap, error::Error, ops::Range, path::PathBuf, sync::Arc, time::{Duration, Instant}};
 
 use bytemuck::{Pod, Zeroable};
-use camera::{Camera, CameraController};
-use mesh::{DefaultVertex3d, Instance, Mesh, Vertex};
+use bookmarks::{Bookmarks, CameraTransition, Viewpoint};
+use camera::{Camera, CameraAttractor, CameraController, DepthOrder};
+use capture::TurntableCapture;
+use collision::{BoundsBehavior, BoundsUniform, Collider, Collision, Obstacles, WorldBounds};
+use compaction::Compaction;
+use console::{Command, Console, ConsoleError, ConsoleResult};
+pub use config::AppConfig;
+use config::{BackgroundMode, PRESETS};
+use culling::{ChunkBounds, ChunkCuller, CullingMode};
+use deferred::Deferred;
+use demo::{Demo, DemoContext};
+use emitter::{Emitter, EmitterUniform};
+use frames::FrameRing;
+use gpu_readback::{Readback, ReadbackResult};
+use graph::RenderGraph;
+use greedy::{GreedyMesh, GreedyVertex, RenderMode, VoxelSource};
+use group::InstanceGroup;
+use hierarchy::Hierarchy;
+use history::History;
+use hot_reload::ShaderWatcher;
+use impostor::ImpostorAtlas;
+use indirect::{KernelDispatch, MultiDraw};
+use instances::{
+    InstanceBuffers, InstanceColor, InstanceColoring, InstanceData, InstanceResult, InstanceTransform, TransformSettings,
+};
+use isosurface::{Isosurface, IsosurfaceVertex};
+use label::{Label, LabelAnchor, LabelRenderer};
+use lod::{Lod, LodLevel, LodSettings};
+use material::{Material, MaterialParams};
+use mesh::{DefaultVertex3d, Instance, Mesh, Vertex, vertex_attributes};
+use occlusion::OcclusionCuller;
+use offsets::ChunkOffsets;
+use oit::WeightedBlended;
+use params::{SimParams, SimParamsBuffer};
+use picking::Picker;
 use pollster::FutureExt;
+use pool::BufferPool;
+use post::{Bloom, PostEffect};
+use profiler::{GpuProfiler, ProfiledPass};
 use rand::Rng;
+use raycast::{Hit, Raycaster};
+use reset::GpuReset;
+use scene::SceneSettings;
+use selection::Selection;
+use stream::DatasetStreamer;
+use sort::InstanceSorter;
+use shader::{RenderModules, ShaderError, ShaderFeatures, ShaderLoader, ShaderPermutations, ShaderResult};
+use shadow::ShadowMaps;
+use statistics::{SimulationStatistics, SimulationStats};
 use texture::Texture2d;
+use timing::{DeltaSmoother, FixedTimestep, FrameStats, LatencyTracker, Replay};
+use tonemap::{ToneMapper, ToneMapping};
+use upscale::Upscale;
+use wells::GravityWells;
 use wgpu::util::DeviceExt;
 use winit::{dpi::PhysicalSize, event::WindowEvent, keyboard::{KeyCode, PhysicalKey}, window::Window};
 
 use crate::window::Game;
 
+/// Position of an instance, copied straight from the simulation. Rotation
+/// and scale come in a second vertex stream, see `InstanceTransform`.
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Pod, Zeroable)]
 pub struct InstanceRepr {
-    position: [f32; 4],
+    position: [f32; 3],
+    /// Offset of the `pulse` animation in cycles, random so instances don't
+    /// pulse in lockstep. The kernels leave it alone. Imported points keep
+    /// their color or voxel run bits here instead.
+    phase: f32,
 }
 
 impl InstanceRepr {
-    const ATTRIBS: &'static [wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
-        1 => Float32x4,
-    ];
+    // The phase rides along in w
+    const ATTRIBS: &'static [wgpu::VertexAttribute] = &vertex_attributes!(Self, 1 => {
+        position: Float32x4,
+    });
 }
 
 impl Instance for InstanceRepr {
@@ -33,30 +142,445 @@ impl Instance for InstanceRepr {
     }
 }
 
+/// Per-frame data bound at group 0 of every render and compute pipeline.
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Pod, Zeroable)]
-pub struct WorldInfo {
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+pub struct FrameUniform {
+    dimensions: [u32; 4],
+    resolution: [f32; 2],
     time: f32,
     delta: f32,
+    frame_index: u32,
+    _padding: [u32; 3],
+}
+
+/// Simulation variant, specialized through the `override` constants of
+/// `compute.wgsl` when the pipeline is created. `params` are read from a
+/// uniform buffer and `bounds`, `boids` and `follow` are pushed with every
+/// step instead, so they can be tuned without rebuilding the pipelines.
+#[derive(Clone, Copy, Debug)]
+pub struct SimulationConstants {
+    pub params: SimParams,
+    pub attract: bool,
+    pub integrator: Integrator,
+    pub bounds: WorldBounds,
+    pub boids: BoidParams,
+    pub follow: CameraAttractor,
+    /// Workgroup size of the kernels, see `App::select_workgroup_dims`.
+    pub workgroup_dims: (u32, u32, u32),
+}
+
+impl Default for SimulationConstants {
+    fn default() -> Self {
+        Self {
+            params: SimParams::default(),
+            attract: true,
+            integrator: Integrator::default(),
+            bounds: WorldBounds::default(),
+            boids: BoidParams::default(),
+            follow: CameraAttractor::default(),
+            workgroup_dims: App::PREFERRED_WORKGROUP_DIMS,
+        }
+    }
 }
 
+/// Mirrors `ComputePushConstants` in `compute.wgsl`, set for every step of
+/// the kernels. As much of it is pushed as the adapter has room for, the hot
+/// fields first, and the rest is uploaded, see [`SimParamsBuffer`].
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Pod, Zeroable)]
 pub struct ComputePushConstants {
-    dimensions: [u32; 4],
-    world_info: WorldInfo,
+    hot: HotConstants,
+    cold: ColdConstants,
+}
+
+/// Mirrors `HotConstants` in `compute.wgsl`, moving with the camera.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct HotConstants {
+    follow: [f32; 4],
+}
+
+/// Mirrors `ColdConstants` in `compute.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct ColdConstants {
+    bounds: BoundsUniform,
+    boids: BoidParams,
+    _padding: [u32; 2],
+    emitter: EmitterUniform,
+}
+
+impl ComputePushConstants {
+    pub const SIZE: u32 = std::mem::size_of::<Self>() as u32;
+    /// Bytes pushed when the adapter has no room for all of them.
+    pub const HOT_SIZE: u32 = std::mem::size_of::<HotConstants>() as u32;
+
+    pub fn new(simulation: &SimulationConstants, emitter: EmitterUniform, camera: &Camera) -> Self {
+        Self {
+            hot: HotConstants {
+                follow: simulation.follow.uniform(camera),
+            },
+            cold: ColdConstants {
+                bounds: simulation.bounds.uniform(),
+                boids: simulation.boids,
+                _padding: [0; 2],
+                emitter,
+            },
+        }
+    }
+
+    /// Bytes of the constants pushed with `limit` bytes of push constants,
+    /// all, the hot ones or none. The rest is uploaded.
+    pub fn pushed_size(limit: u32) -> u32 {
+        [Self::SIZE, Self::HOT_SIZE]
+            .into_iter()
+            .find(|&size| size <= limit)
+            .unwrap_or(0)
+    }
+
+    /// Bytes of the constants pushed on `device`, which is only created with
+    /// push constants for as many bytes as it pushes.
+    pub fn device_pushed_size(device: &wgpu::Device) -> u32 {
+        if device.features().contains(wgpu::Features::PUSH_CONSTANTS) {
+            Self::pushed_size(device.limits().max_push_constant_size)
+        } else {
+            0
+        }
+    }
+
+    /// Defines selecting the constants `compute.wgsl` reads from push
+    /// constants when `pushed_size` bytes of them are pushed.
+    pub fn defines(pushed_size: u32) -> &'static [&'static str] {
+        match pushed_size {
+            0 => &[],
+            Self::SIZE => &["PUSH_CONSTANTS"],
+            _ => &["PUSH_HOT_CONSTANTS"],
+        }
+    }
 }
 
+/// Mirrors `BoidParams` in `compute.wgsl`, read from the
+/// [`ComputePushConstants`] by the boids kernel.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct BoidParams {
+    pub radius: f32,
+    pub separation: f32,
+    pub alignment: f32,
+    pub cohesion: f32,
+    pub speed: f32,
+    pub neighbors: u32,
+}
+
+impl Default for BoidParams {
+    fn default() -> Self {
+        Self {
+            radius: 200.0,
+            separation: 40.0,
+            alignment: 0.5,
+            cohesion: 0.05,
+            speed: 40.0,
+            neighbors: 16,
+        }
+    }
+}
+
+/// Time integration under the attractor, see `integrate` in `compute.wgsl`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+    Euler,
+    #[default]
+    SemiImplicitEuler,
+    Verlet,
+}
+
+impl Integrator {
+    pub const ALL: [Self; 3] = [Self::Euler, Self::SemiImplicitEuler, Self::Verlet];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Euler => "euler",
+            Self::SemiImplicitEuler => "semi-implicit",
+            Self::Verlet => "verlet",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|integrator| integrator.name() == name)
+    }
+}
+
+/// CPU reference of a kernel, used when compute shaders are unavailable.
+pub type CpuKernel = fn(&mut [[f32; 4]], &mut [[f32; 4]], f32, &SimulationConstants);
+
+/// Simulation kernel that can be switched at runtime, an entry point of
+/// `compute.wgsl` together with its CPU reference.
+#[derive(Clone, Copy, Debug)]
+pub struct SimulationKernel {
+    pub name: &'static str,
+    pub entry_point: &'static str,
+    pub cpu: CpuKernel,
+    /// Replaces `SimulationConstants::attract`. Only instances pulled by the
+    /// attractor have a potential energy.
+    pub attract: bool,
+    /// Entry points dispatched in order before every step, each over the
+    /// whole state before the kernel reads what they wrote.
+    pub prepasses: &'static [&'static str],
+    /// Particles drawn as spheres in place of cubes.
+    pub spheres: bool,
+}
+
+impl SimulationKernel {
+    pub fn constants(&self, simulation: &SimulationConstants) -> SimulationConstants {
+        SimulationConstants {
+            attract: self.attract,
+            ..*simulation
+        }
+    }
+}
+
+/// Every kernel gets its own compute pipeline over the same instance buffers,
+/// so switching keeps the simulated state.
+pub const KERNELS: &[SimulationKernel] = &[
+    SimulationKernel {
+        name: "attract",
+        entry_point: "compute_main",
+        cpu: cpu_kernels::compute_main,
+        attract: true,
+        prepasses: &[],
+        spheres: false,
+    },
+    SimulationKernel {
+        name: "drift",
+        entry_point: "compute_main",
+        cpu: cpu_kernels::compute_main,
+        attract: false,
+        prepasses: &[],
+        spheres: false,
+    },
+    SimulationKernel {
+        name: "vortex",
+        entry_point: "vortex_main",
+        cpu: cpu_kernels::vortex_main,
+        attract: false,
+        prepasses: &[],
+        spheres: false,
+    },
+    SimulationKernel {
+        name: "flock",
+        entry_point: "flock_main",
+        cpu: cpu_kernels::flock_main,
+        attract: false,
+        prepasses: &[],
+        spheres: false,
+    },
+    SimulationKernel {
+        name: "wave",
+        entry_point: "wave_main",
+        cpu: cpu_kernels::wave_main,
+        attract: false,
+        prepasses: &[],
+        spheres: false,
+    },
+    SimulationKernel {
+        name: "nbody",
+        entry_point: "nbody_main",
+        cpu: cpu_kernels::nbody_main,
+        attract: false,
+        prepasses: &[],
+        spheres: false,
+    },
+    SimulationKernel {
+        name: "nbody-tiled",
+        entry_point: "nbody_tiled_main",
+        cpu: cpu_kernels::nbody_main,
+        attract: false,
+        prepasses: &[],
+        spheres: false,
+    },
+    SimulationKernel {
+        name: "curl",
+        entry_point: "curl_main",
+        cpu: cpu_kernels::curl_main,
+        attract: false,
+        prepasses: &[],
+        spheres: false,
+    },
+    SimulationKernel {
+        name: "boids",
+        entry_point: "boids_main",
+        cpu: cpu_kernels::boids_main,
+        attract: false,
+        prepasses: &[],
+        spheres: false,
+    },
+    SimulationKernel {
+        name: "sph",
+        entry_point: "sph_force_main",
+        cpu: cpu_kernels::sph_main,
+        attract: false,
+        prepasses: &["sph_clear_main", "sph_hash_main", "sph_density_main"],
+        spheres: true,
+    },
+];
+
 #[allow(dead_code)]
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum PipelineSelector {
     Default,
-    Compute,
     Custom {
         name: &'static str
     }
 }
 
+/// Pipeline drawing the instances with `default.wgsl`, rebuilt with the
+/// material.
+struct DefaultVariant {
+    selector: PipelineSelector,
+    label: &'static str,
+    /// Blends and leaves the depth buffer alone, for instances drawn back
+    /// to front after everything opaque.
+    transparent: bool,
+    topology: wgpu::PrimitiveTopology,
+    cull_mode: Option<wgpu::Face>,
+    /// Anything but `Fill` needs an adapter feature, see
+    /// [`DefaultVariant::supported`].
+    polygon_mode: wgpu::PolygonMode,
+}
+
+impl DefaultVariant {
+    const POLYGON_MODE_FEATURES: wgpu::Features =
+        wgpu::Features::POLYGON_MODE_LINE.union(wgpu::Features::POLYGON_MODE_POINT);
+
+    fn supported(&self, features: wgpu::Features) -> bool {
+        match self.polygon_mode {
+            wgpu::PolygonMode::Fill => true,
+            wgpu::PolygonMode::Line => features.contains(wgpu::Features::POLYGON_MODE_LINE),
+            wgpu::PolygonMode::Point => features.contains(wgpu::Features::POLYGON_MODE_POINT),
+        }
+    }
+
+    /// Selector of the pipeline drawing the opaque instances in `polygon_mode`.
+    fn opaque_selector(polygon_mode: wgpu::PolygonMode) -> PipelineSelector {
+        match polygon_mode {
+            wgpu::PolygonMode::Fill => PipelineSelector::Default,
+            wgpu::PolygonMode::Line => PipelineSelector::Custom { name: "wireframe" },
+            wgpu::PolygonMode::Point => PipelineSelector::Custom { name: "vertices" },
+        }
+    }
+}
+
+const DEFAULT_VARIANTS: &[DefaultVariant] = &[
+    DefaultVariant {
+        selector: PipelineSelector::Default,
+        label: "default_pipeline",
+        transparent: false,
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        cull_mode: Some(wgpu::Face::Back),
+        polygon_mode: wgpu::PolygonMode::Fill,
+    },
+    DefaultVariant {
+        selector: PipelineSelector::Custom { name: "transparent" },
+        label: "transparent_pipeline",
+        transparent: true,
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        cull_mode: Some(wgpu::Face::Back),
+        polygon_mode: wgpu::PolygonMode::Fill,
+    },
+    // Distant levels of detail
+    DefaultVariant {
+        selector: PipelineSelector::Custom { name: "points" },
+        label: "points_pipeline",
+        transparent: false,
+        topology: wgpu::PrimitiveTopology::PointList,
+        cull_mode: Some(wgpu::Face::Back),
+        polygon_mode: wgpu::PolygonMode::Fill,
+    },
+    // Flat instance group meshes
+    DefaultVariant {
+        selector: PipelineSelector::Custom { name: "double_sided" },
+        label: "double_sided_pipeline",
+        transparent: false,
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        cull_mode: None,
+        polygon_mode: wgpu::PolygonMode::Fill,
+    },
+    // Edges and corners of the opaque instances, cycled with V
+    DefaultVariant {
+        selector: PipelineSelector::Custom { name: "wireframe" },
+        label: "wireframe_pipeline",
+        transparent: false,
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        cull_mode: Some(wgpu::Face::Back),
+        polygon_mode: wgpu::PolygonMode::Line,
+    },
+    DefaultVariant {
+        selector: PipelineSelector::Custom { name: "vertices" },
+        label: "vertices_pipeline",
+        transparent: false,
+        topology: wgpu::PrimitiveTopology::TriangleList,
+        cull_mode: Some(wgpu::Face::Back),
+        polygon_mode: wgpu::PolygonMode::Point,
+    },
+];
+
+/// Targets a variant of the default pipelines is built for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DefaultTarget {
+    /// The scene, with its sample count.
+    Scene,
+    /// The G-buffer of deferred shading, see [`Deferred`].
+    GBuffer,
+    /// The accumulation targets of translucent groups, see
+    /// [`WeightedBlended`].
+    Accumulation,
+}
+
+/// Steps recorded by `update` and not submitted yet, see
+/// [`App::submit_simulation`].
+struct PendingSimulation {
+    commands: wgpu::CommandBuffer,
+    /// Statistics were recorded, their readback starts once submitted.
+    statistics: bool,
+    /// So were the chunk bounds.
+    chunk_bounds: bool,
+}
+
+/// Passes of a frame, scheduled by [`App::render_graph`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FramePass {
+    /// Sorting, level of detail selection, culling and isosurface
+    /// extraction ahead of the draws.
+    Prepare,
+    /// Instance depth from the light, with the `shadowed` feature.
+    Shadows,
+    /// The opaque instances into the G-buffer, with deferred shading.
+    GBuffer,
+    /// Everything drawn with depth, and the text labels on top unless
+    /// translucent groups follow.
+    Scene,
+    /// Translucent groups, blended over the scene without sorting, and the
+    /// text labels on top.
+    Transparency,
+    /// Index into [`App::post_effects`].
+    PostEffect(usize),
+    ToneMapping,
+    Upscale,
+    Capture,
+}
+
+// Resources of the render graph
+const SURFACE: &str = "surface";
+const VISIBLE_INSTANCES: &str = "visible_instances";
+const SHADOW_MAPS: &str = "shadow_maps";
+/// Owned by [`Deferred`].
+const G_BUFFER: &str = "g_buffer";
+/// Resolved scene, in HDR, when tone mapped.
+const HDR_SCENE: &str = "hdr_scene";
+/// Resolved scene at the internal resolution when upscaled.
+const UPSCALE_SCENE: &str = "upscale_scene";
+
 #[allow(dead_code)]
 pub enum Pipeline {
     Render(wgpu::RenderPipeline),
@@ -69,42 +593,197 @@ pub struct App<'a> {
     instance: wgpu::Instance,
     surface: wgpu::Surface<'a>,
     surface_config: wgpu::SurfaceConfiguration,
-    multisample_framebuffer: wgpu::TextureView,
+    /// Resolved into the graph's scene target, rendered to directly with a
+    /// single sample.
+    multisample_framebuffer: Option<wgpu::TextureView>,
+    /// Samples per pixel of the scene, see `--msaa`.
+    sample_count: u32,
     depth_texture: Texture2d,
+    /// Orders the passes of a frame and owns the textures between them.
+    render_graph: RenderGraph<FramePass>,
+    /// Renders the scene below the native resolution when set.
+    upscale: Option<Upscale>,
+    /// Renders the scene in HDR when set.
+    tone_mapping: Option<ToneMapping>,
+    /// Makes the bright parts of the HDR scene glow when set.
+    bloom: Option<Bloom>,
+    /// Shades the opaque instances from a G-buffer when set, see `--deferred`.
+    deferred: Option<Deferred>,
+    /// Blends the translucent groups over the scene when there are any.
+    weighted_blended: Option<WeightedBlended>,
+    /// Color format of the scene pipelines and their multisampled target.
+    scene_format: wgpu::TextureFormat,
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
     
     pipelines: HashMap<PipelineSelector, Pipeline>,
     cube_mesh: Mesh,
+    /// Drawn in place of the cube for kernels simulating round particles.
+    sphere_mesh: Mesh,
+    /// Static instances with meshes of their own, see `--group`.
+    instance_groups: Vec<InstanceGroup>,
+    /// Draws of every instance group whole in the scene pass, indexed by
+    /// group and then frame slot, since each binds its slot's uniform
+    /// offset. Recorded again on the next frame after being cleared,
+    /// whenever their pipelines change.
+    group_bundles: Option<Vec<Vec<wgpu::RenderBundle>>>,
+    impostor_atlas: Option<ImpostorAtlas>,
+    shaders: ShaderLoader,
+    default_shaders: ShaderPermutations,
+    default_layouts: Vec<wgpu::BindGroupLayout>,
+    /// The default layouts followed by the chunk offsets, for the pipelines
+    /// drawing the instance chunks.
+    instance_layouts: Vec<wgpu::BindGroupLayout>,
+    chunk_offsets: ChunkOffsets,
+    material: Material,
+    shader_error: Option<ShaderError>,
+    /// Watches the shader directory with `--hot-reload`.
+    shader_watcher: Option<ShaderWatcher>,
+    render_mode: RenderMode,
+    /// How the opaque instances are rasterized, cycled with V.
+    polygon_mode: wgpu::PolygonMode,
+    /// Built from the CPU-side positions the first time greedy meshing is switched on.
+    greedy_mesh: Option<GreedyMesh>,
 
     camera: Camera,
     camera_controller: CameraController,
+    bookmarks: Bookmarks,
+    camera_transition: Option<CameraTransition>,
+    modifiers: winit::keyboard::ModifiersState,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
 
+    scene: SceneSettings,
+    scene_buffer: wgpu::Buffer,
+    scene_bind_group: wgpu::BindGroup,
+    /// Bound with the scene, drawn with the `shadowed` feature.
+    shadow_maps: ShadowMaps,
+
+    dimensions: [u32; 4],
     positions: Vec<[f32; 4]>,
     velocities: Vec<[f32; 4]>,
-    positions_buffer_vsh: wgpu::Buffer,
-    positions_buffer: wgpu::Buffer,
-    velocities_buffer: wgpu::Buffer,
-    pv_bind_group: wgpu::BindGroup,
+    transforms: Vec<InstanceTransform>,
+    transform_settings: TransformSettings,
+    colors: Vec<InstanceColor>,
+    coloring: InstanceColoring,
+    /// Opacity of the instances, 1 unless they can be sorted.
+    instance_alpha: f32,
+    instance_buffers: InstanceBuffers,
+    /// Draws the instances from an argument buffer when supported.
+    multi_draw: Option<MultiDraw>,
+    /// Orders the instances back to front when they are transparent.
+    sorter: Option<InstanceSorter>,
+    /// Draws distant instances with coarser meshes.
+    lod: Option<Lod>,
+    /// Leaves expired instances out of the draw.
+    compaction: Option<Compaction>,
+    /// Created the first time occlusion culling is turned on.
+    occlusion: Option<OcclusionCuller>,
+    /// Longest instance lifetime, when instances expire.
+    lifetime: Option<f32>,
+    /// Respawns expired instances.
+    emitter: Emitter,
+    /// Places the instances relative to their cluster.
+    hierarchy: Option<Hierarchy>,
+    /// One pair per instance chunk, indexed by the side of the state read.
+    pv_bind_groups: Option<Vec<[wgpu::BindGroup; 2]>>,
+    /// Workgroup counts of the kernels per instance chunk.
+    kernel_dispatch: KernelDispatch,
+    /// Scatters the instances again on the GPU, see [`App::reset_simulation`].
+    gpu_reset: GpuReset,
+    raycaster: Option<Raycaster>,
+    /// Created on the first pick.
+    picker: Option<Picker>,
+    /// Picked instances, outlined in the scene.
+    selection: Selection,
+    streamer: Option<DatasetStreamer>,
+    isosurface: Option<Isosurface>,
+    sdf: bool,
+    labels: LabelRenderer,
+    capture: Option<TurntableCapture>,
+    replay: Option<Replay>,
+    history: Option<History>,
+    collision: Collision,
+    sim_params: SimParamsBuffer,
+    /// Dropped with the middle mouse button, uploaded to `sim_params`.
+    gravity_wells: GravityWells,
+    console: Console,
+    buffer_pool: BufferPool,
+    /// Recycled staging memory for the per frame uploads.
+    staging_belt: wgpu::util::StagingBelt,
+    frame: FrameUniform,
+    frame_buffer: wgpu::Buffer,
+    frame_bind_group: wgpu::BindGroup,
+    frame_ring: FrameRing,
+    low_latency: bool,
+    latency: LatencyTracker,
+    /// Times the passes with `--gpu-profile`.
+    profiler: Option<GpuProfiler>,
+
+    culling_mode: CullingMode,
+    chunk_culler: ChunkCuller,
+    /// Recomputes the bounds of `chunk_culler` from the simulated state,
+    /// `None` in compatibility mode which simulates on the CPU.
+    chunk_bounds: Option<ChunkBounds>,
 
     start_time: Instant,
     time: f64,
     last_delta: f64,
+    delta_smoother: DeltaSmoother,
+    timestep: FixedTimestep,
+    frame_stats: FrameStats,
+    run_frame_stats: FrameStats,
+    last_stats_time: f64,
+    simulation_statistics: Option<SimulationStatistics>,
+    /// Submitted along with the next frame, so its kernels overlap the
+    /// frame's passes.
+    pending_simulation: Option<PendingSimulation>,
+    /// Latest statistics, `None` until the first readback.
+    simulation_stats: Option<SimulationStats>,
+    /// First statistics since the simulation last changed, energy and
+    /// center of mass drift are measured against it.
+    simulation_reference: Option<SimulationStats>,
+    last_simulation_stats_time: f64,
+    show_statistics: bool,
+    base_title: String,
+    title_status: String,
+    preset: Option<&'static str>,
+    compat: bool,
+    simulation: SimulationConstants,
+    /// Index into `KERNELS`.
+    kernel: usize,
+    demo: Box<dyn Demo>,
+    demo_name: &'static str,
+    frame_interval: Option<Duration>,
+    next_frame: Instant,
     paused: bool,
+    /// Steps still to run while paused, queued by single stepping.
+    queued_steps: u32,
+    focused: bool,
+    background: BackgroundMode,
 }
 
 impl App<'_> {
-    const DIMENSIONS: (u32, u32, u32, u32) = (1024, 1024, 4, 0);
-    const WORKGROUP_DIMS: (u32, u32, u32) = (8, 8, 4);
-    const OBJECT_COUNT: u32 = Self::DIMENSIONS.0 * Self::DIMENSIONS.1 * Self::DIMENSIONS.2;
-    const MULTISAMPLE_SAMPLES: u32 = 8;
+    /// Workgroup size of the kernels where the device and scene allow it.
+    const PREFERRED_WORKGROUP_DIMS: (u32, u32, u32) = (8, 8, 4);
+    /// Largest scene simulated on the CPU in compatibility mode.
+    const COMPAT_DIMENSIONS: (u32, u32, u32) = (64, 64, 4);
+    /// Seconds between frame statistics updates.
+    const STATS_PERIOD: f64 = 1.0;
+    /// Seconds between simulation statistics updates.
+    const SIMULATION_STATS_PERIOD: f64 = 0.25;
+    /// Push constant block of the simulation kernels, the only pipelines using them.
+    const PUSH_CONSTANTS_NEEDED: u32 = ComputePushConstants::SIZE;
+    /// Size of the staging buffers per frame uploads are written through.
+    /// Larger uploads get a buffer of their own.
+    const STAGING_CHUNK_SIZE: u64 = 1 << 20;
+    /// Emitter seeds reserved per frame, more than any frame has steps.
+    const MAX_SEEDS_PER_FRAME: u32 = 64;
 
     fn generate_random_vectors(count: usize, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>) -> Vec<[f32; 4]> {
         let mut vectors = Vec::with_capacity(count);
-        let mut rng = rand::rng();
+        let mut rng = random::rng();
 
         for _ in 0..count {
             vectors.push([
@@ -118,14 +797,74 @@ impl App<'_> {
         vectors
     }
 
+    /// Gives every instance a random animation phase, see `InstanceRepr`.
+    fn generate_phases(positions: &mut [[f32; 4]]) {
+        let mut rng = random::rng();
+        for position in positions {
+            position[3] = rng.random();
+        }
+    }
+
+    /// Gives every instance a random lifetime of up to `lifetime` seconds in
+    /// the w of its velocity, or an endless one.
+    fn generate_lifetimes(velocities: &mut [[f32; 4]], lifetime: Option<f32>) {
+        let mut rng = random::rng();
+        for velocity in velocities {
+            velocity[3] = lifetime.map_or(f32::INFINITY, |lifetime| rng.random_range(0.0..lifetime));
+        }
+    }
+
+    fn kernel_index(name: &str) -> Option<usize> {
+        KERNELS.iter().position(|kernel| kernel.name == name)
+    }
+
+    /// Pauses the simulation and runs `steps` more steps with the next frame.
+    fn step_once(&mut self, steps: u32) {
+        self.paused = true;
+        self.queued_steps = self.queued_steps.saturating_add(steps);
+    }
+
+    /// Whether the emitter respawns instances, which takes a lifetime to
+    /// expire them.
+    fn emitter_active(&self) -> bool {
+        self.emitter.settings.position.is_some() && self.lifetime.is_some()
+    }
+
+    /// Workgroup size of the kernels within the device's limits. The z axis
+    /// of the preferred size shrinks to the depth of the scene, leaving its
+    /// invocations to x, then axes are halved, largest first, until the
+    /// workgroup fits.
+    fn select_workgroup_dims(limits: &wgpu::Limits, depth: u32) -> (u32, u32, u32) {
+        let (x, y, z) = Self::PREFERRED_WORKGROUP_DIMS;
+        let z_fit = z.min(depth.max(1).next_power_of_two());
+        let mut dims = [x * (z / z_fit), y, z_fit];
+        let axis_limits = [
+            limits.max_compute_workgroup_size_x,
+            limits.max_compute_workgroup_size_y,
+            limits.max_compute_workgroup_size_z,
+        ];
+        for (dim, limit) in dims.iter_mut().zip(axis_limits) {
+            if *dim > limit {
+                *dim = 1 << limit.max(1).ilog2();
+            }
+        }
+        while dims.iter().product::<u32>() > limits.max_compute_invocations_per_workgroup.max(1) {
+            let largest = (0..3).max_by_key(|&axis| dims[axis]).unwrap_or_default();
+            dims[largest] /= 2;
+        }
+
+        (dims[0], dims[1], dims[2])
+    }
+
     fn create_multisampled_framebuffer(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
         sample_count: u32
     ) -> wgpu::TextureView {
         let multisampled_texture_extent = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width: size.0,
+            height: size.1,
             depth_or_array_layers: 1,
         };
         let multisampled_frame_descriptor = &wgpu::TextureDescriptor {
@@ -133,7 +872,7 @@ impl App<'_> {
             mip_level_count: 1,
             sample_count,
             dimension: wgpu::TextureDimension::D2,
-            format: config.view_formats[0],
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             label: None,
             view_formats: &[],
@@ -144,89 +883,336 @@ impl App<'_> {
             .create_view(&wgpu::TextureViewDescriptor::default())
     }
 
-    pub async fn new(window: Arc<Window>) -> Result<Self, Box<dyn Error>> {
+    /// Lays out the passes that are enabled, the graph works out the order
+    /// and which of them ends up on the surface.
+    fn create_render_graph(
+        surface_format: wgpu::TextureFormat,
+        tone_mapped: bool,
+        post_effects: usize,
+        upscaled: bool,
+        capture: bool,
+        deferred: bool,
+        translucent: bool,
+    ) -> RenderGraph<FramePass> {
+        let mut graph = RenderGraph::new();
+        let upscale_input = if upscaled {
+            graph.add_texture(UPSCALE_SCENE, surface_format);
+            UPSCALE_SCENE
+        } else {
+            SURFACE
+        };
+        let scene_target = if tone_mapped {
+            graph.add_texture(HDR_SCENE, ToneMapping::FORMAT);
+            HDR_SCENE
+        } else {
+            upscale_input
+        };
+
+        graph.add_pass(FramePass::Prepare, &[], &[VISIBLE_INSTANCES]);
+        graph.add_pass(FramePass::Shadows, &[], &[SHADOW_MAPS]);
+        let mut scene_reads = vec![VISIBLE_INSTANCES, SHADOW_MAPS];
+        if deferred {
+            graph.add_pass(FramePass::GBuffer, &[VISIBLE_INSTANCES], &[G_BUFFER]);
+            scene_reads.push(G_BUFFER);
+        }
+        graph.add_pass(FramePass::Scene, &scene_reads, &[scene_target]);
+        if translucent {
+            graph.add_pass(FramePass::Transparency, &[scene_target], &[scene_target]);
+        }
+        if tone_mapped {
+            for index in 0..post_effects {
+                graph.add_pass(FramePass::PostEffect(index), &[HDR_SCENE], &[HDR_SCENE]);
+            }
+            graph.add_pass(FramePass::ToneMapping, &[HDR_SCENE], &[upscale_input]);
+        }
+        if upscaled {
+            graph.add_pass(FramePass::Upscale, &[UPSCALE_SCENE], &[SURFACE]);
+        }
+        if capture {
+            graph.add_pass(FramePass::Capture, &[SURFACE], &[]);
+        }
+        graph
+    }
+
+    pub async fn new(window: Arc<Window>, config: &AppConfig) -> Result<Self, Box<dyn Error>> {
+        if let Some(preset) = config.preset {
+            log::info!("Using preset {preset}.");
+        }
+        debug_labels::set_enabled(config.debug_labels);
+        if let Some(seed) = config.seed {
+            random::seed(seed);
+            log::info!("Generating the scene from seed {seed}.");
+        }
+
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
+            backends: config.backends,
             ..Default::default()
         });
 
         let surface = instance.create_surface(window.clone())?;
 
         let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
+            power_preference: config.power_preference,
             compatible_surface: Some(&surface),
             force_fallback_adapter: false,
-        }).await.ok_or("Failed to get adapter")?;
+        }).await.ok_or_else(|| format!("Failed to get adapter for backends {:?}", config.backends))?;
+
+        if let Some(dir) = &config.trace_dir {
+            std::fs::create_dir_all(dir)?;
+            if cfg!(feature = "trace") {
+                log::info!("Recording API trace into {}.", dir.display());
+            } else {
+                log::warn!("Built without the `trace` feature, no API trace will be recorded.");
+            }
+        }
+
+        // Only request the push constant space the pipelines use, since
+        // adapters may offer as little as 128 bytes or none at all. With less
+        // room than the kernel settings need only the hot ones are pushed
+        let push_constants_supported = if config.push_constants && adapter.features().contains(wgpu::Features::PUSH_CONSTANTS) {
+            adapter.limits().max_push_constant_size
+        } else {
+            0
+        };
+        let push_constant_size = ComputePushConstants::pushed_size(push_constants_supported);
+        log::debug!(
+            "Push constants: {push_constants_supported} bytes supported, {} needed.",
+            Self::PUSH_CONSTANTS_NEEDED,
+        );
+
+        // Only the simulation kernels use push constants, everything else reads
+        // the frame uniform. The kernels read the settings that aren't pushed
+        // from a uniform buffer
+        let push_constants = push_constant_size > 0;
+        if push_constant_size < Self::PUSH_CONSTANTS_NEEDED {
+            log::warn!(
+                "{push_constants_supported} bytes of push constants available, {} bytes of the kernel settings are uploaded.",
+                Self::PUSH_CONSTANTS_NEEDED - push_constant_size,
+            );
+        }
+
+        let compute_supported = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        let compat = config.compat || !compute_supported;
+        if !compute_supported && !config.compat {
+            log::warn!("Adapter lacks compute shaders, falling back to compatibility mode.");
+        }
+
+        let dimensions = if compat {
+            (
+                config.dimensions.0.min(Self::COMPAT_DIMENSIONS.0),
+                config.dimensions.1.min(Self::COMPAT_DIMENSIONS.1),
+                config.dimensions.2.min(Self::COMPAT_DIMENSIONS.2),
+            )
+        } else {
+            config.dimensions
+        };
+        let object_count = dimensions.0 * dimensions.1 * dimensions.2;
+        log::info!("Simulating {object_count} objects.");
+
+        let mut required_features = adapter.features()
+            & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES | DefaultVariant::POLYGON_MODE_FEATURES);
+        if push_constants {
+            required_features |= wgpu::Features::PUSH_CONSTANTS;
+        }
+        let multi_draw = config.multi_draw && !compat && adapter.features().contains(MultiDraw::FEATURES);
+        if multi_draw {
+            required_features |= MultiDraw::FEATURES;
+        } else if config.multi_draw {
+            log::warn!("Adapter lacks multi-draw indirect, instances are drawn one range at a time.");
+        }
+        let gpu_profile = config.gpu_profile && !compat && adapter.features().contains(GpuProfiler::FEATURES);
+        if gpu_profile {
+            required_features |= GpuProfiler::FEATURES;
+        } else if config.gpu_profile {
+            log::warn!("Adapter lacks timestamp queries, passes aren't timed.");
+        }
+        let required_limits = wgpu::Limits {
+            max_push_constant_size: if push_constants { push_constant_size } else { 0 },
+            ..if compat {
+                wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits())
+            } else {
+                // Larger buffers mean fewer instance chunks
+                wgpu::Limits {
+                    max_buffer_size: adapter.limits().max_buffer_size,
+                    max_storage_buffer_binding_size: adapter.limits().max_storage_buffer_binding_size,
+                    ..Default::default()
+                }
+            }
+        };
+
+        let required_features = if config.spirv_passthrough {
+            if !cfg!(feature = "spirv") {
+                log::warn!("Built without the `spirv` feature, SPIR-V shaders won't be loaded.");
+            } else if !adapter.features().contains(wgpu::Features::SPIRV_SHADER_PASSTHROUGH) {
+                log::warn!("Adapter doesn't support SPIR-V passthrough, SPIR-V goes through naga.");
+            }
+            required_features | (adapter.features() & wgpu::Features::SPIRV_SHADER_PASSTHROUGH)
+        } else {
+            required_features
+        };
 
         let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
             label: Some("device"),
-            required_features: wgpu::Features::PUSH_CONSTANTS |
-                wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-            required_limits: wgpu::Limits {
-                max_push_constant_size: 256,
-                ..Default::default()
-            },
+            required_features,
+            required_limits,
             memory_hints: wgpu::MemoryHints::Performance,
-        }, None).await?;
+        }, config.trace_dir.as_deref()).await?;
 
-        log::info!("Selected device {} with driver {}.", adapter.get_info().name, adapter.get_info().driver);
+        let adapter_info = adapter.get_info();
+        log::info!(
+            "Selected device {} ({:?}) on {} backend with driver {} {}.",
+            adapter_info.name,
+            adapter_info.device_type,
+            adapter_info.backend,
+            adapter_info.driver,
+            adapter_info.driver_info,
+        );
 
         let size = window.inner_size();
         let mut surface_config = surface.get_default_config(&adapter, size.width, size.height)
             .expect("Surface isn't supported by adapter.");
         let view_format = surface_config.format.add_srgb_suffix();
         surface_config.view_formats.push(view_format);
-        surface.configure(&device, &surface_config);
 
-        let multisample_framebuffer = Self::create_multisampled_framebuffer(
+        let frame_ring = FrameRing::new(
             &device,
-            &surface_config,
-            Self::MULTISAMPLE_SAMPLES,
+            config.frames_in_flight,
+            std::mem::size_of::<FrameUniform>() as u64,
         );
+        surface_config.desired_maximum_frame_latency = frame_ring.frames_in_flight();
+        log::info!("Frames in flight: {}.", frame_ring.frames_in_flight());
 
-        let mut pipelines = HashMap::new();
+        if config.low_latency {
+            if surface.get_capabilities(&adapter).present_modes.contains(&wgpu::PresentMode::Mailbox) {
+                surface_config.present_mode = wgpu::PresentMode::Mailbox;
+            }
+            log::info!("Low latency mode, presenting with {:?}.", surface_config.present_mode);
+        }
 
-        let cube_mesh = Mesh::create(
-            &device,
-            &[
-                DefaultVertex3d { position: [-0.5, -0.5, -0.5]},
-                DefaultVertex3d { position: [0.5, -0.5, -0.5]},
-                DefaultVertex3d { position: [0.5, -0.5, 0.5]},
-                DefaultVertex3d { position: [-0.5, -0.5, 0.5]},
-
-                DefaultVertex3d { position: [-0.5, 0.5, -0.5]},
-                DefaultVertex3d { position: [0.5, 0.5, -0.5]},
-                DefaultVertex3d { position: [0.5, 0.5, 0.5]},
-                DefaultVertex3d { position: [-0.5, 0.5, 0.5]},
-            ],
-            &[
-                // bottom
-                0, 1, 2,
-                0, 2, 3,
+        if config.capture.frames > 0 {
+            if !surface.get_capabilities(&adapter).usages.contains(wgpu::TextureUsages::COPY_SRC) {
+                return Err("The surface can't be copied from, so frames can't be captured".into());
+            }
+            surface_config.usage |= wgpu::TextureUsages::COPY_SRC;
+        }
 
-                // top
-                4, 6, 5,
-                4, 7, 6,
+        if let Some(alpha_mode) = config.alpha_mode {
+            let supported = surface.get_capabilities(&adapter).alpha_modes;
+            if !supported.contains(&alpha_mode) {
+                return Err(format!(
+                    "Alpha mode {alpha_mode:?} isn't supported by the surface (supported: {supported:?})"
+                ).into());
+            }
+            surface_config.alpha_mode = alpha_mode;
+        }
+        log::info!("Surface alpha mode: {:?}.", surface_config.alpha_mode);
+        surface.configure(&device, &surface_config);
 
-                // back
-                0, 5, 1,
-                0, 4, 5,
+        let mut pipelines = HashMap::new();
 
-                // front
-                3, 2, 6,
-                3, 6, 7,
+        let cube_mesh = Mesh::cube(&device);
+        let sphere_mesh = Mesh::sphere(&device, 0.5, 8, 12);
+        let instance_groups: Vec<_> = config
+            .groups
+            .iter()
+            .map(|group| {
+                log::info!("Instance group of {} {} instances.", group.count, group.shape.name());
+                InstanceGroup::new(&device, group, &config.transforms, config.coloring)
+            })
+            .collect();
 
-                // left
-                3, 4, 0,
-                3, 7, 4,
+        // Hot reloading edits the shaders the binary was built from
+        let shader_dir = config
+            .shader_dir
+            .clone()
+            .or_else(|| config.hot_reload.then(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders"))));
+        if let Some(dir) = shader_dir.as_deref().filter(|dir| !dir.is_dir()) {
+            log::warn!("Shader directory {} doesn't exist, using embedded shaders.", dir.display());
+        }
+        let shader_watcher = shader_dir
+            .as_deref()
+            .filter(|dir| config.hot_reload && dir.is_dir())
+            .and_then(|dir| ShaderWatcher::new(dir).map_err(|error| log::warn!("{error}.")).ok());
+        let shaders = ShaderLoader::new(shader_dir, config.spirv_passthrough);
+        // Impostors are chosen in the level of detail pass, which needs compute shaders
+        let impostor_atlas = (!compat).then(|| ImpostorAtlas::bake(&device, &queue, &cube_mesh, &shaders));
 
-                // right
-                1, 6, 2,
-                1, 5, 6,
-            ]
+        let upscaled = config.upscale.enabled();
+        let render_size = if upscaled {
+            config.upscale.internal_size((size.width, size.height))
+        } else {
+            (size.width, size.height)
+        };
+        let tone_mapper = config.tone_mapping.filter(|_| {
+            let supported = ToneMapping::supported(&adapter, &device);
+            if !supported {
+                log::warn!("{:?} can't be rendered to and resolved on this device, rendering without HDR.", ToneMapping::FORMAT);
+            }
+            supported
+        });
+        let bloom_settings = config.bloom.filter(|_| tone_mapper.is_some());
+        // The point lights are a storage buffer, which compatibility limits leave out
+        let deferred_settings = config.deferred.filter(|_| {
+            if compat {
+                log::warn!("Deferred shading needs storage buffers, instances are shaded forward.");
+            }
+            !compat
+        });
+        // Each accumulation target blends differently
+        let translucent = instance_groups.iter().any(|group| group.translucent) && {
+            let supported = adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::INDEPENDENT_BLEND);
+            if !supported {
+                log::warn!("Translucent groups need independent blending, they are drawn opaque.");
+            }
+            supported
+        };
+        let mut render_graph = Self::create_render_graph(
+            surface_config.view_formats[0],
+            tone_mapper.is_some(),
+            usize::from(bloom_settings.is_some()),
+            upscaled,
+            config.capture.frames > 0,
+            deferred_settings.is_some(),
+            translucent,
+        );
+        render_graph.compile(&device, render_size)?;
+
+        let upscale = upscaled.then(|| {
+            Upscale::new(
+                &device,
+                &shaders,
+                surface_config.view_formats[0],
+                config.upscale.clone(),
+                render_graph.texture(UPSCALE_SCENE),
+                (size.width, size.height),
+            )
+        });
+        let tone_mapping = tone_mapper.map(|tone_mapper| {
+            let scene = render_graph.texture(HDR_SCENE);
+            ToneMapping::new(&device, &shaders, surface_config.view_formats[0], tone_mapper, scene, config.gamma)
+        });
+        let bloom = bloom_settings.map(|settings| Bloom::new(&device, &shaders, settings, render_graph.texture(HDR_SCENE)));
+        let scene_format = if tone_mapping.is_some() { ToneMapping::FORMAT } else { surface_config.view_formats[0] };
+        let sample_count = Self::pick_sample_count(
+            config.msaa,
+            &Self::supported_sample_counts(&adapter, &device, scene_format, translucent),
         );
+        if sample_count != config.msaa {
+            log::warn!("{}x MSAA isn't supported with {scene_format:?}, using {sample_count}x.", config.msaa);
+        }
+        let multisample_framebuffer = (sample_count > 1)
+            .then(|| Self::create_multisampled_framebuffer(&device, scene_format, render_size, sample_count));
 
-        let camera = Camera::new(size.width as f32 / size.height as f32);
+        let camera = Camera {
+            depth_order: config.depth_order,
+            ..Camera::new(size.width as f32 / size.height as f32)
+        };
         let camera_controller = CameraController::new(1.0, 0.001);
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("camera_buffer"),
@@ -239,7 +1225,7 @@ impl App<'_> {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -260,185 +1246,1046 @@ impl App<'_> {
             ]
         });
 
-        let depth_texture = Texture2d::create_depth_texture(
-            &device,
-            &surface_config,
-            Self::MULTISAMPLE_SAMPLES,
-            Some("depth_texture"),
-        );
-
-        pipelines.insert(
-            PipelineSelector::Default,
-            Pipeline::Render(Self::default_pipeline(
-                &device,
-                &[&camera_bind_group_layout],
-                surface_config.format
-            ))
-        );
-
-        _ = window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
-        window.set_cursor_visible(false);
-
-        let positions = Self::generate_random_vectors(
-            Self::OBJECT_COUNT as usize,
-            cgmath::Point3::new(-10000.0, -10000.0, -10000.0),
-            cgmath::Point3::new(10000.0, 10000.0, 10000.0),
-        ).into_iter().map(|v| v.into()).collect::<Vec<_>>();
-        let velocities = Self::generate_random_vectors(
-            Self::OBJECT_COUNT as usize,
-            cgmath::Point3::new(-20.0, -20.0, -20.0),
-            cgmath::Point3::new(20.0, 20.0, 20.0),
-        ).into_iter().map(|v| v.into()).collect::<Vec<_>>();
+        let mut scene = SceneSettings {
+            viewport_height: render_size.1 as f32,
+            light: config.light,
+            fog: config.fog,
+            exposure: config.exposure,
+            gamma: config.gamma,
+            background: config.background_color,
+            output_format: tone_mapping.is_none().then_some(scene_format),
+            ..Default::default()
+        };
+        scene.set_draw_distance(config.max_draw_distance);
+        if !config.impostors || impostor_atlas.is_none() {
+            scene.impostor_threshold = 0.0;
+        }
+        let scene_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("scene_buffer"),
+            contents: bytemuck::cast_slice(&[scene.uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
 
-        let positions_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("positions_buffer"),
-            contents: bytemuck::cast_slice(&positions),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        // The material table is a storage buffer, which compatibility limits leave out
+        let materials_buffer = (!compat).then(|| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("materials_buffer"),
+                contents: bytemuck::cast_slice(&MaterialParams::PALETTE),
+                usage: wgpu::BufferUsages::STORAGE,
+            })
         });
-        let positions_buffer_vsh = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("positions_buffer_vsh"),
-            contents: bytemuck::cast_slice(&positions),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        let mut scene_layout_entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        let mut scene_entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: scene_buffer.as_entire_binding(),
+            }
+        ];
+        if let Some(materials_buffer) = &materials_buffer {
+            scene_layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            });
+            scene_entries.push(wgpu::BindGroupEntry {
+                binding: 1,
+                resource: materials_buffer.as_entire_binding(),
+            });
+        }
+        let shadow_maps = ShadowMaps::new(&device, &shaders, config.shadows);
+        scene_layout_entries.extend(ShadowMaps::layout_entries());
+        scene_entries.extend(shadow_maps.bind_group_entries());
+        let scene_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("scene"),
+            entries: &scene_layout_entries,
         });
-        let velocities_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("velocities_buffer"),
-            contents: bytemuck::cast_slice(&velocities),
-            usage: wgpu::BufferUsages::STORAGE,
+        let scene_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("scene"),
+            layout: &scene_bind_group_layout,
+            entries: &scene_entries,
         });
 
-        let pv_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("pv_bind_layout"),
+        let frame = FrameUniform {
+            dimensions: [dimensions.0, dimensions.1, dimensions.2, 0],
+            resolution: [render_size.0 as f32, render_size.1 as f32],
+            ..Default::default()
+        };
+        // One region per frame in flight, selected with a dynamic offset
+        let frame_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frame_buffer"),
+            size: frame_ring.buffer_size(),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&frame_buffer, frame_ring.uniform_offset() as u64, bytemuck::bytes_of(&frame));
+        let frame_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("frame"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                    visibility: if compat {
+                        wgpu::ShaderStages::VERTEX_FRAGMENT
+                    } else {
+                        wgpu::ShaderStages::VERTEX_FRAGMENT | wgpu::ShaderStages::COMPUTE
                     },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
                         min_binding_size: None,
                     },
                     count: None,
                 },
-            ]
+            ],
         });
-        let pv_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("pv_bind_group"),
-            layout: &pv_bind_group_layout,
+        let frame_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("frame"),
+            layout: &frame_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: positions_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: velocities_buffer.as_entire_binding(),
-                },
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &frame_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<FrameUniform>() as u64),
+                    }),
+                }
             ]
         });
 
-        pipelines.insert(PipelineSelector::Compute, Pipeline::Compute(
-            Self::compute_pipeline(&device, &[&pv_bind_group_layout])
-        ));
-
-        Ok(Self {
-            window,
-            instance,
-            surface,
-            surface_config,
-            multisample_framebuffer,
-            adapter,
-            device,
-            queue,
-            depth_texture,
+        let depth_texture = Texture2d::create_sized_depth_texture(&device, render_size, sample_count, Some("depth_texture"));
 
-            pipelines,
-            cube_mesh,
+        let mut default_shaders = ShaderPermutations::new(
+            "default.wgsl",
+            shaders.render_source("default.wgsl", include_str!("../shaders/default.wgsl")),
+        );
+        let dataset = config
+            .dataset
+            .as_deref()
+            .map(|path| pointcloud::open(path, config.merge_voxels))
+            .transpose()?;
+        let mut material = config.material;
+        if let Some(features) = dataset.as_ref().map(|dataset| dataset.features()) {
+            // Without generated instances there's no grid to color by
+            if features.contains(ShaderFeatures::POINT_COLOR) || dimensions.0 * dimensions.1 * dimensions.2 == 0 {
+                material.features = material.features.without(ShaderFeatures::INSTANCED_COLOR);
+            }
+            material.features = material.features | features;
+        }
+        if compat && material.features.contains(ShaderFeatures::MATERIALS) {
+            log::warn!("Materials need storage buffers, instances are drawn without them.");
+            material.features = material.features.without(ShaderFeatures::MATERIALS);
+        }
+        let default_layouts = vec![
+            frame_bind_group_layout.clone(),
+            camera_bind_group_layout.clone(),
+            scene_bind_group_layout.clone(),
+        ];
+        let mut chunk_offsets = ChunkOffsets::new(&device);
+        let instance_layouts = [default_layouts.clone(), vec![chunk_offsets.layout().clone()]].concat();
 
-            camera,
-            camera_controller,
-            camera_buffer,
-            camera_bind_group,
+        for variant in DEFAULT_VARIANTS.iter().filter(|variant| variant.supported(device.features())) {
+            pipelines.insert(
+                variant.selector,
+                Pipeline::Render(Self::default_pipeline(
+                    &device,
+                    &instance_layouts.iter().collect::<Vec<_>>(),
+                    scene_format,
+                    sample_count,
+                    camera.depth_order,
+                    default_shaders.get(&device, material.features)?,
+                    variant,
+                    DefaultTarget::Scene,
+                ))
+            );
+        }
+        if let Some(impostor_atlas) = &impostor_atlas {
+            pipelines.insert(
+                PipelineSelector::Custom { name: "impostor" },
+                Pipeline::Render(Self::impostor_pipeline(
+                    &device,
+                    &[
+                        &frame_bind_group_layout,
+                        &camera_bind_group_layout,
+                        &scene_bind_group_layout,
+                        &impostor_atlas.bind_group_layout,
+                    ],
+                    scene_format,
+                    sample_count,
+                    camera.depth_order,
+                    &shaders,
+                ))
+            );
+        }
+        pipelines.insert(
+            PipelineSelector::Custom { name: "greedy" },
+            Pipeline::Render(Self::lit_mesh_pipeline(
+                &device,
+                &default_layouts.iter().collect::<Vec<_>>(),
+                scene_format,
+                sample_count,
+                camera.depth_order,
+                &shaders,
+                "greedy_pipeline",
+                GreedyVertex::desc(),
+            ))
+        );
 
-            positions,
-            velocities,
-            positions_buffer_vsh,
-            positions_buffer,
-            velocities_buffer,
-            pv_bind_group,
+        let indirect_supported = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION);
+        let isosurface = match (config.isosurface, compat || !indirect_supported) {
+            (true, false) => {
+                pipelines.insert(
+                    PipelineSelector::Custom { name: "isosurface" },
+                    Pipeline::Render(Self::lit_mesh_pipeline(
+                        &device,
+                        &default_layouts.iter().collect::<Vec<_>>(),
+                        scene_format,
+                        sample_count,
+                        camera.depth_order,
+                        &shaders,
+                        "isosurface_pipeline",
+                        IsosurfaceVertex::desc(),
+                    ))
+                );
+                Some(Isosurface::new(&device, &frame_bind_group_layout, &shaders))
+            }
+            (true, true) => {
+                log::warn!("The isosurface needs compute shaders and indirect draws, it is disabled.");
+                None
+            }
+            (false, _) => None,
+        };
 
-            start_time: Instant::now(),
-            time: 0.0,
-            last_delta: 0.001,
-            paused: false,
-        })
-    }
+        let mut labels = LabelRenderer::new(
+            &device,
+            &queue,
+            &default_layouts.iter().collect::<Vec<_>>(),
+            scene_format,
+            sample_count,
+            camera.depth_order,
+            &shaders,
+        );
+        labels.add(Label {
+            anchor: LabelAnchor::Position(cgmath::Point3::new(0.0, 0.0, 0.0)),
+            text: "Origin".to_string(),
+            color: [255, 255, 255, 255],
+            background: None,
+        });
+
+        pipelines.insert(
+            PipelineSelector::Custom { name: "outline" },
+            Pipeline::Render(Self::outline_pipeline(
+                &device,
+                &[&frame_bind_group_layout, &camera_bind_group_layout],
+                scene_format,
+                sample_count,
+                camera.depth_order,
+                &shaders,
+            ))
+        );
+        let selection = Selection::new(&device);
+        if config.sdf {
+            pipelines.insert(
+                PipelineSelector::Custom { name: "sdf" },
+                Pipeline::Render(Self::sdf_pipeline(
+                    &device,
+                    &default_layouts.iter().collect::<Vec<_>>(),
+                    scene_format,
+                    sample_count,
+                    camera.depth_order,
+                    &shaders,
+                ))
+            );
+        }
+
+        _ = window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
+        window.set_cursor_visible(false);
+
+        let simulation = SimulationConstants {
+            integrator: config.integrator,
+            bounds: config.bounds,
+            follow: config.follow,
+            workgroup_dims: Self::select_workgroup_dims(&device.limits(), dimensions.2),
+            ..Default::default()
+        };
+        if !compat {
+            log::debug!("Kernel workgroup size: {:?}.", simulation.workgroup_dims);
+        }
+        let demo_entry = demo::find(config.demo).unwrap_or(&demo::DEMOS[0]);
+        let mut demo = (demo_entry.create)();
+        let (mut positions, mut velocities) = demo.init(&DemoContext {
+            simulation: &simulation,
+            count: object_count as usize,
+        });
+        Self::generate_phases(&mut positions);
+        if config.lifetime.is_some() && (compat || !indirect_supported) {
+            log::warn!("Instance lifetimes need compute shaders and indirect draws, instances never expire.");
+        }
+        let lifetime = config.lifetime.filter(|_| !compat && indirect_supported);
+        Self::generate_lifetimes(&mut velocities, lifetime);
+        let transforms = InstanceTransform::generate(positions.len(), &config.transforms);
+        if config.instance_alpha < 1.0 && compat {
+            log::warn!("Sorting transparent instances needs compute shaders, they are drawn opaque.");
+        }
+        let instance_alpha = if compat { 1.0 } else { config.instance_alpha };
+        let colors = InstanceColor::generate(&velocities, config.coloring, instance_alpha);
+        let kernel = config
+            .kernel
+            .and_then(Self::kernel_index)
+            .unwrap_or_else(|| Self::kernel_index(demo.kernel()).unwrap_or_default());
+        log::info!("Running demo {} with kernel {}.", demo_entry.name, KERNELS[kernel].name);
+        if let Some(impostor_atlas) = impostor_atlas.as_ref().filter(|_| KERNELS[kernel].spheres) {
+            impostor_atlas.rebake(&device, &queue, &sphere_mesh);
+        }
+        if config.emitter.position.is_some() && lifetime.is_none() {
+            log::warn!("The emitter needs instance lifetimes, it is disabled.");
+        }
+        let mut chunk_culler = ChunkCuller::build(&positions, ChunkCuller::DEFAULT_CHUNK_SIZE, config.transforms.max_extent());
+        if !compat {
+            // How fast the instances start out is only known once the GPU reduced their bounds
+            chunk_culler.invalidate();
+        }
+        let deferred = match deferred_settings {
+            Some(_) if !default_shaders.get(&device, material.features)?.deferred => {
+                log::warn!("GLSL replacements of default.wgsl have no deferred path, instances are shaded forward.");
+                None
+            }
+            Some(settings) => {
+                let mut deferred = Deferred::new(&device, settings, &positions, render_size, camera.depth_order);
+                let (pipelines, lighting) = Self::deferred_pipelines(
+                    &device,
+                    &instance_layouts,
+                    scene_format,
+                    sample_count,
+                    camera.depth_order,
+                    default_shaders.get(&device, material.features)?,
+                    &deferred,
+                );
+                deferred.set_pipelines(pipelines, lighting);
+                Some(deferred)
+            }
+            None => None,
+        };
+        let weighted_blended = if translucent {
+            let modules = default_shaders.get(&device, material.features)?;
+            let mut weighted_blended = WeightedBlended::new(&device, &shaders, scene_format, render_size, sample_count);
+            if modules.deferred {
+                weighted_blended.set_pipelines(Self::opaque_variant_pipelines(
+                    &device,
+                    &instance_layouts,
+                    scene_format,
+                    sample_count,
+                    camera.depth_order,
+                    modules,
+                    DefaultTarget::Accumulation,
+                ));
+            } else {
+                log::warn!("GLSL replacements of default.wgsl have no accumulation entry point, translucent groups are drawn opaque.");
+            }
+            Some(weighted_blended)
+        } else {
+            None
+        };
+
+        let instance_buffers = InstanceBuffers::new(
+            &device,
+            InstanceData {
+                positions: &positions,
+                velocities: &velocities,
+                transforms: &transforms,
+                colors: &colors,
+            },
+            dimensions,
+            compat,
+        )?;
+        chunk_offsets.write(&device, &queue, &instance_buffers.chunks);
+        let multi_draw = multi_draw.then(|| MultiDraw::new(&device));
+        let sorter = if instance_alpha >= 1.0 {
+            None
+        } else if !InstanceSorter::fits(&device.limits(), &instance_buffers) {
+            log::warn!("Sort keys of every instance exceed the storage buffer limits, instances are drawn opaque.");
+            None
+        } else {
+            Some(InstanceSorter::new(&device, &shaders, &instance_buffers))
+        };
+        // Impostors are picked out by the level of detail pass, so presets
+        // drawing them run it even without --lod
+        let lod_settings = config.lod.clone().or_else(|| {
+            let impostors = config.preset.is_some() && config.impostors && impostor_atlas.is_some();
+            if impostors {
+                log::info!("Preset draws impostors, levels of detail enabled at the default distances.");
+            }
+            impostors.then(LodSettings::default)
+        });
+        let lod = lod_settings.as_ref().and_then(|settings| {
+            let levels = LodLevel::defaults(&device, settings);
+            let impostors = impostor_atlas.is_some();
+            if compat || !indirect_supported {
+                log::warn!("Levels of detail need compute shaders and indirect draws, they are disabled.");
+                None
+            } else if !Lod::fits(&device.limits(), levels.len() + impostors as usize, &instance_buffers) {
+                log::warn!("Level of detail lists exceed the storage buffer limits, they are disabled.");
+                None
+            } else {
+                Some(Lod::new(&device, &shaders, levels, impostors, &instance_buffers))
+            }
+        });
+        if !lod.as_ref().is_some_and(Lod::impostors) {
+            // Chosen in the level of detail pass, there's nothing to draw them from
+            scene.impostor_threshold = 0.0;
+        }
+        let compaction = lifetime.map(|_| Compaction::new(&device, &shaders, &instance_buffers));
+        let culling_mode = match config.culling_mode {
+            CullingMode::GpuOcclusion if compat || !indirect_supported => {
+                log::warn!("Occlusion culling needs compute shaders and indirect draws, culling chunks instead.");
+                CullingMode::CpuChunks
+            }
+            // Both pick the instances to draw in passes of their own
+            CullingMode::GpuOcclusion if sorter.is_some() || lod.is_some() => {
+                log::warn!(
+                    "Occlusion culling can't be combined with transparent instances or levels of detail, culling chunks instead."
+                );
+                CullingMode::CpuChunks
+            }
+            mode => mode,
+        };
+        let occlusion = (culling_mode == CullingMode::GpuOcclusion)
+            .then(|| OcclusionCuller::new(&device, &shaders, &instance_buffers, &depth_texture, camera.depth_order));
+        let hierarchy = config.hierarchy.as_ref().and_then(|settings| {
+            if compat {
+                log::warn!("Clusters need compute shaders, they are disabled.");
+                None
+            } else if sorter.is_some() || lod.is_some() || compaction.is_some() {
+                log::warn!("Clusters can't be combined with sorting, levels of detail or lifetimes, they are disabled.");
+                None
+            } else {
+                Some(Hierarchy::new(&device, &shaders, &frame_bind_group_layout, settings, &instance_buffers))
+            }
+        });
+
+        let streamer = dataset.map(|dataset| {
+            DatasetStreamer::new(&device, dataset, DatasetStreamer::DEFAULT_RESIDENT_BLOCKS)
+        });
+
+        let capture = if config.capture.frames > 0 {
+            // Generated instances, or the dataset when there are none
+            let points = match &streamer {
+                Some(streamer) if positions.is_empty() => streamer.dataset().points(),
+                _ => positions.as_slice(),
+            };
+            Some(TurntableCapture::new(&config.capture, surface_config.format, points)?)
+        } else {
+            None
+        };
+
+        let simulation_statistics = (!compat).then(|| SimulationStatistics::new(&device, &instance_buffers, &shaders));
+        let chunk_bounds = (!compat)
+            .then(|| ChunkBounds::new(&device, &instance_buffers, ChunkCuller::DEFAULT_CHUNK_SIZE, &shaders));
+
+        let history = match config.history.interval {
+            0 => None,
+            _ if compat => {
+                log::warn!("The simulation runs on the CPU in compatibility mode, it can't be rewound.");
+                None
+            }
+            _ => Some(History::new(&config.history, &instance_buffers)),
+        };
+
+        let collision = Collision::new(&device, &queue, Obstacles::load(&config.collision)?);
+        let sim_params = SimParamsBuffer::new(&device, &simulation.params);
+        let gpu_reset = GpuReset::new(&device);
+
+        let (pv_bind_groups, raycaster) = if compat {
+            (None, None)
+        } else {
+            Self::create_gpu_simulation(
+                &device,
+                &mut pipelines,
+                &instance_buffers,
+                &frame_bind_group_layout,
+                &collision,
+                &sim_params,
+                &gpu_reset,
+                &simulation,
+                &shaders,
+            )
+        };
+        let profiler = gpu_profile.then(|| GpuProfiler::new(&device, &queue));
+        let mut kernel_dispatch = KernelDispatch::new(indirect_supported);
+        if !compat {
+            kernel_dispatch.write(
+                &device,
+                &queue,
+                &instance_buffers.chunks,
+                [dimensions.0, dimensions.1, dimensions.2, 0],
+                simulation.workgroup_dims,
+            );
+        }
+
+        Ok(Self {
+            base_title: window.title(),
+            title_status: String::new(),
+            window,
+            instance,
+            surface,
+            surface_config,
+            multisample_framebuffer,
+            sample_count,
+            render_graph,
+            upscale,
+            tone_mapping,
+            bloom,
+            deferred,
+            weighted_blended,
+            scene_format,
+            adapter,
+            device,
+            queue,
+            depth_texture,
+
+            pipelines,
+            cube_mesh,
+            sphere_mesh,
+            instance_groups,
+            group_bundles: None,
+            impostor_atlas,
+            shaders,
+            default_shaders,
+            default_layouts,
+            instance_layouts,
+            chunk_offsets,
+            material,
+            shader_error: None,
+            shader_watcher,
+            render_mode: RenderMode::Instanced,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            greedy_mesh: None,
+
+            camera,
+            camera_controller,
+            bookmarks: Bookmarks::load(config.bookmarks.clone()),
+            camera_transition: None,
+            modifiers: winit::keyboard::ModifiersState::empty(),
+            camera_buffer,
+            camera_bind_group,
+
+            scene,
+            scene_buffer,
+            scene_bind_group,
+            shadow_maps,
+
+            dimensions: [dimensions.0, dimensions.1, dimensions.2, 0],
+            positions,
+            velocities,
+            transforms,
+            transform_settings: config.transforms.clone(),
+            colors,
+            coloring: config.coloring,
+            instance_alpha,
+            instance_buffers,
+            multi_draw,
+            sorter,
+            lod,
+            compaction,
+            occlusion,
+            lifetime,
+            emitter: Emitter::new(&config.emitter),
+            hierarchy,
+            pv_bind_groups,
+            kernel_dispatch,
+            gpu_reset,
+            raycaster,
+            picker: None,
+            selection,
+            streamer,
+            isosurface,
+            sdf: config.sdf,
+            labels,
+            capture,
+            replay: config.replay.map(Replay::new),
+            history,
+            collision,
+            sim_params,
+            gravity_wells: GravityWells::default(),
+            console: Console::default(),
+            buffer_pool: BufferPool::default(),
+            staging_belt: wgpu::util::StagingBelt::new(Self::STAGING_CHUNK_SIZE),
+            frame,
+            frame_buffer,
+            frame_bind_group,
+            frame_ring,
+            low_latency: config.low_latency,
+            latency: LatencyTracker::default(),
+            profiler,
+
+            culling_mode,
+            chunk_culler,
+            chunk_bounds,
+
+            start_time: Instant::now(),
+            time: 0.0,
+            last_delta: 0.001,
+            delta_smoother: DeltaSmoother::default(),
+            timestep: FixedTimestep::new(config.timestep, config.max_substeps),
+            frame_stats: FrameStats::default(),
+            run_frame_stats: FrameStats::default(),
+            last_stats_time: 0.0,
+            simulation_statistics,
+            pending_simulation: None,
+            simulation_stats: None,
+            simulation_reference: None,
+            last_simulation_stats_time: 0.0,
+            show_statistics: true,
+            preset: config.preset,
+            compat,
+            simulation,
+            kernel,
+            demo,
+            demo_name: demo_entry.name,
+            frame_interval: config.max_fps.map(|fps| Duration::from_secs_f64(1.0 / fps as f64)),
+            next_frame: Instant::now(),
+            paused: !config.simulate,
+            queued_steps: 0,
+            focused: true,
+            background: config.background,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_gpu_simulation(
+        device: &wgpu::Device,
+        pipelines: &mut HashMap<PipelineSelector, Pipeline>,
+        instance_buffers: &InstanceBuffers,
+        frame_bind_group_layout: &wgpu::BindGroupLayout,
+        collision: &Collision,
+        sim_params: &SimParamsBuffer,
+        reset: &GpuReset,
+        simulation: &SimulationConstants,
+        shaders: &ShaderLoader,
+    ) -> (Option<Vec<[wgpu::BindGroup; 2]>>, Option<Raycaster>) {
+        let pv_bind_group_layout = Self::pv_bind_group_layout(device);
+        // One bind group per side of the state it reads
+        let pv_bind_groups = instance_buffers
+            .chunks
+            .iter()
+            .map(|chunk| {
+                // Kept alive by the bind groups
+                let sph_scratch = Self::sph_scratch(device, chunk.range.len() as u32);
+                [0, 1].map(|side| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("pv_bind_group"),
+                        layout: &pv_bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: chunk.positions[1 - side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: chunk.velocities[1 - side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: chunk.transforms.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: chunk.positions[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 4,
+                                resource: chunk.velocities[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 5,
+                                resource: sph_scratch.as_entire_binding(),
+                            },
+                        ]
+                    })
+                })
+            })
+            .collect();
+
+        let raycaster = Raycaster::new(device, instance_buffers, shaders);
+
+        let layouts = [frame_bind_group_layout, &pv_bind_group_layout, collision.layout(), sim_params.layout()];
+        for kernel in KERNELS {
+            pipelines.insert(PipelineSelector::Custom { name: kernel.name }, Pipeline::Compute(
+                Self::compute_pipeline(device, &layouts, kernel, simulation, shaders)
+            ));
+            for &prepass in kernel.prepasses {
+                pipelines.insert(PipelineSelector::Custom { name: prepass }, Pipeline::Compute(
+                    Self::compute_entry_pipeline(device, &layouts, prepass, simulation, shaders)
+                ));
+            }
+        }
+        pipelines.insert(PipelineSelector::Custom { name: "respawn" }, Pipeline::Compute(
+            Self::compute_entry_pipeline(device, &layouts, "respawn_main", simulation, shaders)
+        ));
+        // Takes the place of the collision
+        let reset_layouts = [frame_bind_group_layout, &pv_bind_group_layout, reset.layout(), sim_params.layout()];
+        pipelines.insert(PipelineSelector::Custom { name: "reset" }, Pipeline::Compute(
+            Self::compute_entry_pipeline(device, &reset_layouts, "reset_main", simulation, shaders)
+        ));
+
+        (Some(pv_bind_groups), Some(raycaster))
+    }
+
+    /// Scratch of the fluid kernel for a chunk of `count` instances: a
+    /// density and a link per instance and the buckets of the spatial hash,
+    /// see `sph` in `compute.wgsl`.
+    fn sph_scratch(device: &wgpu::Device, count: u32) -> wgpu::Buffer {
+        let buckets = count.next_power_of_two();
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("sph_scratch"),
+            size: (2 * count + buckets) as u64 * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Group 1 of the kernels: the state written by a step, the transforms,
+    /// the state it reads and the scratch of the fluid kernel.
+    fn pv_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("pv_bind_layout"),
+            entries: &[
+                entry(0, false),
+                entry(1, false),
+                entry(2, false),
+                entry(3, true),
+                entry(4, true),
+                entry(5, false),
+            ],
+        })
+    }
 
     fn compute_pipeline(
         device: &wgpu::Device,
         bind_group_layouts: &[&wgpu::BindGroupLayout],
+        kernel: &SimulationKernel,
+        simulation: &SimulationConstants,
+        shaders: &ShaderLoader,
+    ) -> wgpu::ComputePipeline {
+        Self::compute_entry_pipeline(
+            device,
+            bind_group_layouts,
+            kernel.entry_point,
+            &kernel.constants(simulation),
+            shaders,
+        )
+    }
+
+    /// Pipeline of any entry point of `compute.wgsl`, kernel or not.
+    fn compute_entry_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        entry_point: &'static str,
+        simulation: &SimulationConstants,
+        shaders: &ShaderLoader,
     ) -> wgpu::ComputePipeline {
-        let compute_module = device.create_shader_module(wgpu::include_wgsl!("../shaders/compute.wgsl"));
+        let constants = shader::override_constants([
+            ("WORKGROUP_SIZE_X", simulation.workgroup_dims.0 as f64),
+            ("WORKGROUP_SIZE_Y", simulation.workgroup_dims.1 as f64),
+            ("WORKGROUP_SIZE_Z", simulation.workgroup_dims.2 as f64),
+            ("ATTRACT", simulation.attract as u32 as f64),
+            ("INTEGRATOR", simulation.integrator as u32 as f64),
+        ]);
+        let pushed_size = ComputePushConstants::device_pushed_size(device);
+        let (compute_module, entry_point) = shaders.compute_module(
+            device,
+            "compute.wgsl",
+            include_str!("../shaders/compute.wgsl"),
+            entry_point,
+            ComputePushConstants::defines(pushed_size),
+        );
 
+        let push_constant_range = [wgpu::PushConstantRange {
+            stages: wgpu::ShaderStages::COMPUTE,
+            range: 0..pushed_size,
+        }];
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("compute_pipeline_layout"),
             bind_group_layouts,
-            push_constant_ranges: &[
-                wgpu::PushConstantRange {
-                    stages: wgpu::ShaderStages::COMPUTE,
-                    range: 0..std::mem::size_of::<ComputePushConstants>() as u32,
-                },
-            ],
+            push_constant_ranges: if pushed_size > 0 { &push_constant_range } else { &[] },
         });
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: Some("compute_pipeline"),
             layout: Some(&layout),
             module: &compute_module,
-            entry_point: Some("compute_main"),
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            entry_point: Some(entry_point),
+            compilation_options: wgpu::PipelineCompilationOptions {
+                constants: &constants,
+                ..Default::default()
+            },
             cache: None,
-        });
-
-        pipeline
+        })
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn default_pipeline(
         device: &wgpu::Device,
         bind_group_layouts: &[&wgpu::BindGroupLayout],
-        color_format: wgpu::TextureFormat
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        modules: &RenderModules,
+        variant: &DefaultVariant,
+        target: DefaultTarget,
     ) -> wgpu::RenderPipeline {
-        let default_module = device.create_shader_module(wgpu::include_wgsl!("../shaders/default.wgsl"));
+        let color_targets = [Some(wgpu::ColorTargetState {
+            format: color_format,
+            write_mask: wgpu::ColorWrites::ALL,
+            blend: variant.transparent.then_some(wgpu::BlendState::ALPHA_BLENDING),
+        })];
+        let g_buffer_targets = Deferred::color_targets();
+        let accumulation_targets = WeightedBlended::color_targets();
+        let (fragment_entry, targets, sample_count): (_, &[_], _) = match target {
+            DefaultTarget::Scene => (modules.fragment_entry, &color_targets, sample_count),
+            // The G-buffer isn't multisampled, the lighting pass shades each pixel once
+            DefaultTarget::GBuffer => (Deferred::FRAGMENT_ENTRY, &g_buffer_targets, 1),
+            DefaultTarget::Accumulation => (WeightedBlended::FRAGMENT_ENTRY, &accumulation_targets, sample_count),
+        };
 
         let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("default_pipeline_layout"),
             bind_group_layouts,
-            push_constant_ranges: &[
-                wgpu::PushConstantRange {
-                    stages: wgpu::ShaderStages::VERTEX,
-                    range: 0..std::mem::size_of::<ComputePushConstants>() as u32,
-                }
-            ],
+            push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("default_pipeline"),
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(variant.label),
             layout: Some(&layout),
             vertex: wgpu::VertexState {
-                module: &default_module,
-                entry_point: Some("vs_main"),
+                module: &modules.vertex,
+                entry_point: Some(modules.vertex_entry),
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
                 buffers: &[
                     DefaultVertex3d::desc(),
                     InstanceRepr::desc(),
+                    InstanceTransform::desc(),
+                    InstanceColor::desc(),
+                ]
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: variant.topology,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: variant.cull_mode,
+                polygon_mode: variant.polygon_mode,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &modules.fragment,
+                entry_point: Some(fragment_entry),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                targets,
+            }),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: !variant.transparent && target != DefaultTarget::Accumulation,
+                depth_compare: depth_order.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Variants of the opaque default pipelines drawing into `target`
+    /// rather than the scene.
+    #[allow(clippy::too_many_arguments)]
+    fn opaque_variant_pipelines(
+        device: &wgpu::Device,
+        instance_layouts: &[wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        modules: &RenderModules,
+        target: DefaultTarget,
+    ) -> Vec<(PipelineSelector, wgpu::RenderPipeline)> {
+        let layouts: Vec<_> = instance_layouts.iter().collect();
+        DEFAULT_VARIANTS
+            .iter()
+            .filter(|variant| !variant.transparent && variant.supported(device.features()))
+            .map(|variant| {
+                let pipeline = Self::default_pipeline(
+                    device,
+                    &layouts,
+                    color_format,
+                    sample_count,
+                    depth_order,
+                    modules,
+                    variant,
+                    target,
+                );
+                (variant.selector, pipeline)
+            })
+            .collect()
+    }
+
+    /// G-buffer variants of the opaque default pipelines, and the lighting
+    /// pass shading what they draw.
+    fn deferred_pipelines(
+        device: &wgpu::Device,
+        instance_layouts: &[wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        modules: &RenderModules,
+        deferred: &Deferred,
+    ) -> (Vec<(PipelineSelector, wgpu::RenderPipeline)>, wgpu::RenderPipeline) {
+        let pipelines = Self::opaque_variant_pipelines(
+            device,
+            instance_layouts,
+            color_format,
+            sample_count,
+            depth_order,
+            modules,
+            DefaultTarget::GBuffer,
+        );
+        // Group 3 is the G-buffer's in the lighting pass
+        let layouts: Vec<_> = instance_layouts.iter().collect();
+        let lighting = deferred.create_lighting_pipeline(
+            device,
+            &[layouts[0], layouts[1], layouts[2], deferred.layout()],
+            color_format,
+            sample_count,
+            modules,
+        );
+        (pipelines, lighting)
+    }
+
+    fn impostor_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        shaders: &ShaderLoader,
+    ) -> wgpu::RenderPipeline {
+        let impostor_module = shaders.module(device, "impostor.wgsl", include_str!("../shaders/impostor.wgsl"));
+        let constants = ImpostorAtlas::override_constants();
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("impostor_pipeline_layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("impostor_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &impostor_module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+                buffers: &[
+                    InstanceRepr::desc(),
+                    InstanceTransform::desc(),
+                ]
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &impostor_module,
+                entry_point: Some("fs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+                targets: &[
+                    Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        write_mask: wgpu::ColorWrites::ALL,
+                        blend: None,
+                    })
+                ]
+            }),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth_order.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Non-instanced, vertex-colored and lit triangles, see `greedy.wgsl`.
+    #[allow(clippy::too_many_arguments)]
+    fn lit_mesh_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        shaders: &ShaderLoader,
+        label: &str,
+        vertex_layout: wgpu::VertexBufferLayout,
+    ) -> wgpu::RenderPipeline {
+        let module = shaders.module(device, "greedy.wgsl", include_str!("../shaders/greedy.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[
+                    vertex_layout,
                 ]
             },
             primitive: wgpu::PrimitiveState {
@@ -449,11 +2296,11 @@ impl App<'_> {
                 ..Default::default()
             },
             multisample: wgpu::MultisampleState {
-                count: Self::MULTISAMPLE_SAMPLES,
+                count: sample_count,
                 ..Default::default()
             },
             fragment: Some(wgpu::FragmentState {
-                module: &default_module,
+                module: &module,
                 entry_point: Some("fs_main"),
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
                 targets: &[
@@ -467,131 +2314,2416 @@ impl App<'_> {
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: Texture2d::DEPTH_FORMAT,
                 depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
+                depth_compare: depth_order.compare(),
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multiview: None,
             cache: None,
+        })
+    }
+
+    /// Fullscreen raymarching of the scene in `sdf.wgsl`. Fragments write
+    /// the depth of their hit, so the regular depth test composites them
+    /// with rasterized geometry.
+    fn sdf_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        shaders: &ShaderLoader,
+    ) -> wgpu::RenderPipeline {
+        let module = shaders.module(device, "sdf.wgsl", include_str!("../shaders/sdf.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("sdf_pipeline_layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
         });
 
-        pipeline
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("sdf_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                targets: &[
+                    Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        write_mask: wgpu::ColorWrites::ALL,
+                        blend: None,
+                    })
+                ]
+            }),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth_order.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Draws the selected instances pushed out by a few pixels and behind
+    /// themselves, leaving a rim around the instances drawn before.
+    fn outline_pipeline(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        depth_order: DepthOrder,
+        shaders: &ShaderLoader,
+    ) -> wgpu::RenderPipeline {
+        let module = shaders.module(device, "outline.wgsl", include_str!("../shaders/outline.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("outline_pipeline_layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("outline_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[DefaultVertex3d::desc(), InstanceRepr::desc(), InstanceTransform::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    write_mask: wgpu::ColorWrites::ALL,
+                    blend: None,
+                })],
+            }),
+            // Tested against the instances, but left out of the depth so
+            // nothing drawn later is hidden by the rim
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: depth_order.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Uploads the frame's instance and uniform data in one submission,
+    /// writing through the staging belt instead of a fresh staging copy per
+    /// `write_buffer`.
+    fn update_buffers(&mut self) {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("update_buffers"),
+        });
+        let device = &self.device;
+        let belt = &mut self.staging_belt;
+        let mut upload = |encoder: &mut wgpu::CommandEncoder, target: &wgpu::Buffer, data: &[u8]| {
+            if let Some(size) = wgpu::BufferSize::new(data.len() as u64) {
+                belt.write_buffer(encoder, target, 0, size, device).copy_from_slice(data);
+            }
+        };
+
+        if self.compat {
+            let spinning = self.transform_settings.spin > 0.0;
+            for chunk in &self.instance_buffers.chunks {
+                let range = chunk.range.start as usize..chunk.range.end as usize;
+                upload(&mut encoder, chunk.positions_vsh.buffer(), bytemuck::cast_slice(&self.positions[range.clone()]));
+                if spinning {
+                    upload(&mut encoder, chunk.transforms.buffer(), bytemuck::cast_slice(&self.transforms[range]));
+                }
+            }
+        } else {
+            debug_labels::push(&mut encoder, || {
+                format!("vertex positions #{}, {} instances", self.frame.frame_index, self.positions.len())
+            });
+            let shown = self.instance_buffers.shown();
+            match &self.hierarchy {
+                Some(hierarchy) => hierarchy.record(
+                    &self.device,
+                    &mut encoder,
+                    shown,
+                    &self.frame_bind_group,
+                    self.frame_ring.uniform_offset(),
+                ),
+                None => {
+                    for chunk in &self.instance_buffers.chunks {
+                        encoder.copy_buffer_to_buffer(
+                            chunk.positions[shown].buffer(), 0,
+                            chunk.positions_vsh.buffer(), 0,
+                            chunk.positions[shown].size(),
+                        );
+                    }
+                }
+            }
+            debug_labels::pop(&mut encoder);
+        }
+
+        upload(&mut encoder, &self.camera_buffer, bytemuck::bytes_of(&self.camera.uniform()));
+        upload(&mut encoder, &self.scene_buffer, bytemuck::bytes_of(&self.scene.uniform()));
+
+        self.staging_belt.finish();
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.staging_belt.recall();
+    }
+}
+
+impl App<'_> {
+    fn update(&mut self, delta: f64) {
+        match &self.capture {
+            Some(capture) => capture.place_camera(&mut self.camera),
+            None => {
+                if let Some(transition) = &mut self.camera_transition {
+                    let viewpoint = transition.advance(delta as f32);
+                    self.camera.eye = viewpoint.eye;
+                    self.camera_controller.set_orientation(viewpoint.yaw, viewpoint.pitch);
+                    if transition.finished() {
+                        self.camera_transition = None;
+                    }
+                }
+                self.camera_controller.update(&mut self.camera, delta as f32);
+            }
+        }
+        self.last_delta = delta;
+
+        if let Some(streamer) = &mut self.streamer {
+            streamer.update(&self.queue, self.camera.eye, self.scene.max_draw_distance);
+        }
+
+        self.frame.time = self.time as f32;
+        // Only the kernels read it, every step simulates the same time
+        self.frame.delta = self.timestep.step as f32;
+        self.frame.frame_index = self.frame.frame_index.wrapping_add(1);
+        self.frame_ring.advance(&self.device);
+        self.queue.write_buffer(
+            &self.frame_buffer,
+            self.frame_ring.uniform_offset() as u64,
+            bytemuck::bytes_of(&self.frame),
+        );
+
+        self.demo.update(self.time, delta);
+
+        let steps = if self.paused {
+            std::mem::take(&mut self.queued_steps)
+        } else {
+            self.timestep.advance(delta)
+        };
+        if let Some(replay) = &mut self.replay {
+            replay.record(steps);
+        }
+        if steps == 0 {
+            return;
+        }
+        if !self.positions.is_empty() {
+            self.drop_greedy_mesh();
+        }
+        let step = self.timestep.step as f32;
+        self.chunk_culler.advance(steps as f32 * step);
+
+        let collect_stats = self.time - self.last_simulation_stats_time >= Self::SIMULATION_STATS_PERIOD;
+        if collect_stats {
+            self.last_simulation_stats_time = self.time;
+        }
+
+        let Some(pv_bind_groups) = &self.pv_bind_groups else {
+            let kernel = &KERNELS[self.kernel];
+            let simulation = kernel.constants(&self.simulation);
+            for _ in 0..steps {
+                (kernel.cpu)(&mut self.positions, &mut self.velocities, step, &simulation);
+                cpu_kernels::follow(&self.positions, &mut self.velocities, simulation.follow.uniform(&self.camera), step);
+                cpu_kernels::pull_wells(
+                    &self.positions,
+                    &mut self.velocities,
+                    self.gravity_wells.wells(),
+                    simulation.params.softening,
+                    step,
+                );
+                cpu_kernels::damp(&mut self.velocities, simulation.params.drag, step);
+                cpu_kernels::collide(&mut self.positions, &mut self.velocities, &self.collision.obstacles);
+                cpu_kernels::confine(
+                    &mut self.positions,
+                    &mut self.velocities,
+                    &self.simulation.bounds,
+                    self.collision.obstacles.restitution,
+                );
+                cpu_kernels::spin(&mut self.transforms, step);
+            }
+            self.chunk_culler.refit(&self.positions);
+            if collect_stats {
+                let histogram_max = SimulationStats::next_histogram_max(self.simulation_stats.as_ref());
+                let stats = SimulationStats::from_state(
+                    &self.positions,
+                    &self.velocities,
+                    histogram_max,
+                    self.simulation.params.gravity,
+                );
+                self.receive_simulation_stats(stats);
+            }
+            return;
+        };
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("simulation"),
+        });
+        debug_labels::push(&mut encoder, || format!("simulation #{}", self.frame.frame_index));
+        let emitter_active = self.emitter_active();
+        if emitter_active {
+            self.emitter.begin_frame();
+            self.sim_params.clear_spawned(&mut encoder);
+        }
+        let constants: Vec<_> = (0..steps)
+            .map(|substep| {
+                let emitter = if emitter_active {
+                    self.emitter.step(step);
+                    let lifetime = self.lifetime.unwrap_or_default();
+                    self.emitter.uniform(lifetime, self.frame.frame_index.wrapping_mul(Self::MAX_SEEDS_PER_FRAME).wrapping_add(substep))
+                } else {
+                    EmitterUniform::default()
+                };
+                ComputePushConstants::new(&self.simulation, emitter, &self.camera)
+            })
+            .collect();
+        self.sim_params.write_constants(&self.device, &self.queue, &constants);
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("compute_pass"),
+                timestamp_writes: self
+                    .profiler
+                    .as_mut()
+                    .map(|profiler| profiler.compute_timestamp_writes(ProfiledPass::Simulation)),
+            });
+
+            let kernel = KERNELS[self.kernel].name;
+            let compute_pipeline = |name| match self.pipelines.get(&PipelineSelector::Custom { name }) {
+                Some(Pipeline::Compute(pipeline)) => Some(pipeline),
+                _ => None,
+            };
+            let kernel_pipeline = compute_pipeline(kernel);
+            let prepass_pipelines: Vec<_> = KERNELS[self.kernel]
+                .prepasses
+                .iter()
+                .filter_map(|&name| compute_pipeline(name))
+                .collect();
+            let respawn_pipeline = compute_pipeline("respawn").filter(|_| emitter_active);
+            if let Some(pipeline) = kernel_pipeline {
+                compute_pass.set_pipeline(pipeline);
+            }
+
+            compute_pass.set_bind_group(0, &self.frame_bind_group, &[self.frame_ring.uniform_offset()]);
+            compute_pass.set_bind_group(2, self.collision.bind_group(), &[]);
+
+            debug_labels::push(&mut compute_pass, || format!("kernel {kernel}, {steps} steps"));
+            for substep in 0..steps {
+                self.sim_params.bind(&mut compute_pass, substep as usize);
+                let front = self.instance_buffers.front();
+                if !prepass_pipelines.is_empty() {
+                    for pipeline in &prepass_pipelines {
+                        compute_pass.set_pipeline(pipeline);
+                        for (index, pv_bind_groups) in pv_bind_groups.iter().enumerate() {
+                            compute_pass.set_bind_group(1, &pv_bind_groups[front], &[]);
+                            self.kernel_dispatch.dispatch(&mut compute_pass, index);
+                        }
+                    }
+                    if let Some(pipeline) = kernel_pipeline {
+                        compute_pass.set_pipeline(pipeline);
+                    }
+                }
+                for (index, (chunk, pv_bind_groups)) in self.instance_buffers.chunks.iter().zip(pv_bind_groups).enumerate() {
+                    debug_labels::marker(&mut compute_pass, || {
+                        format!("chunk {index}: {} instances", chunk.range.len())
+                    });
+                    compute_pass.set_bind_group(1, &pv_bind_groups[front], &[]);
+                    self.kernel_dispatch.dispatch(&mut compute_pass, index);
+                }
+                // Brings back what the step expired, in the state it wrote
+                if let Some(respawn_pipeline) = respawn_pipeline {
+                    compute_pass.set_pipeline(respawn_pipeline);
+                    for (index, pv_bind_groups) in pv_bind_groups.iter().enumerate() {
+                        compute_pass.set_bind_group(1, &pv_bind_groups[front], &[]);
+                        self.kernel_dispatch.dispatch(&mut compute_pass, index);
+                    }
+                    if let Some(pipeline) = kernel_pipeline {
+                        compute_pass.set_pipeline(pipeline);
+                    }
+                }
+                // Everything recorded from here on reads the state the step wrote
+                self.instance_buffers.swap();
+            }
+            debug_labels::pop(&mut compute_pass);
+        }
+
+        if let Some(history) = &mut self.history {
+            debug_labels::push(&mut encoder, || format!("history, {} snapshots", history.len()));
+            history.tick(&self.device, &mut encoder, &self.instance_buffers, self.time);
+            debug_labels::pop(&mut encoder);
+        }
+
+        debug_labels::push(&mut encoder, || "statistics".to_string());
+        let histogram_max = SimulationStats::next_histogram_max(self.simulation_stats.as_ref());
+        let front = self.instance_buffers.front();
+        let statistics = self
+            .simulation_statistics
+            .as_ref()
+            .filter(|_| collect_stats)
+            .is_some_and(|statistics| {
+                statistics.record(
+                    &self.device,
+                    &self.queue,
+                    &mut encoder,
+                    &mut self.frame_ring,
+                    front,
+                    histogram_max,
+                    self.simulation.params.gravity,
+                )
+            });
+        let chunk_bounds = self
+            .chunk_bounds
+            .as_mut()
+            .filter(|_| self.culling_mode == CullingMode::CpuChunks)
+            .is_some_and(|chunk_bounds| chunk_bounds.record(&mut encoder, front, &self.chunk_culler));
+        debug_labels::pop(&mut encoder);
+        debug_labels::pop(&mut encoder);
+
+        self.pending_simulation = Some(PendingSimulation {
+            commands: encoder.finish(),
+            statistics,
+            chunk_bounds,
+        });
+    }
+
+    /// Submits the simulation steps recorded since the last submission, on
+    /// their own unless a frame goes first. Frames draw the side of the state
+    /// the steps before wrote, which the steps only read or overwrite once
+    /// the frame copied it, so the GPU is free to run the frame's render pass
+    /// alongside the kernels. The next frame shows what they simulated.
+    /// Commands in `after` need the steps done, like resolving the queries
+    /// timing them.
+    fn submit_simulation(
+        &mut self,
+        frame: Option<wgpu::CommandBuffer>,
+        after: Option<wgpu::CommandBuffer>,
+    ) {
+        let simulation = self.pending_simulation.take();
+        if frame.is_none() && simulation.is_none() {
+            return;
+        }
+
+        let statistics = simulation.as_ref().is_some_and(|simulation| simulation.statistics);
+        let chunk_bounds = simulation.as_ref().is_some_and(|simulation| simulation.chunk_bounds);
+        let commands = frame.into_iter().chain(simulation.map(|simulation| simulation.commands)).chain(after);
+        let submission = self.queue.submit(commands);
+        self.frame_ring.submitted(&self.queue, submission);
+        if statistics {
+            self.frame_ring.map_readbacks();
+        }
+        if let Some(chunk_bounds) = self.chunk_bounds.as_ref().filter(|_| chunk_bounds) {
+            chunk_bounds.submitted();
+        }
+        self.instance_buffers.show_front();
+    }
+
+    /// Starts copying the current instance positions back from the GPU. The
+    /// future resolves once a later device poll sees the copy finished.
+    /// Compatibility mode reads back the vertex positions written from the CPU.
+    pub fn read_positions(&self) -> impl Future<Output = ReadbackResult<Vec<[f32; 3]>>> + use<> {
+        let front = self.instance_buffers.front();
+        let sources: Vec<_> = self
+            .instance_buffers
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let positions = if self.compat { &chunk.positions_vsh } else { &chunk.positions[front] };
+                (positions.buffer(), positions.size())
+            })
+            .collect();
+        let readback = Readback::<[f32; 4]>::new(&self.device, &self.queue, &sources);
+        async move { Ok(readback.await?.into_iter().map(|[x, y, z, _]| [x, y, z]).collect()) }
+    }
+
+    /// Restores the simulation `steps` snapshots back, returning how long ago
+    /// the restored snapshot was taken.
+    fn rewind(&mut self, steps: usize) -> ConsoleResult<f64> {
+        let history = self
+            .history
+            .as_mut()
+            .ok_or_else(|| ConsoleError::new("No history is recorded, see --history".to_string()))?;
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("rewind"),
+        });
+        debug_labels::marker(&mut encoder, || format!("rewind {steps} of {} snapshots", history.len()));
+        let time = history
+            .rewind(&mut encoder, &self.instance_buffers, steps)
+            .ok_or_else(|| ConsoleError::new("No snapshots to rewind to yet".to_string()))?;
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.invalidate_chunk_bounds();
+
+        Ok(self.time - time)
+    }
+
+    /// Effects run over the HDR scene, in order, ahead of tone mapping.
+    fn post_effects(&self) -> impl Iterator<Item = &dyn PostEffect> {
+        self.bloom.iter().map(|bloom| bloom as &dyn PostEffect)
+    }
+
+    /// Records one pass of the frame, `image` and `view` being the surface's.
+    fn record_frame_pass(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        pass: FramePass,
+        image: &wgpu::SurfaceTexture,
+        view: &wgpu::TextureView,
+    ) {
+        match pass {
+            FramePass::Prepare => self.record_prepare_pass(encoder),
+            FramePass::Shadows => {
+                if self.material.features.contains(ShaderFeatures::LIT | ShaderFeatures::SHADOWED) {
+                    let instance_mesh = if KERNELS[self.kernel].spheres { &self.sphere_mesh } else { &self.cube_mesh };
+                    let shadow_maps = &self.shadow_maps;
+                    shadow_maps.update(
+                        &self.queue,
+                        &self.camera,
+                        self.scene.light.unit_direction(),
+                        self.scene.max_draw_distance,
+                    );
+                    debug_labels::push(encoder, || format!("shadows in {} cascades", shadow_maps.settings.cascades));
+                    shadow_maps.record(encoder, instance_mesh, &self.instance_buffers, 0..self.positions.len() as u32);
+                    debug_labels::pop(encoder);
+                }
+            }
+            FramePass::GBuffer => self.record_g_buffer_pass(encoder),
+            FramePass::Scene => self.record_scene_pass(encoder, view),
+            FramePass::Transparency => self.record_transparency_pass(encoder, view),
+            FramePass::PostEffect(index) => {
+                if let Some(effect) = self.post_effects().nth(index) {
+                    debug_labels::push(encoder, || effect.name().to_string());
+                    effect.record(encoder, self.render_graph.texture(HDR_SCENE));
+                    debug_labels::pop(encoder);
+                }
+            }
+            FramePass::ToneMapping => {
+                if let Some(tone_mapping) = &self.tone_mapping {
+                    let target = self.render_graph.writes(pass)[0];
+                    debug_labels::marker(encoder, || format!("tone mapping with {:?}", tone_mapping.tone_mapper()));
+                    tone_mapping.record(encoder, self.render_graph.view(target, view));
+                }
+            }
+            FramePass::Upscale => {
+                if let Some(upscale) = &self.upscale {
+                    debug_labels::push(encoder, || {
+                        let (width, height) = upscale.internal_size();
+                        format!("upscale from {width}x{height}")
+                    });
+                    upscale.record(encoder, view);
+                    debug_labels::pop(encoder);
+                }
+            }
+            FramePass::Capture => {
+                if let Some(capture) = &mut self.capture {
+                    debug_labels::marker(encoder, || "capture readback".to_string());
+                    capture.copy_frame(&self.device, encoder, &image.texture);
+                }
+            }
+        }
+    }
+
+    fn record_prepare_pass(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let instance_mesh = if KERNELS[self.kernel].spheres { &self.sphere_mesh } else { &self.cube_mesh };
+        if let Some(sorter) = &self.sorter {
+            debug_labels::push(encoder, || format!("back to front sort of {} instances", self.positions.len()));
+            sorter.record(
+                &self.device,
+                &self.queue,
+                encoder,
+                &self.instance_buffers,
+                self.instance_buffers.shown(),
+                instance_mesh,
+                self.camera.eye,
+            );
+            debug_labels::pop(encoder);
+        } else if let Some(lod) = &self.lod {
+            debug_labels::push(encoder, || format!("level of detail selection of {} instances", self.positions.len()));
+            let projection = self.camera.projection(self.camera.aspect);
+            lod.record(
+                &self.device,
+                &self.queue,
+                encoder,
+                self.instance_buffers.shown(),
+                self.camera.eye,
+                self.scene.max_draw_distance,
+                projection.y.y * self.scene.viewport_height * 0.5,
+                self.scene.impostor_threshold,
+            );
+            debug_labels::pop(encoder);
+        } else if let (Some(occlusion), CullingMode::GpuOcclusion) = (&mut self.occlusion, self.culling_mode) {
+            debug_labels::push(encoder, || format!("occlusion culling of {} instances", self.positions.len()));
+            occlusion.record(
+                &self.device,
+                &self.queue,
+                encoder,
+                self.instance_buffers.shown(),
+                instance_mesh,
+                self.camera.projection(self.camera.aspect) * self.camera.view(),
+                self.camera.eye,
+                self.scene.max_draw_distance,
+            );
+            debug_labels::pop(encoder);
+        } else if let Some(compaction) = &self.compaction {
+            debug_labels::push(encoder, || format!("compaction of {} instances", self.positions.len()));
+            compaction.record(&self.device, &self.queue, encoder, self.instance_buffers.shown(), instance_mesh);
+            debug_labels::pop(encoder);
+        }
+        self.selection.gather(encoder, &self.instance_buffers);
+        if let Some(isosurface) = &self.isosurface {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("isosurface_pass"),
+                timestamp_writes: None,
+            });
+            debug_labels::marker(&mut compute_pass, || "isosurface extraction".to_string());
+            compute_pass.set_bind_group(0, &self.frame_bind_group, &[self.frame_ring.uniform_offset()]);
+            isosurface.extract(&self.queue, &mut compute_pass);
+        }
+    }
+
+    /// Ranges of the instances to draw, culled by chunk when that's on.
+    fn visible_ranges(&mut self) -> Vec<Range<u32>> {
+        let all_instances = 0..self.positions.len() as u32;
+        match self.culling_mode {
+            CullingMode::Disabled => vec![all_instances],
+            CullingMode::CpuChunks => self.chunk_culler.cull(&self.camera.frustum()).to_vec(),
+            // Culled per instance on the GPU, ranges only go to the impostors
+            CullingMode::GpuOcclusion => vec![all_instances],
+        }
+    }
+
+    fn instances_label(ranges: &[Range<u32>], count: usize) -> String {
+        let visible: usize = ranges.iter().map(|range| range.len()).sum();
+        format!("instances, {visible} of {count} in {} ranges", ranges.len())
+    }
+
+    /// Deferred shading, unless it's switched off or greedy meshing draws
+    /// the scene instead.
+    fn deferred_shading(&self) -> Option<&Deferred> {
+        let greedy = self.render_mode == RenderMode::GreedyMesh && self.greedy_mesh.is_some();
+        self.deferred.as_ref().filter(|deferred| deferred.enabled && !greedy)
+    }
+
+    /// Weighted blended transparency, unless the shaders have no path for it
+    /// and the translucent groups are drawn opaque.
+    fn weighted_blending(&self) -> Option<&WeightedBlended> {
+        self.weighted_blended.as_ref().filter(|weighted_blended| !weighted_blended.pipelines.is_empty())
+    }
+
+    /// Draws the opaque instances, with the pipelines writing the G-buffer
+    /// when `g_buffer` is set.
+    fn draw_opaque_instances(&mut self, render_pass: &mut wgpu::RenderPass<'_>, ranges: &[Range<u32>], g_buffer: bool) {
+        let instance_mesh = if KERNELS[self.kernel].spheres { &self.sphere_mesh } else { &self.cube_mesh };
+        let pipelines = match &self.deferred {
+            Some(deferred) if g_buffer => &deferred.pipelines,
+            _ => &self.pipelines,
+        };
+        let occlusion = self.occlusion.as_ref().filter(|_| self.culling_mode == CullingMode::GpuOcclusion);
+        let opaque = DefaultVariant::opaque_selector(self.polygon_mode);
+        if let Pipeline::Render(pipeline) = &pipelines[&opaque] {
+            render_pass.set_pipeline(pipeline);
+        }
+        // Culled and sorted draws index their compacted lists, only whole
+        // chunks know where their instances are in the grid
+        self.chunk_offsets.bind(render_pass, 0);
+
+        match (&self.lod, occlusion, &self.compaction, &mut self.multi_draw) {
+            // Transparent instances are drawn sorted after everything opaque
+            _ if self.sorter.is_some() => {}
+            (Some(lod), ..) => {
+                for (index, level) in lod.levels().iter().enumerate() {
+                    debug_labels::marker(render_pass, || format!("level of detail {index}: {}", level.name));
+                    let selector = if level.points {
+                        PipelineSelector::Custom { name: "points" }
+                    } else {
+                        opaque
+                    };
+                    if let Pipeline::Render(pipeline) = &pipelines[&selector] {
+                        render_pass.set_pipeline(pipeline);
+                    }
+                    lod.draw(render_pass, index);
+                }
+                if let Pipeline::Render(pipeline) = &pipelines[&opaque] {
+                    render_pass.set_pipeline(pipeline);
+                }
+            }
+            (None, Some(occlusion), ..) => {
+                debug_labels::marker(render_pass, || "instances left after occlusion culling".to_string());
+                occlusion.draw(render_pass, instance_mesh);
+            }
+            (None, None, Some(compaction), _) => {
+                debug_labels::marker(render_pass, || "instances left after compaction".to_string());
+                compaction.draw(render_pass, instance_mesh);
+            }
+            (None, None, None, Some(multi_draw)) => {
+                let batches = self.instance_buffers.batches(ranges);
+                multi_draw.write(&self.device, &self.queue, instance_mesh, &batches);
+                debug_labels::marker(render_pass, || {
+                    format!("multi-draw, {} draws in {} batches", multi_draw.draw_count(), batches.len())
+                });
+                for (index, chunk) in self.instance_buffers.chunks.iter().enumerate() {
+                    self.chunk_offsets.bind(render_pass, index);
+                    render_pass.set_vertex_buffer(2, chunk.transforms.slice());
+                    render_pass.set_vertex_buffer(3, chunk.colors.slice());
+                    multi_draw.draw(render_pass, instance_mesh, chunk.positions_vsh.buffer(), index);
+                }
+            }
+            (None, None, None, None) => {
+                for range in ranges {
+                    for (index, chunk, instances) in self.instance_buffers.split(range.clone()) {
+                        self.chunk_offsets.bind(render_pass, index);
+                        render_pass.set_vertex_buffer(2, chunk.transforms.slice());
+                        render_pass.set_vertex_buffer(3, chunk.colors.slice());
+                        instance_mesh.draw_instanced(render_pass, chunk.positions_vsh.buffer(), instances);
+                    }
+                }
+            }
+        }
+        if let Some(streamer) = &self.streamer {
+            debug_labels::marker(render_pass, || {
+                format!("streamed, {} points", streamer.resident_points())
+            });
+            self.chunk_offsets.bind(render_pass, 0);
+            render_pass.set_vertex_buffer(2, streamer.transforms().slice(..));
+            for (buffer, colors, len) in streamer.resident() {
+                render_pass.set_vertex_buffer(3, colors.slice(..));
+                self.cube_mesh.draw_instanced(render_pass, buffer, 0..len);
+            }
+        }
+    }
+
+    /// Renders the opaque instances into the G-buffer, lit at the start of
+    /// the scene pass.
+    fn record_g_buffer_pass(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(deferred) = self.deferred_shading() else {
+            return;
+        };
+        let mut render_pass = deferred.begin_geometry_pass(encoder);
+        render_pass.set_bind_group(0, &self.frame_bind_group, &[self.frame_ring.uniform_offset()]);
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.scene_bind_group, &[]);
+
+        let ranges = self.visible_ranges();
+        debug_labels::push(&mut render_pass, || Self::instances_label(&ranges, self.positions.len()));
+        self.draw_opaque_instances(&mut render_pass, &ranges, true);
+        debug_labels::pop(&mut render_pass);
+    }
+
+    /// Records the draw of every instance group into a render bundle for
+    /// each frame slot. Nothing in them changes from frame to frame, so
+    /// replaying them saves encoding every group's pipeline, buffers and
+    /// draw each frame.
+    fn record_group_bundles(&self) -> Vec<Vec<wgpu::RenderBundle>> {
+        let record = |group: &InstanceGroup, offset| {
+            let mut encoder = self.device.create_render_bundle_encoder(&wgpu::RenderBundleEncoderDescriptor {
+                label: Some("instance_group_bundle_encoder"),
+                color_formats: &[Some(self.scene_format)],
+                depth_stencil: Some(wgpu::RenderBundleDepthStencil {
+                    format: Texture2d::DEPTH_FORMAT,
+                    depth_read_only: false,
+                    stencil_read_only: true,
+                }),
+                sample_count: self.sample_count,
+                multiview: None,
+            });
+            encoder.set_bind_group(0, &self.frame_bind_group, &[offset]);
+            encoder.set_bind_group(1, &self.camera_bind_group, &[]);
+            encoder.set_bind_group(2, &self.scene_bind_group, &[]);
+            self.chunk_offsets.bind_bundle(&mut encoder, 0);
+            if let Pipeline::Render(pipeline) = &self.pipelines[&group.selector] {
+                encoder.set_pipeline(pipeline);
+            }
+            group.bundle(&mut encoder);
+            encoder.finish(&wgpu::RenderBundleDescriptor {
+                label: Some("instance_group_bundle"),
+            })
+        };
+        self.instance_groups
+            .iter()
+            .map(|group| self.frame_ring.uniform_offsets().map(|offset| record(group, offset)).collect())
+            .collect()
+    }
+
+    /// Draws into the multisampled framebuffer, resolved into the graph's
+    /// scene target, or into the target itself without multisampling.
+    fn record_scene_pass(&mut self, encoder: &mut wgpu::CommandEncoder, surface_view: &wgpu::TextureView) {
+        if self.group_bundles.is_none() && !self.instance_groups.is_empty() {
+            self.group_bundles = Some(self.record_group_bundles());
+        }
+        // Not covered by the occlusion culler, so culled here either way
+        let frustum = (self.culling_mode != CullingMode::Disabled).then(|| self.camera.frustum());
+        for group in &mut self.instance_groups {
+            group.cull(frustum.as_ref());
+        }
+
+        let target = self.render_graph.view(self.render_graph.writes(FramePass::Scene)[0], surface_view);
+        let (view, resolve_target) = match &self.multisample_framebuffer {
+            Some(framebuffer) => (framebuffer, Some(target)),
+            None => (target, None),
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(
+                        self.scene.background(self.material.features.contains(ShaderFeatures::FOGGED)),
+                    ),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.camera.depth_order.far()),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: self
+                .profiler
+                .as_mut()
+                .map(|profiler| profiler.render_timestamp_writes(ProfiledPass::Render)),
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_bind_group(0, &self.frame_bind_group, &[self.frame_ring.uniform_offset()]);
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.scene_bind_group, &[]);
+
+        // Before anything else, it overwrites the depth
+        let deferred = match self.deferred_shading() {
+            Some(deferred) => {
+                debug_labels::marker(&mut render_pass, || {
+                    format!("deferred lighting with {} point lights", deferred.settings.point_lights)
+                });
+                deferred.draw_lighting(&mut render_pass);
+                true
+            }
+            None => false,
+        };
+
+        if let (RenderMode::GreedyMesh, Some(greedy_mesh)) = (self.render_mode, &self.greedy_mesh) {
+            debug_labels::push(&mut render_pass, || format!("greedy mesh, {} triangles", greedy_mesh.triangles));
+            if let Pipeline::Render(pipeline) = &self.pipelines[&PipelineSelector::Custom { name: "greedy" }] {
+                render_pass.set_pipeline(pipeline);
+            }
+            greedy_mesh.draw(&mut render_pass);
+            debug_labels::pop(&mut render_pass);
+        } else {
+            let ranges = self.visible_ranges();
+            if !deferred {
+                debug_labels::push(&mut render_pass, || Self::instances_label(&ranges, self.positions.len()));
+                self.draw_opaque_instances(&mut render_pass, &ranges, false);
+                debug_labels::pop(&mut render_pass);
+            }
+
+            // Only filled by the level of detail pass, which transparent instances skip
+            let impostors = self.lod.as_ref().filter(|_| self.sorter.is_none() && self.scene.impostor_threshold > 0.0);
+            if let (Some(impostor_atlas), Some(lod)) = (&self.impostor_atlas, impostors) {
+                debug_labels::push(&mut render_pass, || {
+                    format!("impostors, threshold {}px", self.scene.impostor_threshold)
+                });
+                if let Pipeline::Render(pipeline) = &self.pipelines[&PipelineSelector::Custom { name: "impostor" }] {
+                    render_pass.set_pipeline(pipeline);
+                }
+
+                render_pass.set_bind_group(3, &impostor_atlas.bind_group, &[]);
+                lod.draw_impostors(&mut render_pass);
+                debug_labels::pop(&mut render_pass);
+            }
+        }
+
+        let weighted_blending = self.weighted_blending().is_some();
+        let drawn = |group: &&InstanceGroup| !(group.translucent && weighted_blending);
+        if let Some(group_bundles) = &self.group_bundles {
+            let bundles: Vec<_> = self
+                .instance_groups
+                .iter()
+                .zip(group_bundles)
+                .filter(|(group, _)| drawn(group) && group.is_whole())
+                .map(|(_, bundles)| &bundles[self.frame_ring.slot()])
+                .collect();
+            if !bundles.is_empty() {
+                debug_labels::marker(&mut render_pass, || format!("bundles of {} instance groups", bundles.len()));
+                render_pass.execute_bundles(bundles);
+                // Executing bundles leaves nothing bound
+                render_pass.set_bind_group(0, &self.frame_bind_group, &[self.frame_ring.uniform_offset()]);
+                render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+                render_pass.set_bind_group(2, &self.scene_bind_group, &[]);
+            }
+        }
+        // Impostors bind their atlas in place of the chunk offsets
+        self.chunk_offsets.bind(&mut render_pass, 0);
+        // Partly culled, drawn chunk by chunk instead
+        let partial = |group: &&InstanceGroup| !group.is_whole() && group.visible_count() > 0;
+        for group in self.instance_groups.iter().filter(drawn).filter(partial) {
+            debug_labels::marker(&mut render_pass, || {
+                format!(
+                    "group of {} {} instances, {} visible",
+                    group.count(),
+                    group.shape.name(),
+                    group.visible_count()
+                )
+            });
+            if let Pipeline::Render(pipeline) = &self.pipelines[&group.selector] {
+                render_pass.set_pipeline(pipeline);
+            }
+            group.draw(&mut render_pass);
+        }
+
+        if let Some(isosurface) = &self.isosurface {
+            debug_labels::marker(&mut render_pass, || "isosurface".to_string());
+            if let Pipeline::Render(pipeline) = &self.pipelines[&PipelineSelector::Custom { name: "isosurface" }] {
+                render_pass.set_pipeline(pipeline);
+            }
+            isosurface.draw(&mut render_pass);
+        }
+
+        if self.sdf {
+            debug_labels::marker(&mut render_pass, || "sdf raymarch".to_string());
+            if let Pipeline::Render(pipeline) = &self.pipelines[&PipelineSelector::Custom { name: "sdf" }] {
+                render_pass.set_pipeline(pipeline);
+            }
+            render_pass.draw(0..3, 0..1);
+        }
+
+        debug_labels::push(&mut render_pass, || format!("demo {}", self.demo_name));
+        self.demo.render(&mut render_pass);
+        debug_labels::pop(&mut render_pass);
+
+        let instance_mesh = if KERNELS[self.kernel].spheres { &self.sphere_mesh } else { &self.cube_mesh };
+        if !self.selection.is_empty() {
+            debug_labels::marker(&mut render_pass, || {
+                format!("outlines of {} selected instances", self.selection.ids().len())
+            });
+            if let Pipeline::Render(pipeline) = &self.pipelines[&PipelineSelector::Custom { name: "outline" }] {
+                render_pass.set_pipeline(pipeline);
+            }
+            self.selection.draw(&mut render_pass, instance_mesh);
+        }
+
+        let greedy = self.render_mode == RenderMode::GreedyMesh && self.greedy_mesh.is_some();
+        if let Some(sorter) = self.sorter.as_ref().filter(|_| !greedy) {
+            debug_labels::marker(&mut render_pass, || {
+                format!("transparent instances, alpha {}", self.instance_alpha)
+            });
+            if let Pipeline::Render(pipeline) = &self.pipelines[&PipelineSelector::Custom { name: "transparent" }] {
+                render_pass.set_pipeline(pipeline);
+            }
+            sorter.draw(&mut render_pass, instance_mesh);
+        }
+
+        // Blended, so after everything opaque
+        if self.weighted_blended.is_none() {
+            debug_labels::marker(&mut render_pass, || "text labels".to_string());
+            self.labels.draw(&mut render_pass);
+        }
+    }
+
+    /// Accumulates the translucent groups in front of the scene's depth and
+    /// blends them over it, then draws the text labels, which they would
+    /// hide otherwise.
+    fn record_transparency_pass(&self, encoder: &mut wgpu::CommandEncoder, surface_view: &wgpu::TextureView) {
+        if let Some(weighted_blended) = self.weighted_blending() {
+            let mut render_pass = weighted_blended.begin_accumulation_pass(encoder, &self.depth_texture.view);
+            render_pass.set_bind_group(0, &self.frame_bind_group, &[self.frame_ring.uniform_offset()]);
+            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.scene_bind_group, &[]);
+            self.chunk_offsets.bind(&mut render_pass, 0);
+            for group in self.instance_groups.iter().filter(|group| group.translucent && group.visible_count() > 0) {
+                debug_labels::marker(&mut render_pass, || {
+                    format!(
+                        "translucent group of {} {} instances, {} visible",
+                        group.count(),
+                        group.shape.name(),
+                        group.visible_count()
+                    )
+                });
+                if let Pipeline::Render(pipeline) = &weighted_blended.pipelines[&group.selector] {
+                    render_pass.set_pipeline(pipeline);
+                }
+                group.draw(&mut render_pass);
+            }
+        }
+
+        let target = self.render_graph.view(self.render_graph.writes(FramePass::Transparency)[0], surface_view);
+        let (view, resolve_target) = match &self.multisample_framebuffer {
+            Some(framebuffer) => (framebuffer, Some(target)),
+            None => (target, None),
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("transparency_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        if let Some(weighted_blended) = self.weighted_blending() {
+            debug_labels::marker(&mut render_pass, || "weighted blended composite".to_string());
+            weighted_blended.draw_composite(&mut render_pass);
+        }
+
+        render_pass.set_bind_group(0, &self.frame_bind_group, &[self.frame_ring.uniform_offset()]);
+        render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.scene_bind_group, &[]);
+        debug_labels::marker(&mut render_pass, || "text labels".to_string());
+        self.labels.draw(&mut render_pass);
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let image = self.surface.get_current_texture()?;
+
+        let view = image.texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(self.surface_config.view_formats[0]),
+            ..Default::default()
+        });
+
+        self.update_buffers();
+        let overlays: Vec<Label> = self
+            .statistics_label()
+            .into_iter()
+            .chain(self.shader_error_label())
+            .chain(self.console.label())
+            .collect();
+        self.labels.update(&self.device, &self.queue, &self.positions, &overlays);
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("frame"),
+        });
+        debug_labels::push(&mut encoder, || format!("frame #{}", self.frame.frame_index));
+        for pass in self.render_graph.order().to_vec() {
+            self.record_frame_pass(&mut encoder, pass, &image, &view);
+        }
+        debug_labels::pop(&mut encoder);
+
+        // The simulation steps submitted along with the frame write timestamps too
+        let mut resolve_encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("profiler_resolve"),
+        });
+        let profiled = self.profiler.as_mut().is_some_and(|profiler| profiler.resolve(&mut resolve_encoder));
+        self.submit_simulation(Some(encoder.finish()), Some(resolve_encoder.finish()));
+        self.latency.record_submit();
+        if let Some(profiler) = self.profiler.as_ref().filter(|_| profiled) {
+            profiler.submitted();
+        }
+
+        if let Some(Err(error)) = self.capture.as_mut().map(|capture| capture.save_frame(&self.device)) {
+            log::error!("Capture failed: {error}");
+        }
+
+        self.window.pre_present_notify();
+        image.present();
+        self.latency.record_present();
+
+        let now = Instant::now();
+        self.frame_stats.record_present(now);
+        self.run_frame_stats.record_present(now);
+
+        Ok(())
+    }
+
+    /// Rebuilds the default pipeline variants for the current material. On failure the
+    /// previous pipeline stays in place.
+    fn rebuild_default_pipeline(&mut self) -> ShaderResult<()> {
+        let modules = self.default_shaders.get(&self.device, self.material.features)?;
+
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipelines: Vec<_> = DEFAULT_VARIANTS
+            .iter()
+            .filter(|variant| variant.supported(self.device.features()))
+            .map(|variant| {
+                let pipeline = Self::default_pipeline(
+                    &self.device,
+                    &self.instance_layouts.iter().collect::<Vec<_>>(),
+                    self.scene_format,
+                    self.sample_count,
+                    self.camera.depth_order,
+                    modules,
+                    variant,
+                    DefaultTarget::Scene,
+                );
+                (variant.selector, pipeline)
+            })
+            .collect();
+        let accumulation_pipelines = self.weighted_blended.as_ref().filter(|_| modules.deferred).map(|_| {
+            Self::opaque_variant_pipelines(
+                &self.device,
+                &self.instance_layouts,
+                self.scene_format,
+                self.sample_count,
+                self.camera.depth_order,
+                modules,
+                DefaultTarget::Accumulation,
+            )
+        });
+        let deferred_pipelines = self.deferred.as_ref().filter(|_| modules.deferred).map(|deferred| {
+            Self::deferred_pipelines(
+                &self.device,
+                &self.instance_layouts,
+                self.scene_format,
+                self.sample_count,
+                self.camera.depth_order,
+                modules,
+                deferred,
+            )
+        });
+        if let Some(error) = self.device.pop_error_scope().block_on() {
+            return Err(ShaderError::new(error.to_string()));
+        }
+
+        for (selector, pipeline) in pipelines {
+            self.pipelines.insert(selector, Pipeline::Render(pipeline));
+        }
+        if let Some(deferred) = &mut self.deferred {
+            match deferred_pipelines {
+                Some((pipelines, lighting)) => deferred.set_pipelines(pipelines, lighting),
+                None if deferred.enabled => {
+                    log::warn!("GLSL replacements of default.wgsl have no deferred path, instances are shaded forward.");
+                    deferred.enabled = false;
+                }
+                None => {}
+            }
+        }
+        if let Some(weighted_blended) = &mut self.weighted_blended {
+            match accumulation_pipelines {
+                Some(pipelines) => weighted_blended.set_pipelines(pipelines),
+                None if !weighted_blended.pipelines.is_empty() => {
+                    log::warn!("GLSL replacements of default.wgsl have no accumulation entry point, translucent groups are drawn opaque.");
+                    weighted_blended.set_pipelines(Vec::new());
+                }
+                None => {}
+            }
+        }
+        self.group_bundles = None;
+        Ok(())
+    }
+
+    /// Rebuilds the pipelines of the shaders changed on disk. A shader that
+    /// fails validation leaves its pipelines as they were.
+    fn reload_shaders(&mut self) {
+        let Some(watcher) = &self.shader_watcher else {
+            return;
+        };
+        for name in watcher.changed() {
+            match self.reload_shader(&name) {
+                Ok(true) => {
+                    log::info!("Reloaded {name}.");
+                    self.shader_error = None;
+                }
+                Ok(false) => log::info!("{name} changed, restart to apply it."),
+                Err(error) => {
+                    log::error!("Keeping the previous pipelines after a shader error:\n{error}");
+                    self.shader_error = Some(error);
+                }
+            }
+            self.update_title();
+        }
+    }
+
+    /// Rebuilds the entries of `pipelines` built from the shader `name`,
+    /// `false` when there are none.
+    fn reload_shader(&mut self, name: &str) -> ShaderResult<bool> {
+        match name {
+            "default.wgsl" => {
+                let source = self.shaders.render_source("default.wgsl", include_str!("../shaders/default.wgsl"));
+                let previous = std::mem::replace(&mut self.default_shaders, ShaderPermutations::new("default.wgsl", source));
+                if let Err(error) = self.rebuild_default_pipeline() {
+                    self.default_shaders = previous;
+                    return Err(error);
+                }
+            }
+            "compute.wgsl" if !self.compat => {
+                self.shaders.check(
+                    &self.device,
+                    "compute.wgsl",
+                    include_str!("../shaders/compute.wgsl"),
+                    ComputePushConstants::defines(ComputePushConstants::device_pushed_size(&self.device)),
+                )?;
+                self.set_simulation(self.simulation);
+            }
+            "impostor.wgsl" if self.impostor_atlas.is_some() => {
+                self.shaders.check(&self.device, "impostor.wgsl", include_str!("../shaders/impostor.wgsl"), &[])?;
+                self.rebuild_scene_pipelines(name);
+            }
+            "greedy.wgsl" => {
+                self.shaders.check(&self.device, "greedy.wgsl", include_str!("../shaders/greedy.wgsl"), &[])?;
+                self.rebuild_scene_pipelines(name);
+            }
+            "outline.wgsl" => {
+                self.shaders.check(&self.device, "outline.wgsl", include_str!("../shaders/outline.wgsl"), &[])?;
+                self.rebuild_scene_pipelines(name);
+            }
+            "sdf.wgsl" if self.sdf => {
+                self.shaders.check(&self.device, "sdf.wgsl", include_str!("../shaders/sdf.wgsl"), &[])?;
+                self.rebuild_scene_pipelines(name);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Rebuilds the pipelines other than the default ones drawing into the
+    /// scene from the shader `name`, which compiles or falls back to the
+    /// embedded copy.
+    fn rebuild_scene_pipelines(&mut self, name: &str) {
+        let layouts: Vec<_> = self.default_layouts.iter().collect();
+        match name {
+            "impostor.wgsl" => {
+                let Some(impostor_atlas) = &self.impostor_atlas else {
+                    return;
+                };
+                let pipeline = Self::impostor_pipeline(
+                    &self.device,
+                    &[layouts[0], layouts[1], layouts[2], &impostor_atlas.bind_group_layout],
+                    self.scene_format,
+                    self.sample_count,
+                    self.camera.depth_order,
+                    &self.shaders,
+                );
+                self.pipelines.insert(PipelineSelector::Custom { name: "impostor" }, Pipeline::Render(pipeline));
+            }
+            "greedy.wgsl" => {
+                let pipeline = |label, vertex_layout| {
+                    Pipeline::Render(Self::lit_mesh_pipeline(
+                        &self.device,
+                        &layouts,
+                        self.scene_format,
+                        self.sample_count,
+                        self.camera.depth_order,
+                        &self.shaders,
+                        label,
+                        vertex_layout,
+                    ))
+                };
+                let greedy = pipeline("greedy_pipeline", GreedyVertex::desc());
+                let isosurface = self
+                    .pipelines
+                    .contains_key(&PipelineSelector::Custom { name: "isosurface" })
+                    .then(|| pipeline("isosurface_pipeline", IsosurfaceVertex::desc()));
+                self.pipelines.insert(PipelineSelector::Custom { name: "greedy" }, greedy);
+                if let Some(isosurface) = isosurface {
+                    self.pipelines.insert(PipelineSelector::Custom { name: "isosurface" }, isosurface);
+                }
+            }
+            "outline.wgsl" => {
+                let pipeline = Self::outline_pipeline(
+                    &self.device,
+                    &layouts[..2],
+                    self.scene_format,
+                    self.sample_count,
+                    self.camera.depth_order,
+                    &self.shaders,
+                );
+                self.pipelines.insert(PipelineSelector::Custom { name: "outline" }, Pipeline::Render(pipeline));
+            }
+            "sdf.wgsl" if self.sdf => {
+                let pipeline = Self::sdf_pipeline(
+                    &self.device,
+                    &layouts,
+                    self.scene_format,
+                    self.sample_count,
+                    self.camera.depth_order,
+                    &self.shaders,
+                );
+                self.pipelines.insert(PipelineSelector::Custom { name: "sdf" }, Pipeline::Render(pipeline));
+            }
+            _ => {}
+        }
+    }
+
+    /// Sample counts the scene can be drawn with, down to the ones the
+    /// accumulation targets resolve with too when there are `translucent`
+    /// groups.
+    fn supported_sample_counts(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        scene_format: wgpu::TextureFormat,
+        translucent: bool,
+    ) -> Vec<u32> {
+        let mut supported = Texture2d::sample_counts(adapter, device, scene_format);
+        if translucent {
+            supported.retain(|&count| WeightedBlended::supports_sample_count(adapter, device, count));
+        }
+        supported
+    }
+
+    /// Most samples up to `requested` of the `supported` ones.
+    fn pick_sample_count(requested: u32, supported: &[u32]) -> u32 {
+        supported.iter().copied().filter(|&count| count <= requested).max().unwrap_or(1)
+    }
+
+    /// Switches the scene to `requested` samples per pixel, or the most
+    /// the adapter supports below it, rebuilding everything drawing into
+    /// the scene and its targets.
+    fn set_sample_count(&mut self, requested: u32) -> ShaderResult<()> {
+        let supported = Self::supported_sample_counts(
+            &self.adapter,
+            &self.device,
+            self.scene_format,
+            self.weighted_blended.is_some(),
+        );
+        let sample_count = Self::pick_sample_count(requested, &supported);
+        if sample_count != requested {
+            log::warn!("{requested}x MSAA isn't supported with {:?}, using {sample_count}x.", self.scene_format);
+        }
+        if sample_count == self.sample_count {
+            return Ok(());
+        }
+
+        let previous = std::mem::replace(&mut self.sample_count, sample_count);
+        if let Err(error) = self.rebuild_default_pipeline() {
+            self.sample_count = previous;
+            return Err(error);
+        }
+        for name in ["impostor.wgsl", "greedy.wgsl", "outline.wgsl", "sdf.wgsl"] {
+            self.rebuild_scene_pipelines(name);
+        }
+        self.labels.set_sample_count(
+            &self.device,
+            &self.default_layouts.iter().collect::<Vec<_>>(),
+            self.scene_format,
+            self.sample_count,
+            self.camera.depth_order,
+            &self.shaders,
+        );
+        if let Some(weighted_blended) = &mut self.weighted_blended {
+            weighted_blended.set_sample_count(&self.device, &self.shaders, self.scene_format, self.sample_count);
+        }
+        self.create_scene_targets((self.depth_texture.size.width, self.depth_texture.size.height));
+        log::info!("MSAA: {}x.", self.sample_count);
+        Ok(())
+    }
+
+    /// Creates the multisampled framebuffer and depth buffer the scene is
+    /// drawn into at the internal resolution `render_size`.
+    fn create_scene_targets(&mut self, render_size: (u32, u32)) {
+        self.multisample_framebuffer = (self.sample_count > 1).then(|| {
+            Self::create_multisampled_framebuffer(&self.device, self.scene_format, render_size, self.sample_count)
+        });
+        self.depth_texture =
+            Texture2d::create_sized_depth_texture(&self.device, render_size, self.sample_count, Some("depth_texture"));
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.resize_depth(&self.device, &self.depth_texture);
+        }
+        if let Some(weighted_blended) = &mut self.weighted_blended {
+            weighted_blended.resize(&self.device, render_size, self.sample_count);
+        }
+    }
+
+    pub fn set_material(&mut self, material: Material) {
+        let previous = std::mem::replace(&mut self.material, material);
+
+        match self.rebuild_default_pipeline() {
+            Ok(()) => {
+                log::info!("Shader features: {:?}.", self.material.features.defines());
+                self.shader_error = None;
+            }
+            Err(error) => {
+                log::error!("Keeping the previous pipeline after a shader error:\n{error}");
+                self.material = previous;
+                self.shader_error = Some(error);
+            }
+        }
+        self.update_title();
+    }
+
+    fn toggle_shader_feature(&mut self, feature: ShaderFeatures) {
+        self.set_material(Material {
+            features: self.material.features.toggled(feature),
+        });
+    }
+
+    /// Switches the opaque instances between deferred and forward shading.
+    fn toggle_deferred_shading(&mut self) {
+        let Some(deferred) = &mut self.deferred else {
+            log::info!("Deferred shading is off, see --deferred.");
+            return;
+        };
+        if deferred.pipelines.is_empty() {
+            log::warn!("GLSL replacements of default.wgsl have no deferred path.");
+            return;
+        }
+        deferred.enabled = !deferred.enabled;
+        log::info!("Shading: {}.", if deferred.enabled { "deferred" } else { "forward" });
+    }
+
+    /// Cycles the opaque instances through filled, wireframe and points,
+    /// skipping the modes the adapter can't rasterize.
+    fn cycle_polygon_mode(&mut self) {
+        const MODES: [wgpu::PolygonMode; 3] = [wgpu::PolygonMode::Fill, wgpu::PolygonMode::Line, wgpu::PolygonMode::Point];
+
+        let current = MODES.iter().position(|&mode| mode == self.polygon_mode).unwrap_or(0);
+        let next = (1..=MODES.len())
+            .map(|step| MODES[(current + step) % MODES.len()])
+            .find(|&mode| self.pipelines.contains_key(&DefaultVariant::opaque_selector(mode)))
+            .unwrap_or(wgpu::PolygonMode::Fill);
+        if next == self.polygon_mode {
+            log::info!("Adapter can only fill polygons, no wireframe or points.");
+            return;
+        }
+        self.polygon_mode = next;
+        log::info!("Polygon mode: {:?}", self.polygon_mode);
+    }
+
+    /// Switches between per-cube instancing and greedy-meshed chunks and logs
+    /// how their triangle counts compare. Frame times show up in the title.
+    /// The mesh is built from the current positions and only drawn while the
+    /// simulation stands still, see [`Self::drop_greedy_mesh`].
+    fn toggle_render_mode(&mut self) {
+        const CUBE_TRIANGLES: usize = 12;
+
+        if self.render_mode == RenderMode::GreedyMesh {
+            self.render_mode = RenderMode::Instanced;
+            log::info!("Render mode: {:?}", self.render_mode);
+            self.update_title();
+            return;
+        }
+
+        if self.greedy_mesh.is_none() {
+            // Generated instances, or the dataset when there are none
+            let greedy_mesh = match &self.streamer {
+                Some(streamer) if self.positions.is_empty() => {
+                    let features = streamer.dataset().features();
+                    GreedyMesh::build(&self.device, streamer.dataset().points(), VoxelSource {
+                        colors: streamer.dataset().colors(),
+                        runs: features.contains(ShaderFeatures::VOXEL_RUNS),
+                    })
+                }
+                _ => {
+                    // Where the simulation took them, not where they started
+                    let positions = self.read_positions();
+                    self.device.poll(wgpu::Maintain::Wait);
+                    match positions.block_on() {
+                        Ok(positions) => {
+                            let points: Vec<_> = positions.into_iter().map(|[x, y, z]| [x, y, z, 0.0]).collect();
+                            GreedyMesh::build(&self.device, &points, VoxelSource::default())
+                        }
+                        Err(error) => {
+                            log::error!("Failed to read back the positions to mesh: {error}");
+                            None
+                        }
+                    }
+                }
+            };
+            self.greedy_mesh = greedy_mesh;
+        }
+        let Some(greedy_mesh) = &self.greedy_mesh else {
+            return;
+        };
+        // The mesh is a snapshot, stepping the simulation drops it again
+        if !self.paused && !self.positions.is_empty() {
+            self.paused = true;
+            log::info!("Simulation paused while greedy meshed, resuming it returns to instancing.");
+        }
+
+        let instances = self.positions.len() + self.streamer.as_ref().map_or(0, |s| s.dataset().len());
+        let instanced_triangles = instances * CUBE_TRIANGLES;
+        self.render_mode = RenderMode::GreedyMesh;
+        log::info!(
+            "Render mode: {:?}, {} triangles for {} voxels against {} instanced triangles ({:.1}x fewer).",
+            self.render_mode,
+            greedy_mesh.triangles,
+            greedy_mesh.voxels,
+            instanced_triangles,
+            instanced_triangles as f64 / greedy_mesh.triangles.max(1) as f64,
+        );
+        self.update_title();
+    }
+
+    /// Drops the greedy mesh once the instances it was built from changed,
+    /// returning to instancing if it was drawn.
+    fn drop_greedy_mesh(&mut self) {
+        if self.greedy_mesh.take().is_some() && self.render_mode == RenderMode::GreedyMesh {
+            self.render_mode = RenderMode::Instanced;
+            log::info!("Render mode: {:?}, the instances moved away from the greedy mesh.", self.render_mode);
+            self.update_title();
+        }
+    }
+
+    /// Nesting limit of `exec`, so scripts running themselves terminate.
+    const MAX_SCRIPT_DEPTH: usize = 8;
+
+    /// Uploads new simulation parameters, read by the next step.
+    pub fn set_sim_params(&mut self, params: SimParams) {
+        if params.gravity != self.simulation.params.gravity {
+            // The potential energy is measured against another attractor now
+            self.simulation_reference = None;
+        }
+        self.simulation.params = params;
+        self.sim_params.write(&self.queue, &params);
+    }
+
+    pub fn set_gravity(&mut self, gravity: f32) {
+        self.set_sim_params(SimParams {
+            gravity,
+            ..self.simulation.params
+        });
+    }
+
+    pub fn set_drag(&mut self, drag: f32) {
+        self.set_sim_params(SimParams {
+            drag: drag.max(0.0),
+            ..self.simulation.params
+        });
+    }
+
+    /// Rebuilds the compute pipelines, the CPU kernels read the constants directly.
+    fn set_simulation(&mut self, simulation: SimulationConstants) {
+        self.simulation = simulation;
+        if !self.compat {
+            (self.pv_bind_groups, self.raycaster) = Self::create_gpu_simulation(
+                &self.device,
+                &mut self.pipelines,
+                &self.instance_buffers,
+                &self.default_layouts[0],
+                &self.collision,
+                &self.sim_params,
+                &self.gpu_reset,
+                &self.simulation,
+                &self.shaders,
+            );
+            self.kernel_dispatch.write(
+                &self.device,
+                &self.queue,
+                &self.instance_buffers.chunks,
+                self.dimensions,
+                self.simulation.workgroup_dims,
+            );
+        }
+    }
+
+    /// Rebuilds everything sized by the instance count after `positions` and
+    /// `velocities` changed. With `keep_state`, only whole z slices were
+    /// added or removed at the end: the instance buffers grow or shrink in
+    /// place and instances that already existed continue from their
+    /// simulated state on the GPU. Fails when a z slice of `dimensions`
+    /// exceeds the buffer limits, callers changing the slice size check
+    /// [`Self::check_dimensions`] before touching the state.
+    fn rebuild_instances(&mut self, dimensions: (u32, u32, u32), keep_state: bool) -> InstanceResult<()> {
+        // Kept on the CPU too for compatibility mode, which simulates there
+        let kept = if keep_state { self.transforms.len().min(self.positions.len()) } else { 0 };
+        Self::generate_phases(&mut self.positions[kept..]);
+        Self::generate_lifetimes(&mut self.velocities[kept..], self.lifetime);
+        self.transforms.truncate(kept);
+        self.transforms.extend(InstanceTransform::generate(self.positions.len() - kept, &self.transform_settings));
+        self.colors.truncate(kept);
+        self.colors.extend(InstanceColor::generate(&self.velocities[kept..], self.coloring, self.instance_alpha));
+
+        let slice_len = dimensions.0 * dimensions.1;
+        if keep_state && slice_len > 0 {
+            let len = self.positions.len() as u32;
+            let start = self.instance_buffers.len();
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("resize_instances"),
+            });
+            debug_labels::marker(&mut encoder, || format!("resize {start} instances to {len}"));
+            let reallocated = match len.cmp(&start) {
+                std::cmp::Ordering::Less => self.instance_buffers.truncate(&self.device, &mut encoder, len, slice_len),
+                std::cmp::Ordering::Equal => false,
+                std::cmp::Ordering::Greater => {
+                    let start = start as usize;
+                    self.instance_buffers.extend(
+                        &self.device,
+                        &self.queue,
+                        &mut encoder,
+                        InstanceData {
+                            positions: &self.positions[start..],
+                            velocities: &self.velocities[start..],
+                            transforms: &self.transforms[start..],
+                            colors: &self.colors[start..],
+                        },
+                        slice_len,
+                        self.compat,
+                    )?
+                }
+            };
+            self.queue.submit(std::iter::once(encoder.finish()));
+            if reallocated {
+                log::debug!("Instance buffers reallocated for {len} instances.");
+            }
+        } else {
+            self.instance_buffers = InstanceBuffers::new(
+                &self.device,
+                InstanceData {
+                    positions: &self.positions,
+                    velocities: &self.velocities,
+                    transforms: &self.transforms,
+                    colors: &self.colors,
+                },
+                dimensions,
+                self.compat,
+            )?;
+        }
+
+        self.chunk_offsets.write(&self.device, &self.queue, &self.instance_buffers.chunks);
+        self.dimensions = [dimensions.0, dimensions.1, dimensions.2, 0];
+        self.frame.dimensions = self.dimensions;
+        self.chunk_culler = ChunkCuller::build(
+            &self.positions,
+            ChunkCuller::DEFAULT_CHUNK_SIZE,
+            self.transform_settings.max_extent(),
+        );
+        if keep_state && !self.compat {
+            // The CPU positions are where the instances started, not where
+            // the simulation took them
+            self.chunk_culler.invalidate();
+        }
+        self.drop_greedy_mesh();
+        if !self.compat {
+            self.simulation_statistics = Some(SimulationStatistics::new(&self.device, &self.instance_buffers, &self.shaders));
+            self.chunk_bounds = Some(ChunkBounds::new(
+                &self.device,
+                &self.instance_buffers,
+                ChunkCuller::DEFAULT_CHUNK_SIZE,
+                &self.shaders,
+            ));
+        }
+        self.simulation_stats = None;
+        self.simulation_reference = None;
+        if let Some(history) = &mut self.history {
+            history.reset(&self.instance_buffers);
+        }
+        let sorted = self.instance_alpha < 1.0 && InstanceSorter::fits(&self.device.limits(), &self.instance_buffers);
+        if self.instance_alpha < 1.0 && !sorted {
+            log::warn!("Sort keys of every instance exceed the storage buffer limits, instances are drawn opaque.");
+        }
+        match (&mut self.sorter, sorted) {
+            (Some(sorter), true) => sorter.resize(&self.device, &self.instance_buffers),
+            (sorter, transparent) => {
+                *sorter = transparent.then(|| InstanceSorter::new(&self.device, &self.shaders, &self.instance_buffers));
+            }
+        }
+        if let Some(lod) = &mut self.lod {
+            if Lod::fits(&self.device.limits(), lod.lists(), &self.instance_buffers) {
+                lod.resize(&self.device, &self.instance_buffers);
+            } else {
+                log::warn!("Level of detail lists exceed the storage buffer limits, they are disabled.");
+                self.lod = None;
+            }
+        }
+        if let Some(compaction) = &mut self.compaction {
+            compaction.resize(&self.device, &self.instance_buffers);
+        }
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.resize(&self.device, &self.instance_buffers);
+        }
+        if let Some(hierarchy) = &mut self.hierarchy {
+            hierarchy.resize(&self.device, &self.instance_buffers);
+        }
+        // Bind groups only cover the instances, not the spare capacity
+        self.set_simulation(self.simulation);
+        Ok(())
+    }
+
+    /// Fails when a z slice of `dimensions` can't be held by the instance
+    /// buffers, before a scene of that size replaces the current one.
+    fn check_dimensions(&self, dimensions: (u32, u32, u32)) -> ConsoleResult<()> {
+        InstanceBuffers::slices_per_chunk(&self.device.limits(), dimensions.0 * dimensions.1, self.compat)
+            .map(|_| ())
+            .map_err(|error| ConsoleError::new(error.message))
+    }
+
+    /// Adds whole z slices of instances around the camera, at least `count` instances.
+    fn spawn_instances(&mut self, count: u32) -> ConsoleResult<u32> {
+        const SPREAD: f32 = 500.0;
+
+        let [width, height, depth, _] = self.dimensions;
+        let slice_len = width * height;
+        if slice_len == 0 {
+            return Err(ConsoleError::new("Spawning needs a generated instance grid".to_string()));
+        }
+
+        let slices = count.div_ceil(slice_len);
+        // Instance indices are u32 throughout, so the whole grid has to fit one
+        let grown = depth
+            .checked_add(slices)
+            .filter(|depth| depth.checked_mul(slice_len).is_some());
+        let Some(grown) = grown else {
+            return Err(ConsoleError::new(format!("Spawning {count} more instances exceeds the instance limit")));
+        };
+        let spawned = slices * slice_len;
+        let spread = cgmath::Vector3::new(SPREAD, SPREAD, SPREAD);
+        self.positions.extend(Self::generate_random_vectors(
+            spawned as usize,
+            self.camera.eye - spread,
+            self.camera.eye + spread,
+        ));
+        self.velocities.extend(Self::generate_random_vectors(
+            spawned as usize,
+            cgmath::Point3::new(-20.0, -20.0, -20.0),
+            cgmath::Point3::new(20.0, 20.0, 20.0),
+        ));
+        // Rebuilds the chunk culler too, which invalidates it
+        self.rebuild_instances((width, height, grown), true)
+            .map_err(|error| ConsoleError::new(error.message))?;
+
+        Ok(spawned)
     }
 
-    fn update_buffers(&self) {
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-        encoder.copy_buffer_to_buffer(
-            &self.positions_buffer, 0,
-            &self.positions_buffer_vsh, 0,
-            std::mem::size_of::<InstanceRepr>() as u64 * Self::OBJECT_COUNT as u64,
-        );
+    /// Removes whole z slices of the newest instances, at least `count`
+    /// instances, always leaving one slice.
+    fn despawn_instances(&mut self, count: u32) -> ConsoleResult<u32> {
+        let [width, height, depth, _] = self.dimensions;
+        let slice_len = width * height;
+        if slice_len == 0 || depth <= 1 {
+            return Err(ConsoleError::new("No instances to remove".to_string()));
+        }
+
+        let slices = count.div_ceil(slice_len).min(depth - 1);
+        self.remove_slices(depth - slices, slices)
+    }
 
+    /// Removes `count` z slices from `first` on, anywhere in the grid, always
+    /// leaving one slice. The last slices are moved into the gap on the GPU,
+    /// so the remaining instances continue from their simulated state.
+    /// Returns the number of instances removed.
+    fn remove_slices(&mut self, first: u32, count: u32) -> ConsoleResult<u32> {
+        let [width, height, depth, _] = self.dimensions;
+        let slice_len = width * height;
+        if slice_len == 0 || depth <= 1 {
+            return Err(ConsoleError::new("No instances to remove".to_string()));
+        }
+        if first >= depth {
+            return Err(ConsoleError::new(format!("No z slice {first}, the grid has {depth}")));
+        }
+
+        let slices = first..first + count.min(depth - first).min(depth - 1);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("remove_instances"),
+        });
+        debug_labels::marker(&mut encoder, || format!("remove z slices {slices:?}"));
+        self.instance_buffers
+            .swap_remove_slices(&self.device, &mut encoder, slices.clone(), slice_len)
+            .map_err(|error| ConsoleError::new(error.message))?;
         self.queue.submit(std::iter::once(encoder.finish()));
 
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera.uniform()]),
-        );
+        instances::swap_remove_slices(&mut self.positions, slices.clone(), slice_len);
+        instances::swap_remove_slices(&mut self.velocities, slices.clone(), slice_len);
+        instances::swap_remove_slices(&mut self.transforms, slices.clone(), slice_len);
+        instances::swap_remove_slices(&mut self.colors, slices.clone(), slice_len);
+        self.rebuild_instances((width, height, depth - slices.len() as u32), true)
+            .map_err(|error| ConsoleError::new(error.message))?;
+
+        Ok(slices.len() as u32 * slice_len)
     }
-}
 
-impl App<'_> {
-    fn update(&mut self, delta: f64) {
-        self.camera_controller.update(&mut self.camera, delta as f32);
-        self.last_delta = delta;
+    /// Switches to a benchmark preset with a freshly generated scene and
+    /// restarts the run statistics.
+    fn apply_preset(&mut self, name: &str) -> ConsoleResult<()> {
+        let preset = PRESETS
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| ConsoleError::new(format!("Unknown preset: {name}")))?;
 
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-        if !self.paused {
-            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("compute_pass"),
-                timestamp_writes: None,
+        let dimensions = if self.compat {
+            (
+                preset.dimensions.0.min(Self::COMPAT_DIMENSIONS.0),
+                preset.dimensions.1.min(Self::COMPAT_DIMENSIONS.1),
+                preset.dimensions.2.min(Self::COMPAT_DIMENSIONS.2),
+            )
+        } else {
+            preset.dimensions
+        };
+        self.check_dimensions(dimensions)?;
+        (self.positions, self.velocities) = self.demo.init(&DemoContext {
+            simulation: &self.simulation,
+            count: (dimensions.0 * dimensions.1 * dimensions.2) as usize,
+        });
+        self.set_kernel(Self::kernel_index(self.demo.kernel()).unwrap_or(self.kernel));
+        self.instance_alpha = if self.compat { 1.0 } else { preset.instance_alpha };
+        self.rebuild_instances(dimensions, false)
+            .map_err(|error| ConsoleError::new(error.message))?;
+        if self.instance_alpha < 1.0 && self.material.features.contains(ShaderFeatures::INSTANCED_COLOR) {
+            // Grid colors follow the drawing order, which changes with the sort
+            self.set_material(Material {
+                features: self.material.features.without(ShaderFeatures::INSTANCED_COLOR) | ShaderFeatures::COLOR_ATTRIBUTE,
             });
+        }
 
-            if let Pipeline::Compute(pipeline) = &self.pipelines[&PipelineSelector::Compute] {
-                compute_pass.set_pipeline(pipeline);
+        self.paused = !preset.simulate;
+        self.culling_mode = preset.culling_mode;
+        self.scene.set_draw_distance(preset.max_draw_distance);
+        self.scene.impostor_threshold = if preset.impostors && self.lod.as_ref().is_some_and(Lod::impostors) {
+            SceneSettings::DEFAULT_IMPOSTOR_THRESHOLD
+        } else {
+            if preset.impostors {
+                log::warn!("Preset {} draws impostors, which need the level of detail pass, see --lod.", preset.name);
             }
+            0.0
+        };
+        self.preset = Some(preset.name);
+        self.run_frame_stats = FrameStats::default();
+        self.update_title();
 
-            compute_pass.set_bind_group(0, &self.pv_bind_group, &[]);
-
-            let push_constants = ComputePushConstants {
-                world_info: WorldInfo { time: self.time as f32, delta: self.last_delta as f32 },
-                dimensions: Self::DIMENSIONS.into(),
-            };
-            compute_pass.set_push_constants(0, bytemuck::bytes_of(&push_constants));
+        Ok(())
+    }
 
-            compute_pass.dispatch_workgroups(
-                Self::DIMENSIONS.0 / Self::WORKGROUP_DIMS.0,
-                Self::DIMENSIONS.1 / Self::WORKGROUP_DIMS.1,
-                Self::DIMENSIONS.2 / Self::WORKGROUP_DIMS.2,
-            );
+    /// Replaces the scene with a freshly initialized one of the current demo
+    /// holding at least `count` instances, reallocating the instance buffers
+    /// and everything sized by them.
+    fn set_instance_count(&mut self, count: u32) -> ConsoleResult<()> {
+        if self.streamer.is_some() {
+            return Err(ConsoleError::new("The instance count of a streamed dataset is fixed".to_string()));
         }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
+        let (width, height, depth) = AppConfig::grid_dimensions(count);
+        let dimensions = if self.compat {
+            (
+                width.min(Self::COMPAT_DIMENSIONS.0),
+                height.min(Self::COMPAT_DIMENSIONS.1),
+                depth.min(Self::COMPAT_DIMENSIONS.2),
+            )
+        } else {
+            (width, height, depth)
+        };
+        self.check_dimensions(dimensions)?;
+        (self.positions, self.velocities) = self.demo.init(&DemoContext {
+            simulation: &self.simulation,
+            count: (dimensions.0 * dimensions.1 * dimensions.2) as usize,
+        });
+        self.rebuild_instances(dimensions, false)
+            .map_err(|error| ConsoleError::new(error.message))?;
+        self.preset = None;
+        self.run_frame_stats = FrameStats::default();
+        self.update_title();
+
+        Ok(())
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let image = self.surface.get_current_texture()?;
+    /// Switches the simulation kernel, baking the impostors again when its
+    /// instances have another shape.
+    fn set_kernel(&mut self, kernel: usize) {
+        let reshaped = KERNELS[kernel].spheres != KERNELS[self.kernel].spheres;
+        self.kernel = kernel;
+        if let Some(impostor_atlas) = self.impostor_atlas.as_ref().filter(|_| reshaped) {
+            let instance_mesh = if KERNELS[kernel].spheres { &self.sphere_mesh } else { &self.cube_mesh };
+            impostor_atlas.rebake(&self.device, &self.queue, instance_mesh);
+        }
+    }
 
-        let view = image.texture.create_view(&wgpu::TextureViewDescriptor {
-            format: Some(self.surface_config.view_formats[0]),
-            ..Default::default()
+    /// Replaces the scene with a freshly initialized demo of the same size,
+    /// simulated by the kernel the demo asks for.
+    fn switch_demo(&mut self, name: &str) -> ConsoleResult<()> {
+        let entry = demo::find(name).ok_or_else(|| {
+            let names: Vec<_> = demo::DEMOS.iter().map(|demo| demo.name).collect();
+            ConsoleError::new(format!("Unknown demo: {name}, try {}", names.join(", ")))
+        })?;
+        if self.streamer.is_some() {
+            return Err(ConsoleError::new("Demos can't replace a streamed dataset".to_string()));
+        }
+
+        let mut demo = (entry.create)();
+        let [width, height, depth, _] = self.dimensions;
+        (self.positions, self.velocities) = demo.init(&DemoContext {
+            simulation: &self.simulation,
+            count: (width * height * depth) as usize,
         });
+        self.set_kernel(Self::kernel_index(demo.kernel()).unwrap_or(self.kernel));
+        self.demo = demo;
+        self.demo_name = entry.name;
+        self.rebuild_instances((width, height, depth), false)
+            .map_err(|error| ConsoleError::new(error.message))?;
+        self.update_title();
 
-        self.update_buffers();
+        Ok(())
+    }
+
+    /// Scatters the instances of the current demo again. Demos over a
+    /// [`demo::ScatterVolume`] are reset in a compute pass, which leaves the CPU
+    /// copy of the state alone, the others are initialized again.
+    fn reset_simulation(&mut self) -> ConsoleResult<()> {
+        if self.streamer.is_some() {
+            return Err(ConsoleError::new("A streamed dataset can't be reset".to_string()));
+        }
+
+        let [width, height, depth, _] = self.dimensions;
+        let (Some(pv_bind_groups), Some(volume), Some(Pipeline::Compute(pipeline))) = (
+            &self.pv_bind_groups,
+            self.demo.scatter(),
+            self.pipelines.get(&PipelineSelector::Custom { name: "reset" }),
+        ) else {
+            (self.positions, self.velocities) = self.demo.init(&DemoContext {
+                simulation: &self.simulation,
+                count: (width * height * depth) as usize,
+            });
+            return self
+                .rebuild_instances((width, height, depth), false)
+                .map_err(|error| ConsoleError::new(error.message));
+        };
 
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        let seed = random::rng().random();
+        self.gpu_reset.write(&self.device, &self.queue, &self.instance_buffers.chunks, &volume, self.lifetime, seed);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("reset"),
+        });
+        debug_labels::marker(&mut encoder, || format!("reset {} instances", self.instance_buffers.len()));
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("render_pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.multisample_framebuffer,
-                    resolve_target: Some(&view),
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("reset_pass"),
                 timestamp_writes: None,
-                occlusion_query_set: None,
             });
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, &self.frame_bind_group, &[self.frame_ring.uniform_offset()]);
+            self.sim_params.bind(&mut compute_pass, 0);
+            let front = self.instance_buffers.front();
+            for (index, pv_bind_groups) in pv_bind_groups.iter().enumerate() {
+                compute_pass.set_bind_group(1, &pv_bind_groups[front], &[]);
+                self.gpu_reset.bind(&mut compute_pass, index);
+                self.kernel_dispatch.dispatch(&mut compute_pass, index);
+            }
+        }
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.instance_buffers.swap();
+        self.instance_buffers.show_front();
 
-            if let Pipeline::Render(pipeline) = &self.pipelines[&PipelineSelector::Default] {
-                render_pass.set_pipeline(pipeline);
+        // Where the instances land is known without reading them back
+        let extent = self.transform_settings.max_extent();
+        let len = self.instance_buffers.len();
+        let chunk_size = ChunkCuller::DEFAULT_CHUNK_SIZE as u32;
+        self.chunk_culler = ChunkCuller::from_chunks(
+            (0..len.div_ceil(chunk_size))
+                .map(|chunk| {
+                    let start = chunk * chunk_size;
+                    GpuReset::chunk_bounds(&volume, self.dimensions, start..len.min(start + chunk_size))
+                })
+                .collect(),
+            extent,
+        );
+        if let Some(chunk_bounds) = &mut self.chunk_bounds {
+            chunk_bounds.discard();
+        }
+        self.simulation_stats = None;
+        self.simulation_reference = None;
+
+        Ok(())
+    }
+
+    /// Drops a gravity well where the crosshair meets the ground plane, or
+    /// some way ahead of the camera, pushing instances away when `repel`.
+    fn drop_gravity_well(&mut self, repel: bool) {
+        let position = self.gravity_wells.drop(self.camera.eye, self.camera.direction, repel);
+        self.sim_params.write_wells(&self.queue, self.gravity_wells.wells());
+        // The wells add energy the reference didn't account for
+        self.simulation_reference = None;
+        log::info!(
+            "Dropped a {} at ({:.1}, {:.1}, {:.1}), {} wells.",
+            if repel { "repulsor" } else { "attractor" },
+            position.x,
+            position.y,
+            position.z,
+            self.gravity_wells.wells().len()
+        );
+    }
+
+    fn clear_gravity_wells(&mut self) {
+        self.gravity_wells.clear();
+        self.sim_params.write_wells(&self.queue, &[]);
+        self.simulation_reference = None;
+        log::info!("Cleared gravity wells.");
+    }
+
+    /// Runs a console or script line, printing the outcome to the console.
+    fn run_command(&mut self, line: &str) {
+        if let Err(error) = self.run_command_at_depth(line, 0) {
+            self.console.print(&format!("Error: {error}"));
+        }
+    }
+
+    fn run_command_at_depth(&mut self, line: &str, depth: usize) -> ConsoleResult<()> {
+        let Some(command) = Command::parse(line)? else {
+            return Ok(());
+        };
+
+        match command {
+            Command::Help => self.console.print(Command::HELP),
+            Command::Params => {
+                for parameter in registry::PARAMETERS {
+                    let value = (parameter.get)(self).unwrap_or_default();
+                    self.console.print(&format!("{} = {value}", parameter.name));
+                }
+            }
+            Command::Get(name) => {
+                let value = (registry::find(&name)?.get)(self)
+                    .ok_or_else(|| ConsoleError::new(format!("{name} is unavailable while its feature is off")))?;
+                self.console.print(&format!("{name} = {value}"));
+            }
+            Command::Set(name, value) => {
+                let parameter = registry::find(&name)?;
+                (parameter.set)(self, value)?;
+                self.console.print(&format!("{name} = {}", (parameter.get)(self).unwrap_or(value)));
+            }
+            Command::Kernel(name) => {
+                let kernel = KERNELS.iter().position(|kernel| kernel.name == name).ok_or_else(|| {
+                    let names: Vec<_> = KERNELS.iter().map(|kernel| kernel.name).collect();
+                    ConsoleError::new(format!("Unknown kernel: {name}, try {}", names.join(", ")))
+                })?;
+                self.set_kernel(kernel);
+                self.simulation_reference = None;
+                self.console.print(&format!("Simulation kernel: {name}"));
+            }
+            Command::Integrator(name) => {
+                let integrator = Integrator::from_name(&name)
+                    .ok_or_else(|| ConsoleError::new(format!("Unknown integrator: {name}")))?;
+                self.set_simulation(SimulationConstants {
+                    integrator,
+                    ..self.simulation
+                });
+                self.simulation_reference = None;
+                self.console.print(&format!("Integrator: {name}"));
+            }
+            Command::ToneMapping(name) => {
+                let tone_mapper = ToneMapper::from_name(&name)
+                    .ok_or_else(|| ConsoleError::new(format!("Unknown tone mapping: {name}")))?;
+                let tone_mapping = self
+                    .tone_mapping
+                    .as_mut()
+                    .ok_or_else(|| ConsoleError::new("The scene is rendered without HDR".to_string()))?;
+                tone_mapping.set_tone_mapper(tone_mapper);
+                self.console.print(&format!("Tone mapping: {name}"));
+            }
+            Command::Bounds(setting) => {
+                let mut bounds = self.simulation.bounds;
+                if setting == "off" {
+                    bounds.shape = None;
+                } else if let Some(behavior) = BoundsBehavior::from_name(&setting) {
+                    bounds.behavior = behavior;
+                } else {
+                    bounds.shape = Some(Collider::parse(&setting).map_err(|e| ConsoleError::new(e.message))?);
+                }
+                self.simulation.bounds = bounds;
+                self.console.print(&format!("World bounds: {bounds}"));
+            }
+            Command::Emitter(position) => {
+                self.emitter.settings.position = position;
+                if position.is_some() && !self.emitter_active() {
+                    return Err(ConsoleError::new("The emitter needs instance lifetimes".to_string()));
+                }
+                match position {
+                    Some(position) => self.console.print(&format!(
+                        "Emitting {} instances per second at {}, {}, {}",
+                        self.emitter.settings.rate, position.x, position.y, position.z,
+                    )),
+                    None => self.console.print("Emitter off"),
+                }
+            }
+            Command::Step(count) => {
+                self.step_once(count);
+                self.console.print(&format!("Paused, {} steps queued", self.queued_steps));
             }
+            Command::Spawn(count) => {
+                let spawned = self.spawn_instances(count)?;
+                self.console.print(&format!("Spawned {spawned} instances, {} in total", self.positions.len()));
+            }
+            Command::Despawn(count) => {
+                let removed = self.despawn_instances(count)?;
+                self.console.print(&format!("Removed {removed} instances, {} left", self.positions.len()));
+            }
+            Command::Remove(first, count) => {
+                let removed = self.remove_slices(first, count)?;
+                self.console.print(&format!("Removed {removed} instances, {} left", self.positions.len()));
+            }
+            Command::Count(count) => {
+                self.set_instance_count(count)?;
+                let [width, height, depth, _] = self.dimensions;
+                self.console.print(&format!("{} instances in a {width}x{height}x{depth} grid", self.positions.len()));
+            }
+            Command::Teleport(position) => {
+                self.camera.eye = position;
+                self.console.print(&format!("Camera at {position:?}"));
+            }
+            Command::Preset(name) => {
+                self.apply_preset(&name)?;
+                self.console.print(&format!("Preset {name}, {} instances", self.positions.len()));
+            }
+            Command::Demo(name) => {
+                self.switch_demo(&name)?;
+                self.console.print(&format!("Demo {name}, {} instances", self.positions.len()));
+            }
+            Command::Rewind(steps) => {
+                let age = self.rewind(steps)?;
+                self.console.print(&format!("Rewound {age:.1}s"));
+            }
+            Command::Exec(path) => self.run_script(&path, depth + 1)?,
+            Command::Export(path) => {
+                let positions = self.read_positions();
+                self.device.poll(wgpu::Maintain::Wait);
+                let positions = positions.block_on().map_err(|e| ConsoleError::new(e.message))?;
+                let csv: String = positions.iter().map(|[x, y, z]| format!("{x},{y},{z}\n")).collect();
+                std::fs::write(&path, csv)
+                    .map_err(|e| ConsoleError::new(format!("Failed to write {}: {e}", path.display())))?;
+                self.console.print(&format!("Exported {} positions to {}", positions.len(), path.display()));
+            }
+            Command::Clear => self.console.clear(),
+        }
+        Ok(())
+    }
 
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            let push_constants = ComputePushConstants {
-                world_info: WorldInfo { time: self.time as f32, delta: self.last_delta as f32 },
-                dimensions: Self::DIMENSIONS.into(),
-            };
-            render_pass.set_push_constants(
-                wgpu::ShaderStages::VERTEX,
-                0,
-                bytemuck::bytes_of(&push_constants)
-            );
+    /// Runs every line of a script as a console command, stopping at the first error.
+    fn run_script(&mut self, path: &std::path::Path, depth: usize) -> ConsoleResult<()> {
+        if depth > Self::MAX_SCRIPT_DEPTH {
+            return Err(ConsoleError::new("Scripts nested too deeply".to_string()));
+        }
+        let script = std::fs::read_to_string(path)
+            .map_err(|e| ConsoleError::new(format!("Failed to read {}: {e}", path.display())))?;
+
+        for (number, line) in script.lines().enumerate() {
+            self.run_command_at_depth(line, depth)
+                .map_err(|e| ConsoleError::new(format!("{}:{}: {e}", path.display(), number + 1)))?;
+        }
+        Ok(())
+    }
+
+    fn receive_simulation_stats(&mut self, stats: SimulationStats) {
+        self.simulation_reference.get_or_insert(stats);
+        self.simulation_stats = Some(stats);
+    }
+
+    /// Bookmark slot of a digit key.
+    fn bookmark_slot(code: KeyCode) -> Option<usize> {
+        const KEYS: [KeyCode; Bookmarks::SLOTS] = [
+            KeyCode::Digit1,
+            KeyCode::Digit2,
+            KeyCode::Digit3,
+            KeyCode::Digit4,
+            KeyCode::Digit5,
+            KeyCode::Digit6,
+            KeyCode::Digit7,
+            KeyCode::Digit8,
+            KeyCode::Digit9,
+        ];
+        KEYS.iter().position(|&key| key == code).map(|index| index + 1)
+    }
+
+    /// Saves the current viewpoint while Ctrl is held, otherwise moves to
+    /// the saved one.
+    fn use_bookmark(&mut self, slot: usize) {
+        let (yaw, pitch) = self.camera_controller.orientation();
+        let current = Viewpoint {
+            eye: self.camera.eye,
+            yaw,
+            pitch,
+        };
+
+        if self.modifiers.control_key() {
+            match self.bookmarks.set(slot, current) {
+                Ok(()) => log::info!("Saved bookmark {slot}."),
+                Err(error) => log::warn!("Failed to save bookmark {slot}: {error}."),
+            }
+        } else if let Some(target) = self.bookmarks.get(slot) {
+            self.camera_transition = Some(CameraTransition::new(current, target));
+        } else {
+            log::info!("Bookmark {slot} is empty.");
+        }
+    }
+
+    /// Frame rate cap in effect, throttled in the background. Captures
+    /// always run at full speed.
+    fn current_frame_interval(&self) -> Option<Duration> {
+        match self.background {
+            BackgroundMode::Throttle(fps) if !self.focused && self.capture.is_none() => {
+                let throttle = Duration::from_secs_f64(1.0 / fps as f64);
+                Some(self.frame_interval.map_or(throttle, |interval| interval.max(throttle)))
+            }
+            _ => self.frame_interval,
+        }
+    }
+
+    fn background_paused(&self) -> bool {
+        self.background == BackgroundMode::Pause && !self.focused && self.capture.is_none()
+    }
+
+    fn set_focused(&mut self, focused: bool, event_loop: &winit::event_loop::ActiveEventLoop) {
+        self.focused = focused;
+        if focused {
+            _ = self.window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
+            self.window.set_cursor_visible(false);
+            // Redraws were throttled or stopped, uncapped ones chain from here again
+            event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
+            self.next_frame = Instant::now();
+            self.window.request_redraw();
+        } else {
+            _ = self.window.set_cursor_grab(winit::window::CursorGrabMode::None);
+            self.window.set_cursor_visible(true);
+            // Their releases go to whichever window is focused now
+            self.camera_controller.release_keys();
+        }
+        log::debug!("Window {}.", if focused { "focused" } else { "unfocused" });
+    }
+
+    /// The last shader error in the top left corner while the previous
+    /// pipeline is kept, hidden in captures.
+    fn shader_error_label(&self) -> Option<Label> {
+        const MARGIN: f32 = 8.0;
+        const COLUMNS: usize = 100;
+        const MAX_LINES: usize = 16;
+
+        let error = self.shader_error.as_ref().filter(|_| self.capture.is_none())?;
+        let mut text = "Shader error, keeping the previous pipeline:".to_string();
+        for line in error.to_string().lines().take(MAX_LINES) {
+            // The font has no box drawing characters for naga's source excerpts
+            let line: String = line
+                .chars()
+                .map(|c| match c {
+                    '│' => '|',
+                    '─' => '-',
+                    '┌' | '└' => '+',
+                    c => c,
+                })
+                .take(COLUMNS)
+                .collect();
+            text += &format!("\n{line}");
+        }
+        Some(Label {
+            anchor: LabelAnchor::Screen([MARGIN, MARGIN]),
+            text,
+            color: [255, 96, 96, 255],
+            background: Some([0, 0, 0, 200]),
+        })
+    }
+
+    /// Simulation statistics in the bottom left corner, hidden in captures.
+    fn statistics_label(&self) -> Option<Label> {
+        const MARGIN: f32 = 8.0;
+        const LINE_HEIGHT: f32 = 16.0;
+
+        let stats = self.simulation_stats.filter(|_| self.show_statistics && self.capture.is_none())?;
+        let reference = self.simulation_reference.filter(|_| KERNELS[self.kernel].attract);
+        let text = stats.overlay_text(reference.as_ref());
+        let height = text.lines().count() as f32 * LINE_HEIGHT;
+        Some(Label {
+            anchor: LabelAnchor::Screen([MARGIN, self.frame.resolution[1] - height - MARGIN]),
+            text,
+            color: [255, 255, 255, 255],
+            background: Some([0, 0, 0, 160]),
+        })
+    }
+
+    /// The window title doubles as the status panel for frame stats and
+    /// shader error summaries, see [`Self::shader_error_label`] for the full error.
+    fn update_title(&self) {
+        let mut title = self.base_title.clone();
+        if !self.title_status.is_empty() {
+            title += &format!(" | {}", self.title_status);
+        }
+        title += &format!(" | {}", self.demo_name);
+        if let Some(status) = self.demo.ui() {
+            title += &format!(": {status}");
+        }
+        if self.render_mode == RenderMode::GreedyMesh {
+            title += " | Greedy meshed";
+        }
+        if let Some(error) = &self.shader_error {
+            title += &format!(" | Shader error: {}", error.summary());
+        }
+        self.window.set_title(&title);
+    }
+
+    /// Logs the backend allocator's heap breakdown. Only Vulkan and DX12 keep such a report.
+    fn log_allocator_report(&self) {
+        let Some(report) = self.device.generate_allocator_report() else {
+            log::info!("Allocator report is not available on {:?}.", self.adapter.get_info().backend);
+            return;
+        };
 
-            self.cube_mesh.draw_instanced(
-                &mut render_pass,
-                &self.positions_buffer_vsh,
-                0..self.positions.len() as u32,
+        let free = report.total_reserved_bytes - report.total_allocated_bytes;
+        log::info!(
+            "Allocator: {} KiB allocated of {} KiB reserved in {} blocks, {:.1}% free.",
+            report.total_allocated_bytes / 1024,
+            report.total_reserved_bytes / 1024,
+            report.blocks.len(),
+            free as f64 / report.total_reserved_bytes.max(1) as f64 * 100.0,
+        );
+        for (i, block) in report.blocks.iter().enumerate() {
+            let allocations = &report.allocations[block.allocations.clone()];
+            let used: u64 = allocations.iter().map(|allocation| allocation.size).sum();
+            log::debug!(
+                "Block {i}: {} KiB, {} allocations using {} KiB.",
+                block.size / 1024,
+                allocations.len(),
+                used / 1024
             );
         }
+        log::debug!("{:.16?}", report);
+    }
 
-        self.queue.submit(std::iter::once(encoder.finish()));
+    /// Finds the closest instance hit by a ray, using current simulated positions.
+    pub fn raycast(&mut self, origin: cgmath::Point3<f32>, direction: cgmath::Vector3<f32>) -> Option<Hit> {
+        match &self.raycaster {
+            Some(raycaster) => raycaster.cast(
+                &self.device,
+                &self.queue,
+                &mut self.frame_ring,
+                &self.instance_buffers,
+                origin,
+                direction,
+            ),
+            None => raycast::cast_cpu(&self.positions, origin, direction),
+        }
+    }
 
-        self.window.pre_present_notify();
-        image.present();
+    fn occlusion_supported(&self) -> bool {
+        !self.compat
+            && self
+                .adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION)
+    }
 
-        Ok(())
+    /// Switches the culling mode, creating the occlusion culler the first
+    /// time it's needed.
+    pub fn set_culling_mode(&mut self, mode: CullingMode) {
+        if mode == CullingMode::GpuOcclusion {
+            if !self.occlusion_supported() {
+                log::warn!("Occlusion culling needs compute shaders and indirect draws.");
+                return;
+            }
+            if self.sorter.is_some() || self.lod.is_some() {
+                // Both pick the instances to draw in passes of their own
+                log::warn!("Occlusion culling can't be combined with transparent instances or levels of detail.");
+                return;
+            }
+            if self.occlusion.is_none() {
+                self.occlusion = Some(OcclusionCuller::new(
+                    &self.device,
+                    &self.shaders,
+                    &self.instance_buffers,
+                    &self.depth_texture,
+                    self.camera.depth_order,
+                ));
+            }
+        }
+        if mode == CullingMode::CpuChunks && self.culling_mode != mode {
+            // Bounds aren't reduced while nothing culls by them
+            self.invalidate_chunk_bounds();
+        }
+        self.culling_mode = mode;
+    }
+
+    /// Stops culling chunks until bounds of where the instances are now come
+    /// in, for when they moved other than by simulating them.
+    fn invalidate_chunk_bounds(&mut self) {
+        if let Some(chunk_bounds) = &mut self.chunk_bounds {
+            self.chunk_culler.invalidate();
+            chunk_bounds.discard();
+        }
+    }
+
+    /// Hands finished chunk bounds readbacks to the culler, and reduces the
+    /// bounds right away when the culler is waiting for them and no
+    /// simulation steps are about to.
+    fn refresh_chunk_bounds(&mut self) {
+        let Some(chunk_bounds) = &mut self.chunk_bounds else {
+            return;
+        };
+        chunk_bounds.apply(&mut self.chunk_culler);
+        if !self.chunk_culler.stale()
+            || chunk_bounds.pending()
+            || self.pending_simulation.is_some()
+            || self.culling_mode != CullingMode::CpuChunks
+        {
+            return;
+        }
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("chunk_bounds"),
+        });
+        if chunk_bounds.record(&mut encoder, self.instance_buffers.front(), &self.chunk_culler) {
+            self.queue.submit(std::iter::once(encoder.finish()));
+            chunk_bounds.submitted();
+        }
+    }
+
+    /// Returns the index of the instance drawn at a window position, reading
+    /// it back from an id render pass. Unlike [`Self::raycast`] it goes by
+    /// the drawn shapes rather than bounding spheres.
+    pub fn pick(&mut self, cursor: winit::dpi::PhysicalPosition<f64>) -> Option<u32> {
+        if cursor.x < 0.0 || cursor.y < 0.0 {
+            return None;
+        }
+        let size = (self.surface_config.width, self.surface_config.height);
+        let picker = self.picker.get_or_insert_with(|| {
+            Picker::new(
+                &self.device,
+                &self.shaders,
+                &self.instance_layouts.iter().collect::<Vec<_>>(),
+                size,
+                self.camera.depth_order,
+            )
+        });
+        picker.pick(
+            &self.device,
+            &self.queue,
+            &mut self.buffer_pool,
+            &[
+                (&self.frame_bind_group, &[self.frame_ring.uniform_offset()]),
+                (&self.camera_bind_group, &[]),
+                (&self.scene_bind_group, &[]),
+            ],
+            &self.chunk_offsets,
+            if KERNELS[self.kernel].spheres { &self.sphere_mesh } else { &self.cube_mesh },
+            &self.instance_buffers,
+            size,
+            (cursor.x as u32, cursor.y as u32),
+        )
     }
 
     fn toggle_fullscreen(&self) {
@@ -605,31 +4737,161 @@ impl App<'_> {
         self.surface_config.width = new_size.width;
         self.surface_config.height = new_size.height;
         self.surface.configure(&self.device, &self.surface_config);
-        self.multisample_framebuffer = Self::create_multisampled_framebuffer(
-            &self.device,
-            &self.surface_config,
-            Self::MULTISAMPLE_SAMPLES,
-        );
-        self.depth_texture = Texture2d::create_depth_texture(
-            &self.device,
-            &self.surface_config,
-            Self::MULTISAMPLE_SAMPLES,
-            Some("depth_texture"),
-        );
+        self.camera.change_aspect(new_size.width as f32 / new_size.height.max(1) as f32);
+
+        let native_size = (new_size.width, new_size.height);
+        let render_size = self
+            .upscale
+            .as_ref()
+            .map_or(native_size, |upscale| upscale.settings().internal_size(native_size));
+        self.scene.viewport_height = render_size.1 as f32;
+        self.frame.resolution = [render_size.0 as f32, render_size.1 as f32];
+        // Everything reading the graph's textures is bound to the old ones
+        self.render_graph.resize(&self.device, render_size);
+        if let Some(upscale) = &mut self.upscale {
+            upscale.resize(&self.device, self.render_graph.texture(UPSCALE_SCENE), native_size);
+        }
+        if let Some(tone_mapping) = &mut self.tone_mapping {
+            tone_mapping.resize(&self.device, self.render_graph.texture(HDR_SCENE));
+        }
+        if let Some(bloom) = &mut self.bloom {
+            bloom.resize(&self.device, self.render_graph.texture(HDR_SCENE));
+        }
+        if let Some(deferred) = &mut self.deferred {
+            deferred.resize(&self.device, render_size);
+        }
+        self.create_scene_targets(render_size);
     }
 }
 
 impl Game for App<'_> {
-    fn init(window: std::sync::Arc<winit::window::Window>) -> Self {
-        Self::new(window).block_on().expect("Failed to init window")
+    type Config = AppConfig;
+
+    fn init(window: std::sync::Arc<winit::window::Window>, config: &AppConfig) -> Self {
+        let mut app = Self::new(window, config).block_on().expect("Failed to init window");
+        if let Some(Err(error)) = config.script.as_deref().map(|script| app.run_script(script, 0)) {
+            app.console.print(&format!("Error: {error}"));
+        }
+
+        app
     }
 
-    fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
-        let time = (Instant::now() - self.start_time).as_secs_f64();
-        let delta = time - self.time;
+    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        // No frame came to take the steps along
+        self.submit_simulation(None, None);
+        if self.background_paused() {
+            event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
+        } else if let Some(interval) = self.current_frame_interval() {
+            let now = Instant::now();
+            if now >= self.next_frame {
+                // Don't try to catch up on missed frames
+                self.next_frame = (self.next_frame + interval).max(now);
+                self.window.request_redraw();
+            }
+            event_loop.set_control_flow(winit::event_loop::ControlFlow::WaitUntil(self.next_frame));
+        }
+
+        if self.low_latency {
+            // Input sampled now shows up in the very next frame
+            self.frame_ring.wait_for_last(&self.device);
+        } else {
+            // Runs the callbacks of finished GPU work
+            self.device.poll(wgpu::Maintain::Poll);
+        }
+        self.buffer_pool.reclaim();
+        self.reload_shaders();
+        if let Some(stats) = SimulationStatistics::take(&mut self.frame_ring) {
+            self.receive_simulation_stats(stats);
+        }
+        self.refresh_chunk_bounds();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.take();
+        }
+
+        if self.capture.as_ref().is_some_and(|capture| capture.finished()) {
+            event_loop.exit();
+            return;
+        }
+        if self.background_paused() {
+            return;
+        }
+
+        if let Some(replay) = self.replay.as_ref().filter(|replay| replay.finished()) {
+            let positions = self.read_positions();
+            self.device.poll(wgpu::Maintain::Wait);
+            match positions.block_on() {
+                Ok(positions) => log::info!(
+                    "Replayed {} steps, positions digest {:016x}.",
+                    replay.steps,
+                    Replay::digest(&positions)
+                ),
+                Err(error) => log::error!("Failed to read back the replayed positions: {error}"),
+            }
+            event_loop.exit();
+            return;
+        }
+
+        let (time, delta) = match &self.capture {
+            // Advances only when a frame was captured, by a fixed step
+            Some(capture) => (capture.time(), capture.time() - self.time),
+            // A step every frame
+            None if self.replay.is_some() => (self.time + self.timestep.step, self.timestep.step),
+            None => {
+                let time = (Instant::now() - self.start_time).as_secs_f64();
+                (time, self.delta_smoother.push(time - self.time))
+            }
+        };
         self.time = time;
 
         self.update(delta);
+
+        if self.time - self.last_stats_time >= Self::STATS_PERIOD {
+            self.last_stats_time = self.time;
+            if let Some(report) = self.frame_stats.report() {
+                log::debug!("{report}");
+                self.title_status = format!(
+                    "{:.0} FPS (1% low {:.0}, 0.1% low {:.0}, {} stutters, variance {:.2} ms²)",
+                    report.average_fps, report.low_1_fps, report.low_01_fps, report.stutters, report.variance_ms2,
+                );
+                if let Some(latency) = self.latency.take_average() {
+                    log::debug!("Submit to present: {:.2} ms.", latency * 1000.0);
+                    self.title_status += &format!(", {:.1} ms submit→present", latency * 1000.0);
+                }
+                if let Some(streamer) = &self.streamer {
+                    log::debug!(
+                        "Streaming {} of {} points.",
+                        streamer.resident_points(),
+                        streamer.dataset().len()
+                    );
+                }
+                if let Some(times) = self.profiler.as_mut().and_then(GpuProfiler::report) {
+                    log::info!("GPU times with kernel {}: {times}.", KERNELS[self.kernel].name);
+                }
+                let pool = self.buffer_pool.stats();
+                log::debug!(
+                    "Buffer pool: {:.0}% hit rate, {} free, {} retired.",
+                    pool.hit_rate() * 100.0,
+                    pool.free,
+                    pool.retired
+                );
+                self.update_title();
+            }
+            if let (Some(stats), Some(reference)) = (&self.simulation_stats, &self.simulation_reference)
+                && KERNELS[self.kernel].attract
+            {
+                log::debug!("{}.", stats.drift_text(reference));
+            }
+            self.frame_stats.reset();
+        }
+    }
+
+    fn exiting(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        if let Some(capture) = &mut self.capture {
+            capture.finish();
+        }
+        if let Some(report) = self.run_frame_stats.report() {
+            log::info!("Frame statistics ({}): {report}", self.preset.unwrap_or("custom"));
+        }
     }
 
     fn device_event(
@@ -638,7 +4900,10 @@ impl Game for App<'_> {
         _device_id: winit::event::DeviceId,
         event: winit::event::DeviceEvent,
     ) {
-        self.camera_controller.process_device_events(&event);
+        // Device events arrive regardless of focus
+        if self.focused {
+            self.camera_controller.process_device_events(&event);
+        }
     }
 
     fn window_event(
@@ -647,25 +4912,179 @@ impl Game for App<'_> {
         _window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
+        // Keys and clicks may read or change the instance state, which has
+        // to go after the steps recorded so far
+        if matches!(event, WindowEvent::KeyboardInput { .. } | WindowEvent::MouseInput { .. }) {
+            self.submit_simulation(None, None);
+        }
+        if let WindowEvent::KeyboardInput { event, .. } = &event {
+            if event.state.is_pressed() && event.physical_key == PhysicalKey::Code(KeyCode::Backquote) {
+                self.console.toggle();
+                return;
+            }
+            if self.console.is_open() {
+                if let Some(line) = self.console.handle_key(event) {
+                    self.run_command(&line);
+                }
+                // Releases still reach the camera so no key is left held down
+                if event.state.is_pressed() {
+                    return;
+                }
+            }
+        }
+
         self.camera_controller.process_window_events(&event);
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
-            WindowEvent::KeyboardInput { event, .. } => {
-                if event.state.is_pressed() {
-                    match event.physical_key {
-                        PhysicalKey::Code(KeyCode::Escape) => event_loop.exit(),
-                        PhysicalKey::Code(KeyCode::KeyF) => {
-                            self.toggle_fullscreen();
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            WindowEvent::KeyboardInput { event, .. } if event.state.is_pressed() => {
+                if let PhysicalKey::Code(code) = event.physical_key
+                    && let Some(slot) = Self::bookmark_slot(code)
+                {
+                    self.use_bookmark(slot);
+                    return;
+                }
+
+                match event.physical_key {
+                    PhysicalKey::Code(KeyCode::Escape) => event_loop.exit(),
+                    PhysicalKey::Code(KeyCode::KeyF) => {
+                        self.toggle_fullscreen();
+                    }
+                    PhysicalKey::Code(KeyCode::KeyP) => {
+                        self.paused = !self.paused;
+                    }
+                    PhysicalKey::Code(KeyCode::Period) => self.step_once(1),
+                    PhysicalKey::Code(KeyCode::KeyC) => {
+                        self.set_culling_mode(match self.culling_mode {
+                            CullingMode::Disabled => CullingMode::CpuChunks,
+                            CullingMode::CpuChunks
+                                if self.occlusion_supported() && self.sorter.is_none() && self.lod.is_none() =>
+                            {
+                                CullingMode::GpuOcclusion
+                            }
+                            CullingMode::CpuChunks | CullingMode::GpuOcclusion => CullingMode::Disabled,
+                        });
+                        log::info!("Culling mode: {:?}", self.culling_mode);
+                    }
+                    PhysicalKey::Code(KeyCode::KeyI) => {
+                        if self.lod.as_ref().is_some_and(Lod::impostors) {
+                            self.scene.toggle_impostors();
+                            log::info!("Impostor threshold: {}px", self.scene.impostor_threshold);
+                        } else {
+                            log::warn!("Impostors are chosen in the level of detail pass, see --lod.");
+                        }
+                    }
+                    PhysicalKey::Code(KeyCode::F1) => self.toggle_shader_feature(ShaderFeatures::TEXTURED),
+                    PhysicalKey::Code(KeyCode::F2) => self.toggle_shader_feature(ShaderFeatures::LIT),
+                    PhysicalKey::Code(KeyCode::F3) => self.toggle_shader_feature(ShaderFeatures::FOGGED),
+                    PhysicalKey::Code(KeyCode::F4) => self.toggle_shader_feature(ShaderFeatures::INSTANCED_COLOR),
+                    PhysicalKey::Code(KeyCode::F5) => self.toggle_shader_feature(ShaderFeatures::POINT_COLOR),
+                    PhysicalKey::Code(KeyCode::F6) => self.toggle_shader_feature(ShaderFeatures::COLOR_ATTRIBUTE),
+                    PhysicalKey::Code(KeyCode::F7) => self.toggle_shader_feature(ShaderFeatures::PULSE),
+                    PhysicalKey::Code(KeyCode::F8) => self.toggle_shader_feature(ShaderFeatures::MATERIALS),
+                    PhysicalKey::Code(KeyCode::F9) => self.toggle_shader_feature(ShaderFeatures::SHADOWED),
+                    PhysicalKey::Code(KeyCode::KeyM) => self.log_allocator_report(),
+                    PhysicalKey::Code(KeyCode::KeyG) => self.toggle_render_mode(),
+                    PhysicalKey::Code(KeyCode::KeyB) => self.toggle_deferred_shading(),
+                    PhysicalKey::Code(KeyCode::KeyV) => self.cycle_polygon_mode(),
+                    PhysicalKey::Code(KeyCode::KeyX) => self.clear_gravity_wells(),
+                    PhysicalKey::Code(KeyCode::KeyK) => {
+                        self.set_kernel((self.kernel + 1) % KERNELS.len());
+                        self.simulation_reference = None;
+                        log::info!("Simulation kernel: {}", KERNELS[self.kernel].name);
+                    }
+                    PhysicalKey::Code(KeyCode::KeyN) => {
+                        let next = demo::DEMOS.iter().position(|demo| demo.name == self.demo_name).unwrap_or(0) + 1;
+                        let name = demo::DEMOS[next % demo::DEMOS.len()].name;
+                        match self.switch_demo(name) {
+                            Ok(()) => log::info!("Demo: {name}"),
+                            Err(error) => log::warn!("{error}"),
                         }
-                        PhysicalKey::Code(KeyCode::KeyP) => {
-                            self.paused = !self.paused;
+                    }
+                    PhysicalKey::Code(KeyCode::KeyR) => match self.reset_simulation() {
+                        Ok(()) => log::info!("Simulation reset."),
+                        Err(error) => log::warn!("{error}."),
+                    },
+                    // Steps back, next to the key stepping forward
+                    PhysicalKey::Code(KeyCode::Comma) => match self.rewind(1) {
+                        Ok(age) => log::info!("Rewound {age:.1}s."),
+                        Err(error) => log::warn!("{error}."),
+                    },
+                    PhysicalKey::Code(KeyCode::KeyT) => {
+                        self.simulation.follow.enabled = !self.simulation.follow.enabled;
+                        log::info!("Camera attractor: {}", if self.simulation.follow.enabled { "on" } else { "off" });
+                    }
+                    PhysicalKey::Code(KeyCode::KeyO) => self.show_statistics = !self.show_statistics,
+                    PhysicalKey::Code(KeyCode::KeyL) => {
+                        self.labels.clear();
+                        log::info!("Cleared labels.");
+                    }
+                    PhysicalKey::Code(KeyCode::BracketLeft) => {
+                        self.scene.scale_draw_distance(0.8);
+                        log::info!("Draw distance: {}", self.scene.max_draw_distance);
+                    }
+                    PhysicalKey::Code(KeyCode::BracketRight) => {
+                        self.scene.scale_draw_distance(1.25);
+                        log::info!("Draw distance: {}", self.scene.max_draw_distance);
+                    }
+                    _ => {}
+                }
+            }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Left,
+                ..
+            } => {
+                match self.raycast(self.camera.eye, self.camera.direction) {
+                    Some(hit) => {
+                        log::info!("Hit instance {} at distance {:.2}", hit.instance, hit.distance);
+                        self.labels.set_instance_label(
+                            hit.instance,
+                            format!("#{}\n{:.1}", hit.instance, hit.distance),
+                            [255, 220, 64, 255],
+                        );
+                    }
+                    None => log::info!("Nothing hit"),
+                }
+            }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Right,
+                ..
+            } => {
+                // The cursor is locked, so pick what the crosshair is on
+                let size = self.window.inner_size();
+                let center = winit::dpi::PhysicalPosition::new(size.width as f64 / 2.0, size.height as f64 / 2.0);
+                // Shift adds to the selection or takes out of it
+                let extend = self.modifiers.shift_key();
+                match self.pick(center) {
+                    Some(instance) if extend && !self.selection.toggle(instance) => {
+                        log::info!("Deselected instance {instance}, {} selected.", self.selection.ids().len());
+                    }
+                    Some(instance) => {
+                        if !extend {
+                            self.selection.select(instance);
                         }
-                        _ => {}
+                        log::info!("Picked instance {instance}, {} selected.", self.selection.ids().len());
+                        self.labels.set_instance_label(instance, format!("#{instance}"), [64, 220, 255, 255]);
+                    }
+                    None if extend => log::info!("Nothing picked."),
+                    None => {
+                        log::info!("Nothing picked, selection cleared.");
+                        self.selection.clear();
                     }
                 }
             }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Middle,
+                ..
+            } => self.drop_gravity_well(self.modifiers.shift_key()),
             WindowEvent::RedrawRequested => {
-                self.window.request_redraw();
+                // Frame limited redraws are requested from about_to_wait
+                if self.current_frame_interval().is_none() && !self.background_paused() {
+                    self.window.request_redraw();
+                }
 
                 match self.render() {
                     Ok(_) => {},
@@ -687,6 +5106,7 @@ impl Game for App<'_> {
             WindowEvent::Resized(new_size) => {
                 self.resize(new_size);
             }
+            WindowEvent::Focused(focused) => self.set_focused(focused, event_loop),
             _ => {}
         }
     }
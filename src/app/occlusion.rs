@@ -0,0 +1,470 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix4, Point3};
+
+use super::{
+    camera::DepthOrder, debug_labels, instances::InstanceBuffers, mesh::Mesh, shader::ShaderLoader, texture::Texture2d,
+};
+
+/// Mirrors `Params` in `occlusion.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct OcclusionParams {
+    view_projection: [[f32; 4]; 4],
+    eye: [f32; 4],
+    size: [f32; 2],
+    levels: u32,
+    count: u32,
+}
+
+/// Farthest depth of every pixel of the previous frame and of every block
+/// of pixels at the levels below, built with a compute pass per level.
+struct DepthPyramid {
+    size: (u32, u32),
+    /// Whether the depth buffer has more than one sample, which picks the
+    /// resolve pipeline.
+    multisampled: bool,
+    levels: u32,
+    /// Resolves the depth buffer into the top level, then one per level below.
+    bind_groups: Vec<wgpu::BindGroup>,
+    /// Every level, for the culling pass.
+    cull_bind_group: wgpu::BindGroup,
+}
+
+impl DepthPyramid {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+    fn new(
+        device: &wgpu::Device,
+        resolve_layouts: &[wgpu::BindGroupLayout; 2],
+        downsample_layout: &wgpu::BindGroupLayout,
+        cull_layout: &wgpu::BindGroupLayout,
+        depth_texture: &Texture2d,
+    ) -> Self {
+        let size = (depth_texture.texture.width(), depth_texture.texture.height());
+        let multisampled = depth_texture.texture.sample_count() > 1;
+        let levels = 32 - size.0.max(size.1).leading_zeros();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("depth_pyramid"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: levels,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let level_views: Vec<wgpu::TextureView> = (0..levels)
+            .map(|level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("depth_pyramid_level"),
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let resolve = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("depth_pyramid_resolve"),
+            layout: &resolve_layouts[usize::from(multisampled)],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: if multisampled { 0 } else { 3 },
+                    resource: wgpu::BindingResource::TextureView(&depth_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&level_views[0]),
+                },
+            ],
+        });
+        let bind_groups = std::iter::once(resolve)
+            .chain(level_views.windows(2).map(|views| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("depth_pyramid_downsample"),
+                    layout: downsample_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&views[0]),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&views[1]),
+                        },
+                    ],
+                })
+            }))
+            .collect();
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let cull_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("depth_pyramid"),
+            layout: cull_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+        });
+
+        Self {
+            size,
+            multisampled,
+            levels,
+            bind_groups,
+            cull_bind_group,
+        }
+    }
+}
+
+/// Visible instances of one chunk.
+struct OcclusionChunk {
+    params_buffer: wgpu::Buffer,
+    draw_buffer: wgpu::Buffer,
+    /// For either side of the simulation state.
+    bind_groups: [wgpu::BindGroup; 2],
+    positions: wgpu::Buffer,
+    transforms: wgpu::Buffer,
+    colors: wgpu::Buffer,
+    count: u32,
+}
+
+/// Hi-Z occlusion culling. Every frame the depth buffer of the previous
+/// frame is reduced to a depth pyramid, then a compute pass tests the
+/// bounding sphere of every instance against it with the current camera
+/// and copies the instances that may be visible into a list per chunk,
+/// counting them into the arguments of an indirect draw.
+///
+/// Testing against the previous frame lets instances that just came out
+/// from behind an occluder show up a frame late. Expired instances are left
+/// out like with the compaction, impostors are drawn regardless.
+pub struct OcclusionCuller {
+    /// Over a depth buffer of a single sample, then over a multisampled one.
+    resolve_layouts: [wgpu::BindGroupLayout; 2],
+    downsample_layout: wgpu::BindGroupLayout,
+    pyramid_layout: wgpu::BindGroupLayout,
+    chunk_layout: wgpu::BindGroupLayout,
+    resolve_pipelines: [wgpu::ComputePipeline; 2],
+    downsample_pipeline: wgpu::ComputePipeline,
+    cull_pipeline: wgpu::ComputePipeline,
+    pyramid: DepthPyramid,
+    /// Whether the depth buffer holds a drawn frame to build the pyramid from.
+    primed: bool,
+    chunks: Vec<OcclusionChunk>,
+}
+
+impl OcclusionCuller {
+    const WORKGROUP_SIZE: u32 = 256;
+    const PYRAMID_WORKGROUP_SIZE: u32 = 8;
+    const DRAW_SIZE: u64 = std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64;
+
+    /// `depth_texture` is the depth buffer of the scene, running the way
+    /// `depth_order` says.
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLoader,
+        instances: &InstanceBuffers,
+        depth_texture: &Texture2d,
+        depth_order: DepthOrder,
+    ) -> Self {
+        let pyramid_module = shaders.module(device, "depth_pyramid.wgsl", include_str!("../shaders/depth_pyramid.wgsl"));
+        let cull_module = shaders.module(device, "occlusion.wgsl", include_str!("../shaders/occlusion.wgsl"));
+
+        let texture_entry = |binding, sample_type, multisampled| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled,
+            },
+            count: None,
+        };
+        let storage_texture_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: DepthPyramid::FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let pyramid_sample_type = wgpu::TextureSampleType::Float { filterable: false };
+        let resolve_layouts = [(3, false), (0, true)].map(|(binding, multisampled)| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("depth_pyramid_resolve"),
+                entries: &[texture_entry(binding, wgpu::TextureSampleType::Depth, multisampled), storage_texture_entry],
+            })
+        });
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("depth_pyramid_downsample"),
+            entries: &[texture_entry(1, pyramid_sample_type, false), storage_texture_entry],
+        });
+        let pyramid_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("depth_pyramid"),
+            entries: &[texture_entry(0, pyramid_sample_type, false)],
+        });
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let chunk_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("occlusion_chunk"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Uniform),
+                entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(2, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(3, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(4, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(5, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(6, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(7, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(8, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+
+        let constants = depth_order.override_constants();
+        let pipeline = |label, module, entry_point, layouts: &[&wgpu::BindGroupLayout]| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+                cache: None,
+            })
+        };
+        let resolve_pipelines = [
+            pipeline(
+                "depth_pyramid_resolve_pipeline",
+                &pyramid_module,
+                "resolve_single_depth",
+                &[&resolve_layouts[0]],
+            ),
+            pipeline("depth_pyramid_resolve_pipeline", &pyramid_module, "resolve_depth", &[&resolve_layouts[1]]),
+        ];
+        let downsample_pipeline = pipeline(
+            "depth_pyramid_downsample_pipeline",
+            &pyramid_module,
+            "downsample",
+            &[&downsample_layout],
+        );
+        let cull_pipeline = pipeline("occlusion_pipeline", &cull_module, "cull", &[&chunk_layout, &pyramid_layout]);
+
+        let pyramid = DepthPyramid::new(device, &resolve_layouts, &downsample_layout, &pyramid_layout, depth_texture);
+        let mut culler = Self {
+            resolve_layouts,
+            downsample_layout,
+            pyramid_layout,
+            chunk_layout,
+            resolve_pipelines,
+            downsample_pipeline,
+            cull_pipeline,
+            pyramid,
+            primed: false,
+            chunks: Vec::new(),
+        };
+        culler.resize(device, instances);
+        culler
+    }
+
+    /// Recreates the lists after the instance buffers changed.
+    pub fn resize(&mut self, device: &wgpu::Device, instances: &InstanceBuffers) {
+        self.chunks = instances
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let buffer = |label, size: u64, usage| {
+                    device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some(label),
+                        size,
+                        usage: wgpu::BufferUsages::STORAGE | usage,
+                        mapped_at_creation: false,
+                    })
+                };
+                let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("occlusion_params"),
+                    size: std::mem::size_of::<OcclusionParams>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let draw_buffer = buffer(
+                    "occlusion_draw",
+                    Self::DRAW_SIZE,
+                    wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                );
+                let positions = buffer("visible_positions", chunk.positions_vsh.size(), wgpu::BufferUsages::VERTEX);
+                let transforms = buffer("visible_transforms", chunk.transforms.size(), wgpu::BufferUsages::VERTEX);
+                let colors = buffer("visible_colors", chunk.colors.size(), wgpu::BufferUsages::VERTEX);
+
+                // Vertex positions, which already include cluster offsets
+                let bind_groups = [0, 1].map(|side| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("occlusion_chunk"),
+                        layout: &self.chunk_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: params_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: chunk.positions_vsh.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: chunk.velocities[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: chunk.transforms.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 4,
+                                resource: chunk.colors.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 5,
+                                resource: positions.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 6,
+                                resource: transforms.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 7,
+                                resource: colors.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 8,
+                                resource: draw_buffer.as_entire_binding(),
+                            },
+                        ],
+                    })
+                });
+
+                OcclusionChunk {
+                    params_buffer,
+                    draw_buffer,
+                    bind_groups,
+                    positions,
+                    transforms,
+                    colors,
+                    count: chunk.range.len() as u32,
+                }
+            })
+            .collect();
+    }
+
+    /// Rebuilds the pyramid after the depth buffer was recreated. Until a
+    /// frame is drawn into it nothing is occluded.
+    pub fn resize_depth(&mut self, device: &wgpu::Device, depth_texture: &Texture2d) {
+        self.pyramid = DepthPyramid::new(
+            device,
+            &self.resolve_layouts,
+            &self.downsample_layout,
+            &self.pyramid_layout,
+            depth_texture,
+        );
+        self.primed = false;
+    }
+
+    /// Records building the pyramid from the depth buffer, still holding the
+    /// previous frame, and culling every chunk drawn with `mesh` against it,
+    /// reading the `front` side of the simulation state.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        front: usize,
+        mesh: &Mesh,
+        view_projection: Matrix4<f32>,
+        eye: Point3<f32>,
+        draw_distance: f32,
+    ) {
+        let max_groups = device.limits().max_compute_workgroups_per_dimension;
+        let draw = wgpu::util::DrawIndexedIndirectArgs {
+            index_count: mesh.element_count(),
+            instance_count: 0,
+            first_index: 0,
+            base_vertex: 0,
+            first_instance: 0,
+        };
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("occlusion_pass"),
+            timestamp_writes: None,
+        });
+        let levels = if self.primed {
+            for (level, bind_group) in self.pyramid.bind_groups.iter().enumerate() {
+                let (width, height) = (self.pyramid.size.0 >> level, self.pyramid.size.1 >> level);
+                debug_labels::marker(&mut compute_pass, || format!("depth pyramid level {level}: {width}x{height}"));
+                compute_pass.set_pipeline(if level == 0 {
+                    &self.resolve_pipelines[usize::from(self.pyramid.multisampled)]
+                } else {
+                    &self.downsample_pipeline
+                });
+                compute_pass.set_bind_group(0, bind_group, &[]);
+                compute_pass.dispatch_workgroups(
+                    width.max(1).div_ceil(Self::PYRAMID_WORKGROUP_SIZE),
+                    height.max(1).div_ceil(Self::PYRAMID_WORKGROUP_SIZE),
+                    1,
+                );
+            }
+            self.pyramid.levels
+        } else {
+            0
+        };
+        self.primed = true;
+
+        compute_pass.set_pipeline(&self.cull_pipeline);
+        compute_pass.set_bind_group(1, &self.pyramid.cull_bind_group, &[]);
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            let params = OcclusionParams {
+                view_projection: view_projection.into(),
+                eye: [eye.x, eye.y, eye.z, draw_distance],
+                size: [self.pyramid.size.0 as f32, self.pyramid.size.1 as f32],
+                levels,
+                count: chunk.count,
+            };
+            queue.write_buffer(&chunk.params_buffer, 0, bytemuck::bytes_of(&params));
+            queue.write_buffer(&chunk.draw_buffer, 0, draw.as_bytes());
+
+            debug_labels::marker(&mut compute_pass, || format!("chunk {index}: {} instances", chunk.count));
+            compute_pass.set_bind_group(0, &chunk.bind_groups[front], &[]);
+            let groups = chunk.count.div_ceil(Self::WORKGROUP_SIZE);
+            compute_pass.dispatch_workgroups(groups.clamp(1, max_groups), groups.div_ceil(max_groups).max(1), 1);
+        }
+    }
+
+    /// Draws the visible instances of every chunk, with a pipeline using the
+    /// default vertex streams.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, mesh: &Mesh) {
+        for chunk in &self.chunks {
+            render_pass.set_vertex_buffer(2, chunk.transforms.slice(..));
+            render_pass.set_vertex_buffer(3, chunk.colors.slice(..));
+            mesh.draw_indexed_indirect(render_pass, chunk.positions.slice(..), &chunk.draw_buffer, 0);
+        }
+    }
+}
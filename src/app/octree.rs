@@ -0,0 +1,128 @@
+use cgmath::Point3;
+
+use super::{camera::Frustum, culling::Aabb};
+
+struct OctreeNode {
+    bounds: Aabb,
+    /// Items that straddle the split planes and stay at this level.
+    items: Vec<usize>,
+    children: Option<[usize; 8]>,
+}
+
+/// Loose octree over a fixed set of bounding boxes (instance chunks).
+///
+/// Items are referred to by their index in the slice passed to [`Octree::build`].
+pub struct Octree {
+    nodes: Vec<OctreeNode>,
+    items: Vec<Aabb>,
+}
+
+impl Octree {
+    const LEAF_CAPACITY: usize = 8;
+    const MAX_DEPTH: u32 = 8;
+
+    pub fn build(items: Vec<Aabb>) -> Self {
+        let mut bounds = Aabb::empty();
+        for item in &items {
+            bounds.extend([item.min.x, item.min.y, item.min.z, 1.0]);
+            bounds.extend([item.max.x, item.max.y, item.max.z, 1.0]);
+        }
+
+        let mut octree = Self {
+            nodes: Vec::new(),
+            items,
+        };
+        let indices = (0..octree.items.len()).collect();
+        octree.build_node(bounds, indices, 0);
+
+        log::debug!(
+            "Built octree with {} nodes over {} items.",
+            octree.nodes.len(),
+            octree.items.len()
+        );
+
+        octree
+    }
+
+    fn build_node(&mut self, bounds: Aabb, mut indices: Vec<usize>, depth: u32) -> usize {
+        let node = self.nodes.len();
+        self.nodes.push(OctreeNode {
+            bounds,
+            items: Vec::new(),
+            children: None,
+        });
+
+        if indices.len() <= Self::LEAF_CAPACITY || depth >= Self::MAX_DEPTH {
+            self.nodes[node].items = indices;
+            return node;
+        }
+
+        let center = bounds.center();
+        let octants: [Aabb; 8] = std::array::from_fn(|i| Aabb {
+            min: Point3::new(
+                if i & 1 == 0 { bounds.min.x } else { center.x },
+                if i & 2 == 0 { bounds.min.y } else { center.y },
+                if i & 4 == 0 { bounds.min.z } else { center.z },
+            ),
+            max: Point3::new(
+                if i & 1 == 0 { center.x } else { bounds.max.x },
+                if i & 2 == 0 { center.y } else { bounds.max.y },
+                if i & 4 == 0 { center.z } else { bounds.max.z },
+            ),
+        });
+
+        let mut child_items: [Vec<usize>; 8] = Default::default();
+        indices.retain(|&i| match octants.iter().position(|o| o.contains(&self.items[i])) {
+            Some(octant) => {
+                child_items[octant].push(i);
+                false
+            }
+            None => true,
+        });
+
+        // Nothing fits into a single octant, splitting further won't help
+        if child_items.iter().all(Vec::is_empty) {
+            self.nodes[node].items = indices;
+            return node;
+        }
+
+        let children = std::array::from_fn(|i| {
+            let items = std::mem::take(&mut child_items[i]);
+            self.build_node(octants[i], items, depth + 1)
+        });
+
+        self.nodes[node].items = indices;
+        self.nodes[node].children = Some(children);
+        node
+    }
+
+    /// Grows every node and item by `amount`. Containment is preserved,
+    /// so the tree stays valid without a rebuild.
+    pub fn inflate(&mut self, amount: f32) {
+        self.nodes.iter_mut().for_each(|n| n.bounds.inflate(amount));
+        self.items.iter_mut().for_each(|i| i.inflate(amount));
+    }
+
+    /// Appends the indices of all items intersecting `frustum` to `out`.
+    pub fn query_frustum(&self, frustum: &Frustum, out: &mut Vec<usize>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if !frustum.intersects_aabb(node.bounds.min, node.bounds.max) {
+                continue;
+            }
+
+            out.extend(node.items.iter().copied().filter(|&i| {
+                frustum.intersects_aabb(self.items[i].min, self.items[i].max)
+            }));
+
+            if let Some(children) = node.children {
+                stack.extend(children);
+            }
+        }
+    }
+}
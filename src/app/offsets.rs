@@ -0,0 +1,102 @@
+use super::instances::InstanceChunk;
+
+/// Index of the first instance of every instance chunk, one uniform per
+/// dynamic offset. Chunks are drawn one at a time from their own buffers,
+/// so `instance_index` restarts at zero in each of them, and shaders add
+/// this to get the index into the whole simulation grid. Bound at group 3
+/// of the pipelines drawing instances.
+pub struct ChunkOffsets {
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// Grown when more chunks don't fit.
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    stride: u64,
+}
+
+impl ChunkOffsets {
+    /// Bindings before it belong to the lighting pass of `default.wgsl`,
+    /// which shares the group.
+    const BINDING: u32 = 5;
+    const SIZE: u64 = std::mem::size_of::<u32>() as u64;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("chunk_offsets"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: Self::BINDING,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(Self::SIZE),
+                },
+                count: None,
+            }],
+        });
+        let stride = Self::SIZE.next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let (buffer, bind_group) = Self::create_buffer(device, &bind_group_layout, stride);
+
+        Self {
+            bind_group_layout,
+            buffer,
+            bind_group,
+            stride,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, size: u64) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk_offsets"),
+            size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("chunk_offsets"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: Self::BINDING,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(Self::SIZE),
+                }),
+            }],
+        });
+        (buffer, bind_group)
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// Writes the first instance of every chunk, after the chunks changed.
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, chunks: &[InstanceChunk]) {
+        let size = chunks.len().max(1) as u64 * self.stride;
+        if self.buffer.size() < size {
+            (self.buffer, self.bind_group) =
+                Self::create_buffer(device, &self.bind_group_layout, size.next_power_of_two());
+        }
+
+        let mut contents = vec![0; size as usize];
+        for (chunk, bytes) in chunks.iter().zip(contents.chunks_mut(self.stride as usize)) {
+            bytes[..Self::SIZE as usize].copy_from_slice(bytemuck::bytes_of(&chunk.range.start));
+        }
+        queue.write_buffer(&self.buffer, 0, &contents);
+    }
+
+    fn offset(&self, chunk: usize) -> u32 {
+        (chunk as u64 * self.stride) as u32
+    }
+
+    /// Binds the first instance of `chunk`. The first chunk starts at zero,
+    /// which also suits instances drawn from other buffers.
+    pub fn bind(&self, render_pass: &mut wgpu::RenderPass, chunk: usize) {
+        render_pass.set_bind_group(3, &self.bind_group, &[self.offset(chunk)]);
+    }
+
+    /// Like [`Self::bind`], recorded into a render bundle.
+    pub fn bind_bundle(&self, encoder: &mut wgpu::RenderBundleEncoder, chunk: usize) {
+        encoder.set_bind_group(3, &self.bind_group, &[self.offset(chunk)]);
+    }
+}
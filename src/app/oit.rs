@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+
+use super::{App, Pipeline, PipelineSelector, shader::ShaderLoader, texture::Texture2d};
+
+/// Targets the translucent fragments are summed into, at the internal
+/// resolution.
+struct AccumulationTargets {
+    /// Weighted premultiplied color, the weighted alpha in a.
+    accumulation: Texture2d,
+    /// Product of one minus the alpha of every fragment.
+    revealage: Texture2d,
+    /// Drawn into instead with multisampling, resolved into the targets
+    /// above.
+    multisampled: Option<[wgpu::TextureView; 2]>,
+}
+
+impl AccumulationTargets {
+    fn new(device: &wgpu::Device, size: (u32, u32), sample_count: u32) -> Self {
+        let formats = [WeightedBlended::ACCUMULATION_FORMAT, WeightedBlended::REVEALAGE_FORMAT];
+        Self {
+            accumulation: Texture2d::create_render_target(
+                device,
+                size,
+                WeightedBlended::ACCUMULATION_FORMAT,
+                Some("oit_accumulation"),
+            ),
+            revealage: Texture2d::create_render_target(device, size, WeightedBlended::REVEALAGE_FORMAT, Some("oit_revealage")),
+            multisampled: (sample_count > 1)
+                .then(|| formats.map(|format| App::create_multisampled_framebuffer(device, format, size, sample_count))),
+        }
+    }
+}
+
+/// Weighted blended order independent transparency, after McGuire and
+/// Bavoil. Translucent groups are drawn in any order into an accumulation
+/// target, summing their colors weighted by alpha and distance, and a
+/// revealage target, multiplying how much of what's behind each one lets
+/// through. A fullscreen pass then blends the weighted average color over
+/// the scene by the revealage. It's an approximation, fragments far apart
+/// are told apart by the weights rather than ordered, but needs no sorting
+/// however many translucent instances overlap.
+pub struct WeightedBlended {
+    targets: AccumulationTargets,
+    layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    composite_pipeline: wgpu::RenderPipeline,
+    /// Variants of the default pipelines writing the accumulation targets.
+    pub pipelines: HashMap<PipelineSelector, Pipeline>,
+}
+
+impl WeightedBlended {
+    pub const FRAGMENT_ENTRY: &'static str = "fs_accumulate";
+    const ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    /// Whether both targets can be rendered and resolved with `sample_count`
+    /// samples.
+    pub fn supports_sample_count(adapter: &wgpu::Adapter, device: &wgpu::Device, sample_count: u32) -> bool {
+        [Self::ACCUMULATION_FORMAT, Self::REVEALAGE_FORMAT]
+            .into_iter()
+            .all(|format| Texture2d::sample_counts(adapter, device, format).contains(&sample_count))
+    }
+
+    /// `color_format` and `sample_count` are the scene's, which the
+    /// composite pass draws into.
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLoader,
+        color_format: wgpu::TextureFormat,
+        size: (u32, u32),
+        sample_count: u32,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("oit_composite"),
+            entries: &[texture_entry(0), texture_entry(1)],
+        });
+
+        let targets = AccumulationTargets::new(device, size, sample_count);
+        let bind_group = Self::create_bind_group(device, &layout, &targets);
+        let composite_pipeline = Self::create_composite_pipeline(device, shaders, &layout, color_format, sample_count);
+
+        Self {
+            targets,
+            layout,
+            bind_group,
+            composite_pipeline,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        targets: &AccumulationTargets,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("oit_composite"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&targets.accumulation.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&targets.revealage.view),
+                },
+            ],
+        })
+    }
+
+    fn create_composite_pipeline(
+        device: &wgpu::Device,
+        shaders: &ShaderLoader,
+        layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> wgpu::RenderPipeline {
+        let module = shaders.module(device, "oit.wgsl", include_str!("../shaders/oit.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("oit_composite_pipeline_layout"),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("oit_composite_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // Shares the pass with the text labels, which test depth
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    /// Color targets of the accumulating pipelines. Colors are summed,
+    /// revealage multiplied by one minus each alpha.
+    pub fn color_targets() -> [Option<wgpu::ColorTargetState>; 2] {
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let multiplicative = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+            operation: wgpu::BlendOperation::Add,
+        };
+        [
+            Some(wgpu::ColorTargetState {
+                format: Self::ACCUMULATION_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: additive,
+                    alpha: additive,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: Self::REVEALAGE_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: multiplicative,
+                    alpha: multiplicative,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+        ]
+    }
+
+    /// Recreates the targets for a new internal resolution or sample count.
+    pub fn resize(&mut self, device: &wgpu::Device, size: (u32, u32), sample_count: u32) {
+        self.targets = AccumulationTargets::new(device, size, sample_count);
+        self.bind_group = Self::create_bind_group(device, &self.layout, &self.targets);
+    }
+
+    /// Rebuilds the composite pipeline for the scene's new sample count, the
+    /// targets follow with [`Self::resize`].
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        shaders: &ShaderLoader,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        self.composite_pipeline =
+            Self::create_composite_pipeline(device, shaders, &self.layout, color_format, sample_count);
+    }
+
+    /// Replaces the pipelines after the material changed.
+    pub fn set_pipelines(&mut self, pipelines: Vec<(PipelineSelector, wgpu::RenderPipeline)>) {
+        self.pipelines = pipelines
+            .into_iter()
+            .map(|(selector, pipeline)| (selector, Pipeline::Render(pipeline)))
+            .collect();
+    }
+
+    /// Render pass accumulating the translucent fragments in front of
+    /// `depth`, the scene's depth buffer, which is only tested.
+    pub fn begin_accumulation_pass<'a>(
+        &self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth: &wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        let targets = &self.targets;
+        let resolved = [&targets.accumulation.view, &targets.revealage.view];
+        let clears = [wgpu::Color::TRANSPARENT, wgpu::Color::WHITE];
+        let attachments: Vec<_> = (0..2)
+            .map(|index| {
+                let (view, resolve_target) = match &targets.multisampled {
+                    Some(multisampled) => (&multisampled[index], Some(resolved[index])),
+                    None => (resolved[index], None),
+                };
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clears[index]),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })
+            })
+            .collect();
+
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("oit_accumulation_pass"),
+            color_attachments: &attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
+
+    /// Blends the accumulated fragments over the scene.
+    pub fn draw_composite(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,295 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{
+    ComputePushConstants,
+    wells::{GravityWell, GravityWells},
+};
+
+/// Mirrors `SimParams` in `compute.wgsl`. The kernels read them from a
+/// uniform buffer, so they can be tuned live without new pipelines.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct SimParams {
+    /// Strength of the attractor at the origin, split between the bodies in
+    /// the n-body kernels.
+    pub gravity: f32,
+    /// Rate per second the velocities decay at, 0 keeps them.
+    pub drag: f32,
+    /// Radius of the shell flocking instances cruise around the y axis on.
+    pub flock_radius: f32,
+    pub cruise_speed: f32,
+    /// How quickly flocking instances turn towards cruising, per second.
+    pub steering: f32,
+    /// Pull back to the ground plane per unit of height.
+    pub wave_stiffness: f32,
+    /// Keeps the pull of close bodies finite in the n-body kernels.
+    pub softening: f32,
+    /// Frequency of the curl noise per world unit.
+    pub noise_scale: f32,
+    /// Scales the velocities of the curl noise flow.
+    pub noise_speed: f32,
+    /// Distance within which fluid particles interact, also the cell size
+    /// of the spatial hash they're found through.
+    pub sph_radius: f32,
+    /// Density the fluid settles at, in weighted neighbours within
+    /// `sph_radius`.
+    pub sph_density: f32,
+    /// Pressure per unit of density over `sph_density`.
+    pub sph_stiffness: f32,
+    /// How strongly fluid particles take on the velocity of their neighbours.
+    pub sph_viscosity: f32,
+    /// Downward acceleration of the fluid, which rests on the ground plane.
+    pub sph_gravity: f32,
+}
+
+impl Default for SimParams {
+    fn default() -> Self {
+        Self {
+            gravity: 1.0e9,
+            drag: 0.0,
+            flock_radius: 4000.0,
+            cruise_speed: 40.0,
+            steering: 0.5,
+            wave_stiffness: 4.0,
+            softening: 50.0,
+            noise_scale: 1.0 / 1500.0,
+            noise_speed: 60.0,
+            // Particles 8 units apart are about at rest
+            sph_radius: 16.0,
+            sph_density: 5.0,
+            sph_stiffness: 400.0,
+            sph_viscosity: 2.0,
+            sph_gravity: 100.0,
+        }
+    }
+}
+
+/// `SimParams` on the GPU, bound at group 3 of the simulation kernels along
+/// with the count of instances the emitter respawned this frame and the
+/// gravity wells. The [`ComputePushConstants`] of every step of a frame
+/// that don't fit the push constants of the device are found there too, at
+/// a dynamic offset per step.
+pub struct SimParamsBuffer {
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+    spawned_buffer: wgpu::Buffer,
+    /// Count of the gravity wells followed by them, like `GravityWells` in
+    /// `compute.wgsl`.
+    wells_buffer: wgpu::Buffer,
+    /// Uploaded constants, `None` while all of them are pushed.
+    constants_buffer: Option<wgpu::Buffer>,
+    constants_stride: u64,
+    /// Leading bytes of the constants that are pushed, see
+    /// [`ComputePushConstants::pushed_size`].
+    pushed_size: u32,
+    /// Constants of the steps of this frame, pushed by [`Self::bind`].
+    pushed: Vec<ComputePushConstants>,
+}
+
+impl SimParamsBuffer {
+    /// Steps of a frame the uploaded constants have room for at first.
+    const INITIAL_STEPS: u64 = 8;
+    /// The count is padded to the alignment of the wells.
+    const WELLS_OFFSET: u64 = std::mem::size_of::<GravityWell>() as u64;
+
+    /// Uploads the constants that don't fit the push constants the device
+    /// was created with.
+    pub fn new(device: &wgpu::Device, params: &SimParams) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("sim_params"),
+            contents: bytemuck::bytes_of(params),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let spawned_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("spawned"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let wells_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gravity_wells"),
+            size: Self::WELLS_OFFSET * (1 + GravityWells::MAX_WELLS as u64),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pushed_size = ComputePushConstants::device_pushed_size(device);
+        let uploaded_size = (ComputePushConstants::SIZE - pushed_size) as u64;
+        let constants_stride = uploaded_size.next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let constants_buffer = (uploaded_size > 0)
+            .then(|| Self::create_constants_buffer(device, Self::INITIAL_STEPS * constants_stride));
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: binding == 2,
+                min_binding_size: if binding == 2 { wgpu::BufferSize::new(uploaded_size) } else { None },
+            },
+            count: None,
+        };
+        let mut entries = vec![
+            entry(0, wgpu::BufferBindingType::Uniform),
+            entry(1, wgpu::BufferBindingType::Storage { read_only: false }),
+            entry(3, wgpu::BufferBindingType::Storage { read_only: true }),
+        ];
+        if constants_buffer.is_some() {
+            entries.push(entry(2, wgpu::BufferBindingType::Uniform));
+        }
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("sim_params"),
+            entries: &entries,
+        });
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &buffer,
+            &spawned_buffer,
+            &wells_buffer,
+            constants_buffer.as_ref(),
+            uploaded_size,
+        );
+
+        Self {
+            bind_group_layout,
+            bind_group,
+            buffer,
+            spawned_buffer,
+            wells_buffer,
+            constants_buffer,
+            constants_stride,
+            pushed_size,
+            pushed: Vec::new(),
+        }
+    }
+
+    fn create_constants_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("compute_constants"),
+            size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        spawned_buffer: &wgpu::Buffer,
+        wells_buffer: &wgpu::Buffer,
+        constants_buffer: Option<&wgpu::Buffer>,
+        uploaded_size: u64,
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: spawned_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wells_buffer.as_entire_binding(),
+            },
+        ];
+        if let Some(constants_buffer) = constants_buffer {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: constants_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(uploaded_size),
+                }),
+            });
+        }
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sim_params"),
+            layout,
+            entries: &entries,
+        })
+    }
+
+    /// Resets the count of respawned instances before the first step of a frame.
+    pub fn clear_spawned(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.clear_buffer(&self.spawned_buffer, 0, None);
+    }
+
+    /// Uploads the parameters after they changed, the next step reads them.
+    pub fn write(&self, queue: &wgpu::Queue, params: &SimParams) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(params));
+    }
+
+    /// Uploads the gravity wells after one was dropped or they were cleared,
+    /// only the first [`GravityWells::MAX_WELLS`] of `wells` are kept.
+    pub fn write_wells(&self, queue: &wgpu::Queue, wells: &[GravityWell]) {
+        let wells = &wells[..wells.len().min(GravityWells::MAX_WELLS)];
+        let mut contents = vec![0; (Self::WELLS_OFFSET as usize) * (1 + wells.len())];
+        contents[..4].copy_from_slice(&(wells.len() as u32).to_ne_bytes());
+        contents[Self::WELLS_OFFSET as usize..].copy_from_slice(bytemuck::cast_slice(wells));
+        queue.write_buffer(&self.wells_buffer, 0, &contents);
+    }
+
+    /// Sets the constants of the steps recorded next, one per step. The
+    /// pushed bytes are kept for [`Self::bind`] and the rest uploaded,
+    /// growing the buffer when a frame runs more steps than it has room for.
+    pub fn write_constants(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, constants: &[ComputePushConstants]) {
+        self.pushed.clear();
+        if self.pushed_size > 0 {
+            self.pushed.extend_from_slice(constants);
+        }
+        let Some(constants_buffer) = &self.constants_buffer else {
+            return;
+        };
+
+        let size = constants.len() as u64 * self.constants_stride;
+        if constants_buffer.size() < size {
+            let constants_buffer = Self::create_constants_buffer(device, size.next_power_of_two());
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.buffer,
+                &self.spawned_buffer,
+                &self.wells_buffer,
+                Some(&constants_buffer),
+                self.uploaded_size(),
+            );
+            self.constants_buffer = Some(constants_buffer);
+        }
+
+        let mut contents = vec![0; size as usize];
+        for (constants, bytes) in constants.iter().zip(contents.chunks_mut(self.constants_stride as usize)) {
+            let uploaded = &bytemuck::bytes_of(constants)[self.pushed_size as usize..];
+            bytes[..uploaded.len()].copy_from_slice(uploaded);
+        }
+        if let Some(constants_buffer) = &self.constants_buffer {
+            queue.write_buffer(constants_buffer, 0, &contents);
+        }
+    }
+
+    /// Binds the parameters with the constants of `step`, after the
+    /// pipeline they're pushed to is set.
+    pub fn bind(&self, compute_pass: &mut wgpu::ComputePass, step: usize) {
+        match &self.constants_buffer {
+            Some(_) => {
+                compute_pass.set_bind_group(3, &self.bind_group, &[(step as u64 * self.constants_stride) as u32]);
+            }
+            None => compute_pass.set_bind_group(3, &self.bind_group, &[]),
+        }
+        if let Some(constants) = self.pushed.get(step) {
+            compute_pass.set_push_constants(0, &bytemuck::bytes_of(constants)[..self.pushed_size as usize]);
+        }
+    }
+
+    fn uploaded_size(&self) -> u64 {
+        (ComputePushConstants::SIZE - self.pushed_size) as u64
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+}
@@ -0,0 +1,225 @@
+use std::sync::mpsc;
+
+use super::{
+    InstanceRepr,
+    camera::DepthOrder,
+    debug_labels,
+    instances::{InstanceBuffers, InstanceTransform},
+    mesh::{DefaultVertex3d, Instance, Mesh, Vertex},
+    offsets::ChunkOffsets,
+    pool::BufferPool,
+    shader::ShaderLoader,
+    texture::Texture2d,
+};
+
+/// Finds the instance under a pixel. On request the instances are drawn
+/// into an integer target holding the index of the instance covering each
+/// pixel, and the pixel asked for is read back. Streamed points and
+/// instance groups aren't drawn, so they can't be picked.
+pub struct Picker {
+    pipeline: wgpu::RenderPipeline,
+    ids: wgpu::Texture,
+    depth_texture: Texture2d,
+    /// The way the camera's projection runs depth.
+    depth_order: DepthOrder,
+}
+
+impl Picker {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+    const ID_SIZE: u64 = std::mem::size_of::<u32>() as u64;
+
+    /// `layouts` are the frame, camera, scene and chunk offset layouts of the
+    /// default pipeline.
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLoader,
+        layouts: &[&wgpu::BindGroupLayout],
+        size: (u32, u32),
+        depth_order: DepthOrder,
+    ) -> Self {
+        let module = shaders.module(device, "pick.wgsl", include_str!("../shaders/pick.wgsl"));
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("pick_pipeline_layout"),
+            bind_group_layouts: layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("pick_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[DefaultVertex3d::desc(), InstanceRepr::desc(), InstanceTransform::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Some("fs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: Self::FORMAT,
+                    write_mask: wgpu::ColorWrites::ALL,
+                    blend: None,
+                })],
+            }),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth_order.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multiview: None,
+            cache: None,
+        });
+
+        let (ids, depth_texture) = Self::create_targets(device, size);
+
+        Self {
+            pipeline,
+            ids,
+            depth_texture,
+            depth_order,
+        }
+    }
+
+    fn create_targets(device: &wgpu::Device, size: (u32, u32)) -> (wgpu::Texture, Texture2d) {
+        let ids = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("pick_ids"),
+            size: wgpu::Extent3d {
+                width: size.0.max(1),
+                height: size.1.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let depth_texture = Texture2d::create_sized_depth_texture(device, size, 1, Some("pick_depth"));
+        (ids, depth_texture)
+    }
+
+    /// Draws the instance ids at `size`, the size of the window, and returns
+    /// the instance covering `pixel`. `bind_groups` are bound in order before
+    /// `chunk_offsets`, with their dynamic offsets. Blocks until the id is
+    /// read back.
+    #[allow(clippy::too_many_arguments)]
+    pub fn pick(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pool: &mut BufferPool,
+        bind_groups: &[(&wgpu::BindGroup, &[u32])],
+        chunk_offsets: &ChunkOffsets,
+        mesh: &Mesh,
+        instances: &InstanceBuffers,
+        size: (u32, u32),
+        pixel: (u32, u32),
+    ) -> Option<u32> {
+        if pixel.0 >= size.0 || pixel.1 >= size.1 {
+            return None;
+        }
+        if (self.ids.width(), self.ids.height()) != size {
+            (self.ids, self.depth_texture) = Self::create_targets(device, size);
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("pick"),
+        });
+        {
+            let view = self.ids.create_view(&wgpu::TextureViewDescriptor::default());
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("pick_pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.depth_order.far()),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            debug_labels::marker(&mut render_pass, || format!("pick pixel {}, {}", pixel.0, pixel.1));
+
+            render_pass.set_pipeline(&self.pipeline);
+            for (index, (bind_group, offsets)) in bind_groups.iter().enumerate() {
+                render_pass.set_bind_group(index as u32, *bind_group, offsets);
+            }
+            for (index, chunk) in instances.chunks.iter().enumerate() {
+                chunk_offsets.bind(&mut render_pass, index);
+                render_pass.set_vertex_buffer(2, chunk.transforms.slice());
+                mesh.draw_instanced(&mut render_pass, chunk.positions_vsh.buffer(), 0..chunk.range.len() as u32);
+            }
+        }
+
+        let staging_buffer = pool.acquire(
+            device,
+            "pick_staging",
+            Self::ID_SIZE,
+            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        );
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.ids,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: pixel.0,
+                    y: pixel.1,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &staging_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging_buffer.slice(..Self::ID_SIZE);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| _ = sender.send(result));
+        device.poll(wgpu::Maintain::Wait);
+        let mapped = receiver.try_recv().map_err(|error| error.to_string());
+        if let Err(error) = mapped.and_then(|result| result.map_err(|error| error.to_string())) {
+            log::error!("Failed to map the picked id: {error}");
+            pool.release(queue, staging_buffer);
+            return None;
+        }
+        let id: u32 = *bytemuck::from_bytes(&slice.get_mapped_range());
+        staging_buffer.unmap();
+        pool.release(queue, staging_buffer);
+
+        id.checked_sub(1)
+    }
+}
@@ -0,0 +1,383 @@
+use std::{fs::File, path::Path};
+
+use super::{
+    culling::Aabb,
+    instances::InstanceColor,
+    shader::ShaderFeatures,
+    stream::{DatasetError, DatasetResult, PointDataset},
+    voxel,
+};
+
+/// Imported points and, when the file has them, their colors.
+type Points = (Vec<[f32; 4]>, Option<Vec<InstanceColor>>);
+
+/// Loads a point dataset, picking the importer by file extension. Files
+/// without a known extension are mapped as raw `[f32; 4]` records.
+///
+/// `merge_voxels` merges runs of voxels in `.vox` models into single instances.
+pub fn open(path: &Path, merge_voxels: bool) -> DatasetResult<PointDataset> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    let ((mut points, colors), features) = match extension.as_deref() {
+        Some("ply") => read_ply(&map(path)?)?,
+        Some("las") => read_las(&map(path)?)?,
+        Some("vox") => {
+            let (points, colors) = voxel::read_vox(&map(path)?, merge_voxels)?;
+            ((points, Some(colors)), ShaderFeatures::POINT_COLOR | ShaderFeatures::VOXEL_RUNS)
+        }
+        Some("laz") => {
+            return Err(DatasetError::new(format!(
+                "{} is LAZ compressed, decompress it to LAS first (e.g. with laszip)",
+                path.display()
+            )));
+        }
+        _ => return PointDataset::open(path),
+    };
+
+    recenter(&mut points);
+    log::info!("Imported {} points from {}.", points.len(), path.display());
+
+    Ok(PointDataset::from_points(points, colors).with_features(features))
+}
+
+fn map(path: &Path) -> DatasetResult<memmap2::Mmap> {
+    let error = |e: std::io::Error| DatasetError::new(format!("Failed to map {}: {e}", path.display()));
+    let file = File::open(path).map_err(error)?;
+    // Safety: only read while importing
+    unsafe { memmap2::Mmap::map(&file) }.map_err(error)
+}
+
+/// Scans are usually in georeferenced coordinates far from the origin, where
+/// f32 positions lose most of their precision.
+fn recenter(points: &mut [[f32; 4]]) {
+    let mut bounds = Aabb::empty();
+    points.iter().for_each(|p| bounds.extend(*p));
+    let center = bounds.center();
+    if !center.x.is_finite() {
+        return;
+    }
+
+    log::debug!("Moving points centered at {center:?} to the origin.");
+    for p in points {
+        p[0] -= center.x;
+        p[1] -= center.y;
+        p[2] -= center.z;
+    }
+}
+
+fn color_features(colors: &Option<Vec<InstanceColor>>) -> ShaderFeatures {
+    if colors.is_some() { ShaderFeatures::POINT_COLOR } else { ShaderFeatures::NONE }
+}
+
+fn invalid(message: impl Into<String>) -> DatasetError {
+    DatasetError::new(message.into())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> DatasetResult<Self> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return Err(invalid(format!("Unknown PLY property type: {name}"))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    fn read(self, bytes: &[u8], format: PlyFormat) -> f64 {
+        macro_rules! read {
+            ($ty:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                (if format == PlyFormat::BinaryBigEndian {
+                    <$ty>::from_be_bytes(bytes)
+                } else {
+                    <$ty>::from_le_bytes(bytes)
+                }) as f64
+            }};
+        }
+
+        match self {
+            Self::I8 => read!(i8),
+            Self::U8 => read!(u8),
+            Self::I16 => read!(i16),
+            Self::U16 => read!(u16),
+            Self::I32 => read!(i32),
+            Self::U32 => read!(u32),
+            Self::F32 => read!(f32),
+            Self::F64 => read!(f64),
+        }
+    }
+
+    /// Maps a color channel to 0..=255, integer channels are taken as 8 bit
+    /// unless they are wider.
+    fn color(self, value: f64) -> u8 {
+        match self {
+            Self::F32 | Self::F64 => (value * 255.0).clamp(0.0, 255.0) as u8,
+            Self::U16 | Self::I16 => (value / 257.0).clamp(0.0, 255.0) as u8,
+            _ => value.clamp(0.0, 255.0) as u8,
+        }
+    }
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    /// `None` for list properties, which only non-vertex elements may have.
+    properties: Vec<(String, Option<PlyType>)>,
+}
+
+/// Reads the `vertex` element of an ASCII or binary PLY file, with optional
+/// `red`, `green`, `blue` and `alpha` properties.
+fn read_ply(data: &[u8]) -> DatasetResult<(Points, ShaderFeatures)> {
+    const END_HEADER: &[u8] = b"end_header";
+
+    let header_end = data
+        .windows(END_HEADER.len())
+        .position(|w| w == END_HEADER)
+        .ok_or_else(|| invalid("PLY header is not terminated"))?;
+    let body_start = data[header_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map(|i| header_end + i + 1)
+        .ok_or_else(|| invalid("PLY header is not terminated"))?;
+    let header = std::str::from_utf8(&data[..header_end]).map_err(|_| invalid("PLY header is not text"))?;
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err(invalid("Missing PLY signature"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _] => {
+                format = Some(match *name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(invalid(format!("Unknown PLY format: {name}"))),
+                });
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid(format!("Invalid PLY element count: {count}")))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", .., name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("PLY property outside of an element"))?
+                .properties
+                .push((name.to_string(), None)),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("PLY property outside of an element"))?
+                .properties
+                .push((name.to_string(), Some(PlyType::parse(ty)?))),
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| invalid("PLY format is missing"))?;
+
+    let vertex_index = elements
+        .iter()
+        .position(|e| e.name == "vertex")
+        .ok_or_else(|| invalid("PLY file has no vertex element"))?;
+    let vertex = &elements[vertex_index];
+    if vertex.properties.iter().any(|(_, ty)| ty.is_none()) {
+        return Err(invalid("PLY vertex element has list properties"));
+    }
+
+    let property = |name: &str| vertex.properties.iter().position(|(n, _)| n == name);
+    let position = [property("x"), property("y"), property("z")];
+    let [Some(x), Some(y), Some(z)] = position else {
+        return Err(invalid("PLY vertices lack x, y or z"));
+    };
+    let color = [property("red"), property("green"), property("blue")];
+    let has_color = color.iter().all(Option::is_some);
+    let alpha = property("alpha");
+
+    let body = &data[body_start..];
+    let types: Vec<PlyType> = vertex.properties.iter().map(|(_, ty)| ty.unwrap()).collect();
+    // Every vertex takes at least a byte, a bogus count can't reserve more
+    let capacity = vertex.count.min(body.len());
+    let mut points = Vec::with_capacity(capacity);
+    let mut colors = has_color.then(|| Vec::with_capacity(capacity));
+    let mut push = |values: &[f64]| {
+        points.push([values[x] as f32, values[y] as f32, values[z] as f32, 0.0]);
+        if let (Some(colors), [Some(r), Some(g), Some(b)]) = (&mut colors, color) {
+            colors.push(InstanceColor {
+                color: [
+                    types[r].color(values[r]),
+                    types[g].color(values[g]),
+                    types[b].color(values[b]),
+                    alpha.map_or(255, |a| types[a].color(values[a])),
+                ],
+                material: 0,
+            });
+        }
+    };
+
+    let mut values = vec![0.0; types.len()];
+    if format == PlyFormat::Ascii {
+        // Elements are written in header order, one per line
+        let skip: usize = elements[..vertex_index].iter().map(|e| e.count).sum();
+        let text = std::str::from_utf8(body).map_err(|_| invalid("PLY body is not text"))?;
+        for line in text.lines().filter(|l| !l.trim().is_empty()).skip(skip).take(vertex.count) {
+            for (value, word) in values.iter_mut().zip(line.split_whitespace()) {
+                *value = word.parse().map_err(|_| invalid(format!("Invalid PLY value: {word}")))?;
+            }
+            push(&values);
+        }
+    } else {
+        let truncated = || invalid("PLY file is truncated");
+        let mut offset: usize = 0;
+        for element in &elements[..vertex_index] {
+            let size: Option<usize> = element.properties.iter().map(|(_, ty)| ty.map(PlyType::size)).sum();
+            let size = size.ok_or_else(|| invalid("Can't skip PLY list elements before the vertices"))?;
+            // Counts come straight from the header
+            offset = size
+                .checked_mul(element.count)
+                .and_then(|size| offset.checked_add(size))
+                .ok_or_else(truncated)?;
+        }
+
+        let stride: usize = types.iter().map(|ty| ty.size()).sum();
+        let end = stride
+            .checked_mul(vertex.count)
+            .and_then(|size| offset.checked_add(size))
+            .filter(|&end| end <= body.len())
+            .ok_or_else(truncated)?;
+        for record in body[offset..end].chunks_exact(stride) {
+            let mut at = 0;
+            for (value, ty) in values.iter_mut().zip(&types) {
+                *value = ty.read(&record[at..at + ty.size()], format);
+                at += ty.size();
+            }
+            push(&values);
+        }
+    }
+
+    if points.len() < vertex.count {
+        return Err(invalid("PLY file is truncated"));
+    }
+    let features = color_features(&colors);
+    Ok(((points, colors), features))
+}
+
+/// Reads uncompressed LAS 1.0 to 1.4 files, taking RGB from point formats that carry it.
+fn read_las(data: &[u8]) -> DatasetResult<(Points, ShaderFeatures)> {
+    const HEADER_SIZE: usize = 227;
+
+    if data.len() < HEADER_SIZE || &data[0..4] != b"LASF" {
+        return Err(invalid("Missing LAS signature"));
+    }
+
+    let u16_at = |at: usize| u16::from_le_bytes(data[at..at + 2].try_into().unwrap());
+    let u32_at = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+    let f64_at = |at: usize| f64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+
+    let version = (data[24], data[25]);
+    let point_offset = u32_at(96) as usize;
+    let format_byte = data[104];
+    if format_byte & 0xc0 != 0 {
+        return Err(invalid("LAS points are LAZ compressed"));
+    }
+    let point_format = format_byte & 0x3f;
+    let record_len = u16_at(105) as usize;
+    let mut count = u32_at(107) as u64;
+    if count == 0 && version >= (1, 4) && data.len() >= 255 {
+        count = u64::from_le_bytes(data[247..255].try_into().unwrap());
+    }
+    let scale = [f64_at(131), f64_at(139), f64_at(147)];
+    let offset = [f64_at(155), f64_at(163), f64_at(171)];
+    // Centered in f64 before narrowing, georeferenced coordinates don't fit f32
+    let center = [
+        (f64_at(179) + f64_at(187)) * 0.5,
+        (f64_at(195) + f64_at(203)) * 0.5,
+        (f64_at(211) + f64_at(219)) * 0.5,
+    ];
+
+    let color_offset = match point_format {
+        2 => Some(20),
+        3 | 5 => Some(28),
+        7 | 8 | 10 => Some(30),
+        0..=10 => None,
+        _ => return Err(invalid(format!("Unsupported LAS point format {point_format}"))),
+    };
+    log::debug!(
+        "LAS {}.{} with {count} points of format {point_format}.",
+        version.0,
+        version.1
+    );
+
+    // The offset and count come straight from the header
+    let end = usize::try_from(count)
+        .ok()
+        .and_then(|count| record_len.checked_mul(count))
+        .and_then(|size| point_offset.checked_add(size))
+        .filter(|&end| end <= data.len());
+    let Some(end) = end.filter(|_| record_len >= 12) else {
+        return Err(invalid("LAS file is truncated"));
+    };
+    let records = data[point_offset..end].chunks_exact(record_len);
+
+    let points = records
+        .clone()
+        .map(|record| {
+            let coordinate = |i: usize| {
+                let raw = i32::from_le_bytes(record[i * 4..i * 4 + 4].try_into().unwrap());
+                (raw as f64 * scale[i] + offset[i] - center[i]) as f32
+            };
+            [coordinate(0), coordinate(1), coordinate(2), 0.0]
+        })
+        .collect();
+    // 16-bit channels, keep the high byte
+    let colors = color_offset.filter(|&at| at + 6 <= record_len).map(|at| {
+        records
+            .map(|record| InstanceColor {
+                color: [record[at + 1], record[at + 3], record[at + 5], 255],
+                material: 0,
+            })
+            .collect()
+    });
+
+    let features = color_features(&colors);
+    Ok(((points, colors), features))
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// Buffers are pooled by power of two size class, so requests of similar size share buffers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct PoolKey {
+    size_class: u64,
+    usage: wgpu::BufferUsages,
+}
+
+impl PoolKey {
+    fn of(buffer: &wgpu::Buffer) -> Self {
+        Self {
+            size_class: buffer.size(),
+            usage: buffer.usage(),
+        }
+    }
+}
+
+/// Released buffer the GPU may still be using.
+struct Retired {
+    key: PoolKey,
+    buffer: wgpu::Buffer,
+    done: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PoolStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Buffers ready for reuse.
+    pub free: usize,
+    /// Released buffers waiting for their submissions to finish.
+    pub retired: usize,
+}
+
+impl PoolStats {
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / (self.hits + self.misses).max(1) as f64
+    }
+}
+
+/// Recycles short-lived buffers (staging, readbacks, per-frame data) instead
+/// of creating and dropping them every frame.
+///
+/// A released buffer only becomes available again once all work submitted
+/// before its release has finished on the GPU.
+#[derive(Default)]
+pub struct BufferPool {
+    free: HashMap<PoolKey, Vec<wgpu::Buffer>>,
+    retired: Vec<Retired>,
+    hits: u64,
+    misses: u64,
+}
+
+impl BufferPool {
+    const MIN_SIZE_CLASS: u64 = 256;
+
+    /// Returns an unmapped buffer of at least `size` bytes. The label is only
+    /// applied when a new buffer has to be created.
+    pub fn acquire(
+        &mut self,
+        device: &wgpu::Device,
+        label: &str,
+        size: u64,
+        usage: wgpu::BufferUsages,
+    ) -> wgpu::Buffer {
+        let key = PoolKey {
+            size_class: size.next_power_of_two().max(Self::MIN_SIZE_CLASS),
+            usage,
+        };
+
+        if let Some(buffer) = self.free.get_mut(&key).and_then(Vec::pop) {
+            self.hits += 1;
+            return buffer;
+        }
+
+        self.misses += 1;
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: key.size_class,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Hands a buffer back once the caller has submitted all work using it.
+    /// The buffer must be unmapped.
+    pub fn release(&mut self, queue: &wgpu::Queue, buffer: wgpu::Buffer) {
+        let done = Arc::new(AtomicBool::new(false));
+        let signal = done.clone();
+        queue.on_submitted_work_done(move || signal.store(true, Ordering::Release));
+
+        self.retired.push(Retired {
+            key: PoolKey::of(&buffer),
+            buffer,
+            done,
+        });
+    }
+
+    /// Makes buffers whose submissions finished available again. Completion is
+    /// noticed during device polls, so call this after polling.
+    pub fn reclaim(&mut self) {
+        let (done, retired) = std::mem::take(&mut self.retired)
+            .into_iter()
+            .partition(|retired| retired.done.load(Ordering::Acquire));
+        self.retired = retired;
+
+        for Retired { key, buffer, .. } in done {
+            self.free.entry(key).or_default().push(buffer);
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.hits,
+            misses: self.misses,
+            free: self.free.values().map(Vec::len).sum(),
+            retired: self.retired.len(),
+        }
+    }
+}
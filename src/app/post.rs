@@ -0,0 +1,316 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{shader::ShaderLoader, texture::Texture2d};
+
+/// Pass over the resolved HDR scene ahead of tone mapping, which reads the
+/// scene and draws back onto it.
+pub trait PostEffect {
+    /// Shown in debug labels.
+    fn name(&self) -> &'static str;
+
+    /// Recreates whatever is sized by the scene after it was resized.
+    fn resize(&mut self, device: &wgpu::Device, scene: &Texture2d);
+
+    /// Records the passes of the effect, `scene` being the one it was
+    /// created or last resized with.
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, scene: &Texture2d);
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BloomSettings {
+    /// Brightness glowing starts at, above what's displayable by default.
+    pub threshold: f32,
+    /// Share of the glow added onto the scene.
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.1,
+        }
+    }
+}
+
+/// Mirrors `Params` in `bloom.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    _padding: f32,
+}
+
+impl From<&BloomSettings> for BloomUniform {
+    fn from(settings: &BloomSettings) -> Self {
+        Self {
+            threshold: settings.threshold,
+            knee: 0.5 * settings.threshold,
+            intensity: settings.intensity,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Halved copies of the scene, each with the bind group reading it.
+struct BloomLevel {
+    texture: Texture2d,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Makes whatever is brighter than the threshold, like emissive materials
+/// and highlights, glow. The bright parts of the scene are blurred over a
+/// chain of ever smaller levels and back up, then added onto the scene.
+pub struct Bloom {
+    pub settings: BloomSettings,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    scene_bind_group: wgpu::BindGroup,
+    levels: Vec<BloomLevel>,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl Bloom {
+    /// Levels below the scene at most, the smallest blurs over about 1/64th
+    /// of it.
+    const MAX_LEVELS: u32 = 6;
+
+    pub fn new(device: &wgpu::Device, shaders: &ShaderLoader, settings: BloomSettings, scene: &Texture2d) -> Self {
+        let module = shaders.module(device, "bloom.wgsl", include_str!("../shaders/bloom.wgsl"));
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("bloom_params"),
+            contents: bytemuck::bytes_of(&BloomUniform::from(&settings)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("bloom_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let pipeline = |label, entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: scene.texture.format(),
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                depth_stencil: None,
+                multiview: None,
+                cache: None,
+            })
+        };
+        let blend = Some(wgpu::BlendState {
+            color: additive,
+            alpha: additive,
+        });
+        let prefilter_pipeline = pipeline("bloom_prefilter_pipeline", "prefilter", None);
+        let downsample_pipeline = pipeline("bloom_downsample_pipeline", "downsample_main", None);
+        let upsample_pipeline = pipeline("bloom_upsample_pipeline", "upsample_main", blend);
+        let composite_pipeline = pipeline("bloom_composite_pipeline", "composite", blend);
+
+        let scene_bind_group = Self::create_bind_group(device, &layout, &uniform_buffer, scene);
+        let levels = Self::create_levels(device, &layout, &uniform_buffer, scene);
+
+        Self {
+            settings,
+            layout,
+            uniform_buffer,
+            scene_bind_group,
+            levels,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        source: &Texture2d,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bloom"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&source.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&source.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Halves the scene until [`Self::MAX_LEVELS`] or a level a texel high.
+    fn create_levels(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        scene: &Texture2d,
+    ) -> Vec<BloomLevel> {
+        let (mut width, mut height) = (scene.size.width, scene.size.height);
+        let mut levels = Vec::new();
+        while levels.len() < Self::MAX_LEVELS as usize && width.min(height) > 1 {
+            (width, height) = (width.div_ceil(2), height.div_ceil(2));
+            let texture = Texture2d::create_render_target(device, (width, height), scene.texture.format(), Some("bloom_level"));
+            let bind_group = Self::create_bind_group(device, layout, uniform_buffer, &texture);
+            levels.push(BloomLevel { texture, bind_group });
+        }
+        levels
+    }
+
+    /// Uploads the settings after they changed.
+    pub fn write(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&BloomUniform::from(&self.settings)));
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &'static str {
+        "bloom"
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, scene: &Texture2d) {
+        self.scene_bind_group = Self::create_bind_group(device, &self.layout, &self.uniform_buffer, scene);
+        self.levels = Self::create_levels(device, &self.layout, &self.uniform_buffer, scene);
+    }
+
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, scene: &Texture2d) {
+        let Some(first) = self.levels.first() else {
+            return;
+        };
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        Self::fullscreen_pass(
+            encoder,
+            "bloom_prefilter_pass",
+            &first.texture.view,
+            clear,
+            &self.prefilter_pipeline,
+            &self.scene_bind_group,
+        );
+        for pair in self.levels.windows(2) {
+            Self::fullscreen_pass(
+                encoder,
+                "bloom_downsample_pass",
+                &pair[1].texture.view,
+                clear,
+                &self.downsample_pipeline,
+                &pair[0].bind_group,
+            );
+        }
+        for pair in self.levels.windows(2).rev() {
+            Self::fullscreen_pass(
+                encoder,
+                "bloom_upsample_pass",
+                &pair[0].texture.view,
+                wgpu::LoadOp::Load,
+                &self.upsample_pipeline,
+                &pair[1].bind_group,
+            );
+        }
+        Self::fullscreen_pass(
+            encoder,
+            "bloom_composite_pass",
+            &scene.view,
+            wgpu::LoadOp::Load,
+            &self.composite_pipeline,
+            &first.bind_group,
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// Pass timed by the [`GpuProfiler`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfiledPass {
+    /// Every simulation step of a frame, in one compute pass.
+    Simulation,
+    Render,
+}
+
+impl ProfiledPass {
+    pub const ALL: [Self; 2] = [Self::Simulation, Self::Render];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Simulation => "simulation",
+            Self::Render => "render",
+        }
+    }
+
+    /// Index of the query written at the beginning of the pass, the one at
+    /// its end follows it.
+    fn query(self) -> u32 {
+        self as u32 * 2
+    }
+}
+
+/// Times passes with timestamp queries written at their beginning and end.
+/// The queries are resolved at the end of a frame and read back
+/// asynchronously like the simulation statistics, skipping frames while a
+/// readback is pending. Times are averaged over the frames up to each report.
+pub struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick.
+    period: f64,
+    /// Passes that wrote their timestamps in the frame being recorded.
+    written: [bool; ProfiledPass::ALL.len()],
+    /// Passes the pending readback has timestamps of.
+    pending: Option<[bool; ProfiledPass::ALL.len()]>,
+    /// Set by the map callback once the readback buffer can be read.
+    mapped: Arc<AtomicBool>,
+    /// Milliseconds measured per pass since the last report and the number
+    /// of measurements.
+    totals: [(f64, u32); ProfiledPass::ALL.len()],
+}
+
+impl GpuProfiler {
+    pub const FEATURES: wgpu::Features = wgpu::Features::TIMESTAMP_QUERY;
+    const QUERY_COUNT: u32 = ProfiledPass::ALL.len() as u32 * 2;
+    const BUFFER_SIZE: u64 = Self::QUERY_COUNT as u64 * wgpu::QUERY_SIZE as u64;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let buffer = |label, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: Self::BUFFER_SIZE,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("pass_timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: Self::QUERY_COUNT,
+            }),
+            resolve_buffer: buffer(
+                "pass_timestamps_resolve",
+                wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            ),
+            readback_buffer: buffer(
+                "pass_timestamps_readback",
+                wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            ),
+            period: queue.get_timestamp_period() as f64,
+            written: [false; ProfiledPass::ALL.len()],
+            pending: None,
+            mapped: Arc::new(AtomicBool::new(false)),
+            totals: [(0.0, 0); ProfiledPass::ALL.len()],
+        }
+    }
+
+    pub fn compute_timestamp_writes(&mut self, pass: ProfiledPass) -> wgpu::ComputePassTimestampWrites<'_> {
+        self.written[pass as usize] = true;
+        wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(pass.query()),
+            end_of_pass_write_index: Some(pass.query() + 1),
+        }
+    }
+
+    pub fn render_timestamp_writes(&mut self, pass: ProfiledPass) -> wgpu::RenderPassTimestampWrites<'_> {
+        self.written[pass as usize] = true;
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(pass.query()),
+            end_of_pass_write_index: Some(pass.query() + 1),
+        }
+    }
+
+    /// Records the resolve and the copy of the timestamps written this frame,
+    /// unless the previous ones haven't been read yet. Returns whether
+    /// anything was recorded, in which case [`Self::submitted`] has to follow
+    /// the submission.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) -> bool {
+        let written = std::mem::take(&mut self.written);
+        if self.pending.is_some() || !written.contains(&true) {
+            return false;
+        }
+
+        encoder.resolve_query_set(&self.query_set, 0..Self::QUERY_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, Self::BUFFER_SIZE);
+        self.pending = Some(written);
+        true
+    }
+
+    /// Starts mapping the timestamps once the recorded resolve was submitted.
+    pub fn submitted(&self) {
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| match result {
+                Ok(()) => mapped.store(true, Ordering::Release),
+                Err(error) => log::error!("Failed to map the pass timestamps: {error}"),
+            });
+    }
+
+    /// Adds the pass times of the finished readback to the totals.
+    /// Completion is noticed during device polls, so call this after polling.
+    pub fn take(&mut self) {
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return;
+        }
+        let Some(written) = self.pending.take() else {
+            return;
+        };
+
+        let timestamps: Vec<u64> = bytemuck::pod_collect_to_vec(&self.readback_buffer.slice(..).get_mapped_range());
+        self.readback_buffer.unmap();
+        for pass in ProfiledPass::ALL.into_iter().filter(|&pass| written[pass as usize]) {
+            let query = pass.query() as usize;
+            let ticks = timestamps[query + 1].saturating_sub(timestamps[query]);
+            let total = &mut self.totals[pass as usize];
+            total.0 += ticks as f64 * self.period / 1.0e6;
+            total.1 += 1;
+        }
+    }
+
+    /// Average milliseconds per measured pass since the last report, `None`
+    /// without measurements.
+    pub fn report(&mut self) -> Option<String> {
+        let totals = std::mem::take(&mut self.totals);
+        let times: Vec<String> = ProfiledPass::ALL
+            .into_iter()
+            .zip(totals)
+            .filter(|(_, (_, count))| *count > 0)
+            .map(|(pass, (total, count))| format!("{} {:.3} ms", pass.name(), total / count as f64))
+            .collect();
+
+        (!times.is_empty()).then(|| times.join(", "))
+    }
+}
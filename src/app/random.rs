@@ -0,0 +1,32 @@
+use std::sync::{
+    OnceLock,
+    atomic::{AtomicU64, Ordering},
+};
+
+use rand::{SeedableRng, rngs::StdRng};
+
+/// Seed of every generator handed out by [`rng`], see `--seed`.
+static SEED: OnceLock<u64> = OnceLock::new();
+/// Generators handed out so far, each gets a stream of its own.
+static STREAMS: AtomicU64 = AtomicU64::new(0);
+
+/// Makes the scenes generated from here on deterministic. Only the first
+/// seed counts.
+pub fn seed(seed: u64) {
+    if SEED.set(seed).is_err() {
+        log::warn!("Random numbers are seeded already, ignoring seed {seed}.");
+    }
+}
+
+/// Generator for one scene setup step, like the positions of a demo. Once
+/// seeded, every generator is derived from the seed and the number of them
+/// handed out before it, so the same startup generates the same scene.
+/// Unseeded ones come from the thread's generator.
+pub fn rng() -> StdRng {
+    let Some(&seed) = SEED.get() else {
+        return StdRng::from_rng(&mut rand::rng());
+    };
+    // Spreads the streams over the seeds, the golden ratio in 64 bits
+    let stream = STREAMS.fetch_add(1, Ordering::Relaxed).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    StdRng::seed_from_u64(seed ^ stream)
+}
@@ -0,0 +1,251 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::{culling::Aabb, debug_labels, frames::FrameRing, instances::InstanceBuffers, shader::{self, ShaderLoader}};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct RayUniform {
+    origin: [f32; 4],
+    direction: [f32; 4],
+    count: u32,
+    first: u32,
+    _padding: [u32; 2],
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    pub instance: u32,
+    pub distance: f32,
+}
+
+/// CPU counterpart of `raycast.wgsl`, for devices without compute shaders.
+pub fn cast_cpu(positions: &[[f32; 4]], origin: Point3<f32>, direction: Vector3<f32>) -> Option<Hit> {
+    const HALF_EXTENT: f32 = 0.5;
+
+    let direction = direction.normalize();
+    let inv_direction = Vector3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+
+    positions
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| {
+            let bounds = Aabb {
+                min: Point3::new(p[0] - HALF_EXTENT, p[1] - HALF_EXTENT, p[2] - HALF_EXTENT),
+                max: Point3::new(p[0] + HALF_EXTENT, p[1] + HALF_EXTENT, p[2] + HALF_EXTENT),
+            };
+            bounds
+                .intersect_ray(origin, inv_direction)
+                .filter(|&t| t <= Raycaster::MAX_DISTANCE)
+                .map(|distance| Hit {
+                    instance: i as u32,
+                    distance,
+                })
+        })
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+/// GPU ray test against every instance cube, with a blocking readback of the closest hit.
+pub struct Raycaster {
+    distance_pipeline: wgpu::ComputePipeline,
+    index_pipeline: wgpu::ComputePipeline,
+    /// One per instance chunk and side of the state.
+    bind_groups: Vec<[wgpu::BindGroup; 2]>,
+    ray_buffer: wgpu::Buffer,
+    result_buffer: wgpu::Buffer,
+}
+
+impl Raycaster {
+    const WORKGROUP_SIZE: u32 = 256;
+    const MAX_DISTANCE: f32 = 100000.0;
+    const RESULT_SIZE: u64 = 2 * std::mem::size_of::<u32>() as u64;
+    const READBACK: &str = "raycast";
+
+    pub fn new(device: &wgpu::Device, instances: &InstanceBuffers, shaders: &ShaderLoader) -> Self {
+        let module = shaders.module(device, "raycast.wgsl", include_str!("../shaders/raycast.wgsl"));
+
+        let ray_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("raycast_ray"),
+            size: std::mem::size_of::<RayUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let result_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("raycast_result"),
+            contents: bytemuck::cast_slice(&[0u32; 2]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("raycast"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_groups = instances
+            .chunks
+            .iter()
+            .map(|chunk| {
+                chunk.positions.each_ref().map(|positions| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("raycast"),
+                        layout: &bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: positions.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: ray_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: result_buffer.as_entire_binding(),
+                            },
+                        ],
+                    })
+                })
+            })
+            .collect();
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("raycast_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let constants = shader::override_constants([("WORKGROUP_SIZE", Self::WORKGROUP_SIZE as f64)]);
+        let create_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                },
+                cache: None,
+            })
+        };
+
+        Self {
+            distance_pipeline: create_pipeline("closest_distance"),
+            index_pipeline: create_pipeline("closest_index"),
+            bind_groups,
+            ray_buffer,
+            result_buffer,
+        }
+    }
+
+    /// Casts a ray against every instance and blocks until the result is read back.
+    pub fn cast(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frames: &mut FrameRing,
+        instances: &InstanceBuffers,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+    ) -> Option<Hit> {
+        let direction = direction.normalize();
+        queue.write_buffer(
+            &self.result_buffer,
+            0,
+            bytemuck::cast_slice(&[f32::INFINITY.to_bits(), u32::MAX]),
+        );
+
+        // The closest distance over all chunks must be known before any chunk
+        // looks for its index. Ray writes land before the following submission.
+        for pipeline in [&self.distance_pipeline, &self.index_pipeline] {
+            for (chunk, bind_groups) in instances.chunks.iter().zip(&self.bind_groups) {
+                let count = chunk.range.len() as u32;
+                queue.write_buffer(
+                    &self.ray_buffer,
+                    0,
+                    bytemuck::bytes_of(&RayUniform {
+                        origin: [origin.x, origin.y, origin.z, 1.0],
+                        direction: [direction.x, direction.y, direction.z, Self::MAX_DISTANCE],
+                        count,
+                        first: chunk.range.start,
+                        _padding: [0; 2],
+                    }),
+                );
+
+                let groups = count.div_ceil(Self::WORKGROUP_SIZE);
+                let max_groups = device.limits().max_compute_workgroups_per_dimension;
+                let dispatch = (groups.min(max_groups), groups.div_ceil(max_groups));
+
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("raycast"),
+                });
+                {
+                    let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                        label: Some("raycast_pass"),
+                        timestamp_writes: None,
+                    });
+                    debug_labels::marker(&mut compute_pass, || {
+                        format!("ray against {count} instances from {}", chunk.range.start)
+                    });
+                    compute_pass.set_bind_group(0, &bind_groups[instances.front()], &[]);
+                    compute_pass.set_pipeline(pipeline);
+                    compute_pass.dispatch_workgroups(dispatch.0, dispatch.1, 1);
+                }
+                queue.submit(std::iter::once(encoder.finish()));
+            }
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("raycast_readback"),
+        });
+        // Every cast takes its result before returning, so the buffer is free
+        // unless the last mapping never finished
+        let Some(staging_buffer) = frames.readback(device, Self::READBACK, Self::RESULT_SIZE) else {
+            log::error!("The raycast readback is still in use.");
+            return None;
+        };
+        encoder.copy_buffer_to_buffer(&self.result_buffer, 0, staging_buffer, 0, Self::RESULT_SIZE);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        frames.map_readbacks();
+        device.poll(wgpu::Maintain::Wait);
+        // A failed mapping was logged, nothing was hit as far as anyone can
+        // tell and the next cast tries again
+        let result: [u32; 2] = frames.take_readback(Self::READBACK)?;
+
+        (result[1] != u32::MAX).then(|| Hit {
+            instance: result[1],
+            distance: f32::from_bits(result[0]),
+        })
+    }
+}
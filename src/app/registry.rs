@@ -0,0 +1,425 @@
+use super::{
+    App,
+    console::{ConsoleError, ConsoleResult},
+    params::SimParams,
+    post::Bloom,
+};
+
+/// A value the console reads with `get` and writes with `set`, see
+/// [`PARAMETERS`].
+pub struct Parameter {
+    pub name: &'static str,
+    /// `None` while the feature holding the value is off.
+    pub get: fn(&App) -> Option<f32>,
+    /// Clamps the value to its valid range and applies it.
+    pub set: fn(&mut App, f32) -> ConsoleResult<()>,
+}
+
+/// Every parameter of the console, in the order `params` lists them.
+pub const PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "draw_distance",
+        get: |app| Some(app.scene.max_draw_distance),
+        set: |app, value| {
+            app.scene.set_draw_distance(value);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "fade_band",
+        get: |app| Some(app.scene.fade_band),
+        set: |app, value| {
+            app.scene.fade_band = value.clamp(0.0, app.scene.max_draw_distance);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "impostor_threshold",
+        get: |app| Some(app.scene.impostor_threshold),
+        set: |app, value| {
+            app.scene.impostor_threshold = value.max(0.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "light_x",
+        get: |app| Some(app.scene.light.direction.x),
+        set: |app, value| {
+            app.scene.light.direction.x = value;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "light_y",
+        get: |app| Some(app.scene.light.direction.y),
+        set: |app, value| {
+            app.scene.light.direction.y = value;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "light_z",
+        get: |app| Some(app.scene.light.direction.z),
+        set: |app, value| {
+            app.scene.light.direction.z = value;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "light_intensity",
+        get: |app| Some(app.scene.light.intensity),
+        set: |app, value| {
+            app.scene.light.intensity = value.max(0.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "ambient",
+        get: |app| Some(app.scene.light.ambient),
+        set: |app, value| {
+            app.scene.light.ambient = value.clamp(0.0, 1.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "fog_density",
+        get: |app| Some(app.scene.fog.density),
+        set: |app, value| {
+            app.scene.fog.density = value.max(0.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "fog_red",
+        get: |app| Some(app.scene.fog.color[0]),
+        set: |app, value| {
+            app.scene.fog.color[0] = value.clamp(0.0, 1.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "fog_green",
+        get: |app| Some(app.scene.fog.color[1]),
+        set: |app, value| {
+            app.scene.fog.color[1] = value.clamp(0.0, 1.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "fog_blue",
+        get: |app| Some(app.scene.fog.color[2]),
+        set: |app, value| {
+            app.scene.fog.color[2] = value.clamp(0.0, 1.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "exposure",
+        get: |app| Some(app.scene.exposure),
+        set: |app, value| {
+            app.scene.exposure = value.max(1.0e-3);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "gamma",
+        get: |app| Some(app.scene.gamma),
+        set: |app, value| {
+            app.scene.gamma = value.max(0.1);
+            if let Some(tone_mapping) = &app.tone_mapping {
+                tone_mapping.set_gamma(&app.queue, app.scene.gamma);
+            }
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "background_red",
+        get: |app| Some(app.scene.background[0]),
+        set: |app, value| {
+            app.scene.background[0] = value.clamp(0.0, 1.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "background_green",
+        get: |app| Some(app.scene.background[1]),
+        set: |app, value| {
+            app.scene.background[1] = value.clamp(0.0, 1.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "background_blue",
+        get: |app| Some(app.scene.background[2]),
+        set: |app, value| {
+            app.scene.background[2] = value.clamp(0.0, 1.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "shadow_distance",
+        get: |app| Some(app.shadow_maps.settings.distance),
+        // Split anew every frame
+        set: |app, value| {
+            app.shadow_maps.settings.distance = value.max(1.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "bloom_threshold",
+        get: |app| app.bloom.as_ref().map(|bloom| bloom.settings.threshold),
+        set: |app, value| {
+            let bloom = bloom(&mut app.bloom)?;
+            bloom.settings.threshold = value.max(0.0);
+            bloom.write(&app.queue);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "bloom_intensity",
+        get: |app| app.bloom.as_ref().map(|bloom| bloom.settings.intensity),
+        set: |app, value| {
+            let bloom = bloom(&mut app.bloom)?;
+            bloom.settings.intensity = value.max(0.0);
+            bloom.write(&app.queue);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "msaa",
+        get: |app| Some(app.sample_count as f32),
+        // Rounded down to the nearest count the adapter supports
+        set: |app, value| {
+            app.set_sample_count(value.clamp(1.0, 16.0) as u32)
+                .map_err(|e| ConsoleError::new(e.message))
+        },
+    },
+    Parameter {
+        name: "camera_speed",
+        get: |app| Some(app.camera_controller.speed),
+        set: |app, value| {
+            app.camera_controller.speed = value.max(0.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "gravity",
+        get: |app| Some(app.simulation.params.gravity),
+        set: |app, value| {
+            app.set_gravity(value);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "drag",
+        get: |app| Some(app.simulation.params.drag),
+        set: |app, value| {
+            app.set_drag(value);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "flock_radius",
+        get: |app| Some(app.simulation.params.flock_radius),
+        set: |app, value| sim_params(app, |params| params.flock_radius = value.max(1.0)),
+    },
+    Parameter {
+        name: "cruise_speed",
+        get: |app| Some(app.simulation.params.cruise_speed),
+        set: |app, value| sim_params(app, |params| params.cruise_speed = value),
+    },
+    Parameter {
+        name: "steering",
+        get: |app| Some(app.simulation.params.steering),
+        set: |app, value| sim_params(app, |params| params.steering = value.max(0.0)),
+    },
+    Parameter {
+        name: "wave_stiffness",
+        get: |app| Some(app.simulation.params.wave_stiffness),
+        set: |app, value| sim_params(app, |params| params.wave_stiffness = value.max(0.0)),
+    },
+    Parameter {
+        name: "softening",
+        get: |app| Some(app.simulation.params.softening),
+        set: |app, value| sim_params(app, |params| params.softening = value.max(0.0)),
+    },
+    Parameter {
+        name: "noise_scale",
+        get: |app| Some(app.simulation.params.noise_scale),
+        set: |app, value| sim_params(app, |params| params.noise_scale = value.max(0.0)),
+    },
+    Parameter {
+        name: "noise_speed",
+        get: |app| Some(app.simulation.params.noise_speed),
+        set: |app, value| sim_params(app, |params| params.noise_speed = value),
+    },
+    Parameter {
+        name: "sph_radius",
+        get: |app| Some(app.simulation.params.sph_radius),
+        set: |app, value| sim_params(app, |params| params.sph_radius = value.max(1.0)),
+    },
+    Parameter {
+        name: "sph_density",
+        get: |app| Some(app.simulation.params.sph_density),
+        set: |app, value| sim_params(app, |params| params.sph_density = value.max(1.0)),
+    },
+    Parameter {
+        name: "sph_stiffness",
+        get: |app| Some(app.simulation.params.sph_stiffness),
+        set: |app, value| sim_params(app, |params| params.sph_stiffness = value.max(0.0)),
+    },
+    Parameter {
+        name: "sph_viscosity",
+        get: |app| Some(app.simulation.params.sph_viscosity),
+        set: |app, value| sim_params(app, |params| params.sph_viscosity = value.max(0.0)),
+    },
+    Parameter {
+        name: "sph_gravity",
+        get: |app| Some(app.simulation.params.sph_gravity),
+        set: |app, value| sim_params(app, |params| params.sph_gravity = value),
+    },
+    Parameter {
+        name: "restitution",
+        get: |app| Some(app.collision.obstacles.restitution),
+        set: |app, value| {
+            app.collision.obstacles.restitution = value.clamp(0.0, 1.0);
+            app.collision.write(&app.queue);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "friction",
+        get: |app| Some(app.collision.obstacles.friction),
+        set: |app, value| {
+            app.collision.obstacles.friction = value.clamp(0.0, 1.0);
+            app.collision.write(&app.queue);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "boid_radius",
+        get: |app| Some(app.simulation.boids.radius),
+        // Pushed with every step, no need to rebuild the pipelines
+        set: |app, value| {
+            app.simulation.boids.radius = value.max(0.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "boid_separation",
+        get: |app| Some(app.simulation.boids.separation),
+        set: |app, value| {
+            app.simulation.boids.separation = value;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "boid_alignment",
+        get: |app| Some(app.simulation.boids.alignment),
+        set: |app, value| {
+            app.simulation.boids.alignment = value;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "boid_cohesion",
+        get: |app| Some(app.simulation.boids.cohesion),
+        set: |app, value| {
+            app.simulation.boids.cohesion = value;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "boid_speed",
+        get: |app| Some(app.simulation.boids.speed),
+        set: |app, value| {
+            app.simulation.boids.speed = value.max(0.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "boid_neighbors",
+        get: |app| Some(app.simulation.boids.neighbors as f32),
+        set: |app, value| {
+            app.simulation.boids.neighbors = value.clamp(0.0, 256.0) as u32;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "timestep",
+        get: |app| Some(app.timestep.step as f32),
+        set: |app, value| {
+            app.timestep.step = value.clamp(1.0e-4, 1.0) as f64;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "substeps",
+        get: |app| Some(app.timestep.max_steps as f32),
+        set: |app, value| {
+            app.timestep.max_steps = value.clamp(1.0, 64.0) as u32;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "emit_rate",
+        get: |app| Some(app.emitter.settings.rate),
+        set: |app, value| {
+            app.emitter.settings.rate = value.max(0.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "emit_speed",
+        get: |app| Some(app.emitter.settings.speed),
+        set: |app, value| {
+            app.emitter.settings.speed = value.max(0.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "follow_distance",
+        get: |app| Some(app.simulation.follow.distance),
+        set: |app, value| {
+            app.simulation.follow.distance = value.max(0.0);
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "follow_strength",
+        get: |app| Some(app.simulation.follow.strength),
+        set: |app, value| {
+            app.simulation.follow.strength = value;
+            Ok(())
+        },
+    },
+    Parameter {
+        name: "well_strength",
+        get: |app| Some(app.gravity_wells.strength),
+        set: |app, value| {
+            app.gravity_wells.strength = value.max(0.0);
+            Ok(())
+        },
+    },
+];
+
+pub fn find(name: &str) -> ConsoleResult<&'static Parameter> {
+    PARAMETERS
+        .iter()
+        .find(|parameter| parameter.name == name)
+        .ok_or_else(|| ConsoleError::new(format!("Unknown parameter: {name}")))
+}
+
+/// Changes a copy of the simulation parameters and uploads it.
+fn sim_params(app: &mut App, change: impl FnOnce(&mut SimParams)) -> ConsoleResult<()> {
+    let mut params = app.simulation.params;
+    change(&mut params);
+    app.set_sim_params(params);
+    Ok(())
+}
+
+fn bloom(bloom: &mut Option<Bloom>) -> ConsoleResult<&mut Bloom> {
+    bloom
+        .as_mut()
+        .ok_or_else(|| ConsoleError::new("Bloom is off, see --bloom".to_string()))
+}
@@ -0,0 +1,163 @@
+use std::ops::Range;
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::Vector3;
+
+use super::{
+    culling::{Aabb, InstanceChunk as CullingChunk},
+    demo::ScatterVolume,
+    instances::InstanceChunk,
+};
+
+/// Mirrors `ResetParams` in `compute.wgsl`, one per instance chunk.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+struct ResetUniform {
+    min: [f32; 3],
+    speed: f32,
+    max: [f32; 3],
+    lifetime: f32,
+    seed: u32,
+    /// Index of the chunk's first instance.
+    first: u32,
+    /// Lifetimes are endless, `lifetime` doesn't count.
+    endless: u32,
+    _padding: u32,
+}
+
+/// Scatters the simulated instances over a demo's box again in a compute
+/// pass, `reset_main` in `compute.wgsl`, in place of generating millions of
+/// them on the CPU and uploading them. Bound at group 2 of the reset
+/// pipeline, with a dynamic offset per chunk.
+pub struct GpuReset {
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// Created with the first reset, grown when more chunks don't fit.
+    buffer: Option<(wgpu::Buffer, wgpu::BindGroup)>,
+    stride: u64,
+}
+
+impl GpuReset {
+    const SIZE: u64 = std::mem::size_of::<ResetUniform>() as u64;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("reset"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(Self::SIZE),
+                },
+                count: None,
+            }],
+        });
+
+        Self {
+            bind_group_layout,
+            buffer: None,
+            stride: Self::SIZE.next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64),
+        }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// Writes the parameters of every chunk, lifetimes of up to `lifetime`
+    /// seconds or endless ones.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        chunks: &[InstanceChunk],
+        volume: &ScatterVolume,
+        lifetime: Option<f32>,
+        seed: u32,
+    ) {
+        let size = chunks.len() as u64 * self.stride;
+        if self.buffer.as_ref().is_none_or(|(buffer, _)| buffer.size() < size) {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("reset_params"),
+                size: size.next_power_of_two(),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("reset"),
+                layout: &self.bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(Self::SIZE),
+                    }),
+                }],
+            });
+            self.buffer = Some((buffer, bind_group));
+        }
+
+        let mut contents = vec![0; size as usize];
+        for (chunk, bytes) in chunks.iter().zip(contents.chunks_mut(self.stride as usize)) {
+            let uniform = ResetUniform {
+                min: volume.min.into(),
+                speed: volume.speed,
+                max: volume.max.into(),
+                lifetime: lifetime.unwrap_or_default(),
+                seed,
+                first: chunk.range.start,
+                endless: lifetime.is_none() as u32,
+                _padding: 0,
+            };
+            bytes[..Self::SIZE as usize].copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        if let Some((buffer, _)) = &self.buffer {
+            queue.write_buffer(buffer, 0, &contents);
+        }
+    }
+
+    /// Binds the parameters of one chunk after [`Self::write`].
+    pub fn bind(&self, compute_pass: &mut wgpu::ComputePass, chunk: usize) {
+        if let Some((_, bind_group)) = &self.buffer {
+            compute_pass.set_bind_group(2, bind_group, &[(chunk as u64 * self.stride) as u32]);
+        }
+    }
+
+    /// Bounds of the culling chunk holding `instances` after a reset. Every
+    /// instance lands in its own cell of the instance grid stretched over
+    /// the volume, so a range of them covers whole rows and slices at most.
+    pub fn chunk_bounds(volume: &ScatterVolume, dimensions: [u32; 4], instances: Range<u32>) -> CullingChunk {
+        let [width, height, depth, _] = dimensions;
+        let cell = |index: u32| [index % width, index / width % height, index / (width * height)];
+        let (first, last) = (cell(instances.start), cell(instances.end.max(instances.start + 1) - 1));
+        let (mut low, mut high) = (first, last);
+        // Ranges spanning slices or rows take up whole ones
+        if first[2] != last[2] {
+            (low[1], high[1]) = (0, height - 1);
+        }
+        if first[2] != last[2] || first[1] != last[1] {
+            (low[0], high[0]) = (0, width - 1);
+        }
+
+        let size = volume.max - volume.min;
+        let corner = |cell: [u32; 3]| {
+            let [x, y, z] = cell.map(|c| c as f32);
+            volume.min
+                + Vector3::new(
+                    x * size.x / width as f32,
+                    y * size.y / height as f32,
+                    z * size.z / depth as f32,
+                )
+        };
+        let mut bounds = Aabb::empty();
+        bounds.extend(corner(low).to_homogeneous().into());
+        bounds.extend(corner(high.map(|c| c + 1)).to_homogeneous().into());
+
+        CullingChunk {
+            range: instances,
+            bounds,
+        }
+    }
+}
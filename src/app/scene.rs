@@ -0,0 +1,181 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+
+/// Light of the `lit` shader feature, shining from infinitely far away.
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+    /// Direction towards the light, normalized when uploaded.
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Fraction of the base color seen on faces turned away from the light.
+    pub ambient: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: Vector3::new(0.4, 0.8, 0.45),
+            color: [1.0; 3],
+            intensity: 1.0,
+            ambient: 0.25,
+        }
+    }
+}
+
+impl DirectionalLight {
+    /// Normalized direction towards the light, a light from nowhere shines
+    /// straight down.
+    pub fn unit_direction(&self) -> Vector3<f32> {
+        if self.direction.magnitude2() > 0.0 {
+            self.direction.normalize()
+        } else {
+            Vector3::unit_y()
+        }
+    }
+}
+
+/// Exponential fog of the `fogged` shader feature, thickening with the
+/// distance from the camera.
+#[derive(Clone, Copy, Debug)]
+pub struct Fog {
+    /// Also the background while the fog is on, so far instances fade into it.
+    pub color: [f32; 3],
+    /// Share of the color fogged over per unit of distance, roughly.
+    pub density: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            color: [0.0; 3],
+            density: 1.0e-4,
+        }
+    }
+}
+
+/// Gamma the sRGB transfer function approximates, which targets in an sRGB
+/// format encode what's written to them with.
+pub const SRGB_GAMMA: f32 = 2.2;
+
+/// Exponent bringing linear colors to `gamma` once written to a target in
+/// `format`, which takes care of part of it when it's sRGB.
+pub fn gamma_exponent(format: wgpu::TextureFormat, gamma: f32) -> f32 {
+    let encoded = if format.is_srgb() { SRGB_GAMMA } else { 1.0 };
+    encoded / gamma
+}
+
+/// Renderer-wide settings that aren't tied to the camera.
+pub struct SceneSettings {
+    /// Instances further than this from the camera are not drawn.
+    pub max_draw_distance: f32,
+    /// Width of the band before `max_draw_distance` over which instances shrink away.
+    pub fade_band: f32,
+    /// Instances covering fewer pixels than this are drawn as impostors. Zero disables impostors.
+    pub impostor_threshold: f32,
+    pub viewport_height: f32,
+    pub light: DirectionalLight,
+    pub fog: Fog,
+    /// Scales the shaded colors, before tone mapping when that's on.
+    pub exposure: f32,
+    /// Gamma of the output, whatever the surface format.
+    pub gamma: f32,
+    /// Drawn over while the fog is off.
+    pub background: [f32; 3],
+    /// Target the scene shaders write the output into, applying the gamma
+    /// themselves. `None` while tone mapping brings the scene into range
+    /// afterwards, which applies it instead.
+    pub output_format: Option<wgpu::TextureFormat>,
+}
+
+impl Default for SceneSettings {
+    fn default() -> Self {
+        Self {
+            max_draw_distance: 40000.0,
+            fade_band: 4000.0,
+            impostor_threshold: Self::DEFAULT_IMPOSTOR_THRESHOLD,
+            viewport_height: 720.0,
+            light: DirectionalLight::default(),
+            fog: Fog::default(),
+            exposure: 1.0,
+            gamma: SRGB_GAMMA,
+            background: [0.0; 3],
+            output_format: None,
+        }
+    }
+}
+
+impl SceneSettings {
+    pub const DEFAULT_IMPOSTOR_THRESHOLD: f32 = 4.0;
+
+    pub fn set_draw_distance(&mut self, distance: f32) {
+        self.max_draw_distance = distance.max(1.0);
+        self.fade_band = self.fade_band.min(self.max_draw_distance);
+    }
+
+    pub fn scale_draw_distance(&mut self, factor: f32) {
+        self.set_draw_distance(self.max_draw_distance * factor);
+        self.fade_band = self.max_draw_distance * 0.1;
+    }
+
+    pub fn toggle_impostors(&mut self) {
+        self.impostor_threshold = if self.impostor_threshold > 0.0 {
+            0.0
+        } else {
+            Self::DEFAULT_IMPOSTOR_THRESHOLD
+        };
+    }
+
+    pub fn uniform(&self) -> SceneUniform {
+        let light = &self.light;
+        SceneUniform {
+            max_draw_distance: self.max_draw_distance,
+            fade_band: self.fade_band,
+            impostor_threshold: self.impostor_threshold,
+            viewport_height: self.viewport_height,
+            light_direction: light.unit_direction().into(),
+            ambient: light.ambient,
+            light_color: light.color.map(|c| c * light.intensity),
+            fog_density: self.fog.density,
+            fog_color: self.fog.color,
+            exposure: self.exposure,
+            gamma: self.shader_gamma_exponent(),
+            _padding: [0.0; 3],
+        }
+    }
+
+    /// Exponent the scene shaders raise their output to, one when tone
+    /// mapping applies the gamma.
+    fn shader_gamma_exponent(&self) -> f32 {
+        self.output_format.map_or(1.0, |format| gamma_exponent(format, self.gamma))
+    }
+
+    /// Color the scene is drawn over, the fog's while `fogged` is on.
+    /// Exposed and gamma corrected like the shaded colors, so the fog fades
+    /// right into it.
+    pub fn background(&self, fogged: bool) -> wgpu::Color {
+        let color = if fogged { self.fog.color } else { self.background };
+        let exponent = self.shader_gamma_exponent();
+        let [r, g, b] = color.map(|c| f64::from((c * self.exposure).max(0.0).powf(exponent)));
+        wgpu::Color { r, g, b, a: 1.0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Zeroable, Pod)]
+pub struct SceneUniform {
+    max_draw_distance: f32,
+    fade_band: f32,
+    impostor_threshold: f32,
+    viewport_height: f32,
+    light_direction: [f32; 3],
+    ambient: f32,
+    /// Scaled by the intensity.
+    light_color: [f32; 3],
+    fog_density: f32,
+    fog_color: [f32; 3],
+    exposure: f32,
+    /// Exponent the output is raised to, see [`gamma_exponent`].
+    gamma: f32,
+    _padding: [f32; 3],
+}
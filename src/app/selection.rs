@@ -0,0 +1,115 @@
+use super::{
+    instances::{InstanceBuffer, InstanceBuffers, InstanceTransform},
+    mesh::Mesh,
+};
+
+/// Picked instances, outlined in the scene. Their positions and transforms
+/// are copied out of the instance buffers into small ones each frame, since
+/// the simulation keeps moving them, and drawn from there with the outline
+/// pipeline in one draw.
+pub struct Selection {
+    /// Indices of the selected instances, oldest first.
+    ids: Vec<u32>,
+    positions: InstanceBuffer<[f32; 4]>,
+    transforms: InstanceBuffer<InstanceTransform>,
+    /// Selected instances copied by the last [`Self::gather`], fewer than
+    /// `ids` when some were removed since.
+    gathered: u32,
+}
+
+impl Selection {
+    /// Selecting more drops the oldest.
+    pub const MAX_SELECTED: usize = 64;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            ids: Vec::new(),
+            positions: InstanceBuffer::new(
+                device,
+                "selected_positions",
+                wgpu::BufferUsages::VERTEX,
+                &[[0.0; 4]; Self::MAX_SELECTED],
+            ),
+            transforms: InstanceBuffer::new(
+                device,
+                "selected_transforms",
+                wgpu::BufferUsages::VERTEX,
+                &[InstanceTransform::IDENTITY; Self::MAX_SELECTED],
+            ),
+            gathered: 0,
+        }
+    }
+
+    pub fn ids(&self) -> &[u32] {
+        &self.ids
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Selects only `id`.
+    pub fn select(&mut self, id: u32) {
+        self.ids.clear();
+        self.ids.push(id);
+    }
+
+    /// Adds `id` to the selection, or takes it out if it was in it already.
+    /// Returns whether it's selected now.
+    pub fn toggle(&mut self, id: u32) -> bool {
+        if let Some(index) = self.ids.iter().position(|&selected| selected == id) {
+            self.ids.remove(index);
+            return false;
+        }
+        if self.ids.len() == Self::MAX_SELECTED {
+            self.ids.remove(0);
+        }
+        self.ids.push(id);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.gathered = 0;
+    }
+
+    /// Records copies of the selected instances out of the buffers of
+    /// `instances` the vertex stage reads, skipping any that no longer
+    /// exist.
+    pub fn gather(&mut self, encoder: &mut wgpu::CommandEncoder, instances: &InstanceBuffers) {
+        const POSITION_SIZE: u64 = std::mem::size_of::<[f32; 4]>() as u64;
+        const TRANSFORM_SIZE: u64 = std::mem::size_of::<InstanceTransform>() as u64;
+
+        self.gathered = 0;
+        for &id in &self.ids {
+            for (_, chunk, instances) in instances.split(id..id + 1) {
+                let (source, target) = (instances.start as u64, self.gathered as u64);
+                encoder.copy_buffer_to_buffer(
+                    chunk.positions_vsh.buffer(),
+                    source * POSITION_SIZE,
+                    self.positions.buffer(),
+                    target * POSITION_SIZE,
+                    POSITION_SIZE,
+                );
+                encoder.copy_buffer_to_buffer(
+                    chunk.transforms.buffer(),
+                    source * TRANSFORM_SIZE,
+                    self.transforms.buffer(),
+                    target * TRANSFORM_SIZE,
+                    TRANSFORM_SIZE,
+                );
+                self.gathered += 1;
+            }
+        }
+    }
+
+    /// Draws the gathered instances as `mesh`, with the outline pipeline
+    /// already set.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, mesh: &Mesh) {
+        if self.gathered == 0 {
+            return;
+        }
+        render_pass.set_vertex_buffer(2, self.transforms.slice());
+        mesh.draw_instanced(render_pass, self.positions.buffer(), 0..self.gathered);
+    }
+}
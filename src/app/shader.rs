@@ -0,0 +1,538 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Display,
+    ops::BitOr,
+    path::PathBuf,
+};
+
+use pollster::FutureExt;
+
+#[derive(Debug, Clone)]
+pub struct ShaderError {
+    pub message: String,
+}
+
+impl ShaderError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+
+    /// Condenses a multi-line naga diagnostic to the error and its
+    /// `line:column` location, for display in the window.
+    pub fn summary(&self) -> String {
+        let lines: Vec<&str> = self.message.lines().map(str::trim).collect();
+        let error = lines.iter().find(|line| line.contains("error:")).or(lines.first());
+        let location = lines
+            .iter()
+            .find_map(|line| line.strip_prefix("┌─"))
+            .map(str::trim);
+
+        match (error, location) {
+            (Some(error), Some(location)) => format!("{error} at {location}"),
+            (Some(error), None) => error.to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+impl Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ShaderError {}
+pub type ShaderResult<T> = Result<T, ShaderError>;
+
+/// Optional features of the default shader, each enabled by a `#define`.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct ShaderFeatures(u32);
+
+impl ShaderFeatures {
+    pub const NONE: Self = Self(0);
+    pub const TEXTURED: Self = Self(1 << 0);
+    pub const LIT: Self = Self(1 << 1);
+    pub const FOGGED: Self = Self(1 << 2);
+    pub const INSTANCED_COLOR: Self = Self(1 << 3);
+    pub const POINT_COLOR: Self = Self(1 << 4);
+    pub const VOXEL_RUNS: Self = Self(1 << 5);
+    pub const COLOR_ATTRIBUTE: Self = Self(1 << 6);
+    pub const PULSE: Self = Self(1 << 7);
+    pub const MATERIALS: Self = Self(1 << 8);
+    pub const SHADOWED: Self = Self(1 << 9);
+
+    const DEFINES: [(Self, &'static str, &'static str); 10] = [
+        (Self::TEXTURED, "TEXTURED", "textured"),
+        (Self::LIT, "LIT", "lit"),
+        (Self::FOGGED, "FOGGED", "fogged"),
+        (Self::INSTANCED_COLOR, "INSTANCED_COLOR", "instanced-color"),
+        (Self::POINT_COLOR, "POINT_COLOR", "point-color"),
+        (Self::VOXEL_RUNS, "VOXEL_RUNS", "voxel-runs"),
+        (Self::COLOR_ATTRIBUTE, "COLOR_ATTRIBUTE", "color-attribute"),
+        (Self::PULSE, "PULSE", "pulse"),
+        (Self::MATERIALS, "MATERIALS", "materials"),
+        (Self::SHADOWED, "SHADOWED", "shadowed"),
+    ];
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn toggled(self, other: Self) -> Self {
+        Self(self.0 ^ other.0)
+    }
+
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Preprocessor defines enabling these features.
+    pub fn defines(self) -> Vec<&'static str> {
+        Self::DEFINES
+            .iter()
+            .filter(|(feature, _, _)| self.contains(*feature))
+            .map(|(_, define, _)| *define)
+            .collect()
+    }
+
+    /// Parses a comma separated list such as `lit,fogged`.
+    pub fn parse(list: &str) -> ShaderResult<Self> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .try_fold(Self::NONE, |features, name| {
+                Self::DEFINES
+                    .iter()
+                    .find(|(_, _, option)| option.eq_ignore_ascii_case(name))
+                    .map(|(feature, _, _)| features | *feature)
+                    .ok_or_else(|| ShaderError::new(format!("Unknown shader feature: {name}")))
+            })
+    }
+}
+
+impl BitOr for ShaderFeatures {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Resolves `#define`, `#ifdef`, `#ifndef`, `#else` and `#endif` directives.
+///
+/// Directives and disabled lines are replaced by empty lines, so line numbers
+/// in naga diagnostics still match the original source.
+pub fn preprocess(source: &str, defines: &[&str]) -> ShaderResult<String> {
+    let mut defined: Vec<String> = defines.iter().map(|d| d.to_string()).collect();
+    // One entry per open conditional: (branch taken, else seen)
+    let mut conditions: Vec<(bool, bool)> = Vec::new();
+    let mut output = String::with_capacity(source.len());
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let active = conditions.iter().all(|(taken, _)| *taken);
+        let trimmed = line.trim_start();
+
+        if let Some(directive) = trimmed.strip_prefix('#') {
+            let mut words = directive.split_whitespace();
+            let name = words.next().unwrap_or_default();
+            let argument = words.next();
+            let require_argument = || argument.ok_or_else(|| ShaderError::new(
+                format!("line {line_number}: #{name} expects a name")
+            ));
+
+            match name {
+                "define" => {
+                    let argument = require_argument()?;
+                    if active {
+                        defined.push(argument.to_string());
+                    }
+                }
+                "ifdef" | "ifndef" => {
+                    let argument = require_argument()?;
+                    let is_defined = defined.iter().any(|d| d == argument);
+                    conditions.push((is_defined == (name == "ifdef"), false));
+                }
+                "else" => match conditions.last_mut() {
+                    Some((taken, else_seen)) if !*else_seen => {
+                        *taken = !*taken;
+                        *else_seen = true;
+                    }
+                    _ => return Err(ShaderError::new(format!("line {line_number}: unexpected #else"))),
+                },
+                "endif" => {
+                    conditions.pop().ok_or_else(|| {
+                        ShaderError::new(format!("line {line_number}: unexpected #endif"))
+                    })?;
+                }
+                _ => return Err(ShaderError::new(format!("line {line_number}: unknown directive #{name}"))),
+            }
+        } else if active {
+            output.push_str(line);
+        }
+        output.push('\n');
+    }
+
+    if !conditions.is_empty() {
+        return Err(ShaderError::new("missing #endif at end of file".to_string()));
+    }
+
+    Ok(output)
+}
+
+/// Creates a shader module, returning validation errors instead of passing
+/// them to the device's uncaptured error handler.
+fn create_checked(
+    device: &wgpu::Device,
+    label: &str,
+    source: wgpu::ShaderSource,
+) -> ShaderResult<wgpu::ShaderModule> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source,
+    });
+
+    match device.pop_error_scope().block_on() {
+        Some(error) => Err(ShaderError::new(error.to_string())),
+        None => Ok(module),
+    }
+}
+
+/// Checks that `bytes` hold whole SPIR-V words starting with the magic
+/// number, in either byte order, since `wgpu::util::make_spirv` panics otherwise.
+#[cfg(feature = "spirv")]
+fn validate_spirv(bytes: &[u8]) -> Result<(), String> {
+    const MAGIC_NUMBER: u32 = 0x0723_0203;
+
+    if bytes.is_empty() || !bytes.len().is_multiple_of(4) {
+        return Err(format!("{} bytes is not a whole number of words", bytes.len()));
+    }
+    let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if magic != MAGIC_NUMBER && magic != MAGIC_NUMBER.swap_bytes() {
+        return Err(format!("magic number {magic:#010x} is not SPIR-V's"));
+    }
+
+    Ok(())
+}
+
+/// Creates a module from validated SPIR-V, handing it to the driver untouched
+/// with `passthrough` when the device allows it.
+#[cfg(feature = "spirv")]
+fn create_spirv(device: &wgpu::Device, label: &str, bytes: &[u8], passthrough: bool) -> ShaderResult<wgpu::ShaderModule> {
+    if !(passthrough && device.features().contains(wgpu::Features::SPIRV_SHADER_PASSTHROUGH)) {
+        return create_checked(device, label, wgpu::util::make_spirv(bytes));
+    }
+
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    // SAFETY: the module skips validation, so it's trusted to match
+    // the pipeline layouts like the WGSL it replaces
+    let module = unsafe {
+        device.create_shader_module_spirv(&wgpu::ShaderModuleDescriptorSpirV {
+            label: Some(label),
+            source: wgpu::util::make_spirv_raw(bytes),
+        })
+    };
+    match device.pop_error_scope().block_on() {
+        Some(error) => Err(ShaderError::new(error.to_string())),
+        None => Ok(module),
+    }
+}
+
+/// Reads `<stem>.<extension>` for a GLSL stage replacing the WGSL shader `name`.
+#[cfg(feature = "glsl")]
+fn read_glsl_stage(dir: &std::path::Path, name: &str, extension: &str) -> Option<String> {
+    let path = dir.join(name).with_extension(extension);
+    let source = std::fs::read_to_string(&path).ok()?;
+    log::info!("Loaded {} from disk.", path.display());
+
+    Some(source)
+}
+
+/// Vertex and fragment stages of a render pipeline. WGSL provides both from
+/// one module, GLSL from one module per stage.
+#[derive(Clone, Debug)]
+pub struct RenderModules {
+    pub vertex: wgpu::ShaderModule,
+    pub vertex_entry: &'static str,
+    pub fragment: wgpu::ShaderModule,
+    pub fragment_entry: &'static str,
+    /// Has the G-buffer and lighting entry points of deferred shading and
+    /// the accumulation one of translucent groups, which GLSL replacements
+    /// leave out.
+    pub deferred: bool,
+}
+
+/// Source of a render shader.
+pub enum RenderSource {
+    Wgsl(String),
+    /// Precompiled, so every feature set gets the same module.
+    #[cfg(feature = "spirv")]
+    Spirv { bytes: Vec<u8>, passthrough: bool },
+    /// Translated through naga. GLSL has its own preprocessor, so feature
+    /// defines are passed to naga rather than resolved by [`preprocess`].
+    #[cfg(feature = "glsl")]
+    Glsl { vertex: String, fragment: String },
+}
+
+/// Loads shaders from an on-disk directory when a file of the same name is
+/// present there, falling back to the copies embedded in the binary.
+///
+/// With the `spirv` feature, a precompiled `<name>.spv` next to where the
+/// WGSL would be takes precedence over both. With the `glsl` feature, GLSL
+/// stages in `<name>.vert`/`<name>.frag` or `<name>.comp` do the same.
+/// GLSL entry points are called `main`.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderLoader {
+    dir: Option<PathBuf>,
+    #[cfg_attr(not(feature = "spirv"), allow(dead_code))]
+    spirv_passthrough: bool,
+}
+
+impl ShaderLoader {
+    /// `spirv_passthrough` hands SPIR-V to the driver untouched when the device
+    /// has `SPIRV_SHADER_PASSTHROUGH`, skipping naga validation and override constants.
+    pub fn new(dir: Option<PathBuf>, spirv_passthrough: bool) -> Self {
+        Self { dir, spirv_passthrough }
+    }
+
+    /// Validates `name` with `defines` set without replacing an invalid disk
+    /// shader by the embedded copy, so a reload can keep what it had.
+    pub fn check(&self, device: &wgpu::Device, name: &'static str, embedded: &'static str, defines: &[&str]) -> ShaderResult<()> {
+        let source = preprocess(&self.source(name, embedded), defines)
+            .map_err(|e| ShaderError::new(format!("{name}: {e}")))?;
+        create_checked(device, name, wgpu::ShaderSource::Wgsl(source.into())).map(|_| ())
+    }
+
+    /// Source of `name`, read from the shader directory if it's there.
+    pub fn source(&self, name: &str, embedded: &'static str) -> Cow<'static, str> {
+        let Some(dir) = &self.dir else {
+            return embedded.into();
+        };
+
+        let path = dir.join(name);
+        match std::fs::read_to_string(&path) {
+            Ok(source) => {
+                log::info!("Loaded {} from disk.", path.display());
+                source.into()
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => embedded.into(),
+            Err(e) => {
+                log::warn!("Failed to read {}: {e}. Using the embedded copy.", path.display());
+                embedded.into()
+            }
+        }
+    }
+
+    /// Render shader `name`, preferring SPIR-V and then GLSL stages from the
+    /// shader directory.
+    pub fn render_source(&self, name: &str, embedded: &'static str) -> RenderSource {
+        #[cfg(feature = "spirv")]
+        if let Some(bytes) = self.read_spirv(name) {
+            log::warn!("{name} is precompiled, its feature defines are not applied.");
+            return RenderSource::Spirv { bytes, passthrough: self.spirv_passthrough };
+        }
+
+        #[cfg(feature = "glsl")]
+        if let Some(dir) = &self.dir {
+            match (read_glsl_stage(dir, name, "vert"), read_glsl_stage(dir, name, "frag")) {
+                (Some(vertex), Some(fragment)) => return RenderSource::Glsl { vertex, fragment },
+                (None, None) => {}
+                _ => log::warn!("GLSL replacement for {name} needs both a .vert and a .frag stage."),
+            }
+        }
+
+        RenderSource::Wgsl(self.source(name, embedded).into_owned())
+    }
+
+    /// Compiles `name`. A disk shader that fails validation is reported and
+    /// replaced by the embedded copy.
+    pub fn module(&self, device: &wgpu::Device, name: &'static str, embedded: &'static str) -> wgpu::ShaderModule {
+        self.module_with_defines(device, name, embedded, &[])
+    }
+
+    /// Compiles `name` after resolving its directives with `defines` set.
+    pub fn module_with_defines(
+        &self,
+        device: &wgpu::Device,
+        name: &'static str,
+        embedded: &'static str,
+        defines: &[&str],
+    ) -> wgpu::ShaderModule {
+        #[cfg(feature = "spirv")]
+        if let Some(module) = self.spirv_module(device, name) {
+            return module;
+        }
+
+        let source = self.source(name, embedded);
+        let from_disk = matches!(source, Cow::Owned(_));
+
+        let module = preprocess(&source, defines)
+            .and_then(|source| create_checked(device, name, wgpu::ShaderSource::Wgsl(source.into())));
+        match module {
+            Ok(module) => module,
+            Err(error) if from_disk => {
+                log::error!("{name} from the shader directory is invalid, using the embedded copy:\n{error}");
+                Self::default().module_with_defines(device, name, embedded, defines)
+            }
+            Err(error) => panic!("Embedded shader {name} is invalid: {error}"),
+        }
+    }
+
+    /// Compiles the compute shader `name` with `defines` set, returning the
+    /// entry point to use with it, which is `main` when a GLSL `<name>.comp`
+    /// replaces it.
+    pub fn compute_module(
+        &self,
+        device: &wgpu::Device,
+        name: &'static str,
+        embedded: &'static str,
+        entry_point: &'static str,
+        defines: &[&str],
+    ) -> (wgpu::ShaderModule, &'static str) {
+        #[cfg(feature = "glsl")]
+        if let Some(source) = self.dir.as_deref().and_then(|dir| read_glsl_stage(dir, name, "comp")) {
+            let source = wgpu::ShaderSource::Glsl {
+                shader: source.into(),
+                stage: wgpu::naga::ShaderStage::Compute,
+                defines: Default::default(),
+            };
+            match create_checked(device, name, source) {
+                Ok(module) => return (module, "main"),
+                Err(error) => log::error!("GLSL replacement for {name} is invalid, falling back:\n{error}"),
+            }
+        }
+
+        (self.module_with_defines(device, name, embedded, defines), entry_point)
+    }
+
+    /// Reads `<name>.spv` from the shader directory, `None` when it's missing
+    /// or not SPIR-V.
+    #[cfg(feature = "spirv")]
+    fn read_spirv(&self, name: &str) -> Option<Vec<u8>> {
+        let path = self.dir.as_ref()?.join(name).with_extension("spv");
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::warn!("Failed to read {}: {e}.", path.display());
+                return None;
+            }
+        };
+        if let Err(reason) = validate_spirv(&bytes) {
+            log::error!("{} is not SPIR-V, {reason}. Falling back to WGSL.", path.display());
+            return None;
+        }
+        log::info!("Loaded {} from disk.", path.display());
+
+        Some(bytes)
+    }
+
+    #[cfg(feature = "spirv")]
+    fn spirv_module(&self, device: &wgpu::Device, name: &'static str) -> Option<wgpu::ShaderModule> {
+        let bytes = self.read_spirv(name)?;
+        match create_spirv(device, name, &bytes, self.spirv_passthrough) {
+            Ok(module) => Some(module),
+            Err(error) => {
+                log::error!("{name} from SPIR-V is invalid, falling back to WGSL:\n{error}");
+                None
+            }
+        }
+    }
+}
+
+/// Compiled variants of one render shader, keyed by feature set.
+pub struct ShaderPermutations {
+    label: &'static str,
+    source: RenderSource,
+    modules: HashMap<ShaderFeatures, RenderModules>,
+}
+
+impl ShaderPermutations {
+    pub fn new(label: &'static str, source: RenderSource) -> Self {
+        Self {
+            label,
+            source,
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Returns the module for `features`, compiling it on first use.
+    ///
+    /// Validation errors are returned instead of reaching the device's
+    /// uncaptured error handler, and failed modules are not cached. The
+    /// preprocessor keeps line numbers, so diagnostics point into the original source.
+    pub fn get(&mut self, device: &wgpu::Device, features: ShaderFeatures) -> ShaderResult<&RenderModules> {
+        if !self.modules.contains_key(&features) {
+            log::debug!("Compiling {} with features {:?}.", self.label, features.defines());
+            let modules = self.compile(device, features)?;
+            self.modules.insert(features, modules);
+        }
+
+        Ok(&self.modules[&features])
+    }
+
+    fn compile(&self, device: &wgpu::Device, features: ShaderFeatures) -> ShaderResult<RenderModules> {
+        match &self.source {
+            RenderSource::Wgsl(source) => {
+                let source = preprocess(source, &features.defines())
+                    .map_err(|e| ShaderError::new(format!("{}: {e}", self.label)))?;
+                let module = create_checked(device, self.label, wgpu::ShaderSource::Wgsl(source.into()))?;
+
+                Ok(RenderModules {
+                    vertex: module.clone(),
+                    vertex_entry: "vs_main",
+                    fragment: module,
+                    fragment_entry: "fs_main",
+                    deferred: true,
+                })
+            }
+            #[cfg(feature = "spirv")]
+            RenderSource::Spirv { bytes, passthrough } => {
+                let module = create_spirv(device, self.label, bytes, *passthrough)?;
+
+                Ok(RenderModules {
+                    vertex: module.clone(),
+                    vertex_entry: "vs_main",
+                    fragment: module,
+                    fragment_entry: "fs_main",
+                    // Whether it has them can't be told from the module
+                    deferred: false,
+                })
+            }
+            #[cfg(feature = "glsl")]
+            RenderSource::Glsl { vertex, fragment } => {
+                let defines: wgpu::naga::FastHashMap<String, String> = features
+                    .defines()
+                    .into_iter()
+                    .map(|define| (define.to_string(), String::new()))
+                    .collect();
+                let stage = |source: &String, stage| {
+                    create_checked(device, self.label, wgpu::ShaderSource::Glsl {
+                        shader: source.into(),
+                        stage,
+                        defines: defines.clone(),
+                    })
+                };
+
+                Ok(RenderModules {
+                    vertex: stage(vertex, wgpu::naga::ShaderStage::Vertex)?,
+                    vertex_entry: "main",
+                    fragment: stage(fragment, wgpu::naga::ShaderStage::Fragment)?,
+                    fragment_entry: "main",
+                    deferred: false,
+                })
+            }
+        }
+    }
+}
+
+/// Collects values for WGSL `override` constants, keyed by identifier, for
+/// `PipelineCompilationOptions::constants`.
+pub fn override_constants<'a>(values: impl IntoIterator<Item = (&'a str, f64)>) -> HashMap<String, f64> {
+    values
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+}
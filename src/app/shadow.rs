@@ -0,0 +1,385 @@
+use std::ops::Range;
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform, Vector3};
+
+use super::{
+    InstanceRepr,
+    camera::Camera,
+    debug_labels,
+    instances::{InstanceBuffers, InstanceTransform},
+    mesh::{DefaultVertex3d, Instance, Mesh, Vertex},
+    shader::ShaderLoader,
+    texture::Texture2d,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShadowSettings {
+    /// Number of cascades, from [`ShadowMaps::MIN_CASCADES`] to
+    /// [`ShadowMaps::MAX_CASCADES`].
+    pub cascades: u32,
+    /// Width and height of the map of every cascade, in texels.
+    pub resolution: u32,
+    /// Distance along the view shadows end at.
+    pub distance: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            cascades: 3,
+            resolution: 1024,
+            distance: 2000.0,
+        }
+    }
+}
+
+/// Mirrors `Shadows` in `default.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct ShadowUniform {
+    /// Light view projection of every cascade.
+    cascades: [[[f32; 4]; 4]; ShadowMaps::MAX_CASCADES],
+    /// Distance along the view every cascade ends at.
+    splits: [f32; ShadowMaps::MAX_CASCADES],
+    /// Distance surfaces are pushed out along their normal before the
+    /// lookup, a texel and a half of every cascade.
+    normal_offsets: [f32; ShadowMaps::MAX_CASCADES],
+    count: u32,
+    /// Size of a texel in texture coordinates.
+    texel_size: f32,
+    _padding: [f32; 2],
+}
+
+/// Mirrors `Cascade` in `shadow.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct CascadeUniform {
+    view_projection: [[f32; 4]; 4],
+    eye: [f32; 3],
+    max_draw_distance: f32,
+}
+
+/// Shadows of the directional light, in the `shadowed` shader feature with
+/// `lit`. The view is split into cascades along its depth, each covered by
+/// a depth map of the instances rendered from the light, the nearest ones
+/// covering the least. `default.wgsl` picks the cascade of a fragment and
+/// filters its shadow over 3x3 texels. Only the instances cast shadows.
+pub struct ShadowMaps {
+    pub settings: ShadowSettings,
+    /// A cascade each, rendered to.
+    layer_views: Vec<wgpu::TextureView>,
+    /// All cascades, sampled in the main pass.
+    array_view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    /// One [`CascadeUniform`] per dynamic offset.
+    cascade_buffer: wgpu::Buffer,
+    cascade_bind_group: wgpu::BindGroup,
+    cascade_stride: u64,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMaps {
+    pub const MIN_CASCADES: u32 = 2;
+    pub const MAX_CASCADES: usize = 4;
+    /// Blend of the splits between even (0) and logarithmic (1) spacing.
+    const SPLIT_BLEND: f32 = 0.75;
+    /// How far towards the light, past the part of the view it covers, a
+    /// cascade still catches casters.
+    const CASTER_DISTANCE: f32 = 2000.0;
+    /// Bindings of [`Self::layout_entries`] in the scene bind group.
+    pub const FIRST_BINDING: u32 = 2;
+
+    pub fn new(device: &wgpu::Device, shaders: &ShaderLoader, settings: ShadowSettings) -> Self {
+        let module = shaders.module(device, "shadow.wgsl", include_str!("../shaders/shadow.wgsl"));
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow_maps"),
+            size: wgpu::Extent3d {
+                width: settings.resolution,
+                height: settings.resolution,
+                depth_or_array_layers: settings.cascades,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture2d::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let layer_views = (0..settings.cascades)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("shadow_cascade"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow_maps"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Linear filtering compares the four nearest texels, smoothing the PCF
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shadow_uniform"),
+            size: std::mem::size_of::<ShadowUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let cascade_size = std::mem::size_of::<CascadeUniform>() as u64;
+        let cascade_stride = cascade_size.next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let cascade_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shadow_cascades"),
+            size: cascade_stride * settings.cascades as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cascade_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shadow_cascade"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(cascade_size),
+                },
+                count: None,
+            }],
+        });
+        let cascade_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_cascade"),
+            layout: &cascade_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &cascade_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(cascade_size),
+                }),
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("shadow_pipeline_layout"),
+            bind_group_layouts: &[&cascade_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("shadow_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Some("vs_main"),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                buffers: &[DefaultVertex3d::desc(), InstanceRepr::desc(), InstanceTransform::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                ..Default::default()
+            },
+            multisample: wgpu::MultisampleState::default(),
+            fragment: None,
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture2d::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                // Keeps lit faces from shadowing themselves
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multiview: None,
+            cache: None,
+        });
+        log::info!(
+            "Shadows in {} cascades of {}px up to {}.",
+            settings.cascades,
+            settings.resolution,
+            settings.distance,
+        );
+
+        Self {
+            settings,
+            layer_views,
+            array_view,
+            sampler,
+            uniform_buffer,
+            cascade_buffer,
+            cascade_bind_group,
+            cascade_stride,
+            pipeline,
+        }
+    }
+
+    /// Entries of the scene bind group layout the maps are read through,
+    /// from [`Self::FIRST_BINDING`] on.
+    pub fn layout_entries() -> [wgpu::BindGroupLayoutEntry; 3] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding: Self::FIRST_BINDING,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: Self::FIRST_BINDING + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: Self::FIRST_BINDING + 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 3] {
+        [
+            wgpu::BindGroupEntry {
+                binding: Self::FIRST_BINDING,
+                resource: wgpu::BindingResource::TextureView(&self.array_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: Self::FIRST_BINDING + 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: Self::FIRST_BINDING + 2,
+                resource: self.uniform_buffer.as_entire_binding(),
+            },
+        ]
+    }
+
+    /// Distances along the view the cascades end at, closer together near
+    /// the camera.
+    fn splits(&self, near: f32) -> Vec<f32> {
+        let far = self.settings.distance.max(near * 2.0);
+        let count = self.settings.cascades;
+        (1..=count)
+            .map(|index| {
+                let fraction = index as f32 / count as f32;
+                let even = near + (far - near) * fraction;
+                let logarithmic = near * (far / near).powf(fraction);
+                even + (logarithmic - even) * Self::SPLIT_BLEND
+            })
+            .collect()
+    }
+
+    /// Orthographic view projection from `direction`, towards the light,
+    /// around the part of the view between `near` and `far`. The box is as
+    /// big as the slice's bounding sphere and moves in whole texels, so
+    /// the shadow edges don't crawl as the camera turns and moves.
+    fn fit_cascade(&self, camera: &Camera, direction: Vector3<f32>, near: f32, far: f32) -> (Matrix4<f32>, f32) {
+        let corners = camera.slice_corners(near, far);
+        let center = Point3::centroid(&corners);
+        let radius = corners
+            .iter()
+            .map(|corner| (corner - center).magnitude())
+            .fold(0.0, f32::max);
+        let radius = (radius * 16.0).ceil() / 16.0;
+
+        let up = if direction.y.abs() > 0.99 { Vector3::unit_x() } else { Vector3::unit_y() };
+        let rotation = Matrix4::look_to_rh(Point3::origin(), -direction, up);
+        let texel = 2.0 * radius / self.settings.resolution as f32;
+        let center = rotation.transform_point(center);
+        let (x, y) = ((center.x / texel).floor() * texel, (center.y / texel).floor() * texel);
+
+        // Right handed, so the light looks down -z
+        let near_plane = -(center.z + radius + Self::CASTER_DISTANCE);
+        let far_plane = -(center.z - radius);
+        let depth = far_plane - near_plane;
+        #[rustfmt::skip]
+        let projection = Matrix4::new(
+            1.0 / radius, 0.0, 0.0, 0.0,
+            0.0, 1.0 / radius, 0.0, 0.0,
+            0.0, 0.0, -1.0 / depth, 0.0,
+            -x / radius, -y / radius, -near_plane / depth, 1.0,
+        );
+        (projection * rotation, texel)
+    }
+
+    /// Fits the cascades to the camera and uploads them.
+    pub fn update(&self, queue: &wgpu::Queue, camera: &Camera, light_direction: Vector3<f32>, max_draw_distance: f32) {
+        let mut uniform = ShadowUniform::zeroed();
+        uniform.count = self.settings.cascades;
+        uniform.texel_size = 1.0 / self.settings.resolution as f32;
+
+        let mut near = camera.near;
+        for (index, far) in self.splits(camera.near).into_iter().enumerate() {
+            let (view_projection, texel) = self.fit_cascade(camera, light_direction, near, far);
+            uniform.cascades[index] = view_projection.into();
+            uniform.splits[index] = far;
+            uniform.normal_offsets[index] = 1.5 * texel;
+            let cascade = CascadeUniform {
+                view_projection: view_projection.into(),
+                eye: camera.eye.into(),
+                max_draw_distance,
+            };
+            queue.write_buffer(&self.cascade_buffer, index as u64 * self.cascade_stride, bytemuck::bytes_of(&cascade));
+            near = far;
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Draws `instances` into every cascade.
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder, mesh: &Mesh, buffers: &InstanceBuffers, instances: Range<u32>) {
+        for (index, view) in self.layer_views.iter().enumerate() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("shadow_pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            debug_labels::marker(&mut render_pass, || format!("shadow cascade {index}"));
+
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, &self.cascade_bind_group, &[(index as u64 * self.cascade_stride) as u32]);
+            for (_, chunk, instances) in buffers.split(instances.clone()) {
+                render_pass.set_vertex_buffer(2, chunk.transforms.slice());
+                mesh.draw_instanced(&mut render_pass, chunk.positions_vsh.buffer(), instances);
+            }
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use std::ops::Range;
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::Point3;
+use wgpu::util::DeviceExt;
+
+use super::{debug_labels, instances::InstanceBuffers, mesh::Mesh, shader::ShaderLoader};
+
+/// Mirrors `Params` in `sort.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct SortParams {
+    eye: [f32; 4],
+    count: u32,
+    padded_len: u32,
+    first: u32,
+    keys: u32,
+    sorted_first: u32,
+    sorted_count: u32,
+    target_chunk: u32,
+    _padding: u32,
+}
+
+/// Mirrors `Step` in `sort.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct SortStep {
+    block: u32,
+    distance: u32,
+    _padding: [u32; 2],
+}
+
+/// Sorted instances held by the slots of one instance chunk, in drawing
+/// order. Sized like the chunk, so they stay within the same limits.
+struct SortedChunk {
+    positions: wgpu::Buffer,
+    transforms: wgpu::Buffer,
+    colors: wgpu::Buffer,
+    count: u32,
+}
+
+/// Gathers the instances of the `source` chunk that sorted into the slots
+/// of the `target` chunk. The params of the pair of a chunk with itself are
+/// also those of its distance keys.
+struct SortPair {
+    source: usize,
+    target: usize,
+    params_buffer: wgpu::Buffer,
+    /// For either side of the simulation state.
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+/// Orders the instances back to front for blending. Every frame a bitonic
+/// sort on the GPU orders the indices of every instance by distance to the
+/// eye, and the positions, transforms and colors are copied in that order
+/// into vertex buffers drawn with the transparent pipeline.
+///
+/// The keys of all chunks are sorted together, so instances blend in order
+/// across chunks. Copying them back out takes a pass for every pair of
+/// chunks, since a pass only binds the instances of one. Expired instances,
+/// without lifetime left in the w of their velocity, sort after the others
+/// and are left out of the indirect draws, counted while computing the keys.
+pub struct InstanceSorter {
+    /// Of the distance keys and the sorting network.
+    keys_layout: wgpu::BindGroupLayout,
+    gather_layout: wgpu::BindGroupLayout,
+    keys_pipeline: wgpu::ComputePipeline,
+    step_pipeline: wgpu::ComputePipeline,
+    gather_pipeline: wgpu::ComputePipeline,
+    /// Every step of the sorting network for the largest supported length,
+    /// shorter sorts run a prefix of them.
+    steps_buffer: wgpu::Buffer,
+    step_stride: u64,
+    /// The live instance count, then the indirect draw of every sorted chunk.
+    counts_buffer: wgpu::Buffer,
+    /// Of every chunk, for either side of the simulation state.
+    keys_bind_groups: Vec<[wgpu::BindGroup; 2]>,
+    sorted: Vec<SortedChunk>,
+    pairs: Vec<SortPair>,
+    /// Instances of every chunk.
+    count: u32,
+    padded_len: u32,
+}
+
+impl InstanceSorter {
+    const WORKGROUP_SIZE: u32 = 256;
+    /// Sorts of up to 2^31 instances.
+    const MAX_STAGES: u32 = 31;
+    const KEY_SIZE: u64 = std::mem::size_of::<u32>() as u64;
+    /// The live count before the draws in `Counts` of `sort.wgsl`.
+    const LIVE_SIZE: u64 = std::mem::size_of::<u32>() as u64;
+    const DRAW_SIZE: u64 = std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64;
+
+    fn padded_len(instances: &InstanceBuffers) -> u64 {
+        (instances.len() as u64).next_power_of_two().max(2)
+    }
+
+    /// Whether the keys of every instance fit one storage binding.
+    pub fn fits(limits: &wgpu::Limits, instances: &InstanceBuffers) -> bool {
+        let size = Self::padded_len(instances) * Self::KEY_SIZE;
+        size <= limits.max_storage_buffer_binding_size as u64 && size <= limits.max_buffer_size
+    }
+
+    pub fn new(device: &wgpu::Device, shaders: &ShaderLoader, instances: &InstanceBuffers) -> Self {
+        let module = shaders.module(device, "sort.wgsl", include_str!("../shaders/sort.wgsl"));
+
+        let entry = |binding, ty, has_dynamic_offset| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // Split in two so neither binds more than the 8 storage buffers every adapter offers
+        let keys_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("sort_keys"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Uniform, false),
+                entry(1, wgpu::BufferBindingType::Uniform, true),
+                entry(2, wgpu::BufferBindingType::Storage { read_only: false }, false),
+                entry(3, wgpu::BufferBindingType::Storage { read_only: false }, false),
+                entry(4, wgpu::BufferBindingType::Storage { read_only: true }, false),
+                entry(10, wgpu::BufferBindingType::Storage { read_only: true }, false),
+                entry(11, wgpu::BufferBindingType::Storage { read_only: false }, false),
+            ],
+        });
+        let gather_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("sort_gather"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Uniform, false),
+                entry(3, wgpu::BufferBindingType::Storage { read_only: false }, false),
+                entry(4, wgpu::BufferBindingType::Storage { read_only: true }, false),
+                entry(5, wgpu::BufferBindingType::Storage { read_only: true }, false),
+                entry(6, wgpu::BufferBindingType::Storage { read_only: true }, false),
+                entry(7, wgpu::BufferBindingType::Storage { read_only: false }, false),
+                entry(8, wgpu::BufferBindingType::Storage { read_only: false }, false),
+                entry(9, wgpu::BufferBindingType::Storage { read_only: false }, false),
+                entry(11, wgpu::BufferBindingType::Storage { read_only: false }, false),
+            ],
+        });
+
+        // Merges of growing blocks, each by halving compare distances
+        let step_stride = (std::mem::size_of::<SortStep>() as u64)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let mut steps = Vec::new();
+        for stage in 1..=Self::MAX_STAGES {
+            for shift in (0..stage).rev() {
+                let step = SortStep {
+                    block: 1 << stage,
+                    distance: 1 << shift,
+                    _padding: [0; 2],
+                };
+                steps.extend_from_slice(bytemuck::bytes_of(&step));
+                steps.resize(steps.len().next_multiple_of(step_stride as usize), 0);
+            }
+        }
+        let steps_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("sort_steps"),
+            contents: &steps,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let pipeline = |layout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("sort_pipeline_layout"),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        let mut sorter = Self {
+            keys_pipeline: pipeline(&keys_layout, "distance_keys"),
+            step_pipeline: pipeline(&keys_layout, "bitonic_step"),
+            gather_pipeline: pipeline(&gather_layout, "gather"),
+            keys_layout,
+            gather_layout,
+            steps_buffer,
+            step_stride,
+            counts_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("sort_counts"),
+                size: Self::LIVE_SIZE + Self::DRAW_SIZE,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }),
+            keys_bind_groups: Vec::new(),
+            sorted: Vec::new(),
+            pairs: Vec::new(),
+            count: 0,
+            padded_len: 0,
+        };
+        sorter.resize(device, instances);
+        sorter
+    }
+
+    /// Recreates the sort buffers after the instance buffers changed.
+    pub fn resize(&mut self, device: &wgpu::Device, instances: &InstanceBuffers) {
+        let buffer = |label, size: u64, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::STORAGE | usage,
+                mapped_at_creation: false,
+            })
+        };
+        self.count = instances.len();
+        self.padded_len = Self::padded_len(instances).min(u32::MAX as u64) as u32;
+        let keys = buffer("sort_keys", self.padded_len as u64 * Self::KEY_SIZE, wgpu::BufferUsages::empty());
+        let values = buffer("sort_values", self.padded_len as u64 * Self::KEY_SIZE, wgpu::BufferUsages::empty());
+        let chunks = instances.chunks.len();
+        self.counts_buffer = buffer(
+            "sort_counts",
+            Self::LIVE_SIZE + chunks.max(1) as u64 * Self::DRAW_SIZE,
+            wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+        );
+
+        self.sorted = instances
+            .chunks
+            .iter()
+            .map(|chunk| SortedChunk {
+                positions: buffer("sorted_positions", chunk.positions[0].size(), wgpu::BufferUsages::VERTEX),
+                transforms: buffer("sorted_transforms", chunk.transforms.size(), wgpu::BufferUsages::VERTEX),
+                colors: buffer("sorted_colors", chunk.colors.size(), wgpu::BufferUsages::VERTEX),
+                count: chunk.range.len() as u32,
+            })
+            .collect();
+
+        self.pairs = (0..chunks * chunks)
+            .map(|pair| {
+                let (source, target) = (pair % chunks, pair / chunks);
+                let (chunk, sorted) = (&instances.chunks[source], &self.sorted[target]);
+                let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("sort_params"),
+                    size: std::mem::size_of::<SortParams>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+
+                let bind_groups = [0, 1].map(|side| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("sort_gather"),
+                        layout: &self.gather_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: params_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: values.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 4,
+                                resource: chunk.positions[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 5,
+                                resource: chunk.transforms.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 6,
+                                resource: chunk.colors.as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 7,
+                                resource: sorted.positions.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 8,
+                                resource: sorted.transforms.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 9,
+                                resource: sorted.colors.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 11,
+                                resource: self.counts_buffer.as_entire_binding(),
+                            },
+                        ],
+                    })
+                });
+
+                SortPair {
+                    source,
+                    target,
+                    params_buffer,
+                    bind_groups,
+                }
+            })
+            .collect();
+
+        self.keys_bind_groups = instances
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let params_buffer = &self.pairs[index * chunks + index].params_buffer;
+                [0, 1].map(|side| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("sort_keys"),
+                        layout: &self.keys_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: params_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                    buffer: &self.steps_buffer,
+                                    offset: 0,
+                                    size: wgpu::BufferSize::new(std::mem::size_of::<SortStep>() as u64),
+                                }),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: keys.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: values.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 4,
+                                resource: chunk.positions[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 10,
+                                resource: chunk.velocities[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 11,
+                                resource: self.counts_buffer.as_entire_binding(),
+                            },
+                        ],
+                    })
+                })
+            })
+            .collect();
+    }
+
+    /// Workgroups covering `threads`, wrapped into rows past the dispatch limit.
+    fn dispatch(compute_pass: &mut wgpu::ComputePass, threads: u32, max_groups: u32) {
+        let groups = threads.div_ceil(Self::WORKGROUP_SIZE);
+        compute_pass.dispatch_workgroups(groups.clamp(1, max_groups), groups.div_ceil(max_groups).max(1), 1);
+    }
+
+    /// Keys the pass of a chunk writes. Those past the last instance are
+    /// padding, written along with the last chunk's.
+    fn keys(&self, chunk: &Range<u32>) -> u32 {
+        if chunk.end == self.count {
+            self.padded_len - chunk.start
+        } else {
+            chunk.len() as u32
+        }
+    }
+
+    /// Records the sort of every instance drawn with `mesh` by distance to
+    /// `eye`, reading the `front` side of the simulation state of `instances`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        instances: &InstanceBuffers,
+        front: usize,
+        mesh: &Mesh,
+        eye: Point3<f32>,
+    ) {
+        let Some(first_keys) = self.keys_bind_groups.first() else {
+            return;
+        };
+        for pair in &self.pairs {
+            let (source, target) = (&instances.chunks[pair.source].range, &instances.chunks[pair.target].range);
+            let params = SortParams {
+                eye: [eye.x, eye.y, eye.z, 1.0],
+                count: source.len() as u32,
+                padded_len: self.padded_len,
+                first: source.start,
+                keys: self.keys(source),
+                sorted_first: target.start,
+                sorted_count: target.len() as u32,
+                target_chunk: pair.target as u32,
+                _padding: 0,
+            };
+            queue.write_buffer(&pair.params_buffer, 0, bytemuck::bytes_of(&params));
+        }
+        let draw = wgpu::util::DrawIndexedIndirectArgs {
+            index_count: mesh.element_count(),
+            instance_count: 0,
+            first_index: 0,
+            base_vertex: 0,
+            first_instance: 0,
+        };
+        let mut counts = vec![0; Self::LIVE_SIZE as usize];
+        for _ in &self.sorted {
+            counts.extend_from_slice(draw.as_bytes());
+        }
+        queue.write_buffer(&self.counts_buffer, 0, &counts);
+
+        let max_groups = device.limits().max_compute_workgroups_per_dimension;
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("sort_pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.keys_pipeline);
+        for (index, (chunk, bind_groups)) in instances.chunks.iter().zip(&self.keys_bind_groups).enumerate() {
+            debug_labels::marker(&mut compute_pass, || format!("chunk {index}: {} distance keys", chunk.range.len()));
+            compute_pass.set_bind_group(0, &bind_groups[front], &[0]);
+            Self::dispatch(&mut compute_pass, self.keys(&chunk.range), max_groups);
+        }
+
+        let stages = self.padded_len.trailing_zeros();
+        debug_labels::marker(&mut compute_pass, || format!("sort {} instances in {stages} stages", self.count));
+        compute_pass.set_pipeline(&self.step_pipeline);
+        for step in 0..stages * (stages + 1) / 2 {
+            compute_pass.set_bind_group(0, &first_keys[front], &[(step as u64 * self.step_stride) as u32]);
+            Self::dispatch(&mut compute_pass, self.padded_len / 2, max_groups);
+        }
+
+        compute_pass.set_pipeline(&self.gather_pipeline);
+        for pair in &self.pairs {
+            debug_labels::marker(&mut compute_pass, || format!("gather chunk {} into chunk {}", pair.source, pair.target));
+            compute_pass.set_bind_group(0, &pair.bind_groups[front], &[]);
+            Self::dispatch(&mut compute_pass, self.sorted[pair.target].count, max_groups);
+        }
+    }
+
+    /// Draws the live sorted instances with a pipeline using the default
+    /// vertex streams and blending, farthest chunk of slots first.
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass, mesh: &Mesh) {
+        for (index, sorted) in self.sorted.iter().enumerate() {
+            render_pass.set_vertex_buffer(2, sorted.transforms.slice(..));
+            render_pass.set_vertex_buffer(3, sorted.colors.slice(..));
+            let offset = Self::LIVE_SIZE + index as u64 * Self::DRAW_SIZE;
+            mesh.draw_indexed_indirect(render_pass, sorted.positions.slice(..), &self.counts_buffer, offset);
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::{debug_labels, frames::FrameRing, instances::InstanceBuffers, shader::ShaderLoader};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct ReduceParams {
+    count: u32,
+    first_group: u32,
+    _padding: [u32; 2],
+}
+
+/// Mirrors `Partial` in `statistics.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct Partial {
+    /// Min, max and sum of speeds, instance count.
+    speeds: [f32; 4],
+    /// Kinetic and potential energy.
+    energy: [f32; 2],
+    _padding0: [f32; 2],
+    /// Sum of positions.
+    center: [f32; 3],
+    _padding1: f32,
+}
+
+/// Mirrors `Result` in `statistics.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct ReduceResult {
+    totals: Partial,
+    bins: [u32; HISTOGRAM_BINS],
+}
+
+/// A reduction's result read back together with the settings it ran with.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct StatisticsReadback {
+    result: ReduceResult,
+    /// Histogram range and gravity.
+    settings: [f32; 4],
+}
+
+pub const HISTOGRAM_BINS: usize = 32;
+
+/// Aggregate instance state. Energies are per unit mass, summed over all
+/// instances, with the potential of the attractor at the origin.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimulationStats {
+    pub min: f32,
+    pub max: f32,
+    pub average: f32,
+    /// Instance counts of equal speed ranges from 0 to `histogram_max`.
+    pub histogram: [u32; HISTOGRAM_BINS],
+    pub histogram_max: f32,
+    pub kinetic: f32,
+    pub potential: f32,
+    /// Center of mass, every instance weighs the same.
+    pub center: [f32; 3],
+}
+
+impl SimulationStats {
+    const CHART_ROWS: usize = 6;
+
+    /// Histogram range for the next reduction, fitted to the fastest
+    /// instance of the previous one.
+    pub fn next_histogram_max(previous: Option<&SimulationStats>) -> f32 {
+        const DEFAULT: f32 = 100.0;
+        const MIN: f32 = 1.0;
+
+        previous.map_or(DEFAULT, |stats| stats.max.max(MIN))
+    }
+
+    /// CPU counterpart of `statistics.wgsl`, for devices without compute shaders.
+    pub fn from_state(positions: &[[f32; 4]], velocities: &[[f32; 4]], histogram_max: f32, gravity: f32) -> Self {
+        let mut stats = Self {
+            min: f32::INFINITY,
+            histogram_max,
+            ..Default::default()
+        };
+        let (mut sum, mut kinetic, mut potential) = (0.0, 0.0, 0.0);
+        let mut center = Vector3::new(0.0, 0.0, 0.0);
+        for (p, v) in positions.iter().zip(velocities) {
+            let speed_squared = v[0] * v[0] + v[1] * v[1] + v[2] * v[2];
+            let speed = speed_squared.sqrt();
+            stats.min = stats.min.min(speed);
+            stats.max = stats.max.max(speed);
+            sum += speed as f64;
+
+            let bin = (speed / histogram_max * HISTOGRAM_BINS as f32) as usize;
+            stats.histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+
+            let position = Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64);
+            kinetic += 0.5 * speed_squared as f64;
+            potential -= gravity as f64 / position.magnitude().max(1.0);
+            center += position;
+        }
+
+        let count = velocities.len().max(1) as f64;
+        stats.min = stats.min.min(stats.max);
+        stats.average = (sum / count) as f32;
+        stats.kinetic = kinetic as f32;
+        stats.potential = potential as f32;
+        stats.center = (center / count).cast().map_or(stats.center, Into::into);
+        stats
+    }
+
+    pub fn energy(&self) -> f32 {
+        self.kinetic + self.potential
+    }
+
+    /// Total energy and center of mass relative to `reference`, which
+    /// changes of the integrator or time step should keep close to zero.
+    pub fn drift_text(&self, reference: &SimulationStats) -> String {
+        let energy_drift = (self.energy() - reference.energy()) / reference.energy().abs().max(f32::EPSILON);
+        format!(
+            "Energy {:.4e} ({:+.3}%) center drift {:.2}",
+            self.energy(),
+            energy_drift * 100.0,
+            (Vector3::from(self.center) - Vector3::from(reference.center)).magnitude(),
+        )
+    }
+
+    /// Summary lines followed by the histogram as a bar chart, one column
+    /// per bin. Drift is only shown against a `reference`.
+    pub fn overlay_text(&self, reference: Option<&SimulationStats>) -> String {
+        let mut text = format!("Speed min {:.1} avg {:.1} max {:.1}\n", self.min, self.average, self.max);
+        if let Some(reference) = reference {
+            text += &self.drift_text(reference);
+            text.push('\n');
+        }
+
+        let tallest = self.histogram.iter().copied().max().unwrap_or_default().max(1);
+        for row in (0..Self::CHART_ROWS).rev() {
+            for &count in &self.histogram {
+                // Any instances at all get at least the bottom row
+                let height = (count as u64 * Self::CHART_ROWS as u64).div_ceil(tallest as u64) as usize;
+                text.push(if height > row { '\u{2588}' } else { ' ' });
+            }
+            text.push('\n');
+        }
+
+        let max = format!("{:.0}", self.histogram_max);
+        text += &format!("0{max:>width$}", width = HISTOGRAM_BINS - 1);
+        text
+    }
+}
+
+/// Reduces the state of every instance to [`SimulationStats`] on the GPU.
+/// Each workgroup reduces its instances to a partial result, a single
+/// workgroup then combines the partials, and only the totals and the
+/// histogram are read back into the frame ring. Readbacks complete
+/// asynchronously during device polls.
+pub struct SimulationStatistics {
+    instances_pipeline: wgpu::ComputePipeline,
+    partials_pipeline: wgpu::ComputePipeline,
+    /// One pair per instance chunk, for either side of the state, with its
+    /// workgroup counts.
+    bind_groups: Vec<([wgpu::BindGroup; 2], (u32, u32))>,
+    settings_buffer: wgpu::Buffer,
+    result_buffer: wgpu::Buffer,
+}
+
+impl SimulationStatistics {
+    const WORKGROUP_SIZE: u32 = 256;
+    const PARTIAL_SIZE: u64 = std::mem::size_of::<Partial>() as u64;
+    const RESULT_SIZE: u64 = std::mem::size_of::<ReduceResult>() as u64;
+    const SETTINGS_SIZE: u64 = std::mem::size_of::<[f32; 4]>() as u64;
+    const READBACK: &str = "statistics";
+
+    pub fn new(device: &wgpu::Device, instances: &InstanceBuffers, shaders: &ShaderLoader) -> Self {
+        let module = shaders.module(device, "statistics.wgsl", include_str!("../shaders/statistics.wgsl"));
+        let max_groups = device.limits().max_compute_workgroups_per_dimension;
+
+        // Extra workgroups of a two dimensional dispatch still write their
+        // partials, so every chunk gets a slot for all of them
+        let mut first_group = 0;
+        let chunks: Vec<_> = instances
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let count = chunk.range.len() as u32;
+                let groups = count.div_ceil(Self::WORKGROUP_SIZE);
+                let dispatch = (groups.clamp(1, max_groups), groups.div_ceil(max_groups).max(1));
+                let params = ReduceParams {
+                    count,
+                    first_group,
+                    _padding: [0; 2],
+                };
+                first_group += dispatch.0 * dispatch.1;
+                (params, dispatch)
+            })
+            .collect();
+
+        let partials_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("statistics_partials"),
+            size: first_group.max(1) as u64 * Self::PARTIAL_SIZE,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("statistics_result"),
+            size: Self::RESULT_SIZE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("statistics_settings"),
+            size: Self::SETTINGS_SIZE,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("statistics"),
+            entries: &[
+                entry(0, wgpu::BufferBindingType::Storage { read_only: true }),
+                entry(1, wgpu::BufferBindingType::Uniform),
+                entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+                entry(4, wgpu::BufferBindingType::Uniform),
+                entry(5, wgpu::BufferBindingType::Storage { read_only: true }),
+            ],
+        });
+        let bind_groups = instances
+            .chunks
+            .iter()
+            .zip(chunks)
+            .map(|(chunk, (params, dispatch))| {
+                let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("statistics_params"),
+                    contents: bytemuck::bytes_of(&params),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let bind_groups = [0, 1].map(|side| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("statistics"),
+                        layout: &bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: chunk.velocities[side].as_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: params_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: partials_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: result_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 4,
+                                resource: settings_buffer.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 5,
+                                resource: chunk.positions[side].as_binding(),
+                            },
+                        ],
+                    })
+                });
+                (bind_groups, dispatch)
+            })
+            .collect();
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("statistics_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            instances_pipeline: create_pipeline("reduce_instances"),
+            partials_pipeline: create_pipeline("reduce_partials"),
+            bind_groups,
+            settings_buffer,
+            result_buffer,
+        }
+    }
+
+    /// Records the reduction of the `front` side of the state and the copy
+    /// of its result into the current slot of `frames`, unless that slot's
+    /// previous result hasn't been taken yet. Returns whether anything was
+    /// recorded, in which case [`FrameRing::map_readbacks`] has to follow
+    /// the submission.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        frames: &mut FrameRing,
+        front: usize,
+        histogram_max: f32,
+        gravity: f32,
+    ) -> bool {
+        if self.bind_groups.is_empty() {
+            return false;
+        }
+        let Some(readback_buffer) =
+            frames.readback(device, Self::READBACK, Self::RESULT_SIZE + Self::SETTINGS_SIZE)
+        else {
+            return false;
+        };
+
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[histogram_max, gravity, 0.0, 0.0]));
+        encoder.clear_buffer(&self.result_buffer, 0, None);
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("statistics_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.instances_pipeline);
+            for (index, (bind_groups, dispatch)) in self.bind_groups.iter().enumerate() {
+                debug_labels::marker(&mut compute_pass, || format!("reduce chunk {index}"));
+                compute_pass.set_bind_group(0, &bind_groups[front], &[]);
+                compute_pass.dispatch_workgroups(dispatch.0, dispatch.1, 1);
+            }
+
+            debug_labels::marker(&mut compute_pass, || "reduce partials".to_string());
+            compute_pass.set_pipeline(&self.partials_pipeline);
+            compute_pass.set_bind_group(0, &self.bind_groups[0].0[front], &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&self.result_buffer, 0, readback_buffer, 0, Self::RESULT_SIZE);
+        encoder.copy_buffer_to_buffer(
+            &self.settings_buffer,
+            0,
+            readback_buffer,
+            Self::RESULT_SIZE,
+            Self::SETTINGS_SIZE,
+        );
+        true
+    }
+
+    /// Returns the latest result whose readback finished. Completion is
+    /// noticed during device polls, so call this after polling.
+    pub fn take(frames: &mut FrameRing) -> Option<SimulationStats> {
+        let StatisticsReadback { result, settings } = frames.take_readback(Self::READBACK)?;
+
+        let [min, max, sum, count] = result.totals.speeds;
+        Some(SimulationStats {
+            min: min.min(max),
+            max,
+            average: sum / count.max(1.0),
+            histogram: result.bins,
+            histogram_max: settings[0],
+            kinetic: result.totals.energy[0],
+            potential: result.totals.energy[1],
+            center: result.totals.center.map(|sum| sum / count.max(1.0)),
+        })
+    }
+}
@@ -0,0 +1,429 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::{self, File},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+
+use cgmath::Point3;
+use wgpu::util::DeviceExt;
+
+use super::{culling::Aabb, instances::{InstanceColor, InstanceTransform}, shader::ShaderFeatures};
+
+#[derive(Debug, Clone)]
+pub struct DatasetError {
+    pub message: String,
+}
+
+impl DatasetError {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Display for DatasetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for DatasetError {}
+pub type DatasetResult<T> = Result<T, DatasetError>;
+
+enum PointData {
+    Mapped(memmap2::Mmap),
+    Owned(Vec<[f32; 4]>),
+}
+
+/// Points in the instance layout, either memory-mapped from a file of
+/// little-endian `[f32; 4]` records or decoded into memory by an importer.
+pub struct PointDataset {
+    data: PointData,
+    /// Colors of imported points, drawn with `POINT_COLOR`.
+    colors: Option<Vec<InstanceColor>>,
+    /// File a mapped dataset was read from.
+    path: Option<PathBuf>,
+    /// Shader features needed to draw the points, e.g. `POINT_COLOR` when
+    /// they have colors.
+    features: ShaderFeatures,
+}
+
+impl PointDataset {
+    const RECORD_SIZE: usize = std::mem::size_of::<[f32; 4]>();
+
+    pub fn open(path: &Path) -> DatasetResult<Self> {
+        let error = |e: std::io::Error| DatasetError::new(format!("Failed to map {}: {e}", path.display()));
+
+        let file = File::open(path).map_err(error)?;
+        // Safety: the file is treated as read-only input, changes to it while
+        // mapped only garble the displayed points
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(error)?;
+        if map.len() % Self::RECORD_SIZE != 0 {
+            return Err(DatasetError::new(format!(
+                "{} is not a whole number of {}-byte points",
+                path.display(),
+                Self::RECORD_SIZE
+            )));
+        }
+
+        Ok(Self {
+            data: PointData::Mapped(map),
+            colors: None,
+            path: Some(path.to_owned()),
+            features: ShaderFeatures::NONE,
+        })
+    }
+
+    pub fn from_points(points: Vec<[f32; 4]>, colors: Option<Vec<InstanceColor>>) -> Self {
+        Self {
+            data: PointData::Owned(points),
+            colors,
+            path: None,
+            features: ShaderFeatures::NONE,
+        }
+    }
+
+    pub fn with_features(self, features: ShaderFeatures) -> Self {
+        Self { features, ..self }
+    }
+
+    pub fn features(&self) -> ShaderFeatures {
+        self.features
+    }
+
+    pub fn points(&self) -> &[[f32; 4]] {
+        match &self.data {
+            // Mappings are page aligned, so the cast can't fail
+            PointData::Mapped(map) => bytemuck::cast_slice(map),
+            PointData::Owned(points) => points,
+        }
+    }
+
+    pub fn colors(&self) -> Option<&[InstanceColor]> {
+        self.colors.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.points().len()
+    }
+}
+
+/// A block read by a worker.
+struct LoadedBlock {
+    block: usize,
+    points: Vec<[f32; 4]>,
+    colors: Option<Vec<InstanceColor>>,
+}
+
+struct Slot {
+    buffer: wgpu::Buffer,
+    /// Present when the dataset has colors.
+    colors: Option<wgpu::Buffer>,
+    block: Option<usize>,
+    len: u32,
+}
+
+/// Streams fixed-size blocks of a dataset into GPU buffers around the
+/// camera. Worker threads copy blocks out of the mapping, which is where
+/// pages actually get read from disk, and the farthest blocks are evicted
+/// once the resident budget is used up.
+pub struct DatasetStreamer {
+    dataset: Arc<PointDataset>,
+    /// Empty until `block_bounds` arrives.
+    blocks: Vec<Aabb>,
+    block_bounds: Option<mpsc::Receiver<Vec<Aabb>>>,
+    slots: Vec<Slot>,
+    /// Slot of each resident block.
+    resident: HashMap<usize, usize>,
+    pending: HashSet<usize>,
+    requests: mpsc::Sender<usize>,
+    loaded: mpsc::Receiver<LoadedBlock>,
+    /// A block's worth of identity transforms, points aren't rotated or scaled.
+    transforms: wgpu::Buffer,
+    /// A block's worth of white, for datasets without colors.
+    colors: wgpu::Buffer,
+}
+
+impl DatasetStreamer {
+    pub const BLOCK_LEN: usize = 65536;
+    pub const DEFAULT_RESIDENT_BLOCKS: usize = 256;
+    /// Requests queued to the workers at once, so a fast moving camera
+    /// doesn't pile up loads of blocks it already left behind.
+    const MAX_PENDING: usize = 16;
+
+    pub fn new(device: &wgpu::Device, dataset: PointDataset, resident_blocks: usize) -> Self {
+        let dataset = Arc::new(dataset);
+        let workers = thread::available_parallelism().map_or(2, |n| n.get().min(4));
+
+        let block_count = dataset.len().div_ceil(Self::BLOCK_LEN);
+        log::info!(
+            "Streaming {} points in {} blocks of {}.",
+            dataset.len(),
+            block_count,
+            Self::BLOCK_LEN
+        );
+
+        // Read from the cache or computed in the background, nothing streams
+        // in until they're known
+        let (bounds_sender, block_bounds) = mpsc::channel();
+        {
+            let dataset = dataset.clone();
+            thread::spawn(move || _ = bounds_sender.send(Self::load_block_bounds(&dataset, workers)));
+        }
+
+        let slots = (0..resident_blocks.min(block_count))
+            .map(|_| Slot {
+                buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("stream_block"),
+                    size: (Self::BLOCK_LEN * PointDataset::RECORD_SIZE) as u64,
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                colors: dataset.colors().map(|_| {
+                    device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("stream_block_colors"),
+                        size: (Self::BLOCK_LEN * std::mem::size_of::<InstanceColor>()) as u64,
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    })
+                }),
+                block: None,
+                len: 0,
+            })
+            .collect();
+
+        let (requests, request_receiver) = mpsc::channel::<usize>();
+        let (loaded_sender, loaded) = mpsc::channel();
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+        for _ in 0..workers {
+            let dataset = dataset.clone();
+            let request_receiver = request_receiver.clone();
+            let loaded_sender = loaded_sender.clone();
+            // Workers exit once the streamer drops its request sender
+            thread::spawn(move || {
+                loop {
+                    // Bound first so the lock is released while copying
+                    let Ok(block) = request_receiver.lock().unwrap().recv() else {
+                        break;
+                    };
+                    let range = Self::block_range(&dataset, block);
+                    let loaded = LoadedBlock {
+                        block,
+                        points: dataset.points()[range.clone()].to_vec(),
+                        colors: dataset.colors().map(|colors| colors[range].to_vec()),
+                    };
+                    if loaded_sender.send(loaded).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let transforms = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("stream_transforms"),
+            contents: bytemuck::cast_slice(&vec![InstanceTransform::IDENTITY; Self::BLOCK_LEN]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let colors = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("stream_colors"),
+            contents: bytemuck::cast_slice(&vec![InstanceColor::WHITE; Self::BLOCK_LEN]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        Self {
+            dataset,
+            blocks: Vec::new(),
+            block_bounds: Some(block_bounds),
+            slots,
+            resident: HashMap::new(),
+            pending: HashSet::new(),
+            requests,
+            loaded,
+            transforms,
+            colors,
+        }
+    }
+
+    fn block_range(dataset: &PointDataset, block: usize) -> Range<usize> {
+        let start = block * Self::BLOCK_LEN;
+        start..(start + Self::BLOCK_LEN).min(dataset.len())
+    }
+
+    /// Cache of the block bounds of a mapped dataset, next to its file.
+    fn bounds_cache(dataset: &PointDataset) -> Option<PathBuf> {
+        let mut path = dataset.path.as_ref()?.as_os_str().to_owned();
+        path.push(".bounds");
+        Some(PathBuf::from(path))
+    }
+
+    /// Bounds of every block from the cache, or computed and cached when it's
+    /// missing or older than the dataset.
+    fn load_block_bounds(dataset: &PointDataset, workers: usize) -> Vec<Aabb> {
+        let Some(cache) = Self::bounds_cache(dataset) else {
+            return Self::block_bounds(dataset, workers);
+        };
+        if let Some(blocks) = Self::read_block_bounds(dataset, &cache) {
+            log::info!("Block bounds read from {}.", cache.display());
+            return blocks;
+        }
+
+        let blocks = Self::block_bounds(dataset, workers);
+        let mut bytes = bytemuck::bytes_of(&[dataset.len() as u64, Self::BLOCK_LEN as u64]).to_vec();
+        for bounds in &blocks {
+            let corners = [bounds.min.x, bounds.min.y, bounds.min.z, bounds.max.x, bounds.max.y, bounds.max.z];
+            bytes.extend_from_slice(bytemuck::bytes_of(&corners));
+        }
+        match fs::write(&cache, bytes) {
+            Ok(()) => log::info!("Block bounds cached in {}.", cache.display()),
+            Err(e) => log::warn!("Failed to cache block bounds in {}: {e}.", cache.display()),
+        }
+        blocks
+    }
+
+    /// Reads a cache of the point count and block length followed by the
+    /// corners of every block, `None` when it doesn't match the dataset.
+    fn read_block_bounds(dataset: &PointDataset, cache: &Path) -> Option<Vec<Aabb>> {
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        if modified(cache)? < modified(dataset.path.as_ref()?)? {
+            return None;
+        }
+
+        let bytes = fs::read(cache).ok()?;
+        let header_size = std::mem::size_of::<[u64; 2]>();
+        let block_count = dataset.len().div_ceil(Self::BLOCK_LEN);
+        if bytes.len() != header_size + block_count * std::mem::size_of::<[f32; 6]>() {
+            return None;
+        }
+        let header: [u64; 2] = bytemuck::pod_read_unaligned(&bytes[..header_size]);
+        if header != [dataset.len() as u64, Self::BLOCK_LEN as u64] {
+            return None;
+        }
+
+        let corners: Vec<[f32; 6]> = bytemuck::pod_collect_to_vec(&bytes[header_size..]);
+        Some(
+            corners
+                .into_iter()
+                .map(|[x0, y0, z0, x1, y1, z1]| Aabb {
+                    min: Point3::new(x0, y0, z0),
+                    max: Point3::new(x1, y1, z1),
+                })
+                .collect(),
+        )
+    }
+
+    /// Bounds of every block, computed in parallel since this touches the whole file.
+    fn block_bounds(dataset: &PointDataset, workers: usize) -> Vec<Aabb> {
+        let points = dataset.points();
+        let block_count = points.len().div_ceil(Self::BLOCK_LEN);
+        let per_worker = block_count.div_ceil(workers).max(1);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..block_count)
+                .step_by(per_worker)
+                .map(|first| {
+                    scope.spawn(move || {
+                        (first..(first + per_worker).min(block_count))
+                            .map(|block| {
+                                let mut bounds = Aabb::empty();
+                                points[Self::block_range(dataset, block)]
+                                    .iter()
+                                    .for_each(|p| bounds.extend(*p));
+                                bounds
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        })
+    }
+
+    /// Requests blocks within `radius` of `eye`, nearest first, evicts the ones
+    /// that fell out of the resident set and uploads finished loads.
+    pub fn update(&mut self, queue: &wgpu::Queue, eye: Point3<f32>, radius: f32) {
+        if let Some(block_bounds) = &self.block_bounds {
+            match block_bounds.try_recv() {
+                Ok(blocks) => {
+                    self.blocks = blocks;
+                    self.block_bounds = None;
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    log::error!("Failed to compute the block bounds");
+                    self.block_bounds = None;
+                }
+            }
+        }
+
+        let mut nearby: Vec<(f32, usize)> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(i, bounds)| (bounds.distance2(eye), i))
+            .filter(|&(distance2, _)| distance2 <= radius * radius)
+            .collect();
+        nearby.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        nearby.truncate(self.slots.len());
+        let wanted: HashSet<usize> = nearby.iter().map(|&(_, block)| block).collect();
+
+        self.resident.retain(|block, &mut slot| {
+            let keep = wanted.contains(block);
+            if !keep {
+                self.slots[slot].block = None;
+            }
+            keep
+        });
+
+        for &(_, block) in &nearby {
+            if self.pending.len() >= Self::MAX_PENDING {
+                break;
+            }
+            if !self.resident.contains_key(&block) && self.pending.insert(block) {
+                _ = self.requests.send(block);
+            }
+        }
+
+        while let Ok(LoadedBlock { block, points, colors }) = self.loaded.try_recv() {
+            self.pending.remove(&block);
+            if !wanted.contains(&block) {
+                continue;
+            }
+            let Some(slot) = self.slots.iter().position(|slot| slot.block.is_none()) else {
+                continue;
+            };
+
+            queue.write_buffer(&self.slots[slot].buffer, 0, bytemuck::cast_slice(&points));
+            if let (Some(buffer), Some(colors)) = (&self.slots[slot].colors, colors) {
+                queue.write_buffer(buffer, 0, bytemuck::cast_slice(&colors));
+            }
+            self.slots[slot].block = Some(block);
+            self.slots[slot].len = points.len() as u32;
+            self.resident.insert(block, slot);
+        }
+    }
+
+    /// Instance and color buffers of resident blocks with their point counts.
+    pub fn resident(&self) -> impl Iterator<Item = (&wgpu::Buffer, &wgpu::Buffer, u32)> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.block.is_some())
+            .map(|slot| (&slot.buffer, slot.colors.as_ref().unwrap_or(&self.colors), slot.len))
+    }
+
+    /// Transforms to draw any resident block with.
+    pub fn transforms(&self) -> &wgpu::Buffer {
+        &self.transforms
+    }
+
+    pub fn resident_points(&self) -> u64 {
+        self.resident().map(|(_, _, len)| len as u64).sum()
+    }
+
+    pub fn dataset(&self) -> &PointDataset {
+        &self.dataset
+    }
+}
@@ -34,6 +34,34 @@ pub struct Texture2d {
 impl Texture2d {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+    /// What the device can do with `format`, the adapter's own capabilities
+    /// only when the device has them enabled.
+    pub fn format_features(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::TextureFormatFeatures {
+        if device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(device.features())
+        }
+    }
+
+    /// Sample counts `color_format` can be rendered and resolved with along
+    /// with [`Self::DEPTH_FORMAT`], ascending and always including 1.
+    pub fn sample_counts(adapter: &wgpu::Adapter, device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Vec<u32> {
+        let color = Self::format_features(adapter, device, color_format).flags;
+        let depth = Self::format_features(adapter, device, Self::DEPTH_FORMAT).flags;
+        let resolve = color.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+        [1, 2, 4, 8, 16]
+            .into_iter()
+            .filter(|&count| {
+                count == 1 || (resolve && color.sample_count_supported(count) && depth.sample_count_supported(count))
+            })
+            .collect()
+    }
+
     pub fn from_file(
         filename: &str,
         device: &wgpu::Device,
@@ -219,15 +247,66 @@ impl Texture2d {
         }
     }
 
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        size: (u32, u32),
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: size.0.max(1),
+            height: size.1.max(1),
+            depth_or_array_layers: 1,
+        };
+
+        let desc = wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            size,
+        }
+    }
+
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         sample_count: u32,
         label: Option<&str>,
+    ) -> Self {
+        Self::create_sized_depth_texture(device, (config.width, config.height), sample_count, label)
+    }
+
+    pub fn create_sized_depth_texture(
+        device: &wgpu::Device,
+        size: (u32, u32),
+        sample_count: u32,
+        label: Option<&str>,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width.max(1),
-            height: config.height.max(1),
+            width: size.0.max(1),
+            height: size.1.max(1),
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -0,0 +1,276 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    time::Instant,
+};
+
+/// Clamps raw frame deltas and smooths them with a short moving average,
+/// so stalls (window drags, shader compiles) don't blow up the simulation.
+pub struct DeltaSmoother {
+    max_step: f64,
+    window: usize,
+    history: VecDeque<f64>,
+}
+
+impl DeltaSmoother {
+    pub fn new(max_step: f64, window: usize) -> Self {
+        Self {
+            max_step,
+            window: window.max(1),
+            history: VecDeque::with_capacity(window.max(1)),
+        }
+    }
+
+    pub fn push(&mut self, raw_delta: f64) -> f64 {
+        if self.history.len() == self.window {
+            self.history.pop_front();
+        }
+        self.history.push_back(raw_delta.clamp(0.0, self.max_step));
+
+        self.history.iter().sum::<f64>() / self.history.len() as f64
+    }
+}
+
+impl Default for DeltaSmoother {
+    fn default() -> Self {
+        Self::new(1.0 / 15.0, 8)
+    }
+}
+
+/// Splits frame deltas into whole simulation steps of a fixed length, so the
+/// simulation advances the same way at any frame rate. The remainder carries
+/// over to the next frame.
+pub struct FixedTimestep {
+    /// Seconds simulated by every step.
+    pub step: f64,
+    /// Most steps run in one frame. Time beyond them is dropped, so a
+    /// simulation slower than real time falls behind instead of taking
+    /// ever more steps per frame.
+    pub max_steps: u32,
+    accumulator: f64,
+}
+
+impl FixedTimestep {
+    pub const DEFAULT_STEP: f64 = 1.0 / 60.0;
+    pub const DEFAULT_MAX_STEPS: u32 = 8;
+
+    pub fn new(step: f64, max_steps: u32) -> Self {
+        Self {
+            step,
+            max_steps: max_steps.max(1),
+            accumulator: 0.0,
+        }
+    }
+
+    /// Number of steps due after `delta` more seconds.
+    pub fn advance(&mut self, delta: f64) -> u32 {
+        self.accumulator += delta;
+        let due = (self.accumulator / self.step).floor();
+        let steps = (due as u32).min(self.max_steps);
+        self.accumulator = if due > self.max_steps as f64 {
+            0.0
+        } else {
+            self.accumulator - steps as f64 * self.step
+        };
+
+        steps
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_STEP, Self::DEFAULT_MAX_STEPS)
+    }
+}
+
+/// Run of a fixed number of simulation steps, one per frame whatever the
+/// frame took, so seeded runs simulate the same trajectories. The positions
+/// after the last step are summed up in a digest to compare runs by.
+pub struct Replay {
+    pub steps: u32,
+    simulated: u32,
+}
+
+impl Replay {
+    pub fn new(steps: u32) -> Self {
+        Self { steps, simulated: 0 }
+    }
+
+    pub fn record(&mut self, steps: u32) {
+        self.simulated += steps;
+    }
+
+    pub fn finished(&self) -> bool {
+        self.simulated >= self.steps
+    }
+
+    /// FNV-1a over the bits of the positions, so any difference shows.
+    pub fn digest(positions: &[[f32; 3]]) -> u64 {
+        bytemuck::cast_slice::<_, u8>(positions)
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3))
+    }
+}
+
+/// Present-to-present interval collection for frame-pacing analysis.
+///
+/// Intervals are counted into logarithmic bins a percent wide rather than
+/// kept, so runs of any length take the same memory. Percentiles and the
+/// stutter threshold are as precise as the bins, the mean and variance exact.
+pub struct FrameStats {
+    last_present: Option<Instant>,
+    /// Interval counts, see [`Self::bin`].
+    bins: Vec<u32>,
+    frames: usize,
+    mean: f64,
+    /// Sum of squared differences from the mean, updated as in Welford's
+    /// algorithm.
+    m2: f64,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self {
+            last_present: None,
+            bins: vec![0; Self::BINS],
+            frames: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameStatsReport {
+    pub frames: usize,
+    pub average_fps: f64,
+    /// FPS at the 99th percentile frame time.
+    pub low_1_fps: f64,
+    /// FPS at the 99.9th percentile frame time.
+    pub low_01_fps: f64,
+    /// Frames that took more than twice the median frame time.
+    pub stutters: usize,
+    /// Variance of present-to-present intervals, in ms².
+    pub variance_ms2: f64,
+}
+
+impl FrameStats {
+    const STUTTER_FACTOR: f64 = 2.0;
+    /// Lower edge of the first bin in seconds, shorter intervals count in it.
+    const MIN_INTERVAL: f64 = 1.0e-4;
+    /// Upper to lower edge of every bin.
+    const BIN_RATIO: f64 = 1.01;
+    /// Up to about 15 seconds, longer intervals count in the last bin.
+    const BINS: usize = 1200;
+
+    pub fn record_present(&mut self, now: Instant) {
+        if let Some(last) = self.last_present.replace(now) {
+            self.record((now - last).as_secs_f64());
+        }
+    }
+
+    fn record(&mut self, interval: f64) {
+        self.frames += 1;
+        let delta = interval - self.mean;
+        self.mean += delta / self.frames as f64;
+        self.m2 += delta * (interval - self.mean);
+        self.bins[Self::bin(interval)] += 1;
+    }
+
+    fn bin(interval: f64) -> usize {
+        let bin = (interval / Self::MIN_INTERVAL).ln() / Self::BIN_RATIO.ln();
+        (bin.max(0.0) as usize).min(Self::BINS - 1)
+    }
+
+    /// Geometric center of a bin.
+    fn interval(bin: usize) -> f64 {
+        Self::MIN_INTERVAL * Self::BIN_RATIO.powf(bin as f64 + 0.5)
+    }
+
+    /// Forgets collected intervals while keeping the last present time.
+    pub fn reset(&mut self) {
+        *self = Self {
+            last_present: self.last_present,
+            ..Self::default()
+        };
+    }
+
+    pub fn report(&self) -> Option<FrameStatsReport> {
+        if self.frames == 0 {
+            return None;
+        }
+
+        let percentile = |p: f64| {
+            let rank = ((self.frames - 1) as f64 * p).round() as usize;
+            let mut counted = 0;
+            let bin = self.bins.iter().position(|&count| {
+                counted += count as usize;
+                counted > rank
+            });
+            Self::interval(bin.unwrap_or(Self::BINS - 1))
+        };
+        let median = percentile(0.5);
+
+        Some(FrameStatsReport {
+            frames: self.frames,
+            average_fps: 1.0 / self.mean,
+            low_1_fps: 1.0 / percentile(0.99),
+            low_01_fps: 1.0 / percentile(0.999),
+            stutters: (Self::bin(median * Self::STUTTER_FACTOR) + 1..Self::BINS)
+                .map(|bin| self.bins[bin] as usize)
+                .sum(),
+            variance_ms2: self.m2 / self.frames as f64 * 1.0e6,
+        })
+    }
+}
+
+impl Display for FrameStatsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} frames, avg {:.1} FPS, 1% low {:.1} FPS, 0.1% low {:.1} FPS, {} stutters, variance {:.3} ms²",
+            self.frames,
+            self.average_fps,
+            self.low_1_fps,
+            self.low_01_fps,
+            self.stutters,
+            self.variance_ms2,
+        )
+    }
+}
+
+/// Time from submitting a frame until presenting it returned, which
+/// includes waiting for a free swapchain image with FIFO presentation.
+///
+/// Only the part after the frame's input was sampled, not input-to-photon
+/// latency: the compositor and display add their own time after presenting.
+#[derive(Default)]
+pub struct LatencyTracker {
+    submitted: Option<Instant>,
+    samples: Vec<f64>,
+}
+
+impl LatencyTracker {
+    /// Call right after submitting the frame's last command buffer.
+    pub fn record_submit(&mut self) {
+        self.submitted = Some(Instant::now());
+    }
+
+    /// Call once presenting the submitted frame returned.
+    pub fn record_present(&mut self) {
+        if let Some(submitted) = self.submitted.take() {
+            self.samples.push(submitted.elapsed().as_secs_f64());
+        }
+    }
+
+    /// Average latency in seconds of frames presented since the last call.
+    pub fn take_average(&mut self) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let average = self.samples.iter().sum::<f64>() / self.samples.len() as f64;
+        self.samples.clear();
+        Some(average)
+    }
+}
@@ -0,0 +1,226 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{scene, shader::ShaderLoader, texture::Texture2d};
+
+/// Curve the HDR scene is brought into the displayable range with.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMapper {
+    /// Filmic, with soft highlights and more contrast.
+    #[default]
+    Aces,
+    /// Plain `x / (1 + x)`, flatter but never clips.
+    Reinhard,
+}
+
+impl ToneMapper {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "aces" => Some(Self::Aces),
+            "reinhard" => Some(Self::Reinhard),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Zeroable, Pod)]
+struct ToneMappingUniform {
+    /// Exponent the mapped colors are raised to, see [`scene::gamma_exponent`].
+    gamma: f32,
+    _padding: [f32; 3],
+}
+
+/// Maps the scene, rendered into a floating point target, onto the surface,
+/// or the upscaler's input, in a fullscreen pass. Lighting can exceed 1
+/// without clipping that way. Text labels are drawn with the scene, so
+/// they're tone mapped too. The scene is already exposed, the output gamma
+/// is applied after the curve.
+pub struct ToneMapping {
+    tone_mapper: ToneMapper,
+    /// Of the target mapped onto.
+    format: wgpu::TextureFormat,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    layout: wgpu::BindGroupLayout,
+    aces_pipeline: wgpu::RenderPipeline,
+    reinhard_pipeline: wgpu::RenderPipeline,
+}
+
+impl ToneMapping {
+    /// Format of the scene and everything drawn into it.
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    /// Whether the device renders to [`Self::FORMAT`] and resolves it, with
+    /// however many samples [`Texture2d::sample_counts`] allows.
+    pub fn supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        let features = Texture2d::format_features(adapter, device, Self::FORMAT);
+        features.allowed_usages.contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            && features.flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+    }
+
+    /// `format` is the one of the target mapped onto, `scene` the resolved
+    /// scene in [`Self::FORMAT`] at the internal resolution and `gamma` the
+    /// output's.
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLoader,
+        format: wgpu::TextureFormat,
+        tone_mapper: ToneMapper,
+        scene: &Texture2d,
+        gamma: f32,
+    ) -> Self {
+        let module = shaders.module(device, "tonemap.wgsl", include_str!("../shaders/tonemap.wgsl"));
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("tone_mapping"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("tone_mapping_uniform"),
+            contents: bytemuck::bytes_of(&ToneMappingUniform {
+                gamma: scene::gamma_exponent(format, gamma),
+                _padding: [0.0; 3],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("tone_mapping_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                depth_stencil: None,
+                multiview: None,
+                cache: None,
+            })
+        };
+        let aces_pipeline = pipeline("aces_pipeline", "aces");
+        let reinhard_pipeline = pipeline("reinhard_pipeline", "reinhard");
+
+        let bind_group = Self::create_bind_group(device, &layout, scene, &uniform_buffer);
+        log::info!("Rendering in {:?}, tone mapped with {:?}.", Self::FORMAT, tone_mapper);
+
+        Self {
+            tone_mapper,
+            format,
+            uniform_buffer,
+            bind_group,
+            layout,
+            aces_pipeline,
+            reinhard_pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        scene: &Texture2d,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("tone_mapping"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&scene.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Rebinds the scene after it was recreated for a new internal resolution.
+    pub fn resize(&mut self, device: &wgpu::Device, scene: &Texture2d) {
+        self.bind_group = Self::create_bind_group(device, &self.layout, scene, &self.uniform_buffer);
+    }
+
+    /// Takes effect with the next frame.
+    pub fn set_gamma(&self, queue: &wgpu::Queue, gamma: f32) {
+        let uniform = ToneMappingUniform {
+            gamma: scene::gamma_exponent(self.format, gamma),
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    pub fn tone_mapper(&self) -> ToneMapper {
+        self.tone_mapper
+    }
+
+    /// Takes effect with the next frame, both curves are built up front.
+    pub fn set_tone_mapper(&mut self, tone_mapper: ToneMapper) {
+        self.tone_mapper = tone_mapper;
+    }
+
+    /// Maps the scene onto `target`, which must be at the internal resolution.
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("tone_mapping_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(match self.tone_mapper {
+            ToneMapper::Aces => &self.aces_pipeline,
+            ToneMapper::Reinhard => &self.reinhard_pipeline,
+        });
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,302 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use super::{shader::ShaderLoader, texture::Texture2d};
+
+/// How the scene is brought from the internal resolution to the window's.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Upscaler {
+    /// A single bilinear sample, cheapest and blurriest.
+    #[default]
+    Bilinear,
+    /// Edge adaptive upsampling followed by contrast adaptive sharpening,
+    /// after AMD FidelityFX Super Resolution 1.
+    Fsr,
+}
+
+impl Upscaler {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bilinear" => Some(Self::Bilinear),
+            "fsr" => Some(Self::Fsr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UpscaleSettings {
+    /// Internal resolution relative to the window, 1 renders at native resolution.
+    pub render_scale: f32,
+    pub upscaler: Upscaler,
+    /// Sharpening of `Upscaler::Fsr` in stops below the maximum, 0 is the sharpest.
+    pub sharpness: f32,
+}
+
+impl Default for UpscaleSettings {
+    fn default() -> Self {
+        Self {
+            render_scale: 1.0,
+            upscaler: Upscaler::default(),
+            sharpness: 0.2,
+        }
+    }
+}
+
+impl UpscaleSettings {
+    pub const MIN_RENDER_SCALE: f32 = 0.25;
+
+    /// Bilinear at native resolution is a plain copy, which is skipped.
+    pub fn enabled(&self) -> bool {
+        self.render_scale < 1.0 || self.upscaler != Upscaler::Bilinear
+    }
+
+    pub fn internal_size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let scale = |size: u32| ((size as f32 * self.render_scale).round() as u32).max(1);
+        (scale(width), scale(height))
+    }
+}
+
+/// Mirrors `Params` in `upscale.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct UpscaleUniform {
+    sharpness: f32,
+    _padding: [f32; 3],
+}
+
+/// Bind groups reading the scene and the textures sized by the window.
+struct UpscaleTargets {
+    /// Size of the scene read.
+    internal_size: (u32, u32),
+    scene_bind_group: wgpu::BindGroup,
+    /// Upsampled scene at the native resolution, sharpened onto the surface.
+    /// Only `Upscaler::Fsr` has a second pass.
+    reconstructed: Option<(Texture2d, wgpu::BindGroup)>,
+}
+
+impl UpscaleTargets {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        format: wgpu::TextureFormat,
+        settings: &UpscaleSettings,
+        scene: &Texture2d,
+        native_size: (u32, u32),
+    ) -> Self {
+        let bind_group = |source: &Texture2d| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("upscale"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&source.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+
+        let reconstructed = (settings.upscaler == Upscaler::Fsr).then(|| {
+            let texture = Texture2d::create_render_target(device, native_size, format, Some("upscale_reconstructed"));
+            let bind_group = bind_group(&texture);
+            (texture, bind_group)
+        });
+
+        Self {
+            internal_size: (scene.size.width, scene.size.height),
+            scene_bind_group: bind_group(scene),
+            reconstructed,
+        }
+    }
+}
+
+/// Reconstructs the scene, resolved at the internal resolution, onto the
+/// surface. Text labels are drawn with the scene, so they're upscaled too.
+pub struct Upscale {
+    settings: UpscaleSettings,
+    format: wgpu::TextureFormat,
+    targets: UpscaleTargets,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    upscale_pipeline: wgpu::RenderPipeline,
+    sharpen_pipeline: wgpu::RenderPipeline,
+}
+
+impl Upscale {
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLoader,
+        format: wgpu::TextureFormat,
+        settings: UpscaleSettings,
+        scene: &Texture2d,
+        native_size: (u32, u32),
+    ) -> Self {
+        let module = shaders.module(device, "upscale.wgsl", include_str!("../shaders/upscale.wgsl"));
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("upscale"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("upscale_params"),
+            contents: bytemuck::bytes_of(&UpscaleUniform {
+                sharpness: (-settings.sharpness).exp2(),
+                _padding: [0.0; 3],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("upscale_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: Some("vs_main"),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point: Some(entry_point),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                depth_stencil: None,
+                multiview: None,
+                cache: None,
+            })
+        };
+        let upscale_pipeline = match settings.upscaler {
+            Upscaler::Bilinear => pipeline("bilinear_pipeline", "bilinear"),
+            Upscaler::Fsr => pipeline("easu_pipeline", "easu"),
+        };
+        let sharpen_pipeline = pipeline("rcas_pipeline", "rcas");
+
+        let targets = UpscaleTargets::new(device, &layout, &uniform_buffer, format, &settings, scene, native_size);
+        log::info!(
+            "Upscaling with {:?} from {:?} to {:?}.",
+            settings.upscaler,
+            targets.internal_size,
+            native_size,
+        );
+
+        Self {
+            settings,
+            format,
+            targets,
+            layout,
+            uniform_buffer,
+            upscale_pipeline,
+            sharpen_pipeline,
+        }
+    }
+
+    /// Rebinds the scene, and recreates the targets, for a new window size.
+    /// `scene` has to be at the internal size of [`Self::settings`].
+    pub fn resize(&mut self, device: &wgpu::Device, scene: &Texture2d, native_size: (u32, u32)) {
+        self.targets = UpscaleTargets::new(
+            device,
+            &self.layout,
+            &self.uniform_buffer,
+            self.format,
+            &self.settings,
+            scene,
+            native_size,
+        );
+    }
+
+    pub fn settings(&self) -> &UpscaleSettings {
+        &self.settings
+    }
+
+    pub fn internal_size(&self) -> (u32, u32) {
+        self.targets.internal_size
+    }
+
+    /// Upscales the scene onto `target`, which must be at the native resolution.
+    pub fn record(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let targets = &self.targets;
+        match &targets.reconstructed {
+            Some((reconstructed, bind_group)) => {
+                Self::fullscreen_pass(encoder, "upscale_pass", &reconstructed.view, &self.upscale_pipeline, &targets.scene_bind_group);
+                Self::fullscreen_pass(encoder, "sharpen_pass", target, &self.sharpen_pipeline, bind_group);
+            }
+            None => Self::fullscreen_pass(encoder, "upscale_pass", target, &self.upscale_pipeline, &targets.scene_bind_group),
+        }
+    }
+
+    fn fullscreen_pass(
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        target: &wgpu::TextureView,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,152 @@
+use super::{
+    instances::InstanceColor,
+    stream::{DatasetError, DatasetResult},
+};
+
+struct VoxModel {
+    size: [u32; 3],
+    /// x, y, z and palette index of every voxel.
+    voxels: Vec<[u8; 4]>,
+}
+
+/// Reads the models of a MagicaVoxel `.vox` file into cube instances and
+/// their palette colors, with `w` holding the run length along x, see
+/// [`merge_runs`].
+///
+/// The scene graph is ignored, models are laid out next to each other along x.
+pub fn read_vox(data: &[u8], merge: bool) -> DatasetResult<(Vec<[f32; 4]>, Vec<InstanceColor>)> {
+    const MODEL_GAP: u32 = 4;
+
+    if data.len() < 8 || &data[0..4] != b"VOX " {
+        return Err(DatasetError::new("Missing VOX signature".to_string()));
+    }
+
+    let mut models = Vec::new();
+    let mut palette = None;
+    let mut size = None;
+
+    // Chunks nest inside MAIN, but every chunk of interest is a leaf, so
+    // walking them in file order is enough
+    let mut at = 8;
+    while at + 12 <= data.len() {
+        let id = &data[at..at + 4];
+        let content_len = u32_at(data, at + 4) as usize;
+        let content_start = at + 12;
+        let content = data
+            .get(content_start..content_start + content_len)
+            .ok_or_else(|| DatasetError::new("VOX chunk is truncated".to_string()))?;
+
+        match id {
+            b"MAIN" => {}
+            b"SIZE" if content.len() >= 12 => {
+                size = Some([u32_at(content, 0), u32_at(content, 4), u32_at(content, 8)]);
+            }
+            b"XYZI" if content.len() >= 4 => {
+                let count = u32_at(content, 0) as usize;
+                let voxels = content[4..]
+                    .chunks_exact(4)
+                    .take(count)
+                    .map(|v| [v[0], v[1], v[2], v[3]])
+                    .collect();
+                models.push(VoxModel {
+                    size: size.take().unwrap_or([256; 3]),
+                    voxels,
+                });
+            }
+            b"RGBA" if content.len() >= 1024 => {
+                let mut colors = [[0; 4]; 256];
+                for (color, bytes) in colors.iter_mut().zip(content.chunks_exact(4)) {
+                    *color = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                }
+                palette = Some(colors);
+            }
+            _ => {}
+        }
+
+        // MAIN's children follow its (empty) content directly
+        at = content_start + content_len;
+    }
+
+    if models.is_empty() {
+        return Err(DatasetError::new("VOX file has no models".to_string()));
+    }
+
+    let palette = palette.unwrap_or_else(|| {
+        log::warn!("VOX file has no palette, using a gray ramp.");
+        std::array::from_fn(|i| [i as u8, i as u8, i as u8, 255])
+    });
+
+    let mut instances = Vec::new();
+    let mut offset = 0;
+    for model in &mut models {
+        // Palette entry i - 1 holds the color of index i
+        let color = |index: u8| palette[(index as usize + 255) % 256];
+        instances.extend(if merge {
+            merge_runs(&mut model.voxels, offset, color)
+        } else {
+            model
+                .voxels
+                .iter()
+                .map(|v| voxel_instance(v, offset, 1, color(v[3])))
+                .collect()
+        });
+        offset += model.size[0] + MODEL_GAP;
+    }
+
+    log::info!(
+        "Read {} voxel models as {} instances.",
+        models.len(),
+        instances.len()
+    );
+    Ok(instances.into_iter().unzip())
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+/// Instance at the center of a run of `len` voxels starting at `v` along x.
+/// VOX files are z-up, instances are y-up.
+fn voxel_instance(v: &[u8; 4], offset: u32, len: u8, color: [u8; 4]) -> ([f32; 4], InstanceColor) {
+    let position = [
+        (offset + v[0] as u32) as f32 + (len - 1) as f32 * 0.5,
+        v[2] as f32,
+        v[1] as f32,
+        len as f32,
+    ];
+    (position, InstanceColor { color, material: 0 })
+}
+
+/// Merges runs of same-colored voxels along x into single stretched instances,
+/// which cuts the instance count of solid models several times over.
+fn merge_runs(
+    voxels: &mut [[u8; 4]],
+    offset: u32,
+    color: impl Fn(u8) -> [u8; 4],
+) -> Vec<([f32; 4], InstanceColor)> {
+    voxels.sort_unstable_by_key(|v| (v[2], v[1], v[0]));
+
+    let mut instances = Vec::new();
+    let mut run: Option<([u8; 4], u8)> = None;
+    for v in voxels.iter() {
+        match &mut run {
+            Some((start, len))
+                if (start[1], start[2], start[3]) == (v[1], v[2], v[3])
+                    && start[0] as u32 + *len as u32 == v[0] as u32
+                    && *len < u8::MAX =>
+            {
+                *len += 1;
+            }
+            _ => {
+                if let Some((start, len)) = run.replace((*v, 1)) {
+                    instances.push(voxel_instance(&start, offset, len, color(start[3])));
+                }
+            }
+        }
+    }
+    if let Some((start, len)) = run {
+        instances.push(voxel_instance(&start, offset, len, color(start[3])));
+    }
+
+    instances
+}
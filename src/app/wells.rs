@@ -0,0 +1,65 @@
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Point3, Vector3};
+
+/// Mirrors `GravityWell` in `compute.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+pub struct GravityWell {
+    pub position: [f32; 3],
+    /// Pull of the well like a body of this mass, negative ones push.
+    pub strength: f32,
+}
+
+/// Points dropped into the world with the middle mouse button that pull
+/// instances in, or push them away with Shift held. Every kernel feels them
+/// through `pull_wells` in `compute.wgsl`, the oldest one makes room once
+/// there are [`Self::MAX_WELLS`].
+pub struct GravityWells {
+    wells: Vec<GravityWell>,
+    /// Strength of the wells dropped from now on.
+    pub strength: f32,
+}
+
+impl Default for GravityWells {
+    fn default() -> Self {
+        Self {
+            wells: Vec::new(),
+            // Pulls at 100 units per second squared from 200 units away
+            strength: 4.0e6,
+        }
+    }
+}
+
+impl GravityWells {
+    pub const MAX_WELLS: usize = 16;
+    /// Distance ahead of the camera wells are dropped at when the view
+    /// doesn't meet the ground plane closer than that.
+    pub const DROP_DISTANCE: f32 = 500.0;
+
+    pub fn wells(&self) -> &[GravityWell] {
+        &self.wells
+    }
+
+    /// Drops a well where the ray from `eye` along `direction` meets the
+    /// ground plane, or [`Self::DROP_DISTANCE`] along it.
+    pub fn drop(&mut self, eye: Point3<f32>, direction: Vector3<f32>, repel: bool) -> Point3<f32> {
+        let direction = direction.normalize();
+        let distance = Some(-eye.y / direction.y)
+            .filter(|&t| t > 0.0 && t < Self::DROP_DISTANCE)
+            .unwrap_or(Self::DROP_DISTANCE);
+        let position = eye + direction * distance;
+
+        if self.wells.len() == Self::MAX_WELLS {
+            self.wells.remove(0);
+        }
+        self.wells.push(GravityWell {
+            position: position.into(),
+            strength: if repel { -self.strength } else { self.strength },
+        });
+        position
+    }
+
+    pub fn clear(&mut self) {
+        self.wells.clear();
+    }
+}
@@ -1,4 +1,4 @@
-use app::App;
+use app::{App, AppConfig};
 use window::GameWindow;
 use winit::event_loop::EventLoop;
 
@@ -8,8 +8,20 @@ mod window;
 fn main() {
     pretty_env_logger::init();
 
+    let config = match AppConfig::from_args(std::env::args().skip(1)) {
+        Ok(Some(config)) => config,
+        Ok(None) => {
+            println!("{}", AppConfig::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{}", AppConfig::USAGE);
+            std::process::exit(2);
+        }
+    };
+
     let event_loop = EventLoop::new().unwrap();
-    let mut window = GameWindow::<App>::new("My app");
+    let mut window = GameWindow::<App>::new("My app", config);
 
     event_loop.run_app(&mut window)
         .expect("Error occured while running application");
@@ -0,0 +1,91 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Mirrors `BloomUniform` in post.rs
+struct Params {
+    // Brightness glowing starts at, eased in over `knee` below it
+    threshold: f32,
+    knee: f32,
+    // Share of the blurred glow added onto the scene
+    intensity: f32,
+};
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+// Single triangle covering the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn tap(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(source, source_sampler, uv).rgb;
+}
+
+// Halves the resolution, the center and four diagonal taps half a source
+// texel out, after the dual filter of Bjørge's "Bandwidth-Efficient
+// Rendering"
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let d = 0.5 / vec2<f32>(textureDimensions(source));
+    var color = tap(uv) * 4.0;
+    color += tap(uv + vec2(-d.x, -d.y));
+    color += tap(uv + vec2(d.x, -d.y));
+    color += tap(uv + vec2(-d.x, d.y));
+    color += tap(uv + vec2(d.x, d.y));
+    return color / 8.0;
+}
+
+// Doubles the resolution with a tent of eight taps around the texel
+fn upsample(uv: vec2<f32>) -> vec3<f32> {
+    let d = 0.5 / vec2<f32>(textureDimensions(source));
+    var color = tap(uv + vec2(-2.0 * d.x, 0.0));
+    color += tap(uv + vec2(2.0 * d.x, 0.0));
+    color += tap(uv + vec2(0.0, -2.0 * d.y));
+    color += tap(uv + vec2(0.0, 2.0 * d.y));
+    color += tap(uv + vec2(-d.x, -d.y)) * 2.0;
+    color += tap(uv + vec2(d.x, -d.y)) * 2.0;
+    color += tap(uv + vec2(-d.x, d.y)) * 2.0;
+    color += tap(uv + vec2(d.x, d.y)) * 2.0;
+    return color / 12.0;
+}
+
+// First downsample of the scene, keeping only what's brighter than the
+// threshold. A quadratic knee keeps the cutoff from flickering.
+@fragment
+fn prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = max(downsample(in.uv), vec3(0.0));
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - params.threshold + params.knee, 0.0, 2.0 * params.knee);
+    soft = soft * soft / (4.0 * params.knee + 1.0e-5);
+    let contribution = max(soft, brightness - params.threshold) / max(brightness, 1.0e-5);
+    return vec4(color * contribution, 1.0);
+}
+
+@fragment
+fn downsample_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(downsample(in.uv), 1.0);
+}
+
+// Added onto the next larger level, which keeps its own downsample
+@fragment
+fn upsample_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(upsample(in.uv), 1.0);
+}
+
+// Added onto the scene, the alpha it's blended with is left alone
+@fragment
+fn composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(upsample(in.uv) * params.intensity, 0.0);
+}
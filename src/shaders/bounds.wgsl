@@ -0,0 +1,62 @@
+struct Piece {
+    // First instance of the piece in the bound chunk
+    first: u32,
+    count: u32,
+    // Where its bounds go in `bounds`
+    slot: u32,
+};
+
+struct Bounds {
+    min: vec3<f32>,
+    // Of the fastest instance
+    speed: f32,
+    max: vec3<f32>,
+};
+
+@group(0) @binding(0)
+var<storage, read> positions: array<vec4<f32>>;
+@group(0) @binding(1)
+var<storage, read> velocities: array<vec4<f32>>;
+// One per workgroup, the part of a culling chunk held by the bound instance chunk
+@group(0) @binding(2)
+var<storage, read> pieces: array<Piece>;
+@group(0) @binding(3)
+var<storage, read_write> bounds: array<Bounds>;
+
+const WORKGROUP_SIZE: u32 = 256u;
+const EMPTY: Bounds = Bounds(vec3(3.4e38), 0.0, vec3(-3.4e38));
+
+var<workgroup> shared_bounds: array<Bounds, WORKGROUP_SIZE>;
+
+fn combine(a: Bounds, b: Bounds) -> Bounds {
+    return Bounds(min(a.min, b.min), max(a.speed, b.speed), max(a.max, b.max));
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn reduce_bounds(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) group: vec3<u32>,
+) {
+    let piece = pieces[group.x];
+
+    var value = EMPTY;
+    for (var i = local; i < piece.count; i += WORKGROUP_SIZE) {
+        let position = positions[piece.first + i].xyz;
+        let speed = length(velocities[piece.first + i].xyz);
+        value = combine(value, Bounds(position, speed, position));
+    }
+
+    // Tree reduction over the workgroup, every invocation has to take part
+    shared_bounds[local] = value;
+    workgroupBarrier();
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if local < stride {
+            shared_bounds[local] = combine(shared_bounds[local], shared_bounds[local + stride]);
+        }
+        workgroupBarrier();
+    }
+
+    if local == 0u {
+        bounds[piece.slot] = shared_bounds[0];
+    }
+}
@@ -0,0 +1,58 @@
+// Stream-compacts the instances of a chunk still alive, with lifetime left in
+// the w of their velocity, into one list drawn indirectly. Survivors are
+// counted with an atomic straight into the arguments of the draw, like the
+// levels of detail, so their order changes from frame to frame.
+
+struct Params {
+    count: u32,
+};
+
+// Mirrors `wgpu::util::DrawIndexedIndirectArgs`
+struct Draw {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+struct Transform {
+    rotation: vec4<f32>,
+    scale: vec4<f32>,
+};
+
+const WORKGROUP_SIZE: u32 = 256u;
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> positions: array<vec4<f32>>;
+@group(0) @binding(2)
+var<storage, read> velocities: array<vec4<f32>>;
+@group(0) @binding(3)
+var<storage, read> transforms: array<Transform>;
+// RGBA8 colors and material ids
+@group(0) @binding(4)
+var<storage, read> colors: array<vec2<u32>>;
+@group(0) @binding(5)
+var<storage, read_write> live_positions: array<vec4<f32>>;
+@group(0) @binding(6)
+var<storage, read_write> live_transforms: array<Transform>;
+@group(0) @binding(7)
+var<storage, read_write> live_colors: array<vec2<u32>>;
+@group(0) @binding(8)
+var<storage, read_write> draw: Draw;
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn compact(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    // Large dispatches wrap into rows of workgroups
+    let i = id.x + id.y * groups.x * WORKGROUP_SIZE;
+    if i >= params.count || velocities[i].w <= 0.0 {
+        return;
+    }
+
+    let slot = atomicAdd(&draw.instance_count, 1u);
+    live_positions[slot] = positions[i];
+    live_transforms[slot] = transforms[i];
+    live_colors[slot] = colors[i];
+}
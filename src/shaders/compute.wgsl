@@ -1,31 +1,914 @@
+struct Frame {
+    dimensions: vec4<u32>,
+    resolution: vec2<f32>,
+    time: f32,
+    delta: f32,
+    frame_index: u32,
+};
+
 @group(0) @binding(0)
+var<uniform> frame: Frame;
+
+struct Transform {
+    // Unit quaternion
+    rotation: vec4<f32>,
+    // Scale in xyz, spin about the local x axis in radians per second in w
+    scale: vec4<f32>,
+};
+
+// The state is double buffered: kernels read the previous step from the
+// `_in` arrays and write the next one, then the host swaps them around
+@group(1) @binding(0)
 var<storage, read_write> positions: array<vec4<f32>>;
-@group(0) @binding(1)
+// w of the velocities is the lifetime left in seconds, counting down
+@group(1) @binding(1)
 var<storage, read_write> velocities: array<vec4<f32>>;
+@group(1) @binding(2)
+var<storage, read_write> transforms: array<Transform>;
+@group(1) @binding(3)
+var<storage, read> positions_in: array<vec4<f32>>;
+@group(1) @binding(4)
+var<storage, read> velocities_in: array<vec4<f32>>;
+// Scratch of the fluid kernel, shared by both sides of a chunk: the density
+// of every instance as f32 bits, the link to the next instance in the same
+// bucket of the spatial hash for every instance, then the head of every
+// bucket. Links and heads are instance indices plus 1, 0 ends a bucket.
+@group(1) @binding(5)
+var<storage, read_write> sph: array<atomic<u32>>;
 
-struct WorldInfo {
-    time: f32,
-    delta: f32,
+struct Collider {
+    // w is 0 for spheres and 1 for boxes
+    center: vec4<f32>,
+    // Radius or half extent
+    extent: vec4<f32>,
 };
 
-struct PushConstants {
-    dimensions: vec4<u32>,
-    world_info: WorldInfo,
+struct Collision {
+    colliders: array<Collider, MAX_COLLIDERS>,
+    count: u32,
+    // Fraction of the speed towards an obstacle kept when bouncing off it
+    restitution: f32,
+    // Fraction of the speed along an obstacle lost on every contact
+    friction: f32,
+    has_volume: u32,
+    // w is the cell size
+    volume_origin: vec4<f32>,
+    volume_size: vec4<u32>,
+};
+
+@group(2) @binding(0)
+var<uniform> collision: Collision;
+// Distances at the corners of the grid cells, R32Float isn't filterable
+@group(2) @binding(1)
+var volume: texture_3d<f32>;
+
+// Mirrors `ResetUniform` in reset.rs, bound by `reset_main` in place of the
+// collision with an offset per chunk
+struct ResetParams {
+    min: vec3<f32>,
+    // Largest velocity along each axis
+    speed: f32,
+    max: vec3<f32>,
+    lifetime: f32,
+    seed: u32,
+    // Index of the chunk's first instance
+    first: u32,
+    endless: u32,
+};
+
+@group(2) @binding(2)
+var<uniform> reset: ResetParams;
+
+// Mirrors `SimParams` in params.rs, tunable while the simulation runs
+struct SimParams {
+    // Strength of the attractor at the origin
+    gravity: f32,
+    // Rate per second the velocities decay at
+    drag: f32,
+    // Flocking without neighbour queries: instances steer towards cruising
+    // around the y axis on a shell, which is enough to keep a flock together
+    flock_radius: f32,
+    cruise_speed: f32,
+    steering: f32,
+    // Pull back to the ground plane per unit of height
+    wave_stiffness: f32,
+    // Keeps the pull of close bodies finite in the n-body kernels
+    softening: f32,
+    // Frequency of the curl noise per world unit and the speed of its flow
+    noise_scale: f32,
+    noise_speed: f32,
+    // Fluid particles interact within `sph_radius`, the cell size of their
+    // spatial hash, and settle at `sph_density` weighted neighbours
+    sph_radius: f32,
+    sph_density: f32,
+    sph_stiffness: f32,
+    sph_viscosity: f32,
+    // Downward acceleration of the fluid, which rests on the ground plane
+    sph_gravity: f32,
+};
+
+@group(3) @binding(0)
+var<uniform> params: SimParams;
+
+// Region instances are kept within
+struct WorldBounds {
+    // w is 0 for spheres and 1 for boxes
+    center: vec4<f32>,
+    // Radius or half extent
+    extent: vec3<f32>,
+    // 0: unbounded, 1: bounce, 2: wrap around
+    behavior: u32,
+};
+
+struct BoidParams {
+    // Distance within which other boids are seen
+    radius: f32,
+    // Weights of the steering rules: away from close neighbours, towards
+    // their average heading and towards their center
+    separation: f32,
+    alignment: f32,
+    cohesion: f32,
+    // Speed boids settle at
+    speed: f32,
+    // Instances looked at on either side in the buffer, which starts out
+    // spatially sorted, in place of a neighbour search
+    neighbors: u32,
+};
+
+// Respawns expired instances, see `respawn_main`
+struct EmitterParams {
+    position: vec3<f32>,
+    speed: f32,
+    // Longest lifetime of respawned instances
+    lifetime: f32,
+    // Instances respawned by the steps of this frame so far at most
+    limit: u32,
+    seed: u32,
+};
+
+// Pushed whenever the adapter has room for them
+struct HotConstants {
+    // xyz is the point ahead of the camera instances are pulled towards, w
+    // the acceleration towards it, 0 while off
+    follow: vec4<f32>,
+};
+
+struct ColdConstants {
+    bounds: WorldBounds,
+    boids: BoidParams,
+    emitter: EmitterParams,
+};
+
+// Set for every step, pushed when the adapter has room for all of them
+struct ComputePushConstants {
+    hot: HotConstants,
+    cold: ColdConstants,
+};
+
+// Constants that aren't pushed get their own entry for every step of a
+// frame, bound at its offset
+#ifdef PUSH_CONSTANTS
+var<push_constant> constants: ComputePushConstants;
+
+fn hot_constants() -> HotConstants {
+    return constants.hot;
 }
 
-var<push_constant> push_constants: PushConstants;
+fn cold_constants() -> ColdConstants {
+    return constants.cold;
+}
+#else
+#ifdef PUSH_HOT_CONSTANTS
+var<push_constant> hot: HotConstants;
+@group(3) @binding(2)
+var<uniform> cold: ColdConstants;
+
+fn hot_constants() -> HotConstants {
+    return hot;
+}
+
+fn cold_constants() -> ColdConstants {
+    return cold;
+}
+#else
+@group(3) @binding(2)
+var<uniform> constants: ComputePushConstants;
+
+fn hot_constants() -> HotConstants {
+    return constants.hot;
+}
+
+fn cold_constants() -> ColdConstants {
+    return constants.cold;
+}
+#endif
+#endif
+
+// Instances respawned this frame, cleared before the first step
+@group(3) @binding(1)
+var<storage, read_write> spawned: atomic<u32>;
+
+// Dropped with the middle mouse button, see wells.rs
+struct GravityWell {
+    position: vec3<f32>,
+    // Negative for wells pushing instances away
+    strength: f32,
+};
+
+struct GravityWells {
+    count: u32,
+    wells: array<GravityWell>,
+};
+
+@group(3) @binding(3)
+var<storage, read> gravity_wells: GravityWells;
+
+override WORKGROUP_SIZE_X: u32 = 8u;
+override WORKGROUP_SIZE_Y: u32 = 8u;
+override WORKGROUP_SIZE_Z: u32 = 4u;
+// Disable to let instances drift with their initial velocities
+override ATTRACT: bool = true;
+// 0: explicit Euler, 1: semi-implicit Euler, 2: velocity Verlet
+override INTEGRATOR: u32 = 1u;
+
+// Bodies loaded into workgroup memory at a time by `nbody_tiled_main`
+const NBODY_TILE: u32 = 256u;
+// Same as `Collision::MAX_COLLIDERS` in collision.rs
+const MAX_COLLIDERS: u32 = 8u;
+// Half the size of an instance cube
+const INSTANCE_RADIUS: f32 = 0.5;
+const NORMAL_EPSILON: f32 = 0.5;
+const FAR_AWAY: f32 = 3.4e38;
+// Step of the central differences taking the curl, in noise space
+const CURL_EPSILON: f32 = 0.05;
+// How quickly instances pick up the velocity of the noise flow, per second
+const CURL_RESPONSE: f32 = 2.0;
 
 fn force(p: vec3<f32>) -> vec3<f32> {
     let l = length(p);
     let d = -p / l;
 
-    return 1.0e9 * d / (l * l);
+    return params.gravity * d / (l * l);
+}
+
+// Pulls towards the y axis while pushing around it, clamped near the axis
+fn swirl(p: vec3<f32>) -> vec3<f32> {
+    let radial = vec3(p.x, 0.0, p.z);
+    let l = max(length(radial), 1.0);
+    let tangent = vec3(-p.z, 0.0, p.x) / l;
+
+    return params.gravity * (tangent - 0.5 * radial / l) / (l * l);
+}
+
+fn flock_velocity(p: vec3<f32>) -> vec3<f32> {
+    let l = max(length(p), 1.0);
+    let tangent = vec3(-p.z, 0.0, p.x) / max(length(p.xz), 1.0);
+
+    return params.cruise_speed * (tangent + (params.flock_radius - l) / params.flock_radius * p / l);
+}
+
+// Softened pull of a body of unit mass, or none when w is 0
+fn attraction(p: vec3<f32>, body: vec4<f32>) -> vec3<f32> {
+    let d = body.xyz - p;
+    let r2 = dot(d, d) + params.softening * params.softening;
+    return body.w * d * inverseSqrt(r2 * r2 * r2);
+}
+
+// PCG hash, see "Hash Functions for GPU Rendering" by Jarzynski and Olano
+fn pcg_hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Random value from -1 to 1 at a corner of the noise lattice
+fn lattice(c: vec3<i32>) -> f32 {
+    let h = pcg_hash(bitcast<u32>(c.x) ^ pcg_hash(bitcast<u32>(c.y) ^ pcg_hash(bitcast<u32>(c.z))));
+    return f32(h >> 8u) / 16777215.0 * 2.0 - 1.0;
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let cell = floor(p);
+    let f = p - cell;
+    let u = f * f * (3.0 - 2.0 * f);
+    let c = vec3<i32>(cell);
+    let x00 = mix(lattice(c), lattice(c + vec3(1, 0, 0)), u.x);
+    let x10 = mix(lattice(c + vec3(0, 1, 0)), lattice(c + vec3(1, 1, 0)), u.x);
+    let x01 = mix(lattice(c + vec3(0, 0, 1)), lattice(c + vec3(1, 0, 1)), u.x);
+    let x11 = mix(lattice(c + vec3(0, 1, 1)), lattice(c + vec3(1, 1, 1)), u.x);
+    return mix(mix(x00, x10, u.y), mix(x01, x11, u.y), u.z);
+}
+
+// Three unrelated noise fields, the vector potential of the flow
+fn potential(p: vec3<f32>) -> vec3<f32> {
+    return vec3(
+        value_noise(p),
+        value_noise(p + vec3(31.416, -47.853, 12.793)),
+        value_noise(p + vec3(-19.321, 5.127, 61.419)),
+    );
+}
+
+// The curl of a potential is free of divergence, so the flow swirls like
+// smoke without sources or sinks instances would bunch up in
+fn curl_noise(p: vec3<f32>) -> vec3<f32> {
+    let dx = potential(p + vec3(CURL_EPSILON, 0.0, 0.0)) - potential(p - vec3(CURL_EPSILON, 0.0, 0.0));
+    let dy = potential(p + vec3(0.0, CURL_EPSILON, 0.0)) - potential(p - vec3(0.0, CURL_EPSILON, 0.0));
+    let dz = potential(p + vec3(0.0, 0.0, CURL_EPSILON)) - potential(p - vec3(0.0, 0.0, CURL_EPSILON));
+    return vec3(dy.z - dz.y, dz.x - dx.z, dx.y - dy.x) / (2.0 * CURL_EPSILON);
+}
+
+// Buckets of the spatial hash, a power of two after the densities and links
+fn sph_buckets() -> u32 {
+    return arrayLength(&sph) - 2u * arrayLength(&positions);
+}
+
+fn sph_cell(p: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(floor(p / params.sph_radius));
+}
+
+// Index of the head of the bucket a cell hashes to in the scratch
+fn sph_bucket(c: vec3<i32>) -> u32 {
+    let h = pcg_hash(bitcast<u32>(c.x) ^ pcg_hash(bitcast<u32>(c.y) ^ pcg_hash(bitcast<u32>(c.z))));
+    return 2u * arrayLength(&positions) + (h & (sph_buckets() - 1u));
+}
+
+// Heads of the buckets of the cells around a position, followed by 0.
+// Cells hashing to the same bucket share an entry, so no neighbour is
+// counted twice.
+fn sph_neighbor_buckets(p: vec3<f32>) -> array<u32, 27> {
+    var buckets: array<u32, 27>;
+    var count = 0u;
+    let cell = sph_cell(p);
+    for (var k = 0u; k < 27u; k++) {
+        let bucket = sph_bucket(cell + vec3(i32(k % 3u), i32(k / 3u % 3u), i32(k / 9u)) - 1);
+        var seen = false;
+        for (var l = 0u; l < count; l++) {
+            seen = seen || buckets[l] == bucket;
+        }
+        if !seen {
+            buckets[count] = bucket;
+            count++;
+        }
+    }
+    return buckets;
+}
+
+// Weight of a neighbour in the density, 1 at the particle itself
+fn sph_weight(r2: f32) -> f32 {
+    let x = max(1.0 - r2 / (params.sph_radius * params.sph_radius), 0.0);
+    return x * x * x;
+}
+
+// Equation of state, fluid below its rest density doesn't pull together
+fn sph_pressure(density: f32) -> f32 {
+    return params.sph_stiffness * max(density - params.sph_density, 0.0);
+}
+
+fn sph_density(i: u32) -> f32 {
+    return bitcast<f32>(atomicLoad(&sph[i]));
+}
+
+struct State {
+    position: vec3<f32>,
+    velocity: vec3<f32>,
+};
+
+// One step of motion under `force`
+fn integrate(p: vec3<f32>, v: vec3<f32>, dt: f32) -> State {
+    switch INTEGRATOR {
+        case 0u: {
+            return State(p + v * dt, v + force(p) * dt);
+        }
+        case 2u: {
+            let half = v + force(p) * dt * 0.5;
+            let next = p + half * dt;
+            return State(next, half + force(next) * dt * 0.5);
+        }
+        default: {
+            let next_v = v + force(p) * dt;
+            return State(p + next_v * dt, next_v);
+        }
+    }
+}
+
+fn collider_distance(collider: Collider, p: vec3<f32>) -> f32 {
+    let q = p - collider.center.xyz;
+    if collider.center.w == 0.0 {
+        return length(q) - collider.extent.x;
+    }
+    let d = abs(q) - collider.extent.xyz;
+    return length(max(d, vec3(0.0))) + min(max(d.x, max(d.y, d.z)), 0.0);
+}
+
+fn volume_sample(cell: vec3<u32>, corner: vec3<u32>) -> f32 {
+    return textureLoad(volume, cell + corner, 0).r;
+}
+
+// Trilinear between the samples, unbounded outside the grid
+fn volume_distance(p: vec3<f32>) -> f32 {
+    let g = (p - collision.volume_origin.xyz) / collision.volume_origin.w;
+    let last = vec3<f32>(collision.volume_size.xyz - 1u);
+    if any(g < vec3(0.0)) || any(g > last) {
+        return FAR_AWAY;
+    }
+
+    // The last cell along each axis includes its far corner
+    let cell = min(vec3<u32>(g), collision.volume_size.xyz - 2u);
+    let f = g - vec3<f32>(cell);
+    let near = mix(
+        mix(volume_sample(cell, vec3(0u, 0u, 0u)), volume_sample(cell, vec3(1u, 0u, 0u)), f.x),
+        mix(volume_sample(cell, vec3(0u, 1u, 0u)), volume_sample(cell, vec3(1u, 1u, 0u)), f.x),
+        f.y,
+    );
+    let far = mix(
+        mix(volume_sample(cell, vec3(0u, 0u, 1u)), volume_sample(cell, vec3(1u, 0u, 1u)), f.x),
+        mix(volume_sample(cell, vec3(0u, 1u, 1u)), volume_sample(cell, vec3(1u, 1u, 1u)), f.x),
+        f.y,
+    );
+    return mix(near, far, f.z);
+}
+
+fn scene_distance(p: vec3<f32>) -> f32 {
+    var d = FAR_AWAY;
+    if collision.has_volume != 0u {
+        d = volume_distance(p);
+    }
+    for (var i = 0u; i < collision.count; i++) {
+        d = min(d, collider_distance(collision.colliders[i], p));
+    }
+    return d;
+}
+
+fn scene_normal(p: vec3<f32>) -> vec3<f32> {
+    // Tetrahedral central differences, as in sdf.wgsl
+    let e = vec2(1.0, -1.0) * NORMAL_EPSILON;
+    let gradient = e.xyy * scene_distance(p + e.xyy) +
+        e.yyx * scene_distance(p + e.yyx) +
+        e.yxy * scene_distance(p + e.yxy) +
+        e.xxx * scene_distance(p + e.xxx);
+    // Flat regions, like the middle of a volume sampled too coarsely
+    if dot(gradient, gradient) == 0.0 {
+        return vec3(0.0, 1.0, 0.0);
+    }
+    return normalize(gradient);
+}
+
+// Slows instances down by the drag over a step
+fn damp(state: State) -> State {
+    return State(state.position, state.velocity * exp(-params.drag * frame.delta));
+}
+
+// Pushes instances that ended up inside an obstacle back to its surface,
+// bouncing off the velocity towards it and slowing the one along it
+fn collide(state: State) -> State {
+    if collision.count == 0u && collision.has_volume == 0u {
+        return state;
+    }
+    let d = scene_distance(state.position) - INSTANCE_RADIUS;
+    if d >= 0.0 {
+        return state;
+    }
+
+    let n = scene_normal(state.position);
+    let position = state.position - n * d;
+    let normal_speed = dot(state.velocity, n);
+    if normal_speed >= 0.0 {
+        return State(position, state.velocity);
+    }
+    let tangent = state.velocity - normal_speed * n;
+    return State(position, tangent * (1.0 - collision.friction) - normal_speed * collision.restitution * n);
+}
+
+// Brings instances that left the world bounds back in, bouncing them off the
+// boundary like off an obstacle or moving them over to the opposite side
+fn confine(state: State) -> State {
+    let bounds = cold_constants().bounds;
+    if bounds.behavior == 0u {
+        return state;
+    }
+    let center = bounds.center.xyz;
+    let offset = state.position - center;
+
+    if bounds.center.w == 0.0 {
+        let radius = bounds.extent.x;
+        let l = length(offset);
+        if l <= radius {
+            return state;
+        }
+        let n = offset / l;
+        if bounds.behavior == 2u {
+            // Through the center, as far inside as it got outside
+            return State(center - n * max(2.0 * radius - l, 0.0), state.velocity);
+        }
+        let normal_speed = max(dot(state.velocity, n), 0.0);
+        return State(center + n * radius, state.velocity - normal_speed * (1.0 + collision.restitution) * n);
+    }
+
+    let e = bounds.extent;
+    if all(abs(offset) <= e) {
+        return state;
+    }
+    if bounds.behavior == 2u {
+        return State(center + offset - 2.0 * e * floor((offset + e) / (2.0 * e)), state.velocity);
+    }
+    let leaving = (abs(offset) > e) & (sign(offset) * state.velocity > vec3(0.0));
+    let velocity = select(state.velocity, -state.velocity * collision.restitution, leaving);
+    return State(center + clamp(offset, -e, e), velocity);
+}
+
+// Pulls towards the point ahead of the camera at the same rate from
+// anywhere, easing off within a unit of it
+fn follow(state: State) -> State {
+    let follow = hot_constants().follow;
+    let strength = follow.w;
+    if strength == 0.0 {
+        return state;
+    }
+    let offset = follow.xyz - state.position;
+    let pull = strength * offset / max(length(offset), 1.0);
+    return State(state.position, state.velocity + pull * frame.delta);
+}
+
+// Pulls towards the gravity wells like towards bodies of their strength
+fn pull_wells(state: State) -> State {
+    var acceleration = vec3(0.0);
+    for (var i = 0u; i < gravity_wells.count; i++) {
+        let well = gravity_wells.wells[i];
+        acceleration += attraction(state.position, vec4(well.position, well.strength));
+    }
+    return State(state.position, state.velocity + acceleration * frame.delta);
+}
+
+// Turns the instance about its local x axis at its spin rate
+fn spin(i: u32) {
+    let rate = transforms[i].scale.w;
+    if rate == 0.0 {
+        return;
+    }
+
+    let half = 0.5 * rate * frame.delta;
+    let q = transforms[i].rotation;
+    // q * (sin(half), 0, 0, cos(half))
+    let turned = vec4(
+        q.x * cos(half) + q.w * sin(half),
+        q.y * cos(half) + q.z * sin(half),
+        q.z * cos(half) - q.y * sin(half),
+        q.w * cos(half) - q.x * sin(half),
+    );
+    transforms[i].rotation = normalize(turned);
+}
+
+// Instances are split into chunks of whole z slices, id.z is relative to the bound chunk.
+// Invocations outside of the chunk get an index past its end.
+fn instance_index(id: vec3<u32>) -> u32 {
+    if any(id.xy >= frame.dimensions.xy) {
+        return arrayLength(&positions);
+    }
+    return id.x + id.y * frame.dimensions.x + id.z * frame.dimensions.x * frame.dimensions.y;
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn compute_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    if i >= arrayLength(&positions) {
+        return;
+    }
+
+    var state = State(positions_in[i].xyz, velocities_in[i].xyz);
+    if ATTRACT {
+        state = integrate(state.position, state.velocity, frame.delta);
+    } else {
+        state.position += state.velocity * frame.delta;
+    }
+    state = confine(collide(damp(pull_wells(follow(state)))));
+    velocities[i] = vec4(state.velocity, velocities_in[i].w - frame.delta);
+    positions[i] = vec4(state.position, positions_in[i].w);
+    spin(i);
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn vortex_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    if i >= arrayLength(&positions) {
+        return;
+    }
+
+    let p = positions_in[i].xyz;
+    let v = velocities_in[i].xyz + swirl(p) * frame.delta;
+    let state = confine(collide(damp(pull_wells(follow(State(p + v * frame.delta, v))))));
+    velocities[i] = vec4(state.velocity, velocities_in[i].w - frame.delta);
+    positions[i] = vec4(state.position, positions_in[i].w);
+    spin(i);
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn flock_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    if i >= arrayLength(&positions) {
+        return;
+    }
+
+    let p = positions_in[i].xyz;
+    let v = velocities_in[i].xyz;
+    let steering = min(params.steering * frame.delta, 1.0);
+    let steered = v + (flock_velocity(p) - v) * steering;
+    let state = confine(collide(damp(pull_wells(follow(State(p + steered * frame.delta, steered))))));
+    velocities[i] = vec4(state.velocity, velocities_in[i].w - frame.delta);
+    positions[i] = vec4(state.position, positions_in[i].w);
+    spin(i);
 }
 
 @compute
-@workgroup_size(8, 8, 4) fn compute_main(@builtin(global_invocation_id) id: vec3<u32>) {
-    let i = id.x + id.y * push_constants.dimensions.x + id.z * push_constants.dimensions.x * push_constants.dimensions.y;
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn wave_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    if i >= arrayLength(&positions) {
+        return;
+    }
+
+    let p = positions_in[i].xyz;
+    let v = velocities_in[i].xyz - vec3(0.0, params.wave_stiffness * p.y * frame.delta, 0.0);
+    let state = confine(collide(damp(pull_wells(follow(State(p + v * frame.delta, v))))));
+    velocities[i] = vec4(state.velocity, velocities_in[i].w - frame.delta);
+    positions[i] = vec4(state.position, positions_in[i].w);
+    spin(i);
+}
+
+// Instances are carried along by a curl noise flow that stays in place
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn curl_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    if i >= arrayLength(&positions) {
+        return;
+    }
+
+    let p = positions_in[i].xyz;
+    let flow = params.noise_speed * curl_noise(p * params.noise_scale);
+    let v = velocities_in[i].xyz + (flow - velocities_in[i].xyz) * min(CURL_RESPONSE * frame.delta, 1.0);
+    let state = confine(collide(damp(pull_wells(follow(State(p + v * frame.delta, v))))));
+    velocities[i] = vec4(state.velocity, velocities_in[i].w - frame.delta);
+    positions[i] = vec4(state.position, positions_in[i].w);
+    spin(i);
+}
+
+// Every instance pulls on every other of its chunk, gravity split evenly
+// between them, so bodies in other chunks aren't felt. Quadratic in the
+// instances, meant for tens of thousands of them.
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn nbody_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    let count = arrayLength(&positions);
+    if i >= count {
+        return;
+    }
+
+    let p = positions_in[i].xyz;
+    var a = vec3(0.0);
+    for (var j = 0u; j < count; j++) {
+        a += attraction(p, vec4(positions_in[j].xyz, 1.0));
+    }
+
+    let v = velocities_in[i].xyz + params.gravity / f32(count) * a * frame.delta;
+    let state = confine(collide(damp(pull_wells(follow(State(p + v * frame.delta, v))))));
+    velocities[i] = vec4(state.velocity, velocities_in[i].w - frame.delta);
+    positions[i] = vec4(state.position, positions_in[i].w);
+    spin(i);
+}
+
+var<workgroup> nbody_tile: array<vec4<f32>, NBODY_TILE>;
+
+// Same as `nbody_main`, with the workgroup loading the bodies into shared
+// memory one tile at a time so every invocation reads them from there
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn nbody_tiled_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let i = instance_index(id);
+    let count = arrayLength(&positions);
+    // Invocations past the end still help loading the tiles
+    let p = positions_in[min(i, count - 1u)].xyz;
+    let invocations = WORKGROUP_SIZE_X * WORKGROUP_SIZE_Y * WORKGROUP_SIZE_Z;
+    var a = vec3(0.0);
+    for (var base = 0u; base < count; base += NBODY_TILE) {
+        for (var k = local; k < NBODY_TILE; k += invocations) {
+            let j = base + k;
+            nbody_tile[k] = select(vec4(0.0), vec4(positions_in[min(j, count - 1u)].xyz, 1.0), j < count);
+        }
+        workgroupBarrier();
+        for (var k = 0u; k < NBODY_TILE; k++) {
+            a += attraction(p, nbody_tile[k]);
+        }
+        workgroupBarrier();
+    }
+    if i >= count {
+        return;
+    }
+
+    let v = velocities_in[i].xyz + params.gravity / f32(count) * a * frame.delta;
+    let state = confine(collide(damp(pull_wells(follow(State(p + v * frame.delta, v))))));
+    velocities[i] = vec4(state.velocity, velocities_in[i].w - frame.delta);
+    positions[i] = vec4(state.position, positions_in[i].w);
+    spin(i);
+}
+
+// The fluid kernel runs `sph_clear_main`, `sph_hash_main` and
+// `sph_density_main` over the chunk before every step. Like in the n-body
+// kernels, particles in other chunks aren't felt.
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn sph_clear_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    let count = arrayLength(&positions);
+    if i >= count {
+        return;
+    }
+
+    // Fewer than twice as many buckets as instances
+    for (var bucket = i; bucket < sph_buckets(); bucket += count) {
+        atomicStore(&sph[2u * count + bucket], 0u);
+    }
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn sph_hash_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    let count = arrayLength(&positions);
+    if i >= count {
+        return;
+    }
+
+    let bucket = sph_bucket(sph_cell(positions_in[i].xyz));
+    atomicStore(&sph[count + i], atomicExchange(&sph[bucket], i + 1u));
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn sph_density_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    let count = arrayLength(&positions);
+    if i >= count {
+        return;
+    }
+
+    let p = positions_in[i].xyz;
+    var buckets = sph_neighbor_buckets(p);
+    var density = 0.0;
+    for (var k = 0u; k < 27u && buckets[k] != 0u; k++) {
+        for (var j = atomicLoad(&sph[buckets[k]]); j != 0u; j = atomicLoad(&sph[count + j - 1u])) {
+            let offset = positions_in[j - 1u].xyz - p;
+            density += sph_weight(dot(offset, offset));
+        }
+    }
+    atomicStore(&sph[i], bitcast<u32>(density));
+}
+
+// Smoothed-particle hydrodynamics: pressure from the densities pushes
+// particles apart where the fluid is denser than at rest, viscosity evens
+// out the velocities of neighbours
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn sph_force_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    let count = arrayLength(&positions);
+    if i >= count {
+        return;
+    }
+
+    let p = positions_in[i].xyz;
+    let v = velocities_in[i].xyz;
+    let pressure = sph_pressure(sph_density(i));
+    var a = vec3(0.0, -params.sph_gravity, 0.0);
+    var buckets = sph_neighbor_buckets(p);
+    for (var k = 0u; k < 27u && buckets[k] != 0u; k++) {
+        for (var j = atomicLoad(&sph[buckets[k]]); j != 0u; j = atomicLoad(&sph[count + j - 1u])) {
+            let n = j - 1u;
+            let offset = p - positions_in[n].xyz;
+            let r = length(offset);
+            if n == i || r >= params.sph_radius || r == 0.0 {
+                continue;
+            }
+            let q = 1.0 - r / params.sph_radius;
+            let density = sph_density(n);
+            a += (pressure + sph_pressure(density)) / (2.0 * density) * q * q * offset / r;
+            a += params.sph_viscosity * (velocities_in[n].xyz - v) / density * q;
+        }
+    }
+
+    let next = v + a * frame.delta;
+    var state = State(p + next * frame.delta, next);
+    if state.position.y < 0.0 {
+        state.position.y = 0.0;
+        state.velocity.y = max(state.velocity.y, 0.0);
+    }
+    state = confine(collide(damp(pull_wells(follow(state)))));
+    velocities[i] = vec4(state.velocity, velocities_in[i].w - frame.delta);
+    positions[i] = vec4(state.position, positions_in[i].w);
+    spin(i);
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn boids_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    let count = arrayLength(&positions);
+    if i >= count {
+        return;
+    }
+
+    let boids = cold_constants().boids;
+    let p = positions_in[i].xyz;
+    let v = velocities_in[i].xyz;
+    var separation = vec3(0.0);
+    var heading = vec3(0.0);
+    var center = vec3(0.0);
+    var seen = 0u;
+    for (var j = i - min(i, boids.neighbors); j <= min(i + boids.neighbors, count - 1u); j++) {
+        let offset = positions_in[j].xyz - p;
+        let d = length(offset);
+        if j == i || d >= boids.radius || d == 0.0 {
+            continue;
+        }
+        separation -= offset / d * (1.0 - d / boids.radius);
+        heading += velocities_in[j].xyz;
+        center += positions_in[j].xyz;
+        seen++;
+    }
+
+    // Boids that lost the flock drift back to cruising around the y axis
+    var steer = (flock_velocity(p) - v) * params.steering * 0.1;
+    if seen > 0u {
+        let n = f32(seen);
+        steer += boids.separation * separation + boids.alignment * (heading / n - v) + boids.cohesion * (center / n - p);
+    }
+    var next = v + steer * frame.delta;
+    let speed = length(next);
+    if speed > 0.0 {
+        next *= mix(speed, boids.speed, min(frame.delta, 1.0)) / speed;
+    }
+
+    let state = confine(collide(damp(pull_wells(follow(State(p + next * frame.delta, next))))));
+    velocities[i] = vec4(state.velocity, velocities_in[i].w - frame.delta);
+    positions[i] = vec4(state.position, positions_in[i].w);
+    spin(i);
+}
+
+// Runs after a step on the state it wrote, bringing expired instances back
+// at the emitter in random directions with fresh lifetimes
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn respawn_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    if i >= arrayLength(&positions) || velocities[i].w > 0.0 {
+        return;
+    }
+
+    let emitter = cold_constants().emitter;
+    if atomicLoad(&spawned) >= emitter.limit {
+        return;
+    }
+    let ticket = atomicAdd(&spawned, 1u);
+    if ticket >= emitter.limit {
+        // Others may skip while this is undone, they get the next step
+        atomicSub(&spawned, 1u);
+        return;
+    }
+
+    let h0 = pcg_hash(ticket ^ pcg_hash(emitter.seed));
+    let h1 = pcg_hash(h0);
+    let h2 = pcg_hash(h1);
+    let random = vec3(f32(h0 >> 8u), f32(h1 >> 8u), f32(h2 >> 8u)) / 16777215.0;
+    // Uniform on the sphere
+    let y = random.x * 2.0 - 1.0;
+    let angle = random.y * 6.2831853;
+    let radial = sqrt(max(1.0 - y * y, 0.0));
+    let direction = vec3(radial * cos(angle), y, radial * sin(angle));
+
+    positions[i] = vec4(emitter.position, positions[i].w);
+    velocities[i] = vec4(direction * emitter.speed, emitter.lifetime * (0.5 + 0.5 * random.z));
+}
+
+// Scatters the instances over the box of `reset` again, each at a random
+// point of its own cell of the instance grid stretched over the box, so
+// chunks stay as compact as the spatially sorted ones the CPU generates
+@compute
+@workgroup_size(WORKGROUP_SIZE_X, WORKGROUP_SIZE_Y, WORKGROUP_SIZE_Z) fn reset_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = instance_index(id);
+    if i >= arrayLength(&positions) {
+        return;
+    }
+
+    let g = reset.first + i;
+    let size = frame.dimensions.xyz;
+    let cell = vec3(g % size.x, g / size.x % size.y, g / (size.x * size.y));
+    let h0 = pcg_hash(g ^ pcg_hash(reset.seed));
+    let h1 = pcg_hash(h0);
+    let h2 = pcg_hash(h1);
+    let h3 = pcg_hash(h2);
+    let h4 = pcg_hash(h3);
+    let h5 = pcg_hash(h4);
+    let h6 = pcg_hash(h5);
+    let h7 = pcg_hash(h6);
+    let jitter = vec3(f32(h0 >> 8u), f32(h1 >> 8u), f32(h2 >> 8u)) / 16777216.0;
+    let unit = vec3(f32(h3 >> 8u), f32(h4 >> 8u), f32(h5 >> 8u)) / 16777215.0 * 2.0 - 1.0;
+    let phase = f32(h6 >> 8u) / 16777216.0;
+    let lifetime = select(f32(h7 >> 8u) / 16777216.0 * reset.lifetime, FAR_AWAY, reset.endless != 0u);
 
-    velocities[i] = vec4(velocities[i].xyz + force(positions[i].xyz) * push_constants.world_info.delta, 1.0);
-    positions[i] = vec4(positions[i].xyz + velocities[i].xyz * push_constants.world_info.delta, 1.0);
+    let position = reset.min + (vec3<f32>(cell) + jitter) / vec3<f32>(size) * (reset.max - reset.min);
+    positions[i] = vec4(position, phase);
+    velocities[i] = vec4(unit * reset.speed, lifetime);
 }
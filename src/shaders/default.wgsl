@@ -1,65 +1,447 @@
 struct VertexInput {
     @location(0) position: vec3<f32>,
+    // After the instance attributes, see `DefaultVertex3d::NORMAL_LOCATION`
+    @location(6) normal: vec3<f32>,
 };
 
 struct InstanceInput {
     @builtin(instance_index) id: u32,
+    // w is the animation phase of simulated instances
     @location(1) position: vec4<f32>,
+    // Unit quaternion
+    @location(2) rotation: vec4<f32>,
+    // Scale in xyz, w is the spin of the simulation
+    @location(3) scale: vec4<f32>,
+    @location(4) color: vec4<f32>,
+    // Index into the material table
+    @location(5) material: u32,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) vertex_color: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) local_position: vec3<f32>,
+    @location(3) alpha: f32,
+    @location(4) @interpolate(flat) material: u32,
+    @location(5) normal: vec3<f32>,
 };
 
 struct Attachments {
     @location(0) color: vec4<f32>,
 }
 
+// Targets of the deferred path, see deferred.rs
+struct GBuffer {
+    // Roughness in a
+    @location(0) albedo: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+    @location(2) emissive: vec4<f32>,
+}
+
+// What lighting needs to know of a fragment
+struct Surface {
+    color: vec3<f32>,
+    roughness: f32,
+    emissive: vec3<f32>,
+};
+
 struct Camera {
     view: mat4x4<f32>,
     inverse_view: mat4x4<f32>,
     projection: mat4x4<f32>,
 };
 
-struct WorldInfo {
+struct Scene {
+    max_draw_distance: f32,
+    fade_band: f32,
+    impostor_threshold: f32,
+    viewport_height: f32,
+    // Towards the light, normalized
+    light_direction: vec3<f32>,
+    ambient: f32,
+    // Scaled by the intensity
+    light_color: vec3<f32>,
+    fog_density: f32,
+    fog_color: vec3<f32>,
+    exposure: f32,
+    // Exponent to the output gamma, left to the target's encoding
+    gamma: f32,
+};
+
+// Mirrors `MaterialParams` in material.rs
+struct MaterialParams {
+    color: vec4<f32>,
+    emissive: vec3<f32>,
+    // 0 for a sharp highlight, 1 for none
+    roughness: f32,
+};
+
+struct Frame {
+    dimensions: vec4<u32>,
+    resolution: vec2<f32>,
     time: f32,
     delta: f32,
+    frame_index: u32,
 };
 
-struct PushConstants {
-    dimensions: vec3<u32>,
-    world_info: WorldInfo,
-}
-
-var<push_constant> push_constants: PushConstants;
+const BASE_COLOR: vec3<f32> = vec3(0.5, 0.1, 0.5);
+const TAU: f32 = 6.2831853;
+// Cycles per second and relative size change of the pulse
+const PULSE_RATE: f32 = 0.5;
+const PULSE_AMPLITUDE: f32 = 0.25;
+// Highlight exponents of the smoothest and roughest materials
+const SMOOTH_SHININESS: f32 = 128.0;
+const ROUGH_SHININESS: f32 = 2.0;
+// Roughness of instances without materials
+const DEFAULT_ROUGHNESS: f32 = 0.6;
 
 @group(0) @binding(0)
+var<uniform> frame: Frame;
+
+@group(1) @binding(0)
 var<uniform> camera: Camera;
 
+@group(2) @binding(0)
+var<uniform> scene: Scene;
+
+// Mirrors `ChunkOffsets` in offsets.rs
+struct Chunk {
+    // Index of the first instance of the drawn chunk, instance indices
+    // restart at every chunk
+    first: u32,
+};
+
+// Shares group 3 with the lighting pass, which never draws instances
+@group(3) @binding(5)
+var<uniform> chunk: Chunk;
+
+#ifdef MATERIALS
+@group(2) @binding(1)
+var<storage, read> materials: array<MaterialParams>;
+#endif
+
+#ifdef SHADOWED
+// Mirrors `ShadowUniform` in shadow.rs
+struct Shadows {
+    // Light view projection of every cascade
+    cascades: array<mat4x4<f32>, 4>,
+    // Distance along the view every cascade ends at
+    splits: vec4<f32>,
+    // Distance surfaces are pushed out along their normal before the lookup
+    normal_offsets: vec4<f32>,
+    count: u32,
+    // Size of a texel in texture coordinates
+    texel_size: f32,
+};
+
+@group(2) @binding(2)
+var shadow_maps: texture_depth_2d_array;
+@group(2) @binding(3)
+var shadow_sampler: sampler_comparison;
+@group(2) @binding(4)
+var<uniform> shadows: Shadows;
+
+// Share of the light reaching `position`, filtered over 3x3 texels of the
+// nearest cascade covering it. Everything past the last one is lit.
+fn shadow(position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let depth = -(camera.view * vec4(position, 1.0)).z;
+    var cascade = 0u;
+    while cascade < shadows.count && depth > shadows.splits[cascade] {
+        cascade += 1u;
+    }
+    if cascade == shadows.count {
+        return 1.0;
+    }
+
+    let offset = position + normal * shadows.normal_offsets[cascade];
+    let clip = shadows.cascades[cascade] * vec4(offset, 1.0);
+    let uv = clip.xy * vec2(0.5, -0.5) + 0.5;
+    if any(uv < vec2(0.0)) || any(uv > vec2(1.0)) || clip.z > 1.0 {
+        return 1.0;
+    }
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let texel = uv + vec2(f32(x), f32(y)) * shadows.texel_size;
+            lit += textureSampleCompareLevel(shadow_maps, shadow_sampler, texel, cascade, clip.z);
+        }
+    }
+    return lit / 9.0;
+}
+#endif
+
+// Read by the lighting pass of the deferred path
+@group(3) @binding(0)
+var g_albedo: texture_2d<f32>;
+@group(3) @binding(1)
+var g_normal: texture_2d<f32>;
+@group(3) @binding(2)
+var g_emissive: texture_2d<f32>;
+// Read as a float texture, GLSL can't load from depth textures
+@group(3) @binding(3)
+var g_depth: texture_2d<f32>;
+// Far is 0 rather than 1, see `DepthOrder`
+override REVERSED_Z: bool = false;
+
+// Mirrors `PointLightUniform` in deferred.rs
+struct PointLight {
+    position: vec3<f32>,
+    // Distance the light fades out at
+    radius: f32,
+    // Scaled by the intensity
+    color: vec3<f32>,
+};
+
+@group(3) @binding(4)
+var<storage, read> point_lights: array<PointLight>;
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
 @vertex
 fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
-    let vpos = instance.position.xyz + in.position;
+
+    let eye = camera.inverse_view[3].xyz;
+    let distance = length(instance.position.xyz - eye);
+    // Instances small on screen are left to the impostors by the level of
+    // detail pass instead, see lod.wgsl
+    if distance > scene.max_draw_distance {
+        // Degenerate triangle, clipped before rasterization
+        out.clip_position = vec4(0.0, 0.0, 0.0, 0.0);
+        out.vertex_color = vec3(0.0);
+        out.world_position = vec3(0.0);
+        out.local_position = vec3(0.0);
+        out.alpha = 0.0;
+        return out;
+    }
+
+    let fade = saturate((scene.max_draw_distance - distance) / max(scene.fade_band, 1.0e-3));
+#ifdef VOXEL_RUNS
+    // Merged voxel runs keep their length along x in w
+    let extent = vec3(max(instance.position.w, 1.0), 1.0, 1.0);
+#else
+    let extent = vec3(1.0);
+#endif
+#ifdef PULSE
+    let pulse = 1.0 + PULSE_AMPLITUDE * sin(TAU * (frame.time * PULSE_RATE + instance.position.w));
+#else
+    let pulse = 1.0;
+#endif
+    let vpos = instance.position.xyz + rotate(instance.rotation, in.position * extent * instance.scale.xyz) * fade * pulse;
     out.clip_position = camera.projection * camera.view * vec4(vpos, 1.0);
-    
-    let x_id = instance.id % push_constants.dimensions.x;
-    let y_id = (instance.id / push_constants.dimensions.x) % push_constants.dimensions.y;
-    let z_id = (instance.id / (push_constants.dimensions.x * push_constants.dimensions.y)) % push_constants.dimensions.z;
+    out.world_position = vpos;
+    out.local_position = in.position;
+    // Stretching a face tilts its normal the other way
+    out.normal = rotate(instance.rotation, in.normal / (extent * instance.scale.xyz));
+    out.alpha = instance.color.a;
+    out.material = instance.material;
+
+    out.vertex_color = BASE_COLOR;
+#ifdef INSTANCED_COLOR
+    let id = chunk.first + instance.id;
+    let x_id = id % frame.dimensions.x;
+    let y_id = (id / frame.dimensions.x) % frame.dimensions.y;
+    let z_id = (id / (frame.dimensions.x * frame.dimensions.y)) % frame.dimensions.z;
 
     let col_offset = 0.5 * normalize(vec3<f32>(
-        f32(x_id) / f32(push_constants.dimensions.x),
-        f32(z_id) / f32(push_constants.dimensions.z),
-        f32(y_id) / f32(push_constants.dimensions.y)
+        f32(x_id) / f32(frame.dimensions.x),
+        f32(z_id) / f32(frame.dimensions.z),
+        f32(y_id) / f32(frame.dimensions.y)
     ));
 
-    out.vertex_color = vec3(0.5, 0.1, 0.5) + col_offset;
+    out.vertex_color += col_offset;
+#endif
+#ifdef COLOR_ATTRIBUTE
+    out.vertex_color = instance.color.rgb;
+#endif
+#ifdef POINT_COLOR
+    // Imported points carry their colors in the color attribute, which
+    // generated instances only use with COLOR_ATTRIBUTE
+    out.vertex_color = instance.color.rgb;
+#endif
     return out;
 }
 
+fn surface(in: VertexOutput) -> Surface {
+    var surface = Surface(in.vertex_color, DEFAULT_ROUGHNESS, vec3(0.0));
+#ifdef MATERIALS
+    let material = materials[min(in.material, arrayLength(&materials) - 1u)];
+    surface.color = material.color.rgb;
+    surface.roughness = material.roughness;
+    surface.emissive = material.emissive;
+#endif
+
+#ifdef TEXTURED
+    // Procedural grid until meshes carry texture coordinates
+    let cell = vec3<i32>(floor(in.local_position * 4.0 + 2.0));
+    if ((cell.x + cell.y + cell.z) & 1) == 0 {
+        surface.color *= 0.6;
+    }
+#endif
+    return surface;
+}
+
+// Blinn-Phong highlight, only on faces turned towards the light
+fn specular(position: vec3<f32>, normal: vec3<f32>, direction: vec3<f32>, roughness: f32, diffuse: f32) -> f32 {
+    let view = normalize(camera.inverse_view[3].xyz - position);
+    let half_vector = normalize(direction + view);
+    let shininess = mix(SMOOTH_SHININESS, ROUGH_SHININESS, roughness);
+    let highlight = select(0.0, pow(max(dot(normal, half_vector), 0.0), shininess), diffuse > 0.0);
+    return (1.0 - roughness) * highlight;
+}
+
+// Lambert diffuse of the directional light over the ambient share of the
+// color
+fn lit(surface: Surface, position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+#ifdef SHADOWED
+    let light = shadow(position, normal);
+#else
+    let light = 1.0;
+#endif
+    let diffuse = max(dot(normal, scene.light_direction), 0.0) * light;
+    var color = surface.color * (scene.ambient + (1.0 - scene.ambient) * diffuse * scene.light_color);
+    color += specular(position, normal, scene.light_direction, surface.roughness, diffuse) * light * scene.light_color;
+    return color;
+}
+
+fn fogged(color: vec3<f32>, position: vec3<f32>) -> vec3<f32> {
+    let eye = camera.inverse_view[3].xyz;
+    let fog = exp(-scene.fog_density * length(position - eye));
+    return mix(scene.fog_color, color, fog);
+}
+
+// Exposed and brought to the output gamma, or left for tone mapping to
+// finish
+fn exposed(color: vec3<f32>) -> vec3<f32> {
+    return pow(max(color * scene.exposure, vec3(0.0)), vec3(scene.gamma));
+}
+
+// Color of a fragment shaded forward
+fn shaded(in: VertexOutput) -> vec3<f32> {
+    let surface = surface(in);
+    var color = surface.color;
+#ifdef LIT
+    color = lit(surface, in.world_position, normalize(in.normal));
+#endif
+    color += surface.emissive;
+#ifdef FOGGED
+    color = fogged(color, in.world_position);
+#endif
+    return exposed(color);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> Attachments {
     var result: Attachments;
-    result.color = vec4(in.vertex_color, 1.0);
+    result.color = vec4(shaded(in), in.alpha);
+    return result;
+}
+
+// Targets of weighted blended transparency, see oit.rs
+struct Accumulation {
+    // Premultiplied and weighted, summed
+    @location(0) accumulation: vec4<f32>,
+    // Multiplies what's there by one minus it
+    @location(1) revealage: f32,
+}
+
+// Translucent groups, in any order
+@fragment
+fn fs_accumulate(in: VertexOutput) -> Accumulation {
+    // McGuire and Bavoil's distance weight, spread over the scene's larger
+    // scale, so nearer fragments win out over the ones behind them
+    let distance = length(in.world_position - camera.inverse_view[3].xyz);
+    let falloff = 1e-5 + pow(distance / 50.0, 2.0) + pow(distance / 2000.0, 6.0);
+    let weight = in.alpha * clamp(10.0 / falloff, 1e-2, 3e3);
+
+    var result: Accumulation;
+    result.accumulation = vec4(shaded(in) * in.alpha, in.alpha) * weight;
+    result.revealage = in.alpha;
+    return result;
+}
+
+// Opaque instances of the deferred path, lit later by `fs_lighting`
+@fragment
+fn fs_gbuffer(in: VertexOutput) -> GBuffer {
+    let surface = surface(in);
+
+    var result: GBuffer;
+    result.albedo = vec4(surface.color, surface.roughness);
+    result.normal = vec4(normalize(in.normal), 0.0);
+    result.emissive = vec4(surface.emissive, 0.0);
+    return result;
+}
+
+struct LightingOutput {
+    @location(0) color: vec4<f32>,
+    // Lets whatever is drawn after the lighting be hidden by the instances
+    @builtin(frag_depth) depth: f32,
+}
+
+// Single triangle covering the screen, no vertex buffer needed
+@vertex
+fn vs_lighting(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+}
+
+// Position a depth of the G-buffer was rendered at, undoing the perspective
+// projection
+fn world_position(pixel: vec2<f32>, depth: f32) -> vec3<f32> {
+    let uv = pixel / vec2<f32>(textureDimensions(g_depth));
+    let ndc = vec2(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let p = camera.projection;
+    let z = -p[3][2] / (depth + p[2][2]);
+    let x = -z * (ndc.x + p[2][0]) / p[0][0];
+    let y = -z * (ndc.y + p[2][1]) / p[1][1];
+    return (camera.inverse_view * vec4(x, y, z, 1.0)).xyz;
+}
+
+// Light of one point light, fading out smoothly towards its radius
+fn point_light(light: PointLight, surface: Surface, position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let offset = light.position - position;
+    let distance = length(offset);
+    if distance >= light.radius {
+        return vec3(0.0);
+    }
+    let direction = offset / max(distance, 1.0e-4);
+    let falloff = 1.0 - distance * distance / (light.radius * light.radius);
+    let diffuse = max(dot(normal, direction), 0.0);
+    let reflected = surface.color * diffuse + specular(position, normal, direction, surface.roughness, diffuse);
+    return reflected * falloff * falloff * light.color;
+}
+
+// Shades the G-buffer into the scene once per pixel, whatever the number of
+// instances covering it. Pixels no instance covers keep the background.
+@fragment
+fn fs_lighting(@builtin(position) clip_position: vec4<f32>) -> LightingOutput {
+    let pixel = vec2<i32>(clip_position.xy);
+    let depth = textureLoad(g_depth, pixel, 0).r;
+    if select(depth >= 1.0, depth <= 0.0, REVERSED_Z) {
+        discard;
+    }
+    let albedo = textureLoad(g_albedo, pixel, 0);
+    let surface = Surface(albedo.rgb, albedo.a, textureLoad(g_emissive, pixel, 0).rgb);
+    let position = world_position(clip_position.xy, depth);
+
+    var color = surface.color;
+#ifdef LIT
+    let normal = normalize(textureLoad(g_normal, pixel, 0).xyz);
+    color = lit(surface, position, normal);
+    for (var index = 0u; index < arrayLength(&point_lights); index++) {
+        color += point_light(point_lights[index], surface, position, normal);
+    }
+#endif
+    color += surface.emissive;
+#ifdef FOGGED
+    color = fogged(color, position);
+#endif
+
+    var result: LightingOutput;
+    result.color = vec4(exposed(color), 1.0);
+    result.depth = depth;
     return result;
 }
@@ -0,0 +1,68 @@
+// Hierarchical depth pyramid for occlusion culling. The top level resolves
+// the depth buffer to the farthest sample of every pixel, and
+// every level below holds the farthest depth of the texels it covers in the
+// level above, so a texel is never nearer than anything drawn under it.
+
+const WORKGROUP_SIZE: u32 = 8u;
+// Far is 0 rather than 1, see `DepthOrder`
+override REVERSED_Z: bool = false;
+
+@group(0) @binding(0)
+var depth: texture_depth_multisampled_2d;
+// Without multisampling
+@group(0) @binding(3)
+var single_depth: texture_depth_2d;
+// Single level views of the pyramid
+@group(0) @binding(1)
+var source: texture_2d<f32>;
+@group(0) @binding(2)
+var destination: texture_storage_2d<r32float, write>;
+
+fn farther(a: f32, b: f32) -> f32 {
+    return select(max(a, b), min(a, b), REVERSED_Z);
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE) fn resolve_depth(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    var farthest = select(0.0, 1.0, REVERSED_Z);
+    for (var sample = 0u; sample < textureNumSamples(depth); sample++) {
+        farthest = farther(farthest, textureLoad(depth, id.xy, i32(sample)));
+    }
+    textureStore(destination, id.xy, vec4(farthest));
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE) fn resolve_single_depth(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    textureStore(destination, id.xy, vec4(textureLoad(single_depth, id.xy, 0)));
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE) fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(destination);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    // The last row and column also cover the texels an odd size leaves over
+    let source_size = textureDimensions(source);
+    let odd = (source_size & vec2(1u)) == vec2(1u);
+    let extent = select(vec2(1u), vec2(2u), odd & (id.xy == size - 1u));
+    var farthest = select(0.0, 1.0, REVERSED_Z);
+    for (var y = 0u; y <= extent.y; y++) {
+        for (var x = 0u; x <= extent.x; x++) {
+            let texel = min(2u * id.xy + vec2(x, y), source_size - 1u);
+            farthest = farther(farthest, textureLoad(source, texel, 0).r);
+        }
+    }
+    textureStore(destination, id.xy, vec4(farthest));
+}
@@ -0,0 +1,47 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) color: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+};
+
+struct Attachments {
+    @location(0) color: vec4<f32>,
+}
+
+struct Camera {
+    view: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+};
+
+// Direction towards the light, the default `DirectionalLight` of the
+// default shader
+const LIGHT_DIRECTION: vec3<f32> = vec3(0.4, 0.8, 0.45);
+const AMBIENT: f32 = 0.25;
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.projection * camera.view * vec4(in.position, 1.0);
+    out.color = in.color;
+    out.normal = in.normal;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> Attachments {
+    let diffuse = max(dot(in.normal, normalize(LIGHT_DIRECTION)), 0.0);
+
+    var result: Attachments;
+    result.color = vec4(in.color * (AMBIENT + (1.0 - AMBIENT) * diffuse), 1.0);
+    return result;
+}
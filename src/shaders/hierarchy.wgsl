@@ -0,0 +1,89 @@
+// Places simulated instances relative to a parent node. Nodes form a tree,
+// each turning about its own y axis at a rate and offset from its parent.
+// `resolve_nodes` composes every node with its ancestors into a world matrix,
+// then `resolve_instances` moves the instances of a chunk, simulated around
+// the origin, to where their parent is.
+
+struct Frame {
+    dimensions: vec4<u32>,
+    resolution: vec2<f32>,
+    time: f32,
+    delta: f32,
+    frame_index: u32,
+};
+
+struct Node {
+    // Offset from the parent in xyz, turn rate in radians per second in w
+    offset: vec4<f32>,
+    // `ROOT` for nodes without a parent
+    parent: u32,
+};
+
+struct Params {
+    count: u32,
+};
+
+const ROOT: u32 = 0xffffffffu;
+// Deeper ancestors are ignored, guarding against cycles
+const MAX_DEPTH: u32 = 8u;
+const WORKGROUP_SIZE: u32 = 256u;
+const NODE_WORKGROUP_SIZE: u32 = 64u;
+
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+
+@group(1) @binding(0)
+var<storage, read> nodes: array<Node>;
+@group(1) @binding(1)
+var<storage, read_write> world: array<mat4x4<f32>>;
+
+@group(2) @binding(0)
+var<uniform> params: Params;
+// Node of every instance in the chunk
+@group(2) @binding(1)
+var<storage, read> parents: array<u32>;
+@group(2) @binding(2)
+var<storage, read> positions: array<vec4<f32>>;
+@group(2) @binding(3)
+var<storage, read_write> world_positions: array<vec4<f32>>;
+
+fn local(node: Node) -> mat4x4<f32> {
+    let angle = node.offset.w * frame.time;
+    let c = cos(angle);
+    let s = sin(angle);
+    return mat4x4(
+        vec4(c, 0.0, -s, 0.0),
+        vec4(0.0, 1.0, 0.0, 0.0),
+        vec4(s, 0.0, c, 0.0),
+        vec4(node.offset.xyz, 1.0),
+    );
+}
+
+@compute
+@workgroup_size(NODE_WORKGROUP_SIZE) fn resolve_nodes(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= arrayLength(&nodes) {
+        return;
+    }
+
+    var matrix = local(nodes[i]);
+    var parent = nodes[i].parent;
+    for (var depth = 0u; depth < MAX_DEPTH && parent != ROOT; depth++) {
+        matrix = local(nodes[parent]) * matrix;
+        parent = nodes[parent].parent;
+    }
+    world[i] = matrix;
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn resolve_instances(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    // Large dispatches wrap into rows of workgroups
+    let i = id.x + id.y * groups.x * WORKGROUP_SIZE;
+    if i >= params.count {
+        return;
+    }
+
+    let p = positions[i];
+    // w is the animation phase
+    world_positions[i] = vec4((world[parents[i]] * vec4(p.xyz, 1.0)).xyz, p.w);
+}
@@ -0,0 +1,171 @@
+struct InstanceInput {
+    @builtin(instance_index) id: u32,
+    @location(1) position: vec4<f32>,
+    // Unit quaternion
+    @location(2) rotation: vec4<f32>,
+    // Scale in xyz, w is the spin of the simulation
+    @location(3) scale: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) vertex_color: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    // Turns the baked normals into world space
+    @location(2) @interpolate(flat) rotation: vec4<f32>,
+};
+
+struct Attachments {
+    @location(0) color: vec4<f32>,
+}
+
+struct Camera {
+    view: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+};
+
+struct Scene {
+    max_draw_distance: f32,
+    fade_band: f32,
+    impostor_threshold: f32,
+    viewport_height: f32,
+    // Towards the light, normalized
+    light_direction: vec3<f32>,
+    ambient: f32,
+    // Scaled by the intensity
+    light_color: vec3<f32>,
+    fog_density: f32,
+    fog_color: vec3<f32>,
+    exposure: f32,
+    // Exponent to the output gamma, left to the target's encoding
+    gamma: f32,
+};
+
+// Axes of the quad facing along `d`
+struct Basis {
+    right: vec3<f32>,
+    up: vec3<f32>,
+};
+
+struct Frame {
+    dimensions: vec4<u32>,
+    resolution: vec2<f32>,
+    time: f32,
+    delta: f32,
+    frame_index: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+@group(2) @binding(0)
+var<uniform> scene: Scene;
+
+@group(3) @binding(0)
+var atlas: texture_2d<f32>;
+@group(3) @binding(1)
+var atlas_sampler: sampler;
+
+const PI: f32 = 3.14159265;
+override AZIMUTH_TILES: u32 = 8u;
+override ELEVATION_TILES: u32 = 4u;
+const IMPOSTOR_RADIUS: f32 = 0.8660254;
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+// Same as in impostor_bake.wgsl, so the quads show the tiles the way they
+// were baked
+fn billboard(d: vec3<f32>) -> Basis {
+    // Straight up or down, y is along `d` and can't give the roll
+    let world_up = select(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0), abs(d.y) > 0.999);
+    let right = normalize(cross(world_up, d));
+    return Basis(right, cross(d, right));
+}
+
+fn tile_index(d: vec3<f32>) -> vec2<u32> {
+    var azimuth = atan2(d.z, d.x);
+    if azimuth < 0.0 {
+        azimuth += 2.0 * PI;
+    }
+    let elevation = asin(clamp(d.y, -1.0, 1.0));
+
+    return vec2(
+        u32(round(azimuth / (2.0 * PI) * f32(AZIMUTH_TILES))) % AZIMUTH_TILES,
+        min(u32(floor((elevation / PI + 0.5) * f32(ELEVATION_TILES))), ELEVATION_TILES - 1u),
+    );
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex: u32, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+
+    // Only the instances the level of detail pass found small enough are
+    // drawn, see lod.wgsl
+    let eye = camera.inverse_view[3].xyz;
+    let distance = length(instance.position.xyz - eye);
+    let radius = IMPOSTOR_RADIUS * max(instance.scale.x, max(instance.scale.y, instance.scale.z));
+
+    var corners = array(
+        vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+        vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0),
+    );
+    let corner = corners[vertex];
+
+    let d = normalize(eye - instance.position.xyz);
+    let basis = billboard(d);
+
+    let fade = saturate((scene.max_draw_distance - distance) / max(scene.fade_band, 1.0e-3));
+    let vpos = instance.position.xyz + (basis.right * corner.x + basis.up * corner.y) * radius * fade;
+    out.clip_position = camera.projection * camera.view * vec4(vpos, 1.0);
+
+    // Keep half a texel away from tile borders to avoid bleeding
+    let tile_uv = clamp(vec2(corner.x * 0.5 + 0.5, 0.5 - corner.y * 0.5), vec2(0.01), vec2(0.99));
+    // The atlas views an unrotated cube, so look it up in the instance's frame.
+    // Rolling about the view direction and non-uniform scale aren't captured.
+    let local_d = rotate(vec4(-instance.rotation.xyz, instance.rotation.w), d);
+    out.uv = (vec2<f32>(tile_index(local_d)) + tile_uv) / vec2(f32(AZIMUTH_TILES), f32(ELEVATION_TILES));
+    out.rotation = instance.rotation;
+
+    let x_id = instance.id % frame.dimensions.x;
+    let y_id = (instance.id / frame.dimensions.x) % frame.dimensions.y;
+    let z_id = (instance.id / (frame.dimensions.x * frame.dimensions.y)) % frame.dimensions.z;
+
+    let col_offset = 0.5 * normalize(vec3<f32>(
+        f32(x_id) / f32(frame.dimensions.x),
+        f32(z_id) / f32(frame.dimensions.z),
+        f32(y_id) / f32(frame.dimensions.y)
+    ));
+
+    out.vertex_color = vec3(0.5, 0.1, 0.5) + col_offset;
+    return out;
+}
+
+// Exposed and brought to the output gamma like the instances, see
+// `exposed` in default.wgsl
+fn exposed(color: vec3<f32>) -> vec3<f32> {
+    return pow(max(color * scene.exposure, vec3(0.0)), vec3(scene.gamma));
+}
+
+// Lambert diffuse of the directional light over the ambient share, like
+// `lit` in default.wgsl without the highlight and shadows
+@fragment
+fn fs_main(in: VertexOutput) -> Attachments {
+    let texel = textureSample(atlas, atlas_sampler, in.uv);
+    if texel.a < 0.5 {
+        discard;
+    }
+
+    let normal = normalize(rotate(in.rotation, texel.rgb * 2.0 - 1.0));
+    let diffuse = max(dot(normal, scene.light_direction), 0.0);
+    let color = in.vertex_color * (scene.ambient + (1.0 - scene.ambient) * diffuse * scene.light_color);
+
+    var result: Attachments;
+    result.color = vec4(exposed(color), 1.0);
+    return result;
+}
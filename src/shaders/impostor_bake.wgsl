@@ -0,0 +1,66 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    // See `DefaultVertex3d::NORMAL_LOCATION`
+    @location(6) normal: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+};
+
+struct BakeConstants {
+    tile: vec2<u32>,
+}
+
+// Axes of the quad facing along `d`
+struct Basis {
+    right: vec3<f32>,
+    up: vec3<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> bake: BakeConstants;
+
+const PI: f32 = 3.14159265;
+override AZIMUTH_TILES: u32 = 8u;
+override ELEVATION_TILES: u32 = 4u;
+const IMPOSTOR_RADIUS: f32 = 0.8660254;
+
+fn tile_direction(tile: vec2<u32>) -> vec3<f32> {
+    let azimuth = f32(tile.x) * 2.0 * PI / f32(AZIMUTH_TILES);
+    let elevation = ((f32(tile.y) + 0.5) / f32(ELEVATION_TILES) - 0.5) * PI;
+
+    return vec3(cos(elevation) * cos(azimuth), sin(elevation), cos(elevation) * sin(azimuth));
+}
+
+// Same as in impostor.wgsl, so the quads show the tiles the way they were baked
+fn billboard(d: vec3<f32>) -> Basis {
+    // Straight up or down, y is along `d` and can't give the roll
+    let world_up = select(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0), abs(d.y) > 0.999);
+    let right = normalize(cross(world_up, d));
+    return Basis(right, cross(d, right));
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    let d = tile_direction(bake.tile);
+    let basis = billboard(d);
+
+    var out: VertexOutput;
+    out.clip_position = vec4(
+        dot(in.position, basis.right) / IMPOSTOR_RADIUS,
+        dot(in.position, basis.up) / IMPOSTOR_RADIUS,
+        0.5 - 0.5 * dot(in.position, d) / IMPOSTOR_RADIUS,
+        1.0,
+    );
+    out.normal = in.normal;
+    return out;
+}
+
+// Normals of the mesh, unrotated, for impostor.wgsl to light like the
+// instances. Alpha is the coverage.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(normalize(in.normal) * 0.5 + 0.5, 1.0);
+}
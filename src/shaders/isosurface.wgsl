@@ -0,0 +1,168 @@
+struct Frame {
+    dimensions: vec4<u32>,
+    resolution: vec2<f32>,
+    time: f32,
+    delta: f32,
+    frame_index: u32,
+};
+
+struct Vertex {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+    color: vec4<f32>,
+};
+
+// Layout of wgpu's DrawIndirectArgs
+struct DrawArgs {
+    vertex_count: atomic<u32>,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+
+@group(1) @binding(0)
+var<storage, read_write> vertices: array<Vertex>;
+@group(1) @binding(1)
+var<storage, read_write> draw: DrawArgs;
+// Marching cubes tables, indexed by the corners of a cell inside the surface
+@group(1) @binding(2)
+var<storage, read> edge_table: array<u32, 256>;
+// Sixteen edges per case, triples ended by -1
+@group(1) @binding(3)
+var<storage, read> tri_table: array<i32, 4096>;
+
+override GRID_SIZE: u32 = 64u;
+override CELL_SIZE: f32 = 64.0;
+override MAX_VERTICES: u32 = 1048576u;
+
+const COLOR: vec3<f32> = vec3(0.2, 0.6, 0.7);
+
+// Corners of a cell, numbered as the tables in isosurface.rs expect
+const CORNERS = array<vec3<f32>, 8>(
+    vec3(0.0, 0.0, 0.0),
+    vec3(1.0, 0.0, 0.0),
+    vec3(1.0, 1.0, 0.0),
+    vec3(0.0, 1.0, 0.0),
+    vec3(0.0, 0.0, 1.0),
+    vec3(1.0, 0.0, 1.0),
+    vec3(1.0, 1.0, 1.0),
+    vec3(0.0, 1.0, 1.0),
+);
+
+// Corners at the ends of each edge, the bottom face, the top face, then the
+// vertical edges
+const EDGES = array<vec2<u32>, 12>(
+    vec2(0u, 1u),
+    vec2(1u, 2u),
+    vec2(2u, 3u),
+    vec2(3u, 0u),
+    vec2(4u, 5u),
+    vec2(5u, 6u),
+    vec2(6u, 7u),
+    vec2(7u, 4u),
+    vec2(0u, 4u),
+    vec2(1u, 5u),
+    vec2(2u, 6u),
+    vec2(3u, 7u),
+);
+
+fn hash(p: vec3<f32>) -> f32 {
+    return fract(sin(dot(p, vec3(127.1, 311.7, 74.7))) * 43758.5453);
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+
+    return mix(
+        mix(
+            mix(hash(i), hash(i + vec3(1.0, 0.0, 0.0)), u.x),
+            mix(hash(i + vec3(0.0, 1.0, 0.0)), hash(i + vec3(1.0, 1.0, 0.0)), u.x),
+            u.y,
+        ),
+        mix(
+            mix(hash(i + vec3(0.0, 0.0, 1.0)), hash(i + vec3(1.0, 0.0, 1.0)), u.x),
+            mix(hash(i + vec3(0.0, 1.0, 1.0)), hash(i + vec3(1.0, 1.0, 1.0)), u.x),
+            u.y,
+        ),
+        u.z,
+    );
+}
+
+// Positive inside: a noisy, slowly drifting blob centered on the origin
+fn density(p: vec3<f32>) -> f32 {
+    let extent = f32(GRID_SIZE) * CELL_SIZE * 0.5;
+    let q = p / extent * 3.0 + vec3(0.0, frame.time * 0.1, 0.0);
+    let noise = value_noise(q) * 0.5 + value_noise(q * 2.0) * 0.25 + value_noise(q * 4.0) * 0.125;
+
+    return 0.7 - length(p) / extent + noise - 0.45;
+}
+
+fn outward_normal(p: vec3<f32>) -> vec3<f32> {
+    let e = CELL_SIZE * 0.5;
+    let gradient = vec3(
+        density(p + vec3(e, 0.0, 0.0)) - density(p - vec3(e, 0.0, 0.0)),
+        density(p + vec3(0.0, e, 0.0)) - density(p - vec3(0.0, e, 0.0)),
+        density(p + vec3(0.0, 0.0, e)) - density(p - vec3(0.0, 0.0, e)),
+    );
+    return -normalize(gradient);
+}
+
+fn surface_point(a: vec3<f32>, b: vec3<f32>, da: f32, db: f32) -> vec3<f32> {
+    return mix(a, b, da / (da - db));
+}
+
+@compute
+@workgroup_size(4, 4, 4) fn extract(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id >= vec3(GRID_SIZE)) {
+        return;
+    }
+
+    let origin = (vec3<f32>(id) - f32(GRID_SIZE) * 0.5) * CELL_SIZE;
+    var positions: array<vec3<f32>, 8>;
+    var values: array<f32, 8>;
+    var inside = 0u;
+    for (var corner = 0u; corner < 8u; corner++) {
+        positions[corner] = origin + CORNERS[corner] * CELL_SIZE;
+        values[corner] = density(positions[corner]);
+        inside |= select(0u, 1u << corner, values[corner] > 0.0);
+    }
+
+    let crossed = edge_table[inside];
+    if crossed == 0u {
+        return;
+    }
+    var points: array<vec3<f32>, 12>;
+    for (var edge = 0u; edge < 12u; edge++) {
+        if (crossed & (1u << edge)) != 0u {
+            let ends = EDGES[edge];
+            points[edge] = surface_point(positions[ends.x], positions[ends.y], values[ends.x], values[ends.y]);
+        }
+    }
+
+    let first = inside * 16u;
+    var count = 0u;
+    while count < 15u && tri_table[first + count] >= 0 {
+        count += 3u;
+    }
+    let base = atomicAdd(&draw.vertex_count, count);
+    if base + count > MAX_VERTICES {
+        // Undo the reservation, later ones overflow as well and undo theirs
+        atomicSub(&draw.vertex_count, count);
+        return;
+    }
+
+    // The table winds triangles counter-clockwise when seen from outside
+    for (var i = 0u; i < count; i++) {
+        let position = points[tri_table[first + i]];
+        vertices[base + i] = Vertex(
+            vec4(position, 1.0),
+            vec4(outward_normal(position), 0.0),
+            vec4(COLOR, 1.0),
+        );
+    }
+}
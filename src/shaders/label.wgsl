@@ -0,0 +1,123 @@
+struct GlyphInput {
+    @location(0) anchor: vec3<f32>,
+    @location(1) offset: vec2<f32>,
+    @location(2) glyph: u32,
+    @location(3) color: u32,
+    @location(4) screen: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+struct Attachments {
+    @location(0) color: vec4<f32>,
+}
+
+struct Camera {
+    view: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+};
+
+struct Frame {
+    dimensions: vec4<u32>,
+    resolution: vec2<f32>,
+    time: f32,
+    delta: f32,
+    frame_index: u32,
+};
+
+struct Scene {
+    max_draw_distance: f32,
+    fade_band: f32,
+    impostor_threshold: f32,
+    viewport_height: f32,
+};
+
+// Atlas layout, see `LabelRenderer::create_atlas`
+const ATLAS_COLUMNS: u32 = 16u;
+const ATLAS_ROWS: u32 = 5u;
+const CELL_PIXELS: vec2<f32> = vec2(6.0, 8.0);
+// Pixels per atlas texel for screen overlays
+const SCREEN_SCALE: f32 = 2.0;
+const GLYPH_ASPECT: f32 = 0.75;
+// Glyph height as a fraction of the distance, so labels keep their size on screen
+const SCREEN_HEIGHT: f32 = 0.02;
+// Up close labels stop shrinking with distance and grow on screen instead
+const MIN_HEIGHT: f32 = 0.25;
+const FADE_START: f32 = 500.0;
+const FADE_END: f32 = 1000.0;
+
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+@group(2) @binding(0)
+var<uniform> scene: Scene;
+
+@group(3) @binding(0)
+var atlas: texture_2d<f32>;
+@group(3) @binding(1)
+var atlas_sampler: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, glyph: GlyphInput) -> VertexOutput {
+    var out: VertexOutput;
+
+    var corners = array(
+        vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+        vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0),
+    );
+    let corner = corners[index];
+
+    let cell = vec2(f32(glyph.glyph % ATLAS_COLUMNS), f32(glyph.glyph / ATLAS_COLUMNS));
+    // Texture rows grow downwards
+    out.uv = (cell + vec2(corner.x, 1.0 - corner.y)) / vec2(f32(ATLAS_COLUMNS), f32(ATLAS_ROWS));
+    out.color = unpack4x8unorm(glyph.color);
+
+    if glyph.screen != 0u {
+        // Pixels from the top left, in front of everything
+        let pixel = glyph.anchor.xy + (glyph.offset + corner) * CELL_PIXELS * SCREEN_SCALE * vec2(1.0, -1.0);
+        let ndc = pixel / frame.resolution * vec2(2.0, -2.0) + vec2(-1.0, 1.0);
+        out.clip_position = vec4(ndc, 0.0, 1.0);
+        return out;
+    }
+
+    let eye = camera.inverse_view[3].xyz;
+    let distance = length(glyph.anchor - eye);
+    let fade = saturate((FADE_END - distance) / (FADE_END - FADE_START));
+    if fade <= 0.0 || distance > scene.max_draw_distance {
+        // Degenerate triangle, clipped before rasterization
+        out.clip_position = vec4(0.0);
+        out.uv = vec2(0.0);
+        out.color = vec4(0.0);
+        return out;
+    }
+
+    // Camera right and up axes in world space
+    let right = camera.inverse_view[0].xyz;
+    let up = camera.inverse_view[1].xyz;
+    let height = max(distance * SCREEN_HEIGHT, MIN_HEIGHT);
+    let local = (glyph.offset + corner) * vec2(GLYPH_ASPECT, 1.0) * height;
+    let position = glyph.anchor + right * local.x + up * local.y;
+    out.clip_position = camera.projection * camera.view * vec4(position, 1.0);
+    out.color.a *= fade;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> Attachments {
+    let coverage = textureSample(atlas, atlas_sampler, in.uv).a;
+    if coverage * in.color.a <= 0.0 {
+        discard;
+    }
+
+    var result: Attachments;
+    result.color = vec4(in.color.rgb, in.color.a * coverage);
+    return result;
+}
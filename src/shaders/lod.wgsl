@@ -0,0 +1,89 @@
+// Buckets the instances of a chunk into one list per level of detail by
+// their distance to the eye, or into the impostor list when they're small on
+// screen. Every list is counted with atomics straight into the arguments of
+// its indirect draw, like the isosurface.
+
+struct Params {
+    // xyz is the eye, w the draw distance
+    eye: vec4<f32>,
+    // Distances at which each coarser level starts, unused ones are f32 max
+    thresholds: vec4<f32>,
+    count: u32,
+    // Instances each level's list has room for
+    capacity: u32,
+    // List of the impostors, after the levels
+    impostor_list: u32,
+    // Pixels an instance of radius 1 covers at distance 1
+    pixel_scale: f32,
+    // Instances covering fewer pixels are impostors, 0 without any
+    impostor_threshold: f32,
+};
+
+// Mirrors `wgpu::util::DrawIndexedIndirectArgs`
+struct Draw {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+struct Transform {
+    rotation: vec4<f32>,
+    scale: vec4<f32>,
+};
+
+const WORKGROUP_SIZE: u32 = 256u;
+const MAX_LEVELS: u32 = 4u;
+// Same as in impostor.wgsl
+const IMPOSTOR_RADIUS: f32 = 0.8660254;
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> positions: array<vec4<f32>>;
+@group(0) @binding(2)
+var<storage, read> transforms: array<Transform>;
+// RGBA8 colors and material ids
+@group(0) @binding(3)
+var<storage, read> colors: array<vec2<u32>>;
+@group(0) @binding(4)
+var<storage, read_write> lod_positions: array<vec4<f32>>;
+@group(0) @binding(5)
+var<storage, read_write> lod_transforms: array<Transform>;
+@group(0) @binding(6)
+var<storage, read_write> lod_colors: array<vec2<u32>>;
+// One per level, then the impostors'
+@group(0) @binding(7)
+var<storage, read_write> draws: array<Draw, MAX_LEVELS + 1u>;
+// Lifetime left in w
+@group(0) @binding(8)
+var<storage, read> velocities: array<vec4<f32>>;
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn bucket(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    // Large dispatches wrap into rows of workgroups
+    let i = id.x + id.y * groups.x * WORKGROUP_SIZE;
+    if i >= params.count || velocities[i].w <= 0.0 {
+        return;
+    }
+
+    let distance = length(positions[i].xyz - params.eye.xyz);
+    if distance > params.eye.w {
+        return;
+    }
+
+    let scale = transforms[i].scale.xyz;
+    let radius = IMPOSTOR_RADIUS * max(scale.x, max(scale.y, scale.z));
+    let impostor = radius * params.pixel_scale / distance < params.impostor_threshold;
+
+    let level = u32(distance >= params.thresholds.x)
+        + u32(distance >= params.thresholds.y)
+        + u32(distance >= params.thresholds.z);
+    let list = select(level, params.impostor_list, impostor);
+    let draw = select(level, MAX_LEVELS, impostor);
+    let slot = list * params.capacity + atomicAdd(&draws[draw].instance_count, 1u);
+    lod_positions[slot] = positions[i];
+    lod_transforms[slot] = transforms[i];
+    lod_colors[slot] = colors[i];
+}
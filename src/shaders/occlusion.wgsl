@@ -0,0 +1,130 @@
+// Occlusion culling against the depth pyramid of the previous frame. The
+// bounding box of every instance's bounding sphere is projected to the
+// screen, and the pyramid level where it covers at most 2x2 texels is
+// sampled at its corners. Instances entirely behind the farthest of them,
+// outside the frustum or past the draw distance are left out, the rest are
+// copied into one list per chunk drawn indirectly, like the compaction.
+// Expired instances are left out too.
+
+struct Params {
+    view_projection: mat4x4<f32>,
+    // xyz eye, w draw distance
+    eye: vec4<f32>,
+    // Size of the top level of the pyramid
+    size: vec2<f32>,
+    // Levels of the pyramid, 0 while it holds no depth to test against
+    levels: u32,
+    count: u32,
+};
+
+// Far is 0 rather than 1, see `DepthOrder`
+override REVERSED_Z: bool = false;
+
+// Mirrors `wgpu::util::DrawIndexedIndirectArgs`
+struct Draw {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+struct Transform {
+    rotation: vec4<f32>,
+    scale: vec4<f32>,
+};
+
+const WORKGROUP_SIZE: u32 = 256u;
+// Bounding sphere of the unit cube, with room for the pulse animation
+const BOUNDING_RADIUS: f32 = 0.8660254 * 1.25;
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> positions: array<vec4<f32>>;
+@group(0) @binding(2)
+var<storage, read> velocities: array<vec4<f32>>;
+@group(0) @binding(3)
+var<storage, read> transforms: array<Transform>;
+// RGBA8 colors and material ids
+@group(0) @binding(4)
+var<storage, read> colors: array<vec2<u32>>;
+@group(0) @binding(5)
+var<storage, read_write> visible_positions: array<vec4<f32>>;
+@group(0) @binding(6)
+var<storage, read_write> visible_transforms: array<Transform>;
+@group(0) @binding(7)
+var<storage, read_write> visible_colors: array<vec2<u32>>;
+@group(0) @binding(8)
+var<storage, read_write> draw: Draw;
+
+@group(1) @binding(0)
+var pyramid: texture_2d<f32>;
+
+fn farther(a: f32, b: f32) -> f32 {
+    return select(max(a, b), min(a, b), REVERSED_Z);
+}
+
+fn visible(center: vec3<f32>, radius: f32) -> bool {
+    var nearest = vec3(1.0e30);
+    var farthest = vec3(-1.0e30);
+    for (var corner = 0u; corner < 8u; corner++) {
+        let offset = vec3<f32>(vec3(corner & 1u, (corner >> 1u) & 1u, (corner >> 2u) & 1u)) * 2.0 - 1.0;
+        let clip = params.view_projection * vec4(center + offset * radius, 1.0);
+        if clip.w <= 0.0 {
+            // Reaches behind the camera
+            return true;
+        }
+        let ndc = clip.xyz / clip.w;
+        nearest = min(nearest, ndc);
+        farthest = max(farthest, ndc);
+    }
+    if any(farthest.xy < vec2(-1.0)) || any(nearest.xy > vec2(1.0)) || (!REVERSED_Z && nearest.z > 1.0) {
+        return false;
+    }
+    // Depth of the nearest corner, the largest one with reversed Z
+    let near_depth = select(nearest.z, farthest.z, REVERSED_Z);
+    if params.levels == 0u || near_depth < 0.0 || near_depth > 1.0 {
+        return true;
+    }
+
+    // Pixels of the top level covered, y down
+    let top = vec2<u32>(params.size) - 1u;
+    let low = min(vec2<u32>(saturate(vec2(nearest.x, -farthest.y) * 0.5 + 0.5) * params.size), top);
+    let high = min(vec2<u32>(saturate(vec2(farthest.x, -nearest.y) * 0.5 + 0.5) * params.size), top);
+    let extent = high - low;
+    let fitting = u32(ceil(log2(f32(max(max(extent.x, extent.y), 1u)))));
+    // Rounding can leave 3 texels covered, one level down covers at most 2
+    let straddling = any((high >> vec2(fitting)) - (low >> vec2(fitting)) > vec2(1u));
+    let level = min(fitting + u32(straddling), params.levels - 1u);
+
+    let last = textureDimensions(pyramid, level) - 1u;
+    let texel_low = min(low >> vec2(level), last);
+    let texel_high = min(high >> vec2(level), last);
+    let mip = i32(level);
+    let depth = farther(
+        farther(textureLoad(pyramid, texel_low, mip).r, textureLoad(pyramid, vec2(texel_high.x, texel_low.y), mip).r),
+        farther(textureLoad(pyramid, vec2(texel_low.x, texel_high.y), mip).r, textureLoad(pyramid, texel_high, mip).r),
+    );
+    return select(near_depth <= depth, near_depth >= depth, REVERSED_Z);
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn cull(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    // Large dispatches wrap into rows of workgroups
+    let i = id.x + id.y * groups.x * WORKGROUP_SIZE;
+    if i >= params.count || velocities[i].w <= 0.0 {
+        return;
+    }
+
+    let center = positions[i].xyz;
+    let scale = transforms[i].scale.xyz;
+    if distance(center, params.eye.xyz) > params.eye.w || !visible(center, BOUNDING_RADIUS * max(scale.x, max(scale.y, scale.z))) {
+        return;
+    }
+
+    let slot = atomicAdd(&draw.instance_count, 1u);
+    visible_positions[slot] = positions[i];
+    visible_transforms[slot] = transforms[i];
+    visible_colors[slot] = colors[i];
+}
@@ -0,0 +1,35 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+// Resolved targets of the accumulation pass, see oit.rs
+@group(0) @binding(0)
+var accumulation: texture_2d<f32>;
+@group(0) @binding(1)
+var revealage: texture_2d<f32>;
+
+// Single triangle covering the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    return out;
+}
+
+// Weighted average color of the fragments, blended over the scene by how
+// much of it they hide
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let revealage = textureLoad(revealage, pixel, 0).r;
+    // Nothing translucent covers the pixel
+    if revealage >= 1.0 {
+        discard;
+    }
+
+    let accumulation = textureLoad(accumulation, pixel, 0);
+    let color = accumulation.rgb / max(accumulation.a, 1e-5);
+    return vec4(color, 1.0 - revealage);
+}
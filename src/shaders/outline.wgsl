@@ -0,0 +1,70 @@
+// Outlines of the selected instances, see selection.rs. Each is drawn
+// again pushed out by a few pixels on screen and back behind itself in
+// depth, so only the rim around the instance already drawn shows.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(1) position: vec4<f32>,
+    @location(2) rotation: vec4<f32>,
+    @location(3) scale: vec4<f32>,
+}
+
+struct Frame {
+    dimensions: vec4<u32>,
+    resolution: vec2<f32>,
+    time: f32,
+    delta: f32,
+    frame_index: u32,
+};
+
+struct Camera {
+    view: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+};
+
+// Matches the color of the label a pick puts up
+const OUTLINE_COLOR: vec3<f32> = vec3(0.25, 0.86, 1.0);
+// In pixels, however far away the instance is
+const OUTLINE_WIDTH: f32 = 3.0;
+
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let view_projection = camera.projection * camera.view;
+    let vpos = instance.position.xyz + rotate(instance.rotation, in.position * instance.scale.xyz);
+    let clip_position = view_projection * vec4(vpos, 1.0);
+    let center = view_projection * vec4(instance.position.xyz, 1.0);
+    // Deeper than the instance reaches, so its front faces hide the hull
+    let eye = camera.inverse_view[3].xyz;
+    let behind = view_projection * vec4(vpos + normalize(vpos - eye) * length(instance.scale.xyz), 1.0);
+    // Left as it is when the camera is inside or right next to it
+    if min(center.w, min(clip_position.w, behind.w)) <= 0.0 {
+        return clip_position;
+    }
+
+    // Away from the center on screen, in pixels so the width stays the same
+    var screen = clip_position.xy / clip_position.w;
+    let away = (screen - center.xy / center.w) * frame.resolution;
+    if dot(away, away) > 0.0 {
+        screen += normalize(away) * OUTLINE_WIDTH * 2.0 / frame.resolution;
+    }
+    return vec4(screen * behind.w, behind.zw);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4(OUTLINE_COLOR, 1.0);
+}
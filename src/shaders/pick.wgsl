@@ -0,0 +1,73 @@
+// Instance ids for picking. Instances are placed like in default.wgsl and
+// write their index plus one, so 0 is left for the background. Fading and
+// the pulse animation are left out, they only change sizes slightly.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct InstanceInput {
+    @builtin(instance_index) index: u32,
+    @location(1) position: vec4<f32>,
+    @location(2) rotation: vec4<f32>,
+    @location(3) scale: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+};
+
+struct Camera {
+    view: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+};
+
+struct Scene {
+    max_draw_distance: f32,
+    fade_band: f32,
+    impostor_threshold: f32,
+    viewport_height: f32,
+};
+
+// Mirrors `ChunkOffsets` in offsets.rs
+struct Chunk {
+    // Index of the first instance of the drawn chunk
+    first: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+@group(2) @binding(0)
+var<uniform> scene: Scene;
+
+@group(3) @binding(5)
+var<uniform> chunk: Chunk;
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.id = chunk.first + instance.index + 1u;
+
+    let eye = camera.inverse_view[3].xyz;
+    if length(instance.position.xyz - eye) > scene.max_draw_distance {
+        // Degenerate triangle, clipped before rasterization
+        out.clip_position = vec4(0.0);
+        return out;
+    }
+
+    let vpos = instance.position.xyz + rotate(instance.rotation, in.position * instance.scale.xyz);
+    out.clip_position = camera.projection * camera.view * vec4(vpos, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) u32 {
+    return in.id;
+}
@@ -0,0 +1,72 @@
+struct Ray {
+    origin: vec4<f32>,
+    // w holds the maximum hit distance
+    direction: vec4<f32>,
+    count: u32,
+    // Index of the first instance in the bound chunk
+    first: u32,
+};
+
+@group(0) @binding(0)
+var<storage, read> positions: array<vec4<f32>>;
+@group(0) @binding(1)
+var<uniform> ray: Ray;
+// [0]: closest hit distance as f32 bits, [1]: index of the closest instance
+@group(0) @binding(2)
+var<storage, read_write> result: array<atomic<u32>, 2>;
+
+const HALF_EXTENT: f32 = 0.5;
+override WORKGROUP_SIZE: u32 = 256u;
+
+fn hit_distance(center: vec3<f32>) -> f32 {
+    let inv_dir = 1.0 / ray.direction.xyz;
+    let t1 = (center - HALF_EXTENT - ray.origin.xyz) * inv_dir;
+    let t2 = (center + HALF_EXTENT - ray.origin.xyz) * inv_dir;
+
+    let t_min = min(t1, t2);
+    let t_max = max(t1, t2);
+    let t_near = max(max(t_min.x, t_min.y), max(t_min.z, 0.0));
+    let t_far = min(min(t_max.x, t_max.y), t_max.z);
+
+    if t_far < t_near || t_near > ray.direction.w {
+        return -1.0;
+    }
+    return t_near;
+}
+
+fn instance_index(id: vec3<u32>, groups: vec3<u32>) -> u32 {
+    return id.x + id.y * groups.x * WORKGROUP_SIZE;
+}
+
+// Positive floats compare the same as their bit patterns, so atomicMin works on them
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn closest_distance(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let i = instance_index(id, groups);
+    if i >= ray.count {
+        return;
+    }
+
+    let t = hit_distance(positions[i].xyz);
+    if t >= 0.0 {
+        atomicMin(&result[0], bitcast<u32>(t));
+    }
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn closest_index(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let i = instance_index(id, groups);
+    if i >= ray.count {
+        return;
+    }
+
+    let t = hit_distance(positions[i].xyz);
+    if t >= 0.0 && bitcast<u32>(t) == atomicLoad(&result[0]) {
+        atomicMin(&result[1], ray.first + i);
+    }
+}
@@ -0,0 +1,167 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+struct Attachments {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+}
+
+struct Camera {
+    view: mat4x4<f32>,
+    inverse_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+};
+
+struct Scene {
+    max_draw_distance: f32,
+    fade_band: f32,
+    impostor_threshold: f32,
+    viewport_height: f32,
+    // Towards the light, normalized
+    light_direction: vec3<f32>,
+    ambient: f32,
+    // Scaled by the intensity
+    light_color: vec3<f32>,
+    fog_density: f32,
+    fog_color: vec3<f32>,
+    exposure: f32,
+    // Exponent to the output gamma, left to the target's encoding
+    gamma: f32,
+};
+
+struct Frame {
+    dimensions: vec4<u32>,
+    resolution: vec2<f32>,
+    time: f32,
+    delta: f32,
+    frame_index: u32,
+};
+
+const MAX_STEPS: i32 = 128;
+// Hit tolerance relative to the distance travelled, so far surfaces don't need more steps
+const HIT_EPSILON: f32 = 1.0e-4;
+const SCENE_SCALE: f32 = 24.0;
+
+@group(0) @binding(0)
+var<uniform> frame: Frame;
+
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+@group(2) @binding(0)
+var<uniform> scene: Scene;
+
+// Single triangle covering the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+fn smooth_union(a: f32, b: f32, k: f32) -> f32 {
+    let h = saturate(0.5 + 0.5 * (b - a) / k);
+    return mix(b, a, h) - k * h * (1.0 - h);
+}
+
+fn sphere(p: vec3<f32>, radius: f32) -> f32 {
+    return length(p) - radius;
+}
+
+fn torus(p: vec3<f32>, radii: vec2<f32>) -> f32 {
+    let q = vec2(length(p.xz) - radii.x, p.y);
+    return length(q) - radii.y;
+}
+
+fn rounded_box(p: vec3<f32>, extent: vec3<f32>, radius: f32) -> f32 {
+    let q = abs(p) - extent + radius;
+    return length(max(q, vec3(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0) - radius;
+}
+
+// Distance in scene units, scaled up to world units by the caller
+fn scene_distance(p: vec3<f32>) -> f32 {
+    let t = frame.time;
+    let bob = vec3(0.0, sin(t) * 0.6, 0.0);
+    let orbit = vec3(cos(t * 0.7), 0.0, sin(t * 0.7)) * 1.6;
+
+    var d = torus(p, vec2(1.6, 0.35));
+    d = smooth_union(d, sphere(p - bob, 0.8), 0.5);
+    d = smooth_union(d, sphere(p - orbit, 0.45), 0.4);
+    return min(d, rounded_box(p - vec3(0.0, -1.2, 0.0), vec3(2.4, 0.15, 2.4), 0.1));
+}
+
+fn distance_at(p: vec3<f32>) -> f32 {
+    return scene_distance(p / SCENE_SCALE) * SCENE_SCALE;
+}
+
+fn normal_at(p: vec3<f32>) -> vec3<f32> {
+    // Tetrahedral central differences, four samples instead of six
+    let e = vec2(1.0, -1.0) * 1.0e-3 * SCENE_SCALE;
+    return normalize(
+        e.xyy * distance_at(p + e.xyy) +
+        e.yyx * distance_at(p + e.yyx) +
+        e.yxy * distance_at(p + e.yxy) +
+        e.xxx * distance_at(p + e.xxx)
+    );
+}
+
+// Exposed and brought to the output gamma like the instances, see
+// `exposed` in default.wgsl
+fn exposed(color: vec3<f32>) -> vec3<f32> {
+    return pow(max(color * scene.exposure, vec3(0.0)), vec3(scene.gamma));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> Attachments {
+    // View space direction through this pixel. Solving the projection for
+    // w = 1 keeps this independent of the handedness of the view.
+    let view_direction = vec3(
+        in.ndc.x / camera.projection[0][0],
+        in.ndc.y / camera.projection[1][1],
+        1.0 / camera.projection[2][3],
+    );
+    let origin = camera.inverse_view[3].xyz;
+    let direction = normalize((camera.inverse_view * vec4(view_direction, 0.0)).xyz);
+
+    var t = 0.0;
+    var hit = false;
+    for (var i = 0; i < MAX_STEPS; i++) {
+        let d = distance_at(origin + direction * t);
+        if d < HIT_EPSILON * max(t, 1.0) {
+            hit = true;
+            break;
+        }
+        t += d;
+        if t > scene.max_draw_distance {
+            break;
+        }
+    }
+    if !hit {
+        discard;
+    }
+
+    let position = origin + direction * t;
+    // Depth the rasterizer would produce for the same point, so the depth
+    // test against the instances works both ways
+    let clip = camera.projection * camera.view * vec4(position, 1.0);
+    let depth = clip.z / clip.w;
+    if depth < 0.0 || depth > 1.0 {
+        discard;
+    }
+
+    let normal = normal_at(position);
+    // Lit by the light of the instances
+    let diffuse = max(dot(normal, scene.light_direction), 0.0);
+    let albedo = 0.5 + 0.5 * normal;
+
+    var result: Attachments;
+    result.color = vec4(exposed(albedo * (scene.ambient + (1.0 - scene.ambient) * diffuse * scene.light_color)), 1.0);
+    result.depth = depth;
+    return result;
+}
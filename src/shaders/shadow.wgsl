@@ -0,0 +1,40 @@
+// Depth of the instances as seen from the light, a cascade at a time.
+// Instances are placed like in default.wgsl, fading and the pulse animation
+// are left out, they only change sizes slightly.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(1) position: vec4<f32>,
+    @location(2) rotation: vec4<f32>,
+    @location(3) scale: vec4<f32>,
+}
+
+// Mirrors `CascadeUniform` in shadow.rs
+struct Cascade {
+    view_projection: mat4x4<f32>,
+    // Instances further than the draw distance from the camera aren't drawn,
+    // so they don't cast shadows either
+    eye: vec3<f32>,
+    max_draw_distance: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> cascade: Cascade;
+
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    return v + 2.0 * cross(q.xyz, cross(q.xyz, v) + q.w * v);
+}
+
+@vertex
+fn vs_main(in: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    if length(instance.position.xyz - cascade.eye) > cascade.max_draw_distance {
+        // Degenerate triangle, clipped before rasterization
+        return vec4(0.0);
+    }
+
+    let vpos = instance.position.xyz + rotate(instance.rotation, in.position * instance.scale.xyz);
+    return cascade.view_projection * vec4(vpos, 1.0);
+}
@@ -0,0 +1,158 @@
+// Back to front order for blended instances: the indices of the instances
+// of every chunk are sorted together by distance to the eye with a bitonic
+// sort, then the instance data is gathered in that order into the vertex
+// buffers of the transparent draw, one pair of chunks at a time. Expired
+// instances sort last with the padding and aren't drawn.
+
+struct Params {
+    eye: vec4<f32>,
+    // Instances of the bound source chunk
+    count: u32,
+    // Power of two the keys of every instance are padded to
+    padded_len: u32,
+    // Index of the first instance of the source chunk in the keys
+    first: u32,
+    // Keys written for the source chunk, the last one writes the padding too
+    keys: u32,
+    // Sorted slots held by the bound target chunk
+    sorted_first: u32,
+    sorted_count: u32,
+    // Index of the target chunk, for its draw
+    target_chunk: u32,
+};
+
+// One compare and swap step of the sorting network
+struct Step {
+    // Size of the bitonic sequences being merged
+    block: u32,
+    // Distance between compared elements
+    distance: u32,
+};
+
+struct Transform {
+    rotation: vec4<f32>,
+    scale: vec4<f32>,
+};
+
+// Mirrors `wgpu::util::DrawIndexedIndirectArgs`
+struct Draw {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+struct Counts {
+    // Instances with lifetime left, sorted before the expired ones
+    live: atomic<u32>,
+    // One per target chunk
+    draws: array<Draw>,
+};
+
+const WORKGROUP_SIZE: u32 = 256u;
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<uniform> step: Step;
+@group(0) @binding(2)
+var<storage, read_write> keys: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> values: array<u32>;
+@group(0) @binding(4)
+var<storage, read> positions: array<vec4<f32>>;
+@group(0) @binding(5)
+var<storage, read> transforms: array<Transform>;
+// RGBA8 colors and material ids
+@group(0) @binding(6)
+var<storage, read> colors: array<vec2<u32>>;
+@group(0) @binding(7)
+var<storage, read_write> sorted_positions: array<vec4<f32>>;
+@group(0) @binding(8)
+var<storage, read_write> sorted_transforms: array<Transform>;
+@group(0) @binding(9)
+var<storage, read_write> sorted_colors: array<vec2<u32>>;
+// Lifetime left in w
+@group(0) @binding(10)
+var<storage, read> velocities: array<vec4<f32>>;
+@group(0) @binding(11)
+var<storage, read_write> counts: Counts;
+
+// Large dispatches wrap into rows of workgroups
+fn thread_index(id: vec3<u32>, groups: vec3<u32>) -> u32 {
+    return id.x + id.y * groups.x * WORKGROUP_SIZE;
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn distance_keys(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let i = thread_index(id, groups);
+    if i >= params.keys {
+        return;
+    }
+
+    let key = params.first + i;
+    values[key] = key;
+    if i < params.count && velocities[i].w > 0.0 {
+        let offset = positions[i].xyz - params.eye.xyz;
+        // Non-negative floats order like their bits, inverted so the farthest comes first.
+        // The largest key is left to expired instances and padding
+        keys[key] = min(~bitcast<u32>(dot(offset, offset)), 0xfffffffeu);
+        atomicAdd(&counts.live, 1u);
+    } else {
+        keys[key] = 0xffffffffu;
+    }
+}
+
+// Equal keys fall back to the index, which keeps the padding after every instance
+fn before(a: u32, b: u32) -> bool {
+    return keys[a] < keys[b] || (keys[a] == keys[b] && values[a] < values[b]);
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn bitonic_step(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let t = thread_index(id, groups);
+    if t >= params.padded_len / 2u {
+        return;
+    }
+
+    // Every thread compares one pair
+    let i = 2u * step.distance * (t / step.distance) + t % step.distance;
+    let j = i + step.distance;
+    let ascending = (i & step.block) == 0u;
+    if before(j, i) == ascending {
+        let key = keys[i];
+        keys[i] = keys[j];
+        keys[j] = key;
+        let value = values[i];
+        values[i] = values[j];
+        values[j] = value;
+    }
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn gather(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let i = thread_index(id, groups);
+    if i >= params.sorted_count {
+        return;
+    }
+
+    // Slots past the live instances hold expired ones, left out of the draw
+    let live = atomicLoad(&counts.live);
+    if i == 0u && params.first == params.sorted_first {
+        let drawn = max(live, params.sorted_first) - params.sorted_first;
+        counts.draws[params.target_chunk].instance_count = min(drawn, params.sorted_count);
+    }
+    if params.sorted_first + i >= live {
+        return;
+    }
+
+    // Only the instances of the bound source chunk, the other pairs copy the rest
+    let source = values[params.sorted_first + i] - params.first;
+    if source >= params.count {
+        return;
+    }
+    sorted_positions[i] = positions[source];
+    sorted_transforms[i] = transforms[source];
+    sorted_colors[i] = colors[source];
+}
@@ -0,0 +1,128 @@
+struct Params {
+    count: u32,
+    // Index of the first partial written for the bound chunk
+    first_group: u32,
+};
+
+struct Settings {
+    // Upper edge of the last histogram bin, faster instances are counted in it too
+    histogram_max: f32,
+    // Strength of the attractor, as in compute.wgsl
+    gravity: f32,
+};
+
+struct Partial {
+    // Min, max and sum of speeds, instance count
+    speeds: vec4<f32>,
+    // Kinetic and potential energy per unit mass
+    energy: vec2<f32>,
+    // Sum of positions
+    center: vec3<f32>,
+};
+
+struct Result {
+    totals: Partial,
+    bins: array<atomic<u32>, HISTOGRAM_BINS>,
+};
+
+@group(0) @binding(0)
+var<storage, read> velocities: array<vec4<f32>>;
+@group(0) @binding(1)
+var<uniform> params: Params;
+// One per workgroup of every chunk
+@group(0) @binding(2)
+var<storage, read_write> partials: array<Partial>;
+@group(0) @binding(3)
+var<storage, read_write> result: Result;
+@group(0) @binding(4)
+var<uniform> settings: Settings;
+@group(0) @binding(5)
+var<storage, read> positions: array<vec4<f32>>;
+
+const WORKGROUP_SIZE: u32 = 256u;
+// Same as `HISTOGRAM_BINS` in statistics.rs, at most `WORKGROUP_SIZE`
+const HISTOGRAM_BINS: u32 = 32u;
+const EMPTY: Partial = Partial(vec4(3.4e38, 0.0, 0.0, 0.0), vec2(0.0), vec3(0.0));
+
+var<workgroup> shared_stats: array<Partial, WORKGROUP_SIZE>;
+// Counted per workgroup first, so instances don't all contend for the same global atomics
+var<workgroup> shared_bins: array<atomic<u32>, HISTOGRAM_BINS>;
+
+fn combine(a: Partial, b: Partial) -> Partial {
+    return Partial(
+        vec4(min(a.speeds.x, b.speeds.x), max(a.speeds.y, b.speeds.y), a.speeds.zw + b.speeds.zw),
+        a.energy + b.energy,
+        a.center + b.center,
+    );
+}
+
+// Tree reduction over the workgroup, every invocation has to take part
+fn reduce_workgroup(local: u32, value: Partial) -> Partial {
+    shared_stats[local] = value;
+    workgroupBarrier();
+    for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u) {
+        if local < stride {
+            shared_stats[local] = combine(shared_stats[local], shared_stats[local + stride]);
+        }
+        workgroupBarrier();
+    }
+    return shared_stats[0];
+}
+
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn reduce_instances(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let i = id.x + id.y * groups.x * WORKGROUP_SIZE;
+
+    if local < HISTOGRAM_BINS {
+        atomicStore(&shared_bins[local], 0u);
+    }
+    workgroupBarrier();
+
+    var value = EMPTY;
+    if i < params.count {
+        let v = velocities[i].xyz;
+        let p = positions[i].xyz;
+        let speed = length(v);
+        value = Partial(
+            vec4(speed, speed, speed, 1.0),
+            // Potential of `force` in compute.wgsl, clamped at the origin
+            vec2(0.5 * dot(v, v), -settings.gravity / max(length(p), 1.0)),
+            p,
+        );
+
+        let bin = u32(speed / settings.histogram_max * f32(HISTOGRAM_BINS));
+        atomicAdd(&shared_bins[min(bin, HISTOGRAM_BINS - 1u)], 1u);
+    }
+    workgroupBarrier();
+
+    if local < HISTOGRAM_BINS {
+        let count = atomicLoad(&shared_bins[local]);
+        if count > 0u {
+            atomicAdd(&result.bins[local], count);
+        }
+    }
+
+    let total = reduce_workgroup(local, value);
+    if local == 0u {
+        partials[params.first_group + group.x + group.y * groups.x] = total;
+    }
+}
+
+// Dispatched as a single workgroup after every chunk wrote its partials
+@compute
+@workgroup_size(WORKGROUP_SIZE) fn reduce_partials(@builtin(local_invocation_index) local: u32) {
+    var value = EMPTY;
+    for (var i = local; i < arrayLength(&partials); i += WORKGROUP_SIZE) {
+        value = combine(value, partials[i]);
+    }
+
+    let total = reduce_workgroup(local, value);
+    if local == 0u {
+        result.totals = total;
+    }
+}
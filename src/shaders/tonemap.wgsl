@@ -0,0 +1,50 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+struct ToneMapping {
+    // Exponent to the output gamma, left to the target's encoding
+    gamma: f32,
+};
+
+// HDR scene, resolved at the internal resolution like the target
+@group(0) @binding(0)
+var scene: texture_2d<f32>;
+
+@group(0) @binding(1)
+var<uniform> tone_mapping: ToneMapping;
+
+// Single triangle covering the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    return out;
+}
+
+fn load(in: VertexOutput) -> vec4<f32> {
+    let color = textureLoad(scene, vec2<i32>(in.clip_position.xy), 0);
+    return vec4(max(color.rgb, vec3(0.0)), color.a);
+}
+
+fn output(color: vec3<f32>) -> vec3<f32> {
+    return pow(color, vec3(tone_mapping.gamma));
+}
+
+// Narkowicz's fit of the ACES filmic curve, which rolls highlights off
+// gently and deepens the shadows
+@fragment
+fn aces(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = load(in);
+    let x = color.rgb;
+    return vec4(output(saturate(x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14))), color.a);
+}
+
+// Compresses every channel towards 1 without ever clipping
+@fragment
+fn reinhard(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = load(in);
+    return vec4(output(color.rgb / (1.0 + color.rgb)), color.a);
+}
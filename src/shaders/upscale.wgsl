@@ -0,0 +1,153 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+struct Params {
+    // RCAS sharpening, exp2(-stops) of `--sharpness`
+    sharpness: f32,
+};
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+// Most negative lobe RCAS uses, beyond it sharpening rings
+const RCAS_LIMIT: f32 = 0.25 - 1.0 / 16.0;
+
+// Single triangle covering the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn bilinear(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(source, source_sampler, in.uv);
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+fn load(texel: vec2<i32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(source));
+    return textureLoad(source, clamp(texel, vec2(0), size - 1), 0).rgb;
+}
+
+// Lanczos-2 approximation of FSR, stretched along edges by `stretch` and
+// with a negative lobe of strength `lobe`
+fn easu_weight(offset: vec2<f32>, direction: vec2<f32>, stretch: vec2<f32>, lobe: f32, clip: f32) -> f32 {
+    let rotated = vec2(dot(offset, direction), dot(offset, vec2(-direction.y, direction.x))) * stretch;
+    let d2 = min(dot(rotated, rotated), clip);
+
+    var window = 2.0 / 5.0 * d2 - 1.0;
+    var base = lobe * d2 - 1.0;
+    window *= window;
+    base *= base;
+    window = 25.0 / 16.0 * window - (25.0 / 16.0 - 1.0);
+    return window * base;
+}
+
+// Edge adaptive spatial upsampling after FSR 1. The luma gradient around the
+// four nearest texels orients the kernel along edges, which keeps them sharp
+// where a bilinear upscale smears them.
+@fragment
+fn easu(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(source));
+    let position = in.uv * size - 0.5;
+    let base = vec2<i32>(floor(position));
+    let f = fract(position);
+
+    // 4x4 texels around the sample, only the 12 without corners are used
+    var colors: array<vec3<f32>, 16>;
+    var lumas: array<f32, 16>;
+    for (var y = 0; y < 4; y++) {
+        for (var x = 0; x < 4; x++) {
+            let color = load(base + vec2(x - 1, y - 1));
+            colors[y * 4 + x] = color;
+            lumas[y * 4 + x] = luma(color);
+        }
+    }
+
+    // Gradient and edge strength at the four nearest texels, weighted bilinearly
+    var direction = vec2(0.0);
+    var strength = 0.0;
+    for (var y = 1; y < 3; y++) {
+        for (var x = 1; x < 3; x++) {
+            let i = y * 4 + x;
+            let weight = select(1.0 - f.x, f.x, x == 2) * select(1.0 - f.y, f.y, y == 2);
+
+            let dx = lumas[i + 1] - lumas[i - 1];
+            let dy = lumas[i + 4] - lumas[i - 4];
+            // A steady ramp is an edge, a lone bright texel isn't
+            let range_x = max(abs(lumas[i + 1] - lumas[i]), abs(lumas[i] - lumas[i - 1]));
+            let range_y = max(abs(lumas[i + 4] - lumas[i]), abs(lumas[i] - lumas[i - 4]));
+            let edge_x = saturate(abs(dx) / max(range_x, 1.0e-5));
+            let edge_y = saturate(abs(dy) / max(range_y, 1.0e-5));
+
+            direction += vec2(dx, dy) * weight;
+            strength += (edge_x * edge_x + edge_y * edge_y) * weight;
+        }
+    }
+
+    let length2 = dot(direction, direction);
+    direction = select(vec2(1.0, 0.0), direction * inverseSqrt(length2), length2 >= 1.0 / 32768.0);
+    strength = 0.5 * strength;
+    strength *= strength;
+
+    // Stretch the kernel along the edge and shrink it across, more on strong edges
+    let stretch_along = 1.0 / max(abs(direction.x), abs(direction.y));
+    let stretch = vec2(1.0 + (stretch_along - 1.0) * strength, 1.0 - 0.5 * strength);
+    let lobe = 0.5 + ((1.0 / 4.0 - 0.04) - 0.5) * strength;
+    let clip = 1.0 / lobe;
+
+    var color = vec3(0.0);
+    var total = 0.0;
+    for (var y = 0; y < 4; y++) {
+        for (var x = 0; x < 4; x++) {
+            if (x == 0 || x == 3) && (y == 0 || y == 3) {
+                continue;
+            }
+            let offset = vec2(f32(x - 1), f32(y - 1)) - f;
+            let weight = easu_weight(offset, direction, stretch, lobe, clip);
+            color += colors[y * 4 + x] * weight;
+            total += weight;
+        }
+    }
+
+    // Negative lobes overshoot, clamp to the nearest texels so edges don't ring
+    let low = min(min(colors[5], colors[6]), min(colors[9], colors[10]));
+    let high = max(max(colors[5], colors[6]), max(colors[9], colors[10]));
+    return vec4(clamp(color / total, low, high), 1.0);
+}
+
+// Robust contrast adaptive sharpening after FSR 1, run at the output
+// resolution. The lobe is limited so sharpening never pushes a texel past
+// its neighbours.
+@fragment
+fn rcas(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = vec2<i32>(in.clip_position.xy);
+    let north = load(texel + vec2(0, -1));
+    let west = load(texel + vec2(-1, 0));
+    let center = load(texel);
+    let east = load(texel + vec2(1, 0));
+    let south = load(texel + vec2(0, 1));
+
+    let low = min(min(north, west), min(east, south));
+    let high = max(max(north, west), max(east, south));
+    let hit_low = min(low, center) / max(4.0 * high, vec3(1.0e-5));
+    let hit_high = (1.0 - max(high, center)) / min(4.0 * low - 4.0, vec3(-1.0e-5));
+    let lobes = max(-hit_low, hit_high);
+    let lobe = max(-RCAS_LIMIT, min(max(lobes.r, max(lobes.g, lobes.b)), 0.0)) * params.sharpness;
+
+    return vec4((lobe * (north + west + east + south) + center) / (4.0 * lobe + 1.0), 1.0);
+}
@@ -8,7 +8,9 @@ use winit::{
 };
 
 pub trait Game {
-    fn init(window: Arc<Window>) -> Self;
+    type Config;
+
+    fn init(window: Arc<Window>, config: &Self::Config) -> Self;
 
     fn window_event(
         &mut self,
@@ -35,23 +37,25 @@ pub struct GameWindow<T: Game> {
     window: Option<Arc<Window>>,
     game: Option<T>,
     title: &'static str,
+    config: T::Config,
 }
 
-impl<T: Game> Default for GameWindow<T> {
+impl<T: Game> Default for GameWindow<T>
+where
+    T::Config: Default,
+{
     fn default() -> Self {
-        Self {
-            window: None,
-            game: None,
-            title: "GameWindow",
-        }
+        Self::new("GameWindow", T::Config::default())
     }
 }
 
 impl<T: Game> GameWindow<T> {
-    pub fn new(title: &'static str) -> Self {
+    pub fn new(title: &'static str, config: T::Config) -> Self {
         Self {
+            window: None,
+            game: None,
             title,
-            ..Default::default()
+            config,
         }
     }
 }
@@ -68,7 +72,7 @@ impl<T: Game> ApplicationHandler for GameWindow<T> {
                 .unwrap(),
         ));
 
-        self.game = Some(Game::init(self.window.clone().unwrap()));
+        self.game = Some(Game::init(self.window.clone().unwrap(), &self.config));
     }
 
     fn device_event(